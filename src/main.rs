@@ -40,21 +40,32 @@ async fn main() {
 
     config.init().await;
 
+    // Initialize Event Bus and its core subscribers
+    crate::sam::services::bus::init();
+    crate::sam::services::metrics::init();
+    crate::sam::services::events::init();
+    crate::sam::services::notifications::init();
+    crate::sam::services::automations::init();
+    crate::sam::services::scheduler::init();
+
     // Initialize Snapcast Server
     crate::sam::services::media::snapcast::init();
 
     // Initialize Web Socket Server
     crate::sam::services::socket::init();
 
-    // Initialize RTSP Service
-    crate::sam::services::rtsp::init();
+    // Initialize Thing drivers (lifx sync, rtsp cameras)
+    crate::sam::services::things::init();
+
+    // Initialize LAN presence detection
+    crate::sam::services::presence::init();
+
+    // Initialize MQTT Bridge
+    crate::sam::services::mqtt::init();
 
     // Initialize Sound Service
     crate::sam::services::sound::init();
     
-    // Syncs database with Lifx API
-    crate::sam::services::lifx::ssync();
-
     // Initialize default settings
     crate::sam::http::api::settings::set_defaults();
     
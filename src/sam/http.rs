@@ -177,6 +177,12 @@ pub fn handle_with_session(current_session: crate::sam::memory::WebSessions, req
         }
 
 
+        // Inbound webhooks are authenticated by their token and signature, not a session
+        if request.url().starts_with("/webhooks/") && request.method() == "POST" {
+            return Ok(api::webhooks::receive(request)?);
+        }
+
+
         // =================================================================
         // Checkpoint -- Redirect the user as required
         // =================================================================
@@ -1,5 +1,8 @@
+pub mod automations;
+pub mod events;
 pub mod humans;
 pub mod io;
+pub mod jobs;
 pub mod locations;
 pub mod observations;
 pub mod pets;
@@ -7,6 +10,7 @@ pub mod services;
 pub mod things;
 pub mod rooms;
 pub mod settings;
+pub mod webhooks;
 
 use rouille::Request;
 use rouille::Response;
@@ -33,6 +37,18 @@ pub fn handle_api_request(current_session: crate::sam::memory::WebSessions, requ
         return Ok(Response::json(&human[0]));
     }
 
+    if request.url().contains("/api/automations"){
+        return Ok(automations::handle(current_session, request)?);
+    }
+
+    if request.url() == "/api/socket" {
+        return Ok(crate::sam::services::socket::handle(current_session, request)?);
+    }
+
+    if request.url() == "/api/events" {
+        return Ok(events::handle(current_session, request)?);
+    }
+
     // IO: GET
     if request.url().contains("/api/io"){
         return Ok(io::handle(current_session, request)?);
@@ -42,6 +58,10 @@ pub fn handle_api_request(current_session: crate::sam::memory::WebSessions, requ
         return Ok(humans::handle(current_session, request)?);
     }
 
+    if request.url().contains("/api/jobs"){
+        return Ok(jobs::handle(current_session, request)?);
+    }
+
     if request.url().contains("/api/locations"){
         return Ok(locations::handle(current_session, request)?);
     }
@@ -62,6 +82,10 @@ pub fn handle_api_request(current_session: crate::sam::memory::WebSessions, requ
         return Ok(settings::handle(current_session, request)?);
     }
 
+    if request.url().contains("/api/webhooks"){
+        return Ok(webhooks::handle(current_session, request)?);
+    }
+
     if request.url().contains("/api/things"){
         return Ok(things::handle(current_session, request)?);
     }
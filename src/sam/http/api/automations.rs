@@ -0,0 +1,144 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// GET/POST           /api/automations
+// GET/PUT/DELETE     /api/automations/{oid}
+// GET                /api/automations/{oid}/log
+// POST               /api/automations/{oid}/dry_run?limit=100
+// POST               /api/automations/webhooks/{name}
+// triggers, conditions and actions are posted as JSON arrays
+
+use rouille::Request;
+use rouille::Response;
+use rouille::post_input;
+use std::io::Read;
+
+pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+
+    let url = request.url().clone();
+    let split = url.split("/");
+    let vec = split.collect::<Vec<&str>>();
+
+    if url.contains("/api/automations/webhooks/") && request.method() == "POST" {
+        let name = vec[4].to_string();
+
+        let mut body = String::new();
+        match request.data() {
+            Some(mut data) => {
+                data.read_to_string(&mut body)?;
+            },
+            None => {}
+        }
+        let payload = serde_json::from_str(&body).unwrap_or(serde_json::Value::String(body));
+
+        crate::sam::services::automations::webhook(name, payload);
+        return Ok(Response::text("ok"));
+    }
+
+    if url == "/api/automations" && request.method() == "GET" {
+        let objects = crate::sam::memory::Automation::select(None, None, None, None)?;
+        return Ok(Response::json(&objects));
+    }
+
+    if url == "/api/automations" && request.method() == "POST" {
+        let mut automation = crate::sam::memory::Automation::new();
+        match parse_input(request, &mut automation) {
+            Ok(_) => {},
+            Err(e) => return Ok(Response::text(e).with_status_code(400))
+        }
+        automation.save()?;
+        crate::sam::services::automations::invalidate();
+        return Ok(Response::json(&automation));
+    }
+
+    if vec.len() > 3 && url.starts_with("/api/automations/") {
+        let oid = vec[3].to_string();
+
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let objects = crate::sam::memory::Automation::select(None, None, None, Some(pg_query))?;
+        if objects.len() == 0 {
+            return Ok(Response::empty_404());
+        }
+        let mut automation = objects[0].clone();
+
+        if url.ends_with("/log") && request.method() == "GET" {
+            let mut pg_query = crate::sam::memory::PostgresQueries::default();
+            pg_query.queries.push(crate::sam::memory::PGCol::String(oid.clone()));
+            pg_query.query_coulmns.push(format!("automation_oid ="));
+            let logs = crate::sam::memory::AutomationLog::select(Some(100), None, None, Some(pg_query))?;
+            return Ok(Response::json(&logs));
+        }
+
+        if url.ends_with("/dry_run") && request.method() == "POST" {
+            let limit = match request.get_param("limit") {
+                Some(limit) => limit.parse::<usize>().unwrap_or(100),
+                None => 100
+            };
+            let results = crate::sam::services::automations::dry_run(&automation, limit)?;
+            return Ok(Response::json(&results));
+        }
+
+        if vec.len() == 4 {
+            if request.method() == "GET" {
+                return Ok(Response::json(&automation));
+            }
+
+            if request.method() == "PUT" {
+                match parse_input(request, &mut automation) {
+                    Ok(_) => {},
+                    Err(e) => return Ok(Response::text(e).with_status_code(400))
+                }
+                automation.updated_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+                automation.save()?;
+                crate::sam::services::automations::invalidate();
+                return Ok(Response::json(&automation));
+            }
+
+            if request.method() == "DELETE" {
+                crate::sam::memory::Automation::destroy(oid)?;
+                crate::sam::services::automations::invalidate();
+                return Ok(Response::text("deleted"));
+            }
+        }
+    }
+
+    return Ok(Response::empty_404());
+}
+
+fn parse_input(request: &Request, automation: &mut crate::sam::memory::Automation) -> Result<(), String> {
+    let input = post_input!(request, {
+        name: String,
+        enabled: Option<String>,
+        triggers: String,
+        conditions: Option<String>,
+        actions: String
+    }).map_err(|e| format!("{}", e))?;
+
+    automation.name = input.name;
+    automation.enabled = input.enabled.map(|e| e == "true" || e == "on").unwrap_or(true);
+    automation.triggers = serde_json::from_str(&input.triggers).map_err(|e| format!("invalid triggers: {}", e))?;
+    automation.conditions = match input.conditions {
+        Some(conditions) => serde_json::from_str(&conditions).map_err(|e| format!("invalid conditions: {}", e))?,
+        None => Vec::new()
+    };
+    automation.actions = serde_json::from_str(&input.actions).map_err(|e| format!("invalid actions: {}", e))?;
+
+    // Catch bad cron expressions before they sit silently in the database
+    for trigger in automation.triggers.iter() {
+        match trigger {
+            crate::sam::memory::AutomationTrigger::Cron{expression} => {
+                crate::sam::tools::cron::CronSchedule::parse(expression).map_err(|e| format!("invalid cron expression '{}': {}", expression, e))?;
+            },
+            _ => {}
+        }
+    }
+    return Ok(());
+}
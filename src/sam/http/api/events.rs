@@ -0,0 +1,138 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// Server-Sent Events stream for live house activity
+// GET /api/events?types=observation_created,job_progress&rooms=ROOM_OID
+// Clients can resume with the Last-Event-ID header (or ?last_event_id=)
+
+use rouille::Request;
+use rouille::Response;
+use rouille::ResponseBody;
+use std::io::Read;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Send a comment line every so often so proxies don't close idle streams
+const KEEP_ALIVE_SECONDS: u64 = 15;
+
+pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+    if request.url() == "/api/events" && request.method() == "GET" {
+
+        let mut filter = crate::sam::services::events::LiveEventFilter::default();
+        filter.human_oid = Some(current_session.human_oid.clone());
+
+        match request.get_param("types") {
+            Some(types) => {
+                for t in types.split(",") {
+                    match crate::sam::services::events::LiveEventType::from_str(t.trim()) {
+                        Ok(event_type) => filter.event_types.push(event_type),
+                        Err(_) => {
+                            return Ok(Response::text(format!("unknown event type: {}", t)).with_status_code(400));
+                        }
+                    }
+                }
+            },
+            None => {}
+        }
+
+        match request.get_param("rooms") {
+            Some(rooms) => {
+                for room in rooms.split(",") {
+                    if room.trim().len() > 0 {
+                        filter.room_oids.push(room.trim().to_string());
+                    }
+                }
+            },
+            None => {}
+        }
+
+        // Resume from Last-Event-ID when the browser reconnects
+        let newest = crate::sam::services::events::last_id();
+        let mut last_event_id = newest;
+        let resume_from = match request.header("Last-Event-ID") {
+            Some(header) => Some(header.to_string()),
+            None => request.get_param("last_event_id")
+        };
+        match resume_from {
+            Some(id) => {
+                match id.trim().parse::<u64>() {
+                    Ok(id) => {
+                        // Ids restart with the process, ignore ids from the future
+                        if id <= newest {
+                            last_event_id = id;
+                        }
+                    },
+                    Err(_) => {}
+                }
+            },
+            None => {}
+        }
+
+        let stream = EventStream{
+            filter: filter,
+            last_event_id: last_event_id,
+            buffer: b"retry: 3000\n\n".to_vec(),
+            position: 0
+        };
+
+        return Ok(Response {
+            status_code: 200,
+            headers: vec![
+                ("Content-Type".into(), "text/event-stream".into()),
+                ("Cache-Control".into(), "no-cache".into()),
+                ("X-Accel-Buffering".into(), "no".into()),
+            ],
+            data: ResponseBody::from_reader(stream),
+            upgrade: None,
+        });
+    }
+
+    return Ok(Response::empty_404());
+}
+
+// Blocking reader handed to rouille, each read waits for the next batch of events
+pub struct EventStream {
+    filter: crate::sam::services::events::LiveEventFilter,
+    last_event_id: u64,
+    buffer: Vec<u8>,
+    position: usize
+}
+
+impl EventStream {
+    fn fill(&mut self) {
+        let (events, cursor) = crate::sam::services::events::wait_since(self.last_event_id, &self.filter, Duration::from_secs(KEEP_ALIVE_SECONDS));
+        self.last_event_id = cursor;
+
+        let mut chunk = String::new();
+        if events.len() == 0 {
+            chunk = format!(": keep-alive\n\n");
+        }
+        for event in events {
+            let data = serde_json::to_string(&event).unwrap_or(format!("{{}}"));
+            chunk = format!("{}id: {}\nevent: {}\ndata: {}\n\n", chunk, event.id, event.event_type, data);
+        }
+
+        self.buffer = chunk.into_bytes();
+        self.position = 0;
+    }
+}
+
+impl Read for EventStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.buffer.len() {
+            self.fill();
+        }
+
+        let remaining = &self.buffer[self.position..];
+        let size = std::cmp::min(remaining.len(), buf.len());
+        buf[..size].copy_from_slice(&remaining[..size]);
+        self.position = self.position + size;
+        return Ok(size);
+    }
+}
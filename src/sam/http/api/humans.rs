@@ -9,6 +9,7 @@
 
 use rouille::Request;
 use rouille::Response;
+use rouille::post_input;
 
 pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
     if request.url() == "/api/humans" {
@@ -16,6 +17,173 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
         return Ok(Response::json(&objects));
     }
 
+    if request.url() == "/api/humans/presence" && request.method() == "GET" {
+        return Ok(Response::json(&crate::sam::services::presence::all()));
+    }
+
+    // GET /api/humans/{oid}/presence (?refresh=true scans now)
+    // POST /api/humans/{oid}/presence/devices
+    // PUT/DELETE /api/humans/{oid}/presence/devices/{device_oid}
+    if request.url().starts_with("/api/humans/") && request.url().contains("/presence"){
+        let url = request.url().clone();
+        let split = url.split("/");
+        let vec = split.collect::<Vec<&str>>();
+        let oid = vec[3].to_string();
+
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let humans = crate::sam::memory::Human::select(None, None, None, Some(pg_query))?;
+        if humans.len() == 0 {
+            return Ok(Response::empty_404());
+        }
+
+        if vec.len() == 5 && request.method() == "GET" {
+            if request.get_param("refresh").map(|r| r == "true").unwrap_or(false) {
+                crate::sam::services::presence::scan()?;
+            }
+            let devices = crate::sam::services::presence::devices(&oid)?;
+            return Ok(Response::json(&serde_json::json!({
+                "human_oid": oid,
+                "tracked": devices.len() > 0,
+                "state": crate::sam::services::presence::state(&oid),
+                "devices": devices,
+            })));
+        }
+
+        if vec.len() == 6 && vec[5] == "devices" && request.method() == "POST" {
+            let mut device = crate::sam::memory::PresenceDevice::new();
+            device.human_oid = oid.clone();
+            match parse_device(request, &mut device) {
+                Ok(_) => {},
+                Err(e) => return Ok(Response::text(e).with_status_code(400))
+            }
+            device.save()?;
+            return Ok(Response::json(&device));
+        }
+
+        if vec.len() == 7 && vec[5] == "devices" {
+            let mut pg_query = crate::sam::memory::PostgresQueries::default();
+            pg_query.queries.push(crate::sam::memory::PGCol::String(vec[6].to_string()));
+            pg_query.query_coulmns.push(format!("oid ="));
+            pg_query.queries.push(crate::sam::memory::PGCol::String(oid.clone()));
+            pg_query.query_coulmns.push(format!(" AND human_oid ="));
+            let devices = crate::sam::memory::PresenceDevice::select(None, None, None, Some(pg_query))?;
+            if devices.len() == 0 {
+                return Ok(Response::empty_404());
+            }
+            let mut device = devices[0].clone();
+
+            if request.method() == "PUT" {
+                match parse_device(request, &mut device) {
+                    Ok(_) => {},
+                    Err(e) => return Ok(Response::text(e).with_status_code(400))
+                }
+                device.updated_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+                device.save()?;
+                return Ok(Response::json(&device));
+            }
+
+            if request.method() == "DELETE" {
+                crate::sam::memory::PresenceDevice::destroy(device.oid)?;
+                return Ok(Response::text("deleted"));
+            }
+        }
+
+        return Ok(Response::empty_404());
+    }
+
+    // PUT /api/humans/{oid}/voice (speaker_id, style_wav, empty for the default voice)
+    if request.url().starts_with("/api/humans/") && request.url().ends_with("/voice") && request.method() == "PUT" {
+        let url = request.url().clone();
+        let split = url.split("/");
+        let vec = split.collect::<Vec<&str>>();
+        let oid = vec[3].to_string();
+
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let humans = crate::sam::memory::Human::select(None, None, None, Some(pg_query))?;
+        if humans.len() == 0 {
+            return Ok(Response::empty_404());
+        }
+
+        let input = post_input!(request, {
+            speaker_id: String,
+            style_wav: String
+        })?;
+        let mut human = humans[0].clone();
+        human.tts_speaker_id = Some(input.speaker_id.trim().to_string()).filter(|s| s.len() > 0);
+        human.tts_style_wav = Some(input.style_wav.trim().to_string()).filter(|s| s.len() > 0);
+        human.updated_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        human.save()?;
+        return Ok(Response::json(&human));
+    }
+
+    // POST /api/humans/recount rebuilds every human's counts from observations
+    if request.url() == "/api/humans/recount" && request.method() == "POST" {
+        let humans = crate::sam::memory::Human::select(None, None, None, None)?;
+        for human in humans.iter() {
+            crate::sam::memory::Human::recount(&human.oid)?;
+        }
+        return Ok(Response::json(&serde_json::json!({"recounted": humans.len()})));
+    }
+
+    // GET /api/humans/{oid}/activity (?days=30)
+    // POST /api/humans/{oid}/recount
+    if request.url().starts_with("/api/humans/") && (request.url().ends_with("/activity") || request.url().ends_with("/recount")) {
+        let url = request.url().clone();
+        let split = url.split("/");
+        let vec = split.collect::<Vec<&str>>();
+        let oid = vec[3].to_string();
+
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let humans = crate::sam::memory::Human::select(None, None, None, Some(pg_query.clone()))?;
+        if humans.len() == 0 {
+            return Ok(Response::empty_404());
+        }
+
+        if vec[4] == "activity" && request.method() == "GET" {
+            let days = match request.get_param("days").map(|d| d.parse::<i64>()) {
+                Some(Ok(days)) if days > 0 => days,
+                Some(_) => return Ok(Response::text("days must be a positive number").with_status_code(400)),
+                None => 30
+            };
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+            let activity = crate::sam::memory::Human::activity(&oid, now - days * 86400)?;
+            return Ok(Response::json(&activity));
+        }
+
+        if vec[4] == "recount" && request.method() == "POST" {
+            crate::sam::memory::Human::recount(&oid)?;
+            let humans = crate::sam::memory::Human::select(None, None, None, Some(pg_query))?;
+            return Ok(Response::json(&humans[0]));
+        }
+
+        return Ok(Response::empty_404());
+    }
+
+    // POST /api/humans/{oid}/merge (human_oid, folded into {oid})
+    if request.url().starts_with("/api/humans/") && request.url().ends_with("/merge") && request.method() == "POST" {
+        let url = request.url().clone();
+        let split = url.split("/");
+        let vec = split.collect::<Vec<&str>>();
+        let oid = vec[3].to_string();
+
+        let input = post_input!(request, {
+            human_oid: String
+        })?;
+        match crate::sam::services::sprec::clusters::merge(&input.human_oid, &oid) {
+            Ok(human) => {
+                crate::sam::services::sprec::build(Some(oid.clone()));
+                return Ok(Response::json(&human));
+            },
+            Err(e) => return Ok(Response::text(format!("{}", e)).with_status_code(400))
+        }
+    }
+
     if request.url().contains("/api/humans") && request.url().contains("/observations"){
        
         let url = request.url().clone();
@@ -66,4 +234,17 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
 
     return Ok(Response::empty_404());
  
-}
\ No newline at end of file
+}
+
+fn parse_device(request: &Request, device: &mut crate::sam::memory::PresenceDevice) -> Result<(), String> {
+    let input = post_input!(request, {
+        name: String,
+        identifier_type: String,
+        identifier: String
+    }).map_err(|e| format!("{}", e))?;
+
+    device.name = input.name.trim().to_string();
+    device.identifier_type = input.identifier_type.trim().to_lowercase();
+    device.identifier = input.identifier;
+    return crate::sam::services::presence::validate(device);
+}
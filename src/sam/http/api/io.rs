@@ -25,20 +25,7 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
 
     match input{
         Some(iput) => {
-            
-            let rivescript_reply = crate::sam::tools::cmd(format!("python3 /opt/sam/scripts/rivescript/brain.py \"{}\"", iput));
-        
-            if rivescript_reply.contains(":::::"){
-                // TODO - Parse Command
-            } 
-        
-            let io = IOReply{
-                text: rivescript_reply,
-                timestamp: 0,
-                response_type: format!("io")
-            };
-        
-            return Ok(Response::json(&io));
+            return Ok(Response::json(&process(&iput)));
         },
         None => {
             let response = Response::text("IO input malformed").with_status_code(500);
@@ -46,4 +33,33 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
         }
     }
    
-}
\ No newline at end of file
+}
+
+// Replies to typed or spoken input
+pub fn process(input: &str) -> IOReply {
+
+    // Built in commands are handled before falling back to rivescript
+    match crate::sam::services::lifx::scene_command(input) {
+        Some(reply) => {
+            return IOReply{
+                text: reply,
+                timestamp: 0,
+                response_type: format!("io")
+            };
+        },
+        None => {}
+    }
+
+    // Input can be anything heard in the house, keep it from escaping the quotes
+    let rivescript_reply = crate::sam::tools::cmd(format!("python3 /opt/sam/scripts/rivescript/brain.py \"{}\"", input.replace("\\", "").replace("\"", "").replace("$", "").replace("`", "")));
+
+    if rivescript_reply.contains(":::::"){
+        // TODO - Parse Command
+    } 
+
+    return IOReply{
+        text: rivescript_reply,
+        timestamp: 0,
+        response_type: format!("io")
+    };
+}
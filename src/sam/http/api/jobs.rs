@@ -0,0 +1,139 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// GET/POST           /api/jobs
+// GET/PUT/DELETE     /api/jobs/{oid}
+// POST               /api/jobs/{oid}/run
+// GET                /api/jobs/internal
+// Jobs belong to the human that created them. Post run_at (unix seconds)
+// for a one-shot job, or cron / interval_seconds for a recurring one.
+
+use rouille::Request;
+use rouille::Response;
+use rouille::post_input;
+use std::str::FromStr;
+
+pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+
+    let url = request.url().clone();
+    let split = url.split("/");
+    let vec = split.collect::<Vec<&str>>();
+
+    if url == "/api/jobs/internal" && request.method() == "GET" {
+        return Ok(Response::json(&crate::sam::services::scheduler::internal_status()));
+    }
+
+    if url == "/api/jobs" && request.method() == "GET" {
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(current_session.human_oid.clone()));
+        pg_query.query_coulmns.push(format!("human_oid ="));
+        let objects = crate::sam::memory::ScheduledJob::select(None, None, Some(format!("next_run_at ASC")), Some(pg_query))?;
+        return Ok(Response::json(&objects));
+    }
+
+    if url == "/api/jobs" && request.method() == "POST" {
+        let mut job = crate::sam::memory::ScheduledJob::new();
+        job.human_oid = Some(current_session.human_oid.clone());
+        match parse_input(request, &mut job) {
+            Ok(_) => {},
+            Err(e) => return Ok(Response::text(e).with_status_code(400))
+        }
+        job.save()?;
+        return Ok(Response::json(&job));
+    }
+
+    if vec.len() > 3 && url.starts_with("/api/jobs/") {
+        let oid = vec[3].to_string();
+
+        // Humans can only see and change their own jobs
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        pg_query.queries.push(crate::sam::memory::PGCol::String(current_session.human_oid.clone()));
+        pg_query.query_coulmns.push(format!(" AND human_oid ="));
+        let objects = crate::sam::memory::ScheduledJob::select(None, None, None, Some(pg_query))?;
+        if objects.len() == 0 {
+            return Ok(Response::empty_404());
+        }
+        let mut job = objects[0].clone();
+
+        if url.ends_with("/run") && request.method() == "POST" {
+            match crate::sam::services::scheduler::run_task(&job) {
+                Ok(_) => job.last_error = None,
+                Err(e) => job.last_error = Some(format!("{}", e))
+            }
+            job.run_count = job.run_count + 1;
+            job.last_run_at = Some(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64);
+            job.save()?;
+            return Ok(Response::json(&job));
+        }
+
+        if vec.len() == 4 {
+            if request.method() == "GET" {
+                return Ok(Response::json(&job));
+            }
+
+            if request.method() == "PUT" {
+                match parse_input(request, &mut job) {
+                    Ok(_) => {},
+                    Err(e) => return Ok(Response::text(e).with_status_code(400))
+                }
+                job.updated_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+                job.save()?;
+                return Ok(Response::json(&job));
+            }
+
+            if request.method() == "DELETE" {
+                crate::sam::memory::ScheduledJob::destroy(oid)?;
+                return Ok(Response::text("deleted"));
+            }
+        }
+    }
+
+    return Ok(Response::empty_404());
+}
+
+fn parse_input(request: &Request, job: &mut crate::sam::memory::ScheduledJob) -> Result<(), String> {
+    let input = post_input!(request, {
+        name: String,
+        task: String,
+        run_at: Option<i64>,
+        cron: Option<String>,
+        interval_seconds: Option<i64>,
+        missed_run_policy: Option<String>,
+        enabled: Option<String>
+    }).map_err(|e| format!("{}", e))?;
+
+    job.name = input.name;
+    job.task = serde_json::from_str(&input.task).map_err(|e| format!("invalid task: {}", e))?;
+    job.run_at = input.run_at;
+    job.cron = input.cron.filter(|c| c.trim().len() > 0);
+    job.interval_seconds = input.interval_seconds;
+    job.enabled = input.enabled.map(|e| e == "true" || e == "on").unwrap_or(true);
+    match input.missed_run_policy {
+        Some(policy) => {
+            job.missed_run_policy = crate::sam::memory::MissedRunPolicy::from_str(&policy).map_err(|_| format!("unknown missed_run_policy: {}", policy))?;
+        },
+        None => {}
+    }
+
+    match &job.cron {
+        Some(expression) => {
+            crate::sam::tools::cron::CronSchedule::parse(expression).map_err(|e| format!("invalid cron expression '{}': {}", expression, e))?;
+        },
+        None => {}
+    }
+
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    match crate::sam::services::scheduler::first_run(job, now) {
+        Some(next_run_at) => job.next_run_at = next_run_at,
+        None => return Err(format!("a job needs run_at, cron or interval_seconds"))
+    }
+    return Ok(());
+}
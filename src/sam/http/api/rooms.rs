@@ -9,6 +9,7 @@
 
 use rouille::Request;
 use rouille::Response;
+use rouille::post_input;
 use serde::{Serialize, Deserialize};
 
 pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
@@ -77,6 +78,30 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
     }
 
 
+    // PUT /api/rooms/{oid}/snapcast (group_id, empty to match the group by name)
+    if request.url().starts_with("/api/rooms/") && request.url().ends_with("/snapcast") && request.method() == "PUT" {
+        let url = request.url().clone();
+        let split = url.split("/");
+        let vec = split.collect::<Vec<&str>>();
+
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(vec[3].to_string()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let rooms = crate::sam::memory::Room::select(None, None, None, Some(pg_query))?;
+        if rooms.len() == 0 {
+            return Ok(Response::empty_404());
+        }
+
+        let input = post_input!(request, {
+            group_id: String
+        })?;
+        let mut room = rooms[0].clone();
+        room.snapcast_group_id = Some(input.group_id.trim().to_string()).filter(|g| g.len() > 0);
+        room.updated_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        room.save()?;
+        return Ok(Response::json(&room));
+    }
+
     if request.url() == "/api/rooms" {
         let objects = crate::sam::memory::Room::select(None, None, None, None)?;
         return Ok(Response::json(&objects));
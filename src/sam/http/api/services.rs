@@ -21,10 +21,22 @@ pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Reques
         return crate::sam::services::lifx::handle(current_session, request);   
     }
 
+    if request.url().contains("/api/services/metrics"){
+        return crate::sam::services::metrics::handle(current_session, request);   
+    }
+
+    if request.url().contains("/api/services/mqtt"){
+        return crate::sam::services::mqtt::handle(current_session, request);   
+    }
+
     if request.url().contains("/api/services/notifications"){
         return crate::sam::services::notifications::handle(current_session, request);   
     }
 
+    if request.url().contains("/api/services/sound"){
+        return crate::sam::services::sound::handle(current_session, request);   
+    }
+
     if request.url().contains("/api/services/osf"){
         return crate::sam::services::osf::handle(current_session, request);   
     }
@@ -38,9 +50,13 @@ pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Reques
     }
     
     if request.url().contains("/api/services/stt"){
-        return crate::sam::services::stt::handle(current_session, request);   
+        return crate::sam::services::stt::handle(current_session, request);
     }
-    
+
+    if request.url().contains("/api/services/sprec"){
+        return crate::sam::services::sprec::handle(current_session, request);
+    }
+
     if request.url().contains("/api/services/jupiter"){
         return crate::sam::services::jupiter::handle(current_session, request);   
     }
@@ -31,6 +31,12 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
             obj.key = input.key;
             obj.values = input.values;
             obj.save()?;
+
+            crate::sam::services::bus::publish(crate::sam::services::bus::Event::SettingChanged{
+                key: obj.key.clone(),
+                values: obj.values.clone(),
+            });
+
             return Ok(Response::json(&obj));
 
         }
@@ -7,6 +7,18 @@
 // Developed by Caleb Mitchell Smith (PixelCoda)
 // Licensed under GPLv3....see LICENSE file.
 
+// GET/POST           /api/things (new_thing_config is the driver's json config)
+// GET                /api/things/drivers
+// POST               /api/things/discover
+// GET                /api/things/{oid}
+// GET                /api/things/states
+// GET                /api/things/health
+// GET                /api/things/{oid}/state (?refresh=true asks the driver)
+// GET                /api/things/{oid}/history (?from=&to=&limit=)
+// GET                /api/things/{oid}/health (?check=true runs a check now)
+// POST               /api/things/{oid}/command (command is a json ThingCommand)
+// PUT                /api/things/{oid}/config (config is the driver's json config)
+
 use rouille::Request;
 use rouille::Response;
 use serde::{Serialize, Deserialize};
@@ -26,8 +38,13 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
             pub name: String,
             pub room: Option<crate::sam::memory::Room>,
             pub thing_type: String, // lifx, etc
+            pub capabilities: Vec<crate::sam::services::things::Capability>,
             pub online_identifiers: Vec<String>,
             pub local_identifiers: Vec<String>,
+            pub online: bool,
+            pub last_seen_at: Option<i64>,
+            pub state: Option<crate::sam::services::things::cache::CachedState>,
+            pub health: Option<crate::sam::services::things::health::HealthStatus>,
             pub created_at: i64,
             pub updated_at: i64
         }
@@ -58,12 +75,17 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
 
             let web_thing = WebThing{
                 id: object.id,
+                capabilities: crate::sam::services::things::capabilities(&object),
+                state: crate::sam::services::things::cache::get(&object.oid),
+                health: crate::sam::services::things::health::status(&object.oid),
                 oid: object.oid,
                 name: object.name,
                 room: room,
                 thing_type: object.thing_type,
                 online_identifiers: object.online_identifiers,
                 local_identifiers: object.local_identifiers,
+                online: object.online,
+                last_seen_at: object.last_seen_at,
                 created_at: object.created_at,
                 updated_at: object.updated_at
             };
@@ -79,7 +101,8 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
             new_thing_ip: String,
             new_thing_username: String,
             new_thing_password: String,
-            new_thing_type: String
+            new_thing_type: String,
+            new_thing_config: Option<String>
         })?;
 
         let mut thing = crate::sam::memory::Thing::new();
@@ -88,6 +111,15 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
         thing.username = input.new_thing_username;
         thing.password = input.new_thing_password;
         thing.thing_type = input.new_thing_type;
+        match input.new_thing_config.filter(|c| c.trim().len() > 0) {
+            Some(config) => {
+                thing.config = match serde_json::from_str(&config) {
+                    Ok(config) => config,
+                    Err(e) => return Ok(Response::text(format!("invalid config: {}", e)).with_status_code(400))
+                };
+            },
+            None => {}
+        }
         thing.save().unwrap();
 
         let mut pg_query = crate::sam::memory::PostgresQueries::default();
@@ -108,5 +140,110 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
         }
     }
 
+    if request.url() == "/api/things/drivers" && request.method() == "GET" {
+        return Ok(Response::json(&crate::sam::services::things::driver_info()));
+    }
+
+    if request.url() == "/api/things/discover" && request.method() == "POST" {
+        return Ok(Response::json(&crate::sam::services::things::discover()));
+    }
+
+    if request.url() == "/api/things/states" && request.method() == "GET" {
+        return Ok(Response::json(&crate::sam::services::things::cache::all()));
+    }
+
+    if request.url() == "/api/things/health" && request.method() == "GET" {
+        return Ok(Response::json(&crate::sam::services::things::health::all()));
+    }
+
+    let url = request.url().clone();
+    let vec = url.split("/").collect::<Vec<&str>>();
+    if vec.len() > 3 && url.starts_with("/api/things/") {
+        let thing = match crate::sam::services::things::find(vec[3])? {
+            Some(thing) => thing,
+            None => return Ok(Response::empty_404())
+        };
+
+        if vec.len() == 4 && request.method() == "GET" {
+            return Ok(Response::json(&thing));
+        }
+
+        if url.ends_with("/state") && request.method() == "GET" {
+            let refresh = request.get_param("refresh").map(|r| r == "true").unwrap_or(false);
+            if refresh {
+                return Ok(Response::json(&crate::sam::services::things::cache::poll(&thing)));
+            }
+            match crate::sam::services::things::cache::get(&thing.oid) {
+                Some(cached) => return Ok(Response::json(&cached)),
+                None => return Ok(Response::json(&crate::sam::services::things::cache::poll(&thing)))
+            }
+        }
+
+        if url.ends_with("/history") && request.method() == "GET" {
+            let mut pg_query = crate::sam::memory::PostgresQueries::default();
+            pg_query.queries.push(crate::sam::memory::PGCol::String(thing.oid.clone()));
+            pg_query.query_coulmns.push(format!("thing_oid ="));
+            match request.get_param("from").and_then(|f| f.parse::<i64>().ok()) {
+                Some(from) => {
+                    pg_query.queries.push(crate::sam::memory::PGCol::BigNumber(from));
+                    pg_query.query_coulmns.push(format!(" AND timestamp >="));
+                },
+                None => {}
+            }
+            match request.get_param("to").and_then(|t| t.parse::<i64>().ok()) {
+                Some(to) => {
+                    pg_query.queries.push(crate::sam::memory::PGCol::BigNumber(to));
+                    pg_query.query_coulmns.push(format!(" AND timestamp <="));
+                },
+                None => {}
+            }
+            let limit = request.get_param("limit").and_then(|l| l.parse::<usize>().ok()).unwrap_or(100);
+            let history = crate::sam::memory::ThingStateHistory::select(Some(limit), None, Some(format!("timestamp DESC")), Some(pg_query))?;
+            return Ok(Response::json(&history));
+        }
+
+        if url.ends_with("/health") && request.method() == "GET" {
+            let run = request.get_param("check").map(|c| c == "true").unwrap_or(false);
+            match crate::sam::services::things::health::status(&thing.oid) {
+                Some(status) if !run => return Ok(Response::json(&status)),
+                _ => return Ok(Response::json(&crate::sam::services::things::health::check(&thing)))
+            }
+        }
+
+        if url.ends_with("/config") && request.method() == "PUT" {
+            let input = post_input!(request, {
+                config: String,
+            })?;
+            let mut thing = thing.clone();
+            thing.config = match serde_json::from_str(&input.config) {
+                Ok(config) => config,
+                Err(e) => return Ok(Response::text(format!("invalid config: {}", e)).with_status_code(400))
+            };
+            if thing.thing_type == "http" {
+                match crate::sam::services::things::http::HttpThingConfig::from_thing(&thing) {
+                    Ok(_) => {},
+                    Err(e) => return Ok(Response::text(format!("{}", e)).with_status_code(400))
+                }
+            }
+            thing.updated_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+            thing.save()?;
+            return Ok(Response::json(&thing));
+        }
+
+        if url.ends_with("/command") && request.method() == "POST" {
+            let input = post_input!(request, {
+                command: String,
+            })?;
+            let command: crate::sam::services::things::ThingCommand = match serde_json::from_str(&input.command) {
+                Ok(command) => command,
+                Err(e) => return Ok(Response::text(format!("invalid command: {}", e)).with_status_code(400))
+            };
+            match crate::sam::services::things::command(&thing, &command) {
+                Ok(result) => return Ok(Response::json(&result)),
+                Err(e) => return Ok(Response::text(format!("{}", e)).with_status_code(502))
+            }
+        }
+    }
+
     return Ok(Response::empty_404());
 }
\ No newline at end of file
@@ -0,0 +1,164 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// GET/POST           /api/webhooks
+// GET/PUT/DELETE     /api/webhooks/{oid}
+// POST               /api/webhooks/{oid}/rotate (new token and secret)
+// GET                /api/webhooks/{oid}/deliveries?limit=50
+// POST               /webhooks/{token} (public, see services/webhooks.rs for signing)
+// actions and mapping are posted as JSON arrays
+
+use rouille::Request;
+use rouille::Response;
+use rouille::post_input;
+use std::io::Read;
+
+// Largest body a webhook will read
+const MAX_BODY: u64 = 1024 * 1024;
+
+pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+
+    let url = request.url().clone();
+    let split = url.split("/");
+    let vec = split.collect::<Vec<&str>>();
+
+    if url == "/api/webhooks" && request.method() == "GET" {
+        let objects = crate::sam::memory::Webhook::select(None, None, Some(format!("name ASC")), None)?;
+        return Ok(Response::json(&objects));
+    }
+
+    if url == "/api/webhooks" && request.method() == "POST" {
+        let mut webhook = crate::sam::memory::Webhook::new();
+        match parse_input(request, &mut webhook) {
+            Ok(_) => {},
+            Err(e) => return Ok(Response::text(e).with_status_code(400))
+        }
+        webhook.save()?;
+        return Ok(Response::json(&webhook));
+    }
+
+    if vec.len() > 3 && url.starts_with("/api/webhooks/") {
+        let oid = vec[3].to_string();
+
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let objects = crate::sam::memory::Webhook::select(None, None, None, Some(pg_query))?;
+        if objects.len() == 0 {
+            return Ok(Response::empty_404());
+        }
+        let mut webhook = objects[0].clone();
+
+        if url.ends_with("/rotate") && request.method() == "POST" {
+            let fresh = crate::sam::memory::Webhook::new();
+            webhook.token = fresh.token;
+            webhook.secret = fresh.secret;
+            webhook.updated_at = fresh.updated_at;
+            webhook.save()?;
+            return Ok(Response::json(&webhook));
+        }
+
+        if url.ends_with("/deliveries") && request.method() == "GET" {
+            let limit = request.get_param("limit").and_then(|l| l.parse::<usize>().ok()).unwrap_or(50);
+            let mut pg_query = crate::sam::memory::PostgresQueries::default();
+            pg_query.queries.push(crate::sam::memory::PGCol::String(webhook.oid.clone()));
+            pg_query.query_coulmns.push(format!("webhook_oid ="));
+            let deliveries = crate::sam::memory::WebhookDelivery::select(Some(limit), None, Some(format!("timestamp DESC")), Some(pg_query))?;
+            return Ok(Response::json(&deliveries));
+        }
+
+        if vec.len() == 4 {
+            if request.method() == "GET" {
+                return Ok(Response::json(&webhook));
+            }
+
+            if request.method() == "PUT" {
+                match parse_input(request, &mut webhook) {
+                    Ok(_) => {},
+                    Err(e) => return Ok(Response::text(e).with_status_code(400))
+                }
+                webhook.updated_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+                webhook.save()?;
+                return Ok(Response::json(&webhook));
+            }
+
+            if request.method() == "DELETE" {
+                crate::sam::memory::Webhook::destroy(oid)?;
+                return Ok(Response::text("deleted"));
+            }
+        }
+    }
+
+    return Ok(Response::empty_404());
+}
+
+// POST /webhooks/{token}
+pub fn receive(request: &Request) -> Result<Response, crate::sam::http::Error> {
+    let token = request.url().trim_start_matches("/webhooks/").trim_end_matches("/").to_string();
+
+    let mut body: Vec<u8> = Vec::new();
+    match request.data() {
+        Some(data) => {
+            data.take(MAX_BODY).read_to_end(&mut body)?;
+        },
+        None => {}
+    }
+
+    let signature = crate::sam::services::webhooks::WebhookSignature{
+        sam_signature: request.header("X-Sam-Signature").map(|h| h.to_string()),
+        sam_timestamp: request.header("X-Sam-Timestamp").map(|h| h.to_string()),
+        hub_signature: request.header("X-Hub-Signature-256").map(|h| h.to_string()),
+    };
+    let remote_addr = match request.header("X-Forwarded-For") {
+        Some(forwarded) => forwarded.to_string(),
+        None => request.remote_addr().ip().to_string()
+    };
+
+    let (code, delivery) = crate::sam::services::webhooks::receive(&token, &body, &signature, remote_addr)?;
+    match delivery {
+        Some(delivery) => {
+            return Ok(Response::json(&serde_json::json!({
+                "delivery": delivery.oid,
+                "status": delivery.status,
+                "error": delivery.error,
+            })).with_status_code(code));
+        },
+        None => return Ok(Response::empty_404())
+    }
+}
+
+fn parse_input(request: &Request, webhook: &mut crate::sam::memory::Webhook) -> Result<(), String> {
+    let input = post_input!(request, {
+        name: String,
+        actions: String,
+        mapping: Option<String>,
+        require_signature: Option<String>,
+        enabled: Option<String>
+    }).map_err(|e| format!("{}", e))?;
+
+    if input.name.trim().len() == 0 {
+        return Err(format!("a webhook needs a name"));
+    }
+    webhook.name = input.name.trim().to_string();
+    webhook.actions = serde_json::from_str(&input.actions).map_err(|e| format!("invalid actions: {}", e))?;
+    match input.mapping.filter(|m| m.trim().len() > 0) {
+        Some(mapping) => {
+            webhook.mapping = serde_json::from_str(&mapping).map_err(|e| format!("invalid mapping: {}", e))?;
+        },
+        None => {}
+    }
+    for mapping in webhook.mapping.iter() {
+        if mapping.pointer.len() > 0 && !mapping.pointer.starts_with("/") {
+            return Err(format!("mapping pointer for {} must start with /", mapping.field));
+        }
+    }
+    webhook.require_signature = input.require_signature.map(|r| r == "true" || r == "on").unwrap_or(true);
+    webhook.enabled = input.enabled.map(|e| e == "true" || e == "on").unwrap_or(true);
+    return Ok(());
+}
@@ -110,7 +110,17 @@ impl Config {
         let c10 = Self::build_table(c9, WebSessions::sql_table_name(), WebSessions::sql_build_statement(), WebSessions::migrations()).await;
         let c11 = Self::build_table(c10, StorageLocation::sql_table_name(), StorageLocation::sql_build_statement(), StorageLocation::migrations()).await;
         let c12 = Self::build_table(c11, FileStorage::sql_table_name(), FileStorage::sql_build_statement(), FileStorage::migrations()).await;
-        let _c13 = Self::build_table(c12, Notification::sql_table_name(), Notification::sql_build_statement(), Notification::migrations()).await;
+        let c13 = Self::build_table(c12, Notification::sql_table_name(), Notification::sql_build_statement(), Notification::migrations()).await;
+        let c14 = Self::build_table(c13, Automation::sql_table_name(), Automation::sql_build_statement(), Automation::migrations()).await;
+        let c15 = Self::build_table(c14, AutomationLog::sql_table_name(), AutomationLog::sql_build_statement(), AutomationLog::migrations()).await;
+        let c16 = Self::build_table(c15, ScheduledJob::sql_table_name(), ScheduledJob::sql_build_statement(), ScheduledJob::migrations()).await;
+        let c17 = Self::build_table(c16, LifxScene::sql_table_name(), LifxScene::sql_build_statement(), LifxScene::migrations()).await;
+        let c18 = Self::build_table(c17, ThingStateHistory::sql_table_name(), ThingStateHistory::sql_build_statement(), ThingStateHistory::migrations()).await;
+        let c19 = Self::build_table(c18, Webhook::sql_table_name(), Webhook::sql_build_statement(), Webhook::migrations()).await;
+        let c20 = Self::build_table(c19, WebhookDelivery::sql_table_name(), WebhookDelivery::sql_build_statement(), WebhookDelivery::migrations()).await;
+        let c21 = Self::build_table(c20, PresenceDevice::sql_table_name(), PresenceDevice::sql_build_statement(), PresenceDevice::migrations()).await;
+        let c22 = Self::build_table(c21, WakeWordTemplate::sql_table_name(), WakeWordTemplate::sql_build_statement(), WakeWordTemplate::migrations()).await;
+        let _c23 = Self::build_table(c22, SpeakerEmbedding::sql_table_name(), SpeakerEmbedding::sql_build_statement(), SpeakerEmbedding::migrations()).await;
 
         
         return Ok(());
@@ -166,6 +176,12 @@ impl Config {
         }
     
     }
+    // Points a table's rows at another human, for merging humans
+    pub fn reassign_human(table_name: String, from_oid: &str, to_oid: &str) -> Result<u64>{
+        let mut client = Config::client()?;
+        let updated = client.execute(format!("UPDATE {} SET human_oid = $1 WHERE human_oid = $2", table_name).as_str(), &[&to_oid, &from_oid])?;
+        return Ok(updated);
+    }
     pub async fn nuke_async() -> Result<()>{
         let config = crate::sam::memory::Config::new();
         // Get a copy of the master key and postgres info
@@ -284,6 +300,7 @@ impl Config {
                     match x {
                         PGCol::String(y) => y as &(dyn postgres::types::ToSql + Sync),
                         PGCol::Number(y) => y as &(dyn postgres::types::ToSql + Sync),
+                        PGCol::BigNumber(y) => y as &(dyn postgres::types::ToSql + Sync),
                         PGCol::Boolean(y) => y as &(dyn postgres::types::ToSql + Sync)
                     }
                 }).collect();
@@ -350,6 +367,46 @@ impl Config {
                         let j = serde_json::to_string(&FileStorage::from_row_lite(&row)?).unwrap();
                         parsed_rows.push(j);
                     }
+                    if table_name == SpeakerEmbedding::sql_table_name(){
+                        let j = serde_json::to_string(&SpeakerEmbedding::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == WakeWordTemplate::sql_table_name(){
+                        let j = serde_json::to_string(&WakeWordTemplate::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == PresenceDevice::sql_table_name(){
+                        let j = serde_json::to_string(&PresenceDevice::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == WebhookDelivery::sql_table_name(){
+                        let j = serde_json::to_string(&WebhookDelivery::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == Webhook::sql_table_name(){
+                        let j = serde_json::to_string(&Webhook::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == ThingStateHistory::sql_table_name(){
+                        let j = serde_json::to_string(&ThingStateHistory::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == LifxScene::sql_table_name(){
+                        let j = serde_json::to_string(&LifxScene::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == ScheduledJob::sql_table_name(){
+                        let j = serde_json::to_string(&ScheduledJob::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == AutomationLog::sql_table_name(){
+                        let j = serde_json::to_string(&AutomationLog::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == Automation::sql_table_name(){
+                        let j = serde_json::to_string(&Automation::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
                 }
     
             },
@@ -416,6 +473,46 @@ impl Config {
                         let j = serde_json::to_string(&FileStorage::from_row_lite(&row)?).unwrap();
                         parsed_rows.push(j);
                     }
+                    if table_name == SpeakerEmbedding::sql_table_name(){
+                        let j = serde_json::to_string(&SpeakerEmbedding::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == WakeWordTemplate::sql_table_name(){
+                        let j = serde_json::to_string(&WakeWordTemplate::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == PresenceDevice::sql_table_name(){
+                        let j = serde_json::to_string(&PresenceDevice::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == WebhookDelivery::sql_table_name(){
+                        let j = serde_json::to_string(&WebhookDelivery::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == Webhook::sql_table_name(){
+                        let j = serde_json::to_string(&Webhook::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == ThingStateHistory::sql_table_name(){
+                        let j = serde_json::to_string(&ThingStateHistory::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == LifxScene::sql_table_name(){
+                        let j = serde_json::to_string(&LifxScene::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == ScheduledJob::sql_table_name(){
+                        let j = serde_json::to_string(&ScheduledJob::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == AutomationLog::sql_table_name(){
+                        let j = serde_json::to_string(&AutomationLog::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
+                    if table_name == Automation::sql_table_name(){
+                        let j = serde_json::to_string(&Automation::from_row(&row)?).unwrap();
+                        parsed_rows.push(j);
+                    }
                 }
             }
         }
@@ -592,11 +689,23 @@ pub struct Human {
     pub oid: String,
     pub name: String,
     pub email: Option<String>,
+    // Passwords are never serialized, observations and bus events carry whole
+    // humans. select goes through json too so it always comes back None here.
+    #[serde(skip_serializing)]
     pub password: Option<String>,
     pub phone_number: Option<String>,
+    // Only changed by record and recount, save leaves them alone
     pub heard_count: i64,
     pub seen_count: i64,
+    pub last_heard_at: Option<i64>,
+    pub last_seen_at: Option<i64>,
+    // Where they were last heard or seen
+    pub last_thing_oid: Option<String>,
+    pub last_room_oid: Option<String>,
     pub authorization_level: i64,
+    // The voice sam answers this human with
+    pub tts_speaker_id: Option<String>,
+    pub tts_style_wav: Option<String>,
     pub created_at: i64,
     pub updated_at: i64
 }
@@ -612,7 +721,13 @@ impl Human {
             phone_number: None,
             heard_count: 0,
             seen_count: 0,
+            last_heard_at: None,
+            last_seen_at: None,
+            last_thing_oid: None,
+            last_room_oid: None,
             authorization_level: 0,
+            tts_speaker_id: None,
+            tts_style_wav: None,
             created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
             updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
         }
@@ -630,7 +745,13 @@ impl Human {
             phone_number varchar NULL,
             heard_count BIGINT NULL,
             seen_count BIGINT NULL,
+            last_heard_at BIGINT NULL,
+            last_seen_at BIGINT NULL,
+            last_thing_oid varchar NULL,
+            last_room_oid varchar NULL,
             authorization_level BIGINT NULL,
+            tts_speaker_id varchar NULL,
+            tts_style_wav varchar NULL,
             created_at BIGINT NULL,
             updated_at BIGINT NULL,
             CONSTRAINT humans_pkey PRIMARY KEY (id));"
@@ -639,7 +760,13 @@ impl Human {
         vec![
             "ALTER TABLE public.humans ADD COLUMN password varchar NULL;",
             "ALTER TABLE public.humans ADD COLUMN created_at BIGINT NULL;",
-            "ALTER TABLE public.humans ADD COLUMN updated_at BIGINT NULL;"
+            "ALTER TABLE public.humans ADD COLUMN updated_at BIGINT NULL;",
+            "ALTER TABLE public.humans ADD COLUMN tts_speaker_id varchar NULL;",
+            "ALTER TABLE public.humans ADD COLUMN tts_style_wav varchar NULL;",
+            "ALTER TABLE public.humans ADD COLUMN last_heard_at BIGINT NULL;",
+            "ALTER TABLE public.humans ADD COLUMN last_seen_at BIGINT NULL;",
+            "ALTER TABLE public.humans ADD COLUMN last_thing_oid varchar NULL;",
+            "ALTER TABLE public.humans ADD COLUMN last_room_oid varchar NULL;"
         ]
     }
     pub fn count() -> Result<i64>{
@@ -717,6 +844,15 @@ impl Human {
                     &self.oid
                 ])?;
             }
+
+            if self.tts_speaker_id.is_some() || self.tts_style_wav.is_some() {
+                client.execute("UPDATE humans SET tts_speaker_id = $1, tts_style_wav = $2 WHERE oid = $3;", 
+                &[
+                    &self.tts_speaker_id,
+                    &self.tts_style_wav,
+                    &self.oid
+                ])?;
+            }
     
    
             
@@ -731,11 +867,9 @@ impl Human {
 
             // Only save if newer than stored information
             if self.updated_at > ads.updated_at {
-                client.execute("UPDATE humans SET name = $1, heard_count = $2, seen_count = $3, authorization_level = $4, updated_at = $5 WHERE oid = $6;", 
+                client.execute("UPDATE humans SET name = $1, authorization_level = $2, updated_at = $3 WHERE oid = $4;", 
                 &[
                     &self.name,
-                    &self.heard_count,
-                    &self.seen_count,
                     &self.authorization_level,
                     &self.updated_at,
                     &ads.oid
@@ -765,6 +899,14 @@ impl Human {
                         &self.oid
                     ])?;
                 }
+
+                // Cleared voices go back to the default
+                client.execute("UPDATE humans SET tts_speaker_id = $1, tts_style_wav = $2 WHERE oid = $3;", 
+                &[
+                    &self.tts_speaker_id,
+                    &self.tts_style_wav,
+                    &ads.oid
+                ])?;
         
             }
 
@@ -805,16 +947,131 @@ impl Human {
             phone_number: sql_phone_number,
             heard_count: row.get("heard_count"),
             seen_count: row.get("seen_count"),
+            last_heard_at: row.get("last_heard_at"),
+            last_seen_at: row.get("last_seen_at"),
+            last_thing_oid: row.get("last_thing_oid"),
+            last_room_oid: row.get("last_room_oid"),
             authorization_level: row.get("authorization_level"),
+            tts_speaker_id: row.get("tts_speaker_id"),
+            tts_style_wav: row.get("tts_style_wav"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at")
         });
     }
+    // Counts one observation of a human in the database itself, so observations
+    // recorded at the same time don't lose each other's increments. An older
+    // observation doesn't move last_thing_oid/last_room_oid back.
+    pub fn record(oid: &str, observation_type: &ObservationType, timestamp: i64, thing_oid: Option<String>, room_oid: Option<String>) -> Result<u64>{
+        let kind = match observation_type {
+            ObservationType::HEARD => "heard",
+            ObservationType::SEEN => "seen",
+            _ => return Ok(0)
+        };
+        let mut client = Config::client()?;
+        let execquery = format!("UPDATE humans SET {kind}_count = COALESCE({kind}_count, 0) + 1,
+            last_{kind}_at = GREATEST(last_{kind}_at, $1),
+            last_thing_oid = CASE WHEN $1 >= COALESCE(GREATEST(last_heard_at, last_seen_at), 0) THEN COALESCE($2, last_thing_oid) ELSE last_thing_oid END,
+            last_room_oid = CASE WHEN $1 >= COALESCE(GREATEST(last_heard_at, last_seen_at), 0) THEN COALESCE($3, last_room_oid) ELSE last_room_oid END
+            WHERE oid = $4;", kind = kind);
+        let updated = client.execute(execquery.as_str(), &[&timestamp, &thing_oid, &room_oid, &oid])?;
+
+        match client.close(){
+            Ok(_) => {},
+            Err(e) => log::error!("failed to close connection to database: {}", e),
+        }
+        return Ok(updated);
+    }
+    // Rebuilds the counts and last_* fields from the observations of a human
+    pub fn recount(oid: &str) -> Result<u64>{
+        let mut client = Config::client()?;
+        let humans = format!("%{},%", oid);
+        let updated = client.execute("UPDATE humans SET
+            heard_count = (SELECT COUNT(*) FROM observations WHERE observation_type = 'HEARD' AND observation_humans LIKE $1),
+            seen_count = (SELECT COUNT(*) FROM observations WHERE observation_type = 'SEEN' AND observation_humans LIKE $1),
+            last_heard_at = (SELECT MAX(timestamp) FROM observations WHERE observation_type = 'HEARD' AND observation_humans LIKE $1),
+            last_seen_at = (SELECT MAX(timestamp) FROM observations WHERE observation_type = 'SEEN' AND observation_humans LIKE $1),
+            last_thing_oid = (SELECT thing_oid FROM observations WHERE observation_type IN ('HEARD', 'SEEN') AND observation_humans LIKE $1 AND NULLIF(thing_oid, '') IS NOT NULL ORDER BY timestamp DESC LIMIT 1),
+            last_room_oid = (SELECT things.room_oid FROM observations JOIN things ON things.oid = observations.thing_oid WHERE observation_type IN ('HEARD', 'SEEN') AND observation_humans LIKE $1 ORDER BY observations.timestamp DESC LIMIT 1)
+            WHERE oid = $2;", &[&humans, &oid])?;
+
+        match client.close(){
+            Ok(_) => {},
+            Err(e) => log::error!("failed to close connection to database: {}", e),
+        }
+        return Ok(updated);
+    }
+    // How often a human was heard and seen since a timestamp, per day and per room
+    pub fn activity(oid: &str, since: i64) -> Result<HumanActivity>{
+        let mut client = Config::client()?;
+        let humans = format!("%{},%", oid);
+        let mut activity = HumanActivity {
+            human_oid: oid.to_string(),
+            since: since,
+            days: Vec::new(),
+            rooms: Vec::new(),
+        };
+
+        for row in client.query("SELECT to_char(to_timestamp(timestamp), 'YYYY-MM-DD') AS day,
+            COUNT(*) FILTER (WHERE observation_type = 'HEARD') AS heard,
+            COUNT(*) FILTER (WHERE observation_type = 'SEEN') AS seen
+            FROM observations WHERE observation_humans LIKE $1 AND timestamp >= $2 AND observation_type IN ('HEARD', 'SEEN')
+            GROUP BY day ORDER BY day ASC;", &[&humans, &since])? {
+            activity.days.push(HumanActivityDay {
+                day: row.get("day"),
+                heard: row.get("heard"),
+                seen: row.get("seen"),
+            });
+        }
+
+        for row in client.query("SELECT things.room_oid AS room_oid, MAX(rooms.name) AS room_name,
+            COUNT(*) FILTER (WHERE observation_type = 'HEARD') AS heard,
+            COUNT(*) FILTER (WHERE observation_type = 'SEEN') AS seen
+            FROM observations LEFT JOIN things ON things.oid = observations.thing_oid LEFT JOIN rooms ON rooms.oid = things.room_oid
+            WHERE observation_humans LIKE $1 AND observations.timestamp >= $2 AND observation_type IN ('HEARD', 'SEEN')
+            GROUP BY things.room_oid ORDER BY COUNT(*) DESC;", &[&humans, &since])? {
+            activity.rooms.push(HumanActivityRoom {
+                room_oid: row.get("room_oid"),
+                room_name: row.get("room_name"),
+                heard: row.get("heard"),
+                seen: row.get("seen"),
+            });
+        }
+
+        match client.close(){
+            Ok(_) => {},
+            Err(e) => log::error!("failed to close connection to database: {}", e),
+        }
+        return Ok(activity);
+    }
     pub fn destroy(oid: String) -> Result<bool>{
         return crate::sam::memory::Config::destroy_row(oid, format!("humans"));
     }
 }
 
+// GET /api/humans/{oid}/activity, observations without a thing have no room
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HumanActivity {
+    pub human_oid: String,
+    pub since: i64,
+    pub days: Vec<HumanActivityDay>,
+    pub rooms: Vec<HumanActivityRoom>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HumanActivityDay {
+    pub day: String,
+    pub heard: i64,
+    pub seen: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HumanActivityRoom {
+    pub room_oid: Option<String>,
+    pub room_name: Option<String>,
+    pub heard: i64,
+    pub seen: i64,
+}
+
 // Face encodings for humans
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct HumanFaceEncoding {
@@ -934,6 +1191,7 @@ pub struct Location {
     pub state: String,
     pub zip_code: String,
     pub lifx_api_key: Option<String>,
+    pub lifx_location_id: Option<String>,
     pub created_at: i64,
     pub updated_at: i64
 }
@@ -949,6 +1207,7 @@ impl Location {
             state: String::new(),
             zip_code: String::new(),
             lifx_api_key: None,
+            lifx_location_id: None,
             created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
             updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
         }
@@ -966,6 +1225,7 @@ impl Location {
             state varchar NULL,
             zip_code varchar NULL,
             lifx_api_key varchar NULL,
+            lifx_location_id varchar NULL,
             created_at BIGINT NULL,
             updated_at BIGINT NULL,
             CONSTRAINT locations_pkey PRIMARY KEY (id));"
@@ -977,7 +1237,8 @@ impl Location {
             "ALTER TABLE public.locations ADD COLUMN lifx_api_key VARCHAR NULL;",
             "ALTER TABLE public.locations ADD COLUMN city VARCHAR NULL;",
             "ALTER TABLE public.locations ADD COLUMN state VARCHAR NULL;",
-            "ALTER TABLE public.locations ADD COLUMN zip_code VARCHAR NULL;"
+            "ALTER TABLE public.locations ADD COLUMN zip_code VARCHAR NULL;",
+            "ALTER TABLE public.locations ADD COLUMN lifx_location_id VARCHAR NULL;"
         ]
     }
     pub fn count() -> Result<i64>{
@@ -1031,6 +1292,14 @@ impl Location {
                 ])?;
             }
 
+            if self.lifx_location_id.is_some() {
+                client.execute("UPDATE locations SET lifx_location_id = $1 WHERE oid = $2;", 
+                &[
+                    &self.lifx_location_id.clone().unwrap(),
+                    &self.oid
+                ])?;
+            }
+
             
             let statement = client.prepare("SELECT * FROM locations WHERE oid = $1")?;
             let _rows_two = client.query(&statement, &[
@@ -1065,6 +1334,14 @@ impl Location {
                     ])?;
                 }
 
+                if self.lifx_location_id.is_some() {
+                    client.execute("UPDATE locations SET lifx_location_id = $1 WHERE oid = $2;", 
+                    &[
+                        &self.lifx_location_id.clone().unwrap(),
+                        &ads.oid
+                    ])?;
+                }
+
             }
 
             let statement_two = client.prepare("SELECT * FROM locations WHERE oid = $1")?;
@@ -1101,6 +1378,7 @@ impl Location {
             state: row.get("state"), 
             zip_code: row.get("zip_code"),
             lifx_api_key: row.get("lifx_api_key"),
+            lifx_location_id: row.get("lifx_location_id"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at")
         });
@@ -1256,6 +1534,8 @@ pub struct Room {
     pub name: String,
     pub icon: String,
     pub location_oid: String,
+    pub lifx_group_id: Option<String>,
+    pub snapcast_group_id: Option<String>,
     pub created_at: i64,
     pub updated_at: i64
 }
@@ -1268,6 +1548,8 @@ impl Room {
             name: String::new(), 
             icon: format!("fa fa-solid fa-cube"),
             location_oid: String::new(),
+            lifx_group_id: None,
+            snapcast_group_id: None,
             created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
             updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
         }
@@ -1282,6 +1564,8 @@ impl Room {
             name varchar NULL,
             icon varchar NULL,
             location_oid varchar NULL,
+            lifx_group_id varchar NULL,
+            snapcast_group_id varchar NULL,
             created_at BIGINT NULL,
             updated_at BIGINT NULL,
             CONSTRAINT rooms_pkey PRIMARY KEY (id));"
@@ -1290,7 +1574,9 @@ impl Room {
         vec![
             "ALTER TABLE public.rooms ADD COLUMN icon varchar NULL;",
             "ALTER TABLE public.rooms ADD COLUMN created_at BIGINT NULL;",
-            "ALTER TABLE public.rooms ADD COLUMN updated_at BIGINT NULL;"
+            "ALTER TABLE public.rooms ADD COLUMN updated_at BIGINT NULL;",
+            "ALTER TABLE public.rooms ADD COLUMN lifx_group_id varchar NULL;",
+            "ALTER TABLE public.rooms ADD COLUMN snapcast_group_id varchar NULL;"
         ]
     }
     pub fn save(&self) -> Result<&Self>{
@@ -1306,11 +1592,13 @@ impl Room {
         ])?;
 
         if rows.len() == 0 {
-            client.execute("INSERT INTO rooms (oid, name, icon, location_oid, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)",
+            client.execute("INSERT INTO rooms (oid, name, icon, location_oid, lifx_group_id, snapcast_group_id, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
                 &[&self.oid.clone(),
                 &self.name,
                 &self.icon,
                 &self.location_oid,
+                &self.lifx_group_id,
+                &self.snapcast_group_id,
                 &self.created_at,
                 &self.updated_at]
             ).unwrap();
@@ -1319,11 +1607,14 @@ impl Room {
 
             // Only save if newer than stored information
             if self.updated_at > ads.updated_at {
-                client.execute("UPDATE rooms SET name = $1, icon = $2, location_oid = $3 WHERE oid = $4;", 
+                client.execute("UPDATE rooms SET name = $1, icon = $2, location_oid = $3, lifx_group_id = $4, snapcast_group_id = $5, updated_at = $6 WHERE oid = $7;", 
                 &[
                     &self.name,
                     &self.icon,
                     &self.location_oid,
+                    &self.lifx_group_id,
+                    &self.snapcast_group_id,
+                    &self.updated_at,
                     &ads.oid
                 ])?;
             }
@@ -1360,6 +1651,8 @@ impl Room {
             name: row.get("name"), 
             icon: icon, 
             location_oid: row.get("location_oid"),
+            lifx_group_id: row.get("lifx_group_id"),
+            snapcast_group_id: row.get("snapcast_group_id"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at")
         });
@@ -1548,9 +1841,19 @@ pub struct Thing {
     pub ip_address: String,
     pub online_identifiers: Vec<String>,
     pub local_identifiers: Vec<String>,
+    #[serde(default = "thing_online_default")]
+    pub online: bool,
+    #[serde(default)]
+    pub last_seen_at: Option<i64>,
+    // Driver specific settings, e.g. the request templates of an http thing
+    #[serde(default)]
+    pub config: serde_json::Value,
     pub created_at: i64,
     pub updated_at: i64
 }
+fn thing_online_default() -> bool {
+    return true;
+}
 impl Thing {
     pub fn new() -> Thing {
         let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
@@ -1566,6 +1869,9 @@ impl Thing {
             ip_address: String::new(), 
             online_identifiers: empty_vec.clone(),
             local_identifiers: empty_vec.clone(),
+            online: true,
+            last_seen_at: None,
+            config: serde_json::Value::Null,
             created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
             updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
         }
@@ -1585,6 +1891,9 @@ impl Thing {
             ip_address varchar NULL,
             online_identifiers varchar NULL,
             local_identifiers varchar NULL,
+            online BOOLEAN NULL,
+            last_seen_at BIGINT NULL,
+            config varchar NULL,
             created_at BIGINT NULL,
             updated_at BIGINT NULL,
             CONSTRAINT things_pkey PRIMARY KEY (id));"
@@ -1595,7 +1904,10 @@ impl Thing {
             "ALTER TABLE public.things ADD COLUMN password varchar NULL;",
             "ALTER TABLE public.things ADD COLUMN ip_address varchar NULL;",
             "ALTER TABLE public.things ADD COLUMN created_at BIGINT NULL;",
-            "ALTER TABLE public.things ADD COLUMN updated_at BIGINT NULL;"
+            "ALTER TABLE public.things ADD COLUMN updated_at BIGINT NULL;",
+            "ALTER TABLE public.things ADD COLUMN online BOOLEAN NULL;",
+            "ALTER TABLE public.things ADD COLUMN last_seen_at BIGINT NULL;",
+            "ALTER TABLE public.things ADD COLUMN config varchar NULL;"
         ]
     }
     pub fn save(&self) -> Result<&Self>{
@@ -1615,7 +1927,7 @@ impl Thing {
         ).unwrap();
 
         if rows.len() == 0 {
-            client.execute("INSERT INTO things (oid, name, room_oid, thing_type, username, password, ip_address, online_identifiers, local_identifiers, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+            client.execute("INSERT INTO things (oid, name, room_oid, thing_type, username, password, ip_address, online_identifiers, local_identifiers, online, last_seen_at, config, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
                 &[&self.oid.clone(),
                 &self.name,
                 &self.room_oid,
@@ -1625,6 +1937,9 @@ impl Thing {
                 &self.ip_address,
                 &self.online_identifiers.join(","),
                 &self.local_identifiers.join(","),
+                &self.online,
+                &self.last_seen_at,
+                &self.config.to_string(),
                 &self.created_at,
                 &self.updated_at]
             )?;        
@@ -1633,7 +1948,7 @@ impl Thing {
 
             // Only save if newer than stored information
             if self.updated_at > ads.updated_at {
-                client.execute("UPDATE things SET name = $1, room_oid = $2, thing_type = $3, username = $4, password = $5, ip_address = $6, online_identifiers = $7, local_identifiers = $8 WHERE oid = $9;", 
+                client.execute("UPDATE things SET name = $1, room_oid = $2, thing_type = $3, username = $4, password = $5, ip_address = $6, online_identifiers = $7, local_identifiers = $8, online = $9, last_seen_at = $10, config = $11, updated_at = $12 WHERE oid = $13;", 
                 &[
                     &self.name,
                     &self.room_oid,
@@ -1643,6 +1958,10 @@ impl Thing {
                     &self.ip_address,
                     &self.online_identifiers.join(","),
                     &self.local_identifiers.join(","),
+                    &self.online,
+                    &self.last_seen_at,
+                    &self.config.to_string(),
+                    &self.updated_at,
                     &ads.oid
                 ])?;
             }
@@ -1652,6 +1971,38 @@ impl Thing {
     
         Ok(self)
     }
+    // Writes only the columns the lifx sync owns, leaving updated_at and
+    // everything a human edits alone.
+    pub fn save_synced(&self) -> Result<u64>{
+        let mut client = Config::client()?;
+        let updated = client.execute("UPDATE things SET name = $1, room_oid = $2, ip_address = $3, online_identifiers = $4, local_identifiers = $5, online = $6, last_seen_at = $7 WHERE oid = $8;",
+            &[
+                &self.name,
+                &self.room_oid,
+                &self.ip_address,
+                &self.online_identifiers.join(","),
+                &self.local_identifiers.join(","),
+                &self.online,
+                &self.last_seen_at,
+                &self.oid
+            ])?;
+        match client.close(){
+            Ok(_) => {},
+            Err(e) => log::error!("failed to close connection to database: {}", e)
+        }
+        Ok(updated)
+    }
+    // Writes only the health columns and leaves updated_at alone, so edits made
+    // while a check was running aren't overwritten. last_seen_at is kept when None.
+    pub fn save_health(oid: &str, online: bool, last_seen_at: Option<i64>) -> Result<u64>{
+        let mut client = Config::client()?;
+        let updated = client.execute("UPDATE things SET online = $1, last_seen_at = COALESCE($2, last_seen_at) WHERE oid = $3;", &[&online, &last_seen_at, &oid])?;
+        match client.close(){
+            Ok(_) => {},
+            Err(e) => log::error!("failed to close connection to database: {}", e)
+        }
+        Ok(updated)
+    }
     pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
         let mut parsed_rows: Vec<Self> = Vec::new();
         let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
@@ -1697,6 +2048,10 @@ impl Thing {
             None => {}
         }  
 
+        // Things from before online was tracked are assumed to be online
+        let online: Option<bool> = row.get("online");
+        let config: Option<String> = row.get("config");
+
         return Ok(Self {
             id: row.get("id"),
             oid: row.get("oid"),
@@ -1708,6 +2063,9 @@ impl Thing {
             ip_address: row.get("ip_address"),
             online_identifiers: online_identifiers,
             local_identifiers: local_identifiers,
+            online: online.unwrap_or(true),
+            last_seen_at: row.get("last_seen_at"),
+            config: config.and_then(|c| serde_json::from_str(&c).ok()).unwrap_or(serde_json::Value::Null),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at")
         });
@@ -1732,6 +2090,10 @@ pub struct Observation {
     pub deep_vision_json: Option<String>,
     pub thing: Option<Thing>,
     pub web_session: Option<WebSessions>,
+    // Which stt engine heard it, how long that took and how sure it was
+    pub stt_engine: Option<String>,
+    pub stt_latency_ms: Option<i64>,
+    pub stt_confidence: Option<f64>,
 }
 impl Observation {
     pub fn new() -> Observation {
@@ -1754,6 +2116,9 @@ impl Observation {
             deep_vision_json: None,
             thing: None,
             web_session: None,
+            stt_engine: None,
+            stt_latency_ms: None,
+            stt_confidence: None,
         }
     }
     pub fn sql_table_name() -> String {
@@ -1765,6 +2130,9 @@ impl Observation {
             "ALTER TABLE public.observations ADD COLUMN deep_vision_json varchar NULL;",
             "ALTER TABLE public.observations ADD COLUMN thing_oid varchar NULL;",
             "ALTER TABLE public.observations ADD COLUMN web_session_id varchar NULL;",
+            "ALTER TABLE public.observations ADD COLUMN stt_engine varchar NULL;",
+            "ALTER TABLE public.observations ADD COLUMN stt_latency_ms BIGINT NULL;",
+            "ALTER TABLE public.observations ADD COLUMN stt_confidence double precision NULL;",
         ]
     }
     pub fn sql_build_statement() -> &'static str {
@@ -1780,6 +2148,9 @@ impl Observation {
             deep_vision_json varchar NULL,
             thing_oid varchar NULL,
             web_session_id varchar NULL,
+            stt_engine varchar NULL,
+            stt_latency_ms BIGINT NULL,
+            stt_confidence double precision NULL,
             CONSTRAINT observations_pkey PRIMARY KEY (id));"
     }
     pub fn save(&self) -> Result<Self>{
@@ -1847,6 +2218,16 @@ impl Observation {
                 ])?;
             }
 
+            if self.stt_engine.is_some() {
+                client.execute("UPDATE observations SET stt_engine = $1, stt_latency_ms = $2, stt_confidence = $3 WHERE oid = $4;", 
+                &[
+                    &self.stt_engine,
+                    &self.stt_latency_ms,
+                    &self.stt_confidence,
+                    &self.oid
+                ])?;
+            }
+
 
             let mut pg_query = PostgresQueries::default();
             pg_query.queries.push(crate::sam::memory::PGCol::String(self.oid.clone()));
@@ -1895,6 +2276,16 @@ impl Observation {
                 ])?;
             }
 
+            if self.stt_engine.is_some() {
+                client.execute("UPDATE observations SET stt_engine = $1, stt_latency_ms = $2, stt_confidence = $3 WHERE oid = $4;", 
+                &[
+                    &self.stt_engine,
+                    &self.stt_latency_ms,
+                    &self.stt_confidence,
+                    &self.oid
+                ])?;
+            }
+
 
     
 
@@ -1924,7 +2315,7 @@ impl Observation {
     }
     pub fn select_lite(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
         let mut parsed_rows: Vec<Self> = Vec::new();
-        let jsons = Config::pg_select(Self::sql_table_name(), Some(format!("id, oid, timestamp, observation_type, observation_objects, observation_humans, observation_notes, deep_vision_json")), limit, offset, order, query)?;
+        let jsons = Config::pg_select(Self::sql_table_name(), Some(format!("id, oid, timestamp, observation_type, thing_oid, observation_objects, observation_humans, observation_notes, deep_vision_json, stt_engine, stt_latency_ms, stt_confidence")), limit, offset, order, query)?;
 
         for j in jsons{
             let object: Self = serde_json::from_str(&j).unwrap();
@@ -1934,6 +2325,39 @@ impl Observation {
 
         Ok(parsed_rows)
     }
+    // Swaps one human for another in every observation of them, observations
+    // that already had both keep one
+    pub fn replace_human(from_oid: &str, to_oid: &str) -> Result<u64>{
+        let mut client = Config::client()?;
+        let from = format!("{},", from_oid);
+        let to = format!("{},", to_oid);
+        let removed = client.execute("UPDATE observations SET observation_humans = replace(observation_humans, $1, '') WHERE observation_humans LIKE $2 AND observation_humans LIKE $3;",
+            &[&from, &format!("%{}%", from), &format!("%{}%", to)])?;
+        let replaced = client.execute("UPDATE observations SET observation_humans = replace(observation_humans, $1, $2) WHERE observation_humans LIKE $3;",
+            &[&from, &to, &format!("%{}%", from)])?;
+        return Ok(removed + replaced);
+    }
+    // HEARD observations without a speaker embedding, oldest first, as (id, oid,
+    // human oids) without their audio. Pass the last id of a page as after_id.
+    pub fn unembedded(after_id: i32, limit: i64, human_oid: Option<&str>) -> Result<Vec<(i32, String, Vec<String>)>>{
+        let mut client = Config::client()?;
+        let humans = human_oid.map(|h| format!("%{},%", h)).unwrap_or(format!("%"));
+        let mut observations: Vec<(i32, String, Vec<String>)> = Vec::new();
+        for row in client.query("SELECT id, oid, observation_humans FROM observations
+            WHERE observation_type = 'HEARD' AND id > $1 AND observation_humans LIKE $2
+            AND NOT EXISTS (SELECT 1 FROM speaker_embeddings WHERE speaker_embeddings.observation_oid = observations.oid)
+            ORDER BY id ASC LIMIT $3;", &[&after_id, &humans, &limit])? {
+            let sql_observation_humans: Option<String> = row.get("observation_humans");
+            let human_oids: Vec<String> = sql_observation_humans.unwrap_or_default().split(",").filter(|h| h.len() > 0).map(|h| h.to_string()).collect();
+            observations.push((row.get("id"), row.get("oid"), human_oids));
+        }
+
+        match client.close(){
+            Ok(_) => {},
+            Err(e) => log::error!("failed to close connection to database: {}", e),
+        }
+        return Ok(observations);
+    }
     fn from_row(row: &Row) -> Result<Self> {
 
         let mut deep_vision: Vec<DeepVisionResult> = Vec::new();
@@ -2040,6 +2464,9 @@ impl Observation {
             deep_vision_json: row.get("deep_vision_json"),
             thing: None,
             web_session: None,
+            stt_engine: row.get("stt_engine"),
+            stt_latency_ms: row.get("stt_latency_ms"),
+            stt_confidence: row.get("stt_confidence"),
         });
     }
     fn from_row_lite(row: &Row) -> Result<Self> {
@@ -2133,6 +2560,19 @@ impl Observation {
             }, 
             None => {}
         }
+
+        // Rooms come from the thing, observations without one store ''
+        let mut thing: Option<Thing> = None;
+        let sql_thing_oid: Option<String> = row.get("thing_oid");
+        match sql_thing_oid.filter(|t| t.len() > 0) {
+            Some(thing_oid) => {
+                let mut pg_query = PostgresQueries::default();
+                pg_query.queries.push(crate::sam::memory::PGCol::String(thing_oid));
+                pg_query.query_coulmns.push(format!("oid ="));
+                thing = Thing::select(None, None, None, Some(pg_query))?.into_iter().next();
+            },
+            None => {}
+        }
         
 
         return Ok(Self {
@@ -2146,8 +2586,11 @@ impl Observation {
             observation_file: None,
             deep_vision,
             deep_vision_json: row.get("deep_vision_json"),
-            thing: None,
+            thing: thing,
             web_session: None,
+            stt_engine: row.get("stt_engine"),
+            stt_latency_ms: row.get("stt_latency_ms"),
+            stt_confidence: row.get("stt_confidence"),
         });
     }
     pub fn destroy(oid: String) -> Result<bool>{
@@ -2294,6 +2737,1487 @@ impl Setting {
     }
 }
 
+// What can start an automation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum AutomationTrigger {
+    // Standard 5 field cron expression evaluated in local time
+    Cron { expression: String },
+    Observation { observation_type: Option<ObservationType>, object: Option<ObservationObjects> },
+    HumanIdentified { human_oid: Option<String>, known: Option<bool> },
+    // Fires when key changes to value, and only when it was from before if from is set
+    ThingState { thing_oid: Option<String>, key: String, value: String, #[serde(default)] from: Option<String> },
+    Webhook { name: String },
+    // state is home or away, any human when human_oid isn't set
+    Presence { human_oid: Option<String>, state: String },
+}
+
+// What must be true for the actions to run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum AutomationCondition {
+    // HH:MM in local time, windows may wrap past midnight (22:00 - 06:00)
+    TimeWindow { start: String, end: String },
+    // The event happened in one of these rooms
+    Room { room_oids: Vec<String> },
+    // mode is any, all or none
+    WhoIsHome { human_oids: Vec<String>, mode: String },
+    Setting { key: String, equals: String },
+}
+
+// What an automation does
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum AutomationAction {
+    LifxSetState {
+        selector: String,
+        power: Option<String>,
+        color: Option<String>,
+        #[serde(default)]
+        brightness: Option<f64>,
+        #[serde(default)]
+        kelvin: Option<i64>,
+        #[serde(default)]
+        duration: Option<f64>,
+        use_public: bool
+    },
+    LifxActivateScene { scene_oid: String, duration: f64, use_public: bool },
+    Tts { text: String },
+    Notify { message: String, human_oid: Option<String> },
+    HttpCall { method: String, url: String, body: Option<String>, headers: Vec<ServiceSetting> },
+    StartRecording { thing_oid: String, seconds: i64 },
+    // command is a ThingCommand, e.g. {"command": "turn_on"}
+    ThingCommand { thing_oid: String, command: serde_json::Value },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Automation {
+    pub id: i32,
+    pub oid: String,
+    pub name: String,
+    pub enabled: bool,
+    pub triggers: Vec<AutomationTrigger>,
+    pub conditions: Vec<AutomationCondition>,
+    pub actions: Vec<AutomationAction>,
+    pub created_at: i64,
+    pub updated_at: i64
+}
+impl Automation {
+    pub fn new() -> Automation {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        Automation { 
+            id: 0,
+            oid: oid,
+            name: String::new(),
+            enabled: true,
+            triggers: Vec::new(),
+            conditions: Vec::new(),
+            actions: Vec::new(),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+            updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("automations")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.automations (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            name varchar NULL,
+            enabled bool DEFAULT true,
+            triggers varchar NULL,
+            conditions varchar NULL,
+            actions varchar NULL,
+            created_at BIGINT NULL,
+            updated_at BIGINT NULL,
+            CONSTRAINT automations_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "",
+        ]
+    }
+    pub fn save(&self) -> Result<&Self>{
+
+        let mut client = Config::client()?;
+
+        // Search for OID matches
+        let mut pg_query = PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(self.oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let rows = Self::select(
+            None, 
+            None, 
+            None, 
+            Some(pg_query)
+        )?;
+
+        let triggers = serde_json::to_string(&self.triggers).unwrap();
+        let conditions = serde_json::to_string(&self.conditions).unwrap();
+        let actions = serde_json::to_string(&self.actions).unwrap();
+
+        if rows.len() == 0 {
+            client.execute("INSERT INTO automations (oid, name, enabled, triggers, conditions, actions, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[&self.oid.clone(),
+                &self.name,
+                &self.enabled,
+                &triggers,
+                &conditions,
+                &actions,
+                &self.created_at,
+                &self.updated_at]
+            )?;
+        } else {
+            let ads = rows[0].clone();
+
+            // Only save if newer than stored information
+            if self.updated_at > ads.updated_at {
+                client.execute("UPDATE automations SET name = $1, enabled = $2, triggers = $3, conditions = $4, actions = $5, updated_at = $6 WHERE oid = $7;", 
+                &[
+                    &self.name,
+                    &self.enabled,
+                    &triggers,
+                    &conditions,
+                    &actions,
+                    &self.updated_at,
+                    &ads.oid
+                ])?;
+            }
+        }
+
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+        
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+
+        let mut triggers: Vec<AutomationTrigger> = Vec::new();
+        let sql_triggers: Option<String> = row.get("triggers");
+        match sql_triggers {
+            Some(json) => {
+                match serde_json::from_str(&json) {
+                    Ok(parsed) => triggers = parsed,
+                    Err(e) => log::error!("failed to parse automation triggers: {}", e)
+                }
+            },
+            None => {}
+        }
+
+        let mut conditions: Vec<AutomationCondition> = Vec::new();
+        let sql_conditions: Option<String> = row.get("conditions");
+        match sql_conditions {
+            Some(json) => {
+                match serde_json::from_str(&json) {
+                    Ok(parsed) => conditions = parsed,
+                    Err(e) => log::error!("failed to parse automation conditions: {}", e)
+                }
+            },
+            None => {}
+        }
+
+        let mut actions: Vec<AutomationAction> = Vec::new();
+        let sql_actions: Option<String> = row.get("actions");
+        match sql_actions {
+            Some(json) => {
+                match serde_json::from_str(&json) {
+                    Ok(parsed) => actions = parsed,
+                    Err(e) => log::error!("failed to parse automation actions: {}", e)
+                }
+            },
+            None => {}
+        }
+
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            name: row.get("name"),
+            enabled: row.get("enabled"),
+            triggers: triggers,
+            conditions: conditions,
+            actions: actions,
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("automations"));
+    }
+}
+
+// Execution log for automations, dry runs are never stored
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AutomationLog {
+    pub id: i32,
+    pub oid: String,
+    pub automation_oid: String,
+    pub event_name: String,
+    pub event_json: String,
+    pub conditions_passed: bool,
+    pub results: Vec<String>,
+    pub timestamp: i64
+}
+impl AutomationLog {
+    pub fn new() -> AutomationLog {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        AutomationLog { 
+            id: 0,
+            oid: oid,
+            automation_oid: String::new(),
+            event_name: String::new(),
+            event_json: String::new(),
+            conditions_passed: false,
+            results: Vec::new(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("automation_logs")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.automation_logs (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            automation_oid varchar NULL,
+            event_name varchar NULL,
+            event_json varchar NULL,
+            conditions_passed bool DEFAULT false,
+            results varchar NULL,
+            timestamp BIGINT NULL,
+            CONSTRAINT automation_logs_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "",
+        ]
+    }
+    pub fn save(&self) -> Result<&Self>{
+        let mut client = Config::client()?;
+        let results = serde_json::to_string(&self.results).unwrap();
+        client.execute("INSERT INTO automation_logs (oid, automation_oid, event_name, event_json, conditions_passed, results, timestamp) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[&self.oid.clone(),
+            &self.automation_oid,
+            &self.event_name,
+            &self.event_json,
+            &self.conditions_passed,
+            &results,
+            &self.timestamp]
+        )?;
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+        
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+        let mut results: Vec<String> = Vec::new();
+        let sql_results: Option<String> = row.get("results");
+        match sql_results {
+            Some(json) => {
+                results = serde_json::from_str(&json).unwrap_or(Vec::new());
+            },
+            None => {}
+        }
+
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            automation_oid: row.get("automation_oid"),
+            event_name: row.get("event_name"),
+            event_json: row.get("event_json"),
+            conditions_passed: row.get("conditions_passed"),
+            results: results,
+            timestamp: row.get("timestamp")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("automation_logs"));
+    }
+}
+
+// What a scheduled job does when it runs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum ScheduledTask {
+    // Reminder delivered as a notification to the owner
+    Notify { message: String },
+    // Reminder spoken out loud through tts
+    Speak { text: String },
+    RunAutomation { automation_oid: String },
+}
+
+// What to do when sam was offline (or busy) when a job should have run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MissedRunPolicy {
+    #[allow(non_camel_case_types)]
+    skip,
+    #[allow(non_camel_case_types)]
+    run_once,
+    #[allow(non_camel_case_types)]
+    run_all
+}
+impl fmt::Display for MissedRunPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+impl std::str::FromStr for MissedRunPolicy {
+    type Err = ();
+    fn from_str(input: &str) -> std::result::Result<MissedRunPolicy, Self::Err> {
+        match input {
+            "skip"  => Ok(MissedRunPolicy::skip),
+            "run_once"  => Ok(MissedRunPolicy::run_once),
+            "run_all"  => Ok(MissedRunPolicy::run_all),
+            _      => Err(()),
+        }
+    }
+}
+
+// One-shot jobs set run_at, recurring jobs set cron or interval_seconds
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledJob {
+    pub id: i32,
+    pub oid: String,
+    pub name: String,
+    pub human_oid: Option<String>,
+    pub task: ScheduledTask,
+    pub run_at: Option<i64>,
+    pub cron: Option<String>,
+    pub interval_seconds: Option<i64>,
+    pub missed_run_policy: MissedRunPolicy,
+    pub enabled: bool,
+    pub next_run_at: i64,
+    pub last_run_at: Option<i64>,
+    pub run_count: i64,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64
+}
+impl ScheduledJob {
+    pub fn new() -> ScheduledJob {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        ScheduledJob { 
+            id: 0,
+            oid: oid,
+            name: String::new(),
+            human_oid: None,
+            task: ScheduledTask::Notify { message: String::new() },
+            run_at: None,
+            cron: None,
+            interval_seconds: None,
+            missed_run_policy: MissedRunPolicy::run_once,
+            enabled: true,
+            next_run_at: 0,
+            last_run_at: None,
+            run_count: 0,
+            last_error: None,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+            updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("scheduled_jobs")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.scheduled_jobs (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            name varchar NULL,
+            human_oid varchar NULL,
+            task varchar NULL,
+            run_at BIGINT NULL,
+            cron varchar NULL,
+            interval_seconds BIGINT NULL,
+            missed_run_policy varchar NULL,
+            enabled bool DEFAULT true,
+            next_run_at BIGINT NULL,
+            last_run_at BIGINT NULL,
+            run_count BIGINT DEFAULT 0,
+            last_error varchar NULL,
+            created_at BIGINT NULL,
+            updated_at BIGINT NULL,
+            CONSTRAINT scheduled_jobs_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "",
+        ]
+    }
+    // The scheduler updates run bookkeeping constantly so saves always win
+    pub fn save(&self) -> Result<&Self>{
+
+        let mut client = Config::client()?;
+
+        // Search for OID matches
+        let mut pg_query = PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(self.oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let rows = Self::select(
+            None, 
+            None, 
+            None, 
+            Some(pg_query)
+        )?;
+
+        let task = serde_json::to_string(&self.task).unwrap();
+        let missed_run_policy = self.missed_run_policy.to_string();
+
+        if rows.len() == 0 {
+            client.execute("INSERT INTO scheduled_jobs (oid, name, human_oid, task, run_at, cron, interval_seconds, missed_run_policy, enabled, next_run_at, last_run_at, run_count, last_error, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
+                &[&self.oid.clone(),
+                &self.name,
+                &self.human_oid,
+                &task,
+                &self.run_at,
+                &self.cron,
+                &self.interval_seconds,
+                &missed_run_policy,
+                &self.enabled,
+                &self.next_run_at,
+                &self.last_run_at,
+                &self.run_count,
+                &self.last_error,
+                &self.created_at,
+                &self.updated_at]
+            )?;
+        } else {
+            client.execute("UPDATE scheduled_jobs SET name = $1, task = $2, run_at = $3, cron = $4, interval_seconds = $5, missed_run_policy = $6, enabled = $7, next_run_at = $8, last_run_at = $9, run_count = $10, last_error = $11, updated_at = $12 WHERE oid = $13;", 
+            &[
+                &self.name,
+                &task,
+                &self.run_at,
+                &self.cron,
+                &self.interval_seconds,
+                &missed_run_policy,
+                &self.enabled,
+                &self.next_run_at,
+                &self.last_run_at,
+                &self.run_count,
+                &self.last_error,
+                &self.updated_at,
+                &self.oid
+            ])?;
+        }
+
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+        
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+
+        let sql_task: Option<String> = row.get("task");
+        let task = match sql_task {
+            Some(json) => {
+                match serde_json::from_str(&json) {
+                    Ok(task) => task,
+                    Err(e) => {
+                        log::error!("failed to parse scheduled job task: {}", e);
+                        ScheduledTask::Notify { message: String::new() }
+                    }
+                }
+            },
+            None => ScheduledTask::Notify { message: String::new() }
+        };
+
+        let mut missed_run_policy = MissedRunPolicy::run_once;
+        let sql_missed_run_policy: Option<String> = row.get("missed_run_policy");
+        match sql_missed_run_policy {
+            Some(policy) => {
+                missed_run_policy = MissedRunPolicy::from_str(&policy).unwrap_or(MissedRunPolicy::run_once);
+            },
+            None => {}
+        }
+
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            name: row.get("name"),
+            human_oid: row.get("human_oid"),
+            task: task,
+            run_at: row.get("run_at"),
+            cron: row.get("cron"),
+            interval_seconds: row.get("interval_seconds"),
+            missed_run_policy: missed_run_policy,
+            enabled: row.get("enabled"),
+            next_run_at: row.get("next_run_at"),
+            last_run_at: row.get("last_run_at"),
+            run_count: row.get("run_count"),
+            last_error: row.get("last_error"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("scheduled_jobs"));
+    }
+}
+
+// State of a single light inside a scene
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LifxSceneState {
+    pub selector: String, // id:xxxx
+    pub label: String,
+    pub power: String,
+    pub hue: f64,
+    pub saturation: f64,
+    pub brightness: f64,
+    pub kelvin: i64,
+}
+
+// State of a non lifx thing in a scene, applied with thing commands
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SceneThingState {
+    pub thing_oid: String,
+    pub name: String,
+    pub power: Option<String>,
+    pub brightness: Option<f64>,
+    pub color: Option<String>,
+}
+
+// Named snapshot of several lights, scoped to a room or a location
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LifxScene {
+    pub id: i32,
+    pub oid: String,
+    pub name: String,
+    pub room_oid: Option<String>,
+    pub location_oid: Option<String>,
+    pub states: Vec<LifxSceneState>,
+    #[serde(default)]
+    pub thing_states: Vec<SceneThingState>,
+    pub created_at: i64,
+    pub updated_at: i64
+}
+impl LifxScene {
+    pub fn new() -> LifxScene {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        LifxScene { 
+            id: 0,
+            oid: oid,
+            name: String::new(),
+            room_oid: None,
+            location_oid: None,
+            states: Vec::new(),
+            thing_states: Vec::new(),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+            updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("lifx_scenes")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.lifx_scenes (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            name varchar NULL,
+            room_oid varchar NULL,
+            location_oid varchar NULL,
+            states varchar NULL,
+            thing_states varchar NULL,
+            created_at BIGINT NULL,
+            updated_at BIGINT NULL,
+            CONSTRAINT lifx_scenes_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "ALTER TABLE public.lifx_scenes ADD COLUMN thing_states varchar NULL;",
+        ]
+    }
+    pub fn save(&self) -> Result<&Self>{
+
+        let mut client = Config::client()?;
+
+        // Search for OID matches
+        let mut pg_query = PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(self.oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let rows = Self::select(
+            None, 
+            None, 
+            None, 
+            Some(pg_query)
+        )?;
+
+        let states = serde_json::to_string(&self.states).unwrap();
+        let thing_states = serde_json::to_string(&self.thing_states).unwrap();
+
+        if rows.len() == 0 {
+            client.execute("INSERT INTO lifx_scenes (oid, name, room_oid, location_oid, states, thing_states, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[&self.oid.clone(),
+                &self.name,
+                &self.room_oid,
+                &self.location_oid,
+                &states,
+                &thing_states,
+                &self.created_at,
+                &self.updated_at]
+            )?;
+        } else {
+            let ads = rows[0].clone();
+
+            // Only save if newer than stored information
+            if self.updated_at > ads.updated_at {
+                client.execute("UPDATE lifx_scenes SET name = $1, room_oid = $2, location_oid = $3, states = $4, thing_states = $5, updated_at = $6 WHERE oid = $7;", 
+                &[
+                    &self.name,
+                    &self.room_oid,
+                    &self.location_oid,
+                    &states,
+                    &thing_states,
+                    &self.updated_at,
+                    &ads.oid
+                ])?;
+            }
+        }
+
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+        
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+
+        let mut states: Vec<LifxSceneState> = Vec::new();
+        let sql_states: Option<String> = row.get("states");
+        match sql_states {
+            Some(json) => {
+                match serde_json::from_str(&json) {
+                    Ok(parsed) => states = parsed,
+                    Err(e) => log::error!("failed to parse lifx scene states: {}", e)
+                }
+            },
+            None => {}
+        }
+
+        let thing_states: Option<String> = row.get("thing_states");
+
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            name: row.get("name"),
+            room_oid: row.get("room_oid"),
+            location_oid: row.get("location_oid"),
+            states: states,
+            thing_states: thing_states.and_then(|t| serde_json::from_str(&t).ok()).unwrap_or(Vec::new()),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("lifx_scenes"));
+    }
+}
+
+// One row per change of a Thing's state, state and previous hold ThingState json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThingStateHistory {
+    pub id: i32,
+    pub oid: String,
+    pub thing_oid: String,
+    pub state: serde_json::Value,
+    pub previous: serde_json::Value,
+    pub changed: Vec<String>,
+    // poll, push or sync
+    pub source: String,
+    pub timestamp: i64
+}
+impl ThingStateHistory {
+    pub fn new() -> ThingStateHistory {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        ThingStateHistory { 
+            id: 0,
+            oid: oid,
+            thing_oid: String::new(),
+            state: serde_json::Value::Null,
+            previous: serde_json::Value::Null,
+            changed: Vec::new(),
+            source: String::new(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("thing_state_history")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.thing_state_history (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            thing_oid varchar NULL,
+            state varchar NULL,
+            previous varchar NULL,
+            changed varchar NULL,
+            source varchar NULL,
+            timestamp BIGINT NULL,
+            CONSTRAINT thing_state_history_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "CREATE INDEX IF NOT EXISTS thing_state_history_thing_oid_timestamp ON public.thing_state_history (thing_oid, timestamp);",
+        ]
+    }
+    pub fn save(&self) -> Result<&Self>{
+        let mut client = Config::client()?;
+        client.execute("INSERT INTO thing_state_history (oid, thing_oid, state, previous, changed, source, timestamp) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[&self.oid.clone(),
+            &self.thing_oid,
+            &self.state.to_string(),
+            &self.previous.to_string(),
+            &serde_json::to_string(&self.changed).unwrap(),
+            &self.source,
+            &self.timestamp]
+        )?;
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+        let state: Option<String> = row.get("state");
+        let previous: Option<String> = row.get("previous");
+        let changed: Option<String> = row.get("changed");
+
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            thing_oid: row.get("thing_oid"),
+            state: state.and_then(|s| serde_json::from_str(&s).ok()).unwrap_or(serde_json::Value::Null),
+            previous: previous.and_then(|s| serde_json::from_str(&s).ok()).unwrap_or(serde_json::Value::Null),
+            changed: changed.and_then(|s| serde_json::from_str(&s).ok()).unwrap_or(Vec::new()),
+            source: row.get("source"),
+            timestamp: row.get("timestamp")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("thing_state_history"));
+    }
+}
+
+// What a webhook does with a delivery, text fields can use {{field}} from the mapping
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum WebhookAction {
+    // A WEBHOOK observation, optionally tied to a thing (and its room)
+    Observation {
+        #[serde(default)]
+        thing_oid: Option<String>,
+        #[serde(default)]
+        notes: Vec<String>,
+    },
+    // Notifies one human, or every human when human_oid is empty
+    Notification {
+        message: String,
+        #[serde(default)]
+        human_oid: Option<String>,
+    },
+    // Publishes WebhookReceived with the webhook's name so automations can trigger on it
+    Event,
+}
+
+// Copies the value at a json pointer in the payload into a named field
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookMapping {
+    pub field: String,
+    pub pointer: String,
+}
+
+// An inbound webhook, called at /webhooks/{token}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Webhook {
+    pub id: i32,
+    pub oid: String,
+    pub name: String,
+    pub token: String,
+    // HMAC-SHA256 key for X-Sam-Signature / X-Hub-Signature-256
+    pub secret: String,
+    pub require_signature: bool,
+    pub enabled: bool,
+    pub actions: Vec<WebhookAction>,
+    pub mapping: Vec<WebhookMapping>,
+    pub last_delivery_at: Option<i64>,
+    pub created_at: i64,
+    pub updated_at: i64
+}
+impl Webhook {
+    pub fn new() -> Webhook {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        Webhook { 
+            id: 0,
+            oid: oid,
+            name: String::new(),
+            token: thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect(),
+            secret: thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect(),
+            require_signature: true,
+            enabled: true,
+            actions: Vec::new(),
+            mapping: Vec::new(),
+            last_delivery_at: None,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+            updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("webhooks")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.webhooks (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            name varchar NULL,
+            token varchar NOT NULL UNIQUE,
+            secret varchar NULL,
+            require_signature BOOLEAN NULL,
+            enabled BOOLEAN NULL,
+            actions varchar NULL,
+            mapping varchar NULL,
+            last_delivery_at BIGINT NULL,
+            created_at BIGINT NULL,
+            updated_at BIGINT NULL,
+            CONSTRAINT webhooks_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "",
+        ]
+    }
+    pub fn save(&self) -> Result<&Self>{
+
+        let mut client = Config::client()?;
+
+        // Search for OID matches
+        let mut pg_query = PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(self.oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let rows = Self::select(
+            None, 
+            None, 
+            None, 
+            Some(pg_query)
+        )?;
+
+        let actions = serde_json::to_string(&self.actions).unwrap();
+        let mapping = serde_json::to_string(&self.mapping).unwrap();
+
+        if rows.len() == 0 {
+            client.execute("INSERT INTO webhooks (oid, name, token, secret, require_signature, enabled, actions, mapping, last_delivery_at, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                &[&self.oid.clone(),
+                &self.name,
+                &self.token,
+                &self.secret,
+                &self.require_signature,
+                &self.enabled,
+                &actions,
+                &mapping,
+                &self.last_delivery_at,
+                &self.created_at,
+                &self.updated_at]
+            )?;
+        } else {
+            let ads = rows[0].clone();
+
+            // Only save if newer than stored information
+            if self.updated_at > ads.updated_at {
+                client.execute("UPDATE webhooks SET name = $1, token = $2, secret = $3, require_signature = $4, enabled = $5, actions = $6, mapping = $7, last_delivery_at = $8, updated_at = $9 WHERE oid = $10;", 
+                &[
+                    &self.name,
+                    &self.token,
+                    &self.secret,
+                    &self.require_signature,
+                    &self.enabled,
+                    &actions,
+                    &mapping,
+                    &self.last_delivery_at,
+                    &self.updated_at,
+                    &ads.oid
+                ])?;
+            }
+        }
+
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+        let actions: Option<String> = row.get("actions");
+        let mapping: Option<String> = row.get("mapping");
+        let require_signature: Option<bool> = row.get("require_signature");
+        let enabled: Option<bool> = row.get("enabled");
+
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            name: row.get("name"),
+            token: row.get("token"),
+            secret: row.get("secret"),
+            require_signature: require_signature.unwrap_or(true),
+            enabled: enabled.unwrap_or(true),
+            actions: actions.and_then(|a| serde_json::from_str(&a).ok()).unwrap_or(Vec::new()),
+            mapping: mapping.and_then(|m| serde_json::from_str(&m).ok()).unwrap_or(Vec::new()),
+            last_delivery_at: row.get("last_delivery_at"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("webhooks"));
+    }
+}
+
+// One call to a webhook, accepted or not
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub oid: String,
+    pub webhook_oid: String,
+    // accepted, rejected or failed
+    pub status: String,
+    pub signature_valid: bool,
+    pub payload: serde_json::Value,
+    pub mapped: serde_json::Value,
+    pub results: Vec<String>,
+    pub error: Option<String>,
+    pub remote_addr: String,
+    pub timestamp: i64
+}
+impl WebhookDelivery {
+    pub fn new() -> WebhookDelivery {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        WebhookDelivery { 
+            id: 0,
+            oid: oid,
+            webhook_oid: String::new(),
+            status: String::new(),
+            signature_valid: false,
+            payload: serde_json::Value::Null,
+            mapped: serde_json::Value::Null,
+            results: Vec::new(),
+            error: None,
+            remote_addr: String::new(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("webhook_deliveries")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.webhook_deliveries (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            webhook_oid varchar NULL,
+            status varchar NULL,
+            signature_valid BOOLEAN NULL,
+            payload varchar NULL,
+            mapped varchar NULL,
+            results varchar NULL,
+            error varchar NULL,
+            remote_addr varchar NULL,
+            timestamp BIGINT NULL,
+            CONSTRAINT webhook_deliveries_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_oid_timestamp ON public.webhook_deliveries (webhook_oid, timestamp);",
+        ]
+    }
+    pub fn save(&self) -> Result<&Self>{
+        let mut client = Config::client()?;
+        client.execute("INSERT INTO webhook_deliveries (oid, webhook_oid, status, signature_valid, payload, mapped, results, error, remote_addr, timestamp) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            &[&self.oid.clone(),
+            &self.webhook_oid,
+            &self.status,
+            &self.signature_valid,
+            &self.payload.to_string(),
+            &self.mapped.to_string(),
+            &serde_json::to_string(&self.results).unwrap(),
+            &self.error,
+            &self.remote_addr,
+            &self.timestamp]
+        )?;
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+        let payload: Option<String> = row.get("payload");
+        let mapped: Option<String> = row.get("mapped");
+        let results: Option<String> = row.get("results");
+        let signature_valid: Option<bool> = row.get("signature_valid");
+
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            webhook_oid: row.get("webhook_oid"),
+            status: row.get("status"),
+            signature_valid: signature_valid.unwrap_or(false),
+            payload: payload.and_then(|p| serde_json::from_str(&p).ok()).unwrap_or(serde_json::Value::Null),
+            mapped: mapped.and_then(|m| serde_json::from_str(&m).ok()).unwrap_or(serde_json::Value::Null),
+            results: results.and_then(|r| serde_json::from_str(&r).ok()).unwrap_or(Vec::new()),
+            error: row.get("error"),
+            remote_addr: row.get("remote_addr"),
+            timestamp: row.get("timestamp")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("webhook_deliveries"));
+    }
+}
+
+// A phone, laptop or watch that gives away where its human is
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PresenceDevice {
+    pub id: i32,
+    pub oid: String,
+    pub human_oid: String,
+    pub name: String,
+    // mac, hostname or ip
+    pub identifier_type: String,
+    pub identifier: String,
+    // Where it answered from last, used to ping it between ARP scans
+    pub last_ip: Option<String>,
+    pub last_seen_at: Option<i64>,
+    pub created_at: i64,
+    pub updated_at: i64
+}
+impl PresenceDevice {
+    pub fn new() -> PresenceDevice {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        PresenceDevice { 
+            id: 0,
+            oid: oid,
+            human_oid: String::new(),
+            name: String::new(),
+            identifier_type: format!("mac"),
+            identifier: String::new(),
+            last_ip: None,
+            last_seen_at: None,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+            updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("presence_devices")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.presence_devices (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            human_oid varchar NULL,
+            name varchar NULL,
+            identifier_type varchar NULL,
+            identifier varchar NULL,
+            last_ip varchar NULL,
+            last_seen_at BIGINT NULL,
+            created_at BIGINT NULL,
+            updated_at BIGINT NULL,
+            CONSTRAINT presence_devices_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "CREATE INDEX IF NOT EXISTS presence_devices_human_oid ON public.presence_devices (human_oid);",
+        ]
+    }
+    pub fn save(&self) -> Result<&Self>{
+
+        let mut client = Config::client()?;
+
+        // Search for OID matches
+        let mut pg_query = PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(self.oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let rows = Self::select(
+            None, 
+            None, 
+            None, 
+            Some(pg_query)
+        )?;
+
+        if rows.len() == 0 {
+            client.execute("INSERT INTO presence_devices (oid, human_oid, name, identifier_type, identifier, last_ip, last_seen_at, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                &[&self.oid.clone(),
+                &self.human_oid,
+                &self.name,
+                &self.identifier_type,
+                &self.identifier,
+                &self.last_ip,
+                &self.last_seen_at,
+                &self.created_at,
+                &self.updated_at]
+            )?;
+        } else {
+            let ads = rows[0].clone();
+
+            // Only save if newer than stored information
+            if self.updated_at > ads.updated_at {
+                client.execute("UPDATE presence_devices SET human_oid = $1, name = $2, identifier_type = $3, identifier = $4, last_ip = $5, last_seen_at = $6, updated_at = $7 WHERE oid = $8;", 
+                &[
+                    &self.human_oid,
+                    &self.name,
+                    &self.identifier_type,
+                    &self.identifier,
+                    &self.last_ip,
+                    &self.last_seen_at,
+                    &self.updated_at,
+                    &ads.oid
+                ])?;
+            }
+        }
+
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            human_oid: row.get("human_oid"),
+            name: row.get("name"),
+            identifier_type: row.get("identifier_type"),
+            identifier: row.get("identifier"),
+            last_ip: row.get("last_ip"),
+            last_seen_at: row.get("last_seen_at"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("presence_devices"));
+    }
+}
+
+// A recording of the wake word, matched against what sam hears
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WakeWordTemplate {
+    pub id: i32,
+    pub oid: String,
+    pub phrase: String,
+    // Who recorded it, templates are shared by everyone
+    pub human_oid: Option<String>,
+    // 16kHz mono wav trimmed to the phrase
+    pub audio: Option<Vec<u8>>,
+    // mfcc frames of the audio
+    pub features: Vec<Vec<f32>>,
+    pub duration_ms: i64,
+    pub created_at: i64,
+    pub updated_at: i64
+}
+impl WakeWordTemplate {
+    pub fn new() -> WakeWordTemplate {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        WakeWordTemplate { 
+            id: 0,
+            oid: oid,
+            phrase: String::new(),
+            human_oid: None,
+            audio: None,
+            features: Vec::new(),
+            duration_ms: 0,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+            updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("wake_word_templates")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.wake_word_templates (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            phrase varchar NULL,
+            human_oid varchar NULL,
+            audio bytea NULL,
+            features varchar NULL,
+            duration_ms BIGINT NULL,
+            created_at BIGINT NULL,
+            updated_at BIGINT NULL,
+            CONSTRAINT wake_word_templates_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "",
+        ]
+    }
+    pub fn save(&self) -> Result<&Self>{
+
+        let mut client = Config::client()?;
+
+        // Search for OID matches
+        let mut pg_query = PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(self.oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let rows = Self::select(
+            None, 
+            None, 
+            None, 
+            Some(pg_query)
+        )?;
+
+        let features = serde_json::to_string(&self.features).unwrap();
+
+        if rows.len() == 0 {
+            client.execute("INSERT INTO wake_word_templates (oid, phrase, human_oid, audio, features, duration_ms, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[&self.oid.clone(),
+                &self.phrase,
+                &self.human_oid,
+                &self.audio,
+                &features,
+                &self.duration_ms,
+                &self.created_at,
+                &self.updated_at]
+            )?;
+        } else {
+            let ads = rows[0].clone();
+
+            // Only save if newer than stored information
+            if self.updated_at > ads.updated_at {
+                client.execute("UPDATE wake_word_templates SET phrase = $1, human_oid = $2, audio = $3, features = $4, duration_ms = $5, updated_at = $6 WHERE oid = $7;", 
+                &[
+                    &self.phrase,
+                    &self.human_oid,
+                    &self.audio,
+                    &features,
+                    &self.duration_ms,
+                    &self.updated_at,
+                    &ads.oid
+                ])?;
+            }
+        }
+
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+        let features: Option<String> = row.get("features");
+
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            phrase: row.get("phrase"),
+            human_oid: row.get("human_oid"),
+            audio: row.get("audio"),
+            features: features.and_then(|f| serde_json::from_str(&f).ok()).unwrap_or(Vec::new()),
+            duration_ms: row.get("duration_ms"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("wake_word_templates"));
+    }
+}
+
+// One clip of a human's voice as a speaker embedding, see services/sprec.rs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpeakerEmbedding {
+    pub id: i32,
+    pub oid: String,
+    pub human_oid: String,
+    // The HEARD observation the clip came from, if any
+    pub observation_oid: Option<String>,
+    // Which encoder made it, embeddings from different encoders don't compare
+    pub encoder: String,
+    pub embedding: Vec<f32>,
+    pub speech_ms: i64,
+    pub created_at: i64,
+    pub updated_at: i64
+}
+impl SpeakerEmbedding {
+    pub fn new() -> SpeakerEmbedding {
+        let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+        SpeakerEmbedding { 
+            id: 0,
+            oid: oid,
+            human_oid: String::new(),
+            observation_oid: None,
+            encoder: String::new(),
+            embedding: Vec::new(),
+            speech_ms: 0,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+            updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        }
+    }
+    pub fn sql_table_name() -> String {
+        return format!("speaker_embeddings")
+    }
+    pub fn sql_build_statement() -> &'static str {
+        "CREATE TABLE public.speaker_embeddings (
+            id serial NOT NULL,
+            oid varchar NOT NULL UNIQUE,
+            human_oid varchar NULL,
+            observation_oid varchar NULL,
+            encoder varchar NULL,
+            embedding varchar NULL,
+            speech_ms BIGINT NULL,
+            created_at BIGINT NULL,
+            updated_at BIGINT NULL,
+            CONSTRAINT speaker_embeddings_pkey PRIMARY KEY (id));"
+    }
+    pub fn migrations() -> Vec<&'static str> {
+        vec![
+            "",
+        ]
+    }
+    pub fn save(&self) -> Result<&Self>{
+
+        let mut client = Config::client()?;
+
+        // Search for OID matches
+        let mut pg_query = PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(self.oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let rows = Self::select(
+            None, 
+            None, 
+            None, 
+            Some(pg_query)
+        )?;
+
+        let embedding = serde_json::to_string(&self.embedding).unwrap();
+
+        if rows.len() == 0 {
+            client.execute("INSERT INTO speaker_embeddings (oid, human_oid, observation_oid, encoder, embedding, speech_ms, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[&self.oid.clone(),
+                &self.human_oid,
+                &self.observation_oid,
+                &self.encoder,
+                &embedding,
+                &self.speech_ms,
+                &self.created_at,
+                &self.updated_at]
+            )?;
+        } else {
+            let ads = rows[0].clone();
+
+            // Only save if newer than stored information
+            if self.updated_at > ads.updated_at {
+                client.execute("UPDATE speaker_embeddings SET human_oid = $1, observation_oid = $2, encoder = $3, embedding = $4, speech_ms = $5, updated_at = $6 WHERE oid = $7;", 
+                &[
+                    &self.human_oid,
+                    &self.observation_oid,
+                    &self.encoder,
+                    &embedding,
+                    &self.speech_ms,
+                    &self.updated_at,
+                    &ads.oid
+                ])?;
+            }
+        }
+
+        return Ok(self);
+    }
+    pub fn select(limit: Option<usize>, offset: Option<usize>, order: Option<String>, query: Option<PostgresQueries>) -> Result<Vec<Self>>{
+        let mut parsed_rows: Vec<Self> = Vec::new();
+        let jsons = crate::sam::memory::Config::pg_select(Self::sql_table_name(), None, limit, offset, order, query)?;
+
+        for j in jsons{
+            let object: Self = serde_json::from_str(&j).unwrap();
+            parsed_rows.push(object);
+        }
+
+        Ok(parsed_rows)
+    }
+    fn from_row(row: &Row) -> Result<Self> {
+        let embedding: Option<String> = row.get("embedding");
+
+        return Ok(Self {
+            id: row.get("id"),
+            oid: row.get("oid"),
+            human_oid: row.get("human_oid"),
+            observation_oid: row.get("observation_oid"),
+            encoder: row.get("encoder"),
+            embedding: embedding.and_then(|e| serde_json::from_str(&e).ok()).unwrap_or(Vec::new()),
+            speech_ms: row.get("speech_ms"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at")
+        });
+    }
+    pub fn destroy(oid: String) -> Result<bool>{
+        return crate::sam::memory::Config::destroy_row(oid, format!("speaker_embeddings"));
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StorageLocation {
     pub id: i32,
@@ -2846,6 +4770,7 @@ impl Default for PostgresQueries {
 pub enum PGCol {
     String(String),
     Number(i32),
+    BigNumber(i64),
     Boolean(bool),
 }
 
@@ -2876,7 +4801,8 @@ pub struct WhoioResult {
 pub enum ObservationType {
     UNKNOWN,
     SEEN,
-    HEARD
+    HEARD,
+    WEBHOOK
 }
 impl fmt::Display for ObservationType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -2890,6 +4816,7 @@ impl std::str::FromStr for ObservationType {
             "UNKNOWN"  => Ok(ObservationType::UNKNOWN),
             "SEEN"  => Ok(ObservationType::SEEN),
             "HEARD"  => Ok(ObservationType::HEARD),
+            "WEBHOOK"  => Ok(ObservationType::WEBHOOK),
             _      => Err(()),
         }
     }
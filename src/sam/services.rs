@@ -21,19 +21,28 @@ error_chain! {
     }
 }
 
+pub mod automations;
+pub mod bus;
 pub mod darknet;
 pub mod dropbox;
+pub mod events;
 pub mod jupiter;
 pub mod lifx;
 pub mod media;
+pub mod metrics;
+pub mod mqtt;
 pub mod notifications;
 pub mod osf;
+pub mod presence;
 pub mod rivescript;
 pub mod rtsp;
+pub mod scheduler;
 pub mod socket;
 pub mod sound;
 pub mod sprec;
 pub mod storage;
 pub mod stt;
+pub mod things;
 pub mod tts;
+pub mod webhooks;
 pub mod who;
\ No newline at end of file
@@ -0,0 +1,496 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// automations.rs evaluates user defined rules against events on the bus.
+// A rule fires when any of its triggers match, all of its conditions pass
+// and then runs every action in order. Each execution is written to the
+// automation_logs table so the web ui can show what happened and why.
+
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use crate::sam::memory::{Automation, AutomationAction, AutomationCondition, AutomationLog, AutomationTrigger};
+use crate::sam::services::bus::Event;
+use serde::{Serialize, Deserialize};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+// A human counts as home if they were observed within this many seconds
+const HOME_WINDOW_SECONDS: i64 = 1800;
+
+pub fn init(){
+    let rx = crate::sam::services::bus::channel("automations");
+    let worker = thread::Builder::new().name("automations".to_string()).spawn(move || {
+        for event in rx {
+            // One bad action shouldn't stop every automation after it
+            if catch_unwind(AssertUnwindSafe(|| evaluate(&event))).is_err() {
+                log::error!("automations panicked handling {}", event.name());
+            }
+        }
+    });
+    match worker{
+        Ok(_) => {
+            log::info!("automations service started successfully");
+        },
+        Err(e) => {
+            log::error!("failed to initialize automations service: {}", e);
+        }
+    }
+
+    // Cron triggers are checked once a minute, on the minute
+    crate::sam::services::scheduler::cron("automations_cron", "* * * * *", || tick(&Local::now()));
+}
+
+fn automations_cache() -> &'static Mutex<Option<Vec<Automation>>> {
+    static AUTOMATIONS: OnceLock<Mutex<Option<Vec<Automation>>>> = OnceLock::new();
+    AUTOMATIONS.get_or_init(|| Mutex::new(None))
+}
+
+// Drops the cached automations, call it whenever one is saved or deleted
+pub fn invalidate(){
+    match automations_cache().lock() {
+        Ok(mut cache) => *cache = None,
+        Err(poisoned) => *poisoned.into_inner() = None
+    }
+}
+
+// Enabled automations, loaded once and kept until invalidate
+pub fn enabled_automations() -> Vec<Automation> {
+    match automations_cache().lock() {
+        Ok(cache) => match cache.as_ref() {
+            Some(automations) => return automations.clone(),
+            None => {}
+        },
+        Err(_) => {}
+    }
+
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::Boolean(true));
+    pg_query.query_coulmns.push(format!("enabled ="));
+    match Automation::select(None, None, None, Some(pg_query)){
+        Ok(automations) => {
+            match automations_cache().lock() {
+                Ok(mut cache) => *cache = Some(automations.clone()),
+                Err(_) => {}
+            }
+            automations
+        },
+        Err(e) => {
+            log::error!("failed to load automations: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// Runs every enabled automation that has a trigger matching the event
+pub fn evaluate(event: &Event){
+    let now = Local::now();
+    for automation in enabled_automations() {
+        if automation.triggers.iter().any(|trigger| trigger_matches(trigger, event)) {
+            execute(&automation, event.name(), serde_json::to_value(event).unwrap_or(serde_json::Value::Null), event_room(event), &now, false);
+        }
+    }
+}
+
+fn tick(now: &DateTime<Local>){
+    for automation in enabled_automations() {
+        for trigger in automation.triggers.iter() {
+            match trigger {
+                AutomationTrigger::Cron{expression} => {
+                    match crate::sam::tools::cron::CronSchedule::parse(expression) {
+                        Ok(schedule) => {
+                            if schedule.matches(now) {
+                                execute(&automation, "Cron", serde_json::json!({"expression": expression}), None, now, false);
+                                break;
+                            }
+                        },
+                        Err(e) => log::error!("automation {} has an invalid cron expression '{}': {}", automation.oid, expression, e)
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn trigger_matches(trigger: &AutomationTrigger, event: &Event) -> bool {
+    match (trigger, event) {
+        (AutomationTrigger::Observation{observation_type, object}, Event::ObservationRecorded{observation, ..}) => {
+            match observation_type {
+                Some(observation_type) => {
+                    if observation_type != &observation.observation_type {
+                        return false;
+                    }
+                },
+                None => {}
+            }
+            match object {
+                Some(object) => {
+                    if !observation.observation_objects.contains(object) {
+                        return false;
+                    }
+                },
+                None => {}
+            }
+            return true;
+        },
+        (AutomationTrigger::HumanIdentified{human_oid, known}, Event::HumanIdentified{human, known: event_known, ..}) => {
+            match human_oid {
+                Some(human_oid) => {
+                    if human_oid != &human.oid {
+                        return false;
+                    }
+                },
+                None => {}
+            }
+            match known {
+                Some(known) => {
+                    if known != event_known {
+                        return false;
+                    }
+                },
+                None => {}
+            }
+            return true;
+        },
+        (AutomationTrigger::ThingState{thing_oid, key, value, from}, Event::ThingStateChanged{thing_oid: event_thing_oid, state, previous, ..}) => {
+            match thing_oid {
+                Some(thing_oid) => {
+                    if Some(thing_oid) != event_thing_oid.as_ref() {
+                        return false;
+                    }
+                },
+                None => {}
+            }
+            let current = match state_value(state, key) {
+                Some(current) => current,
+                None => return false
+            };
+            // Only transitions count, a poll repeating the same value isn't one
+            let before = state_value(previous, key);
+            if before.as_ref() == Some(&current) {
+                return false;
+            }
+            match from {
+                Some(from) => {
+                    if before.map(|b| b.to_lowercase()) != Some(from.to_lowercase()) {
+                        return false;
+                    }
+                },
+                None => {}
+            }
+            return current.to_lowercase() == value.to_lowercase();
+        },
+        (AutomationTrigger::Webhook{name}, Event::WebhookReceived{name: event_name, ..}) => {
+            return name == event_name;
+        },
+        (AutomationTrigger::Presence{human_oid, state}, Event::PresenceChanged{human, home, ..}) => {
+            match human_oid {
+                Some(human_oid) => {
+                    if human_oid != &human.oid {
+                        return false;
+                    }
+                },
+                None => {}
+            }
+            let current = if *home { "home" } else { "away" };
+            return state.to_lowercase() == current;
+        },
+        _ => false
+    }
+}
+
+fn state_value(state: &serde_json::Value, key: &str) -> Option<String> {
+    match state.get(key) {
+        Some(serde_json::Value::String(s)) => Some(s.clone()),
+        Some(serde_json::Value::Null) | None => None,
+        Some(other) => Some(other.to_string())
+    }
+}
+
+pub fn event_room(event: &Event) -> Option<String> {
+    match event {
+        Event::ObservationRecorded{room_oid, ..} => room_oid.clone(),
+        Event::HumanIdentified{room_oid, ..} => room_oid.clone(),
+        Event::ThingStateChanged{room_oid, ..} => room_oid.clone(),
+        _ => None
+    }
+}
+
+// Returns None when every condition passes, otherwise the reason the first one failed
+pub fn check_conditions(automation: &Automation, room_oid: &Option<String>, time: &DateTime<Local>) -> Option<String> {
+    for condition in automation.conditions.iter() {
+        match condition {
+            AutomationCondition::TimeWindow{start, end} => {
+                let start_time = NaiveTime::parse_from_str(start, "%H:%M");
+                let end_time = NaiveTime::parse_from_str(end, "%H:%M");
+                match (start_time, end_time) {
+                    (Ok(start_time), Ok(end_time)) => {
+                        let now = time.time();
+                        let inside = if start_time <= end_time {
+                            now >= start_time && now < end_time
+                        } else {
+                            // Window wraps past midnight
+                            now >= start_time || now < end_time
+                        };
+                        if !inside {
+                            return Some(format!("outside of time window {} - {}", start, end));
+                        }
+                    },
+                    _ => return Some(format!("invalid time window {} - {}", start, end))
+                }
+            },
+            AutomationCondition::Room{room_oids} => {
+                match room_oid {
+                    Some(room_oid) => {
+                        if !room_oids.contains(room_oid) {
+                            return Some(format!("room {} not in condition", room_oid));
+                        }
+                    },
+                    None => return Some(format!("event has no room"))
+                }
+            },
+            AutomationCondition::WhoIsHome{human_oids, mode} => {
+                let home: Vec<bool> = human_oids.iter().map(|oid| is_home(oid, time.timestamp())).collect();
+                let passed = match mode.as_str() {
+                    "all" => home.iter().all(|h| *h),
+                    "none" => home.iter().all(|h| !*h),
+                    _ => home.iter().any(|h| *h)
+                };
+                if !passed {
+                    return Some(format!("who is home condition ({}) failed", mode));
+                }
+            },
+            AutomationCondition::Setting{key, equals} => {
+                let mut pg_query = crate::sam::memory::PostgresQueries::default();
+                pg_query.queries.push(crate::sam::memory::PGCol::String(key.clone()));
+                pg_query.query_coulmns.push(format!("key ="));
+                match crate::sam::memory::Setting::select(None, None, None, Some(pg_query)) {
+                    Ok(settings) => {
+                        if settings.len() == 0 || !settings[0].values.contains(equals) {
+                            return Some(format!("setting {} is not {}", key, equals));
+                        }
+                    },
+                    Err(e) => return Some(format!("failed to load setting {}: {}", key, e))
+                }
+            }
+        }
+    }
+    return None;
+}
+
+// A human is home if their devices are on the LAN, or when they have none,
+// if they were heard or seen shortly before the given time
+pub fn is_home(human_oid: &str, timestamp: i64) -> bool {
+    if crate::sam::services::presence::is_tracked(human_oid) {
+        return crate::sam::services::presence::is_home(human_oid);
+    }
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("%{}%", human_oid)));
+    pg_query.query_coulmns.push(format!("observation_humans ilike"));
+    pg_query.queries.push(crate::sam::memory::PGCol::BigNumber(timestamp - HOME_WINDOW_SECONDS));
+    pg_query.query_coulmns.push(format!(" AND timestamp >="));
+    pg_query.queries.push(crate::sam::memory::PGCol::BigNumber(timestamp));
+    pg_query.query_coulmns.push(format!(" AND timestamp <="));
+    match crate::sam::memory::Observation::select_lite(Some(1), None, None, Some(pg_query)) {
+        Ok(observations) => observations.len() > 0,
+        Err(e) => {
+            log::error!("failed to check if {} is home: {}", human_oid, e);
+            false
+        }
+    }
+}
+
+// Checks conditions and runs the actions, dry runs only describe what would happen
+pub fn execute(automation: &Automation, event_name: &str, event_json: serde_json::Value, room_oid: Option<String>, time: &DateTime<Local>, dry_run: bool) -> AutomationLog {
+    let mut log = AutomationLog::new();
+    log.automation_oid = automation.oid.clone();
+    log.event_name = event_name.to_string();
+    log.event_json = event_json.to_string();
+    log.timestamp = time.timestamp();
+
+    match check_conditions(automation, &room_oid, time) {
+        Some(reason) => {
+            log.conditions_passed = false;
+            log.results.push(reason);
+        },
+        None => {
+            log.conditions_passed = true;
+            for action in automation.actions.iter() {
+                if dry_run {
+                    log.results.push(format!("would run {}", serde_json::to_string(action).unwrap_or(String::new())));
+                } else {
+                    match run_action(action) {
+                        Ok(result) => log.results.push(result),
+                        Err(e) => log.results.push(format!("error: {}", e))
+                    }
+                }
+            }
+        }
+    }
+
+    if !dry_run && log.conditions_passed {
+        log::info!("automation '{}' fired on {}", automation.name, event_name);
+        match log.save() {
+            Ok(_) => {},
+            Err(e) => log::error!("failed to save automation log: {}", e)
+        }
+    }
+    return log;
+}
+
+pub fn run_action(action: &AutomationAction) -> Result<String, crate::sam::services::Error> {
+    match action {
+        AutomationAction::LifxSetState{selector, power, color, brightness, kelvin, duration, use_public} => {
+            let service = crate::sam::services::lifx::get_lifx_service_db_obj()?;
+            let change = crate::sam::services::lifx::ThingStateChange{
+                power: power.clone(),
+                color: color.clone(),
+                brightness: *brightness,
+                kelvin: *kelvin,
+                infrared: None,
+            };
+            let results = crate::sam::services::lifx::apply_state(service.secret, selector.clone(), *use_public, &change, duration.unwrap_or(0.0))?;
+            let ok = results.results.iter().filter(|r| r.status == "ok").count();
+            return Ok(format!("set lifx {} ({}/{} lights ok)", selector, ok, results.results.len()));
+        },
+        AutomationAction::LifxActivateScene{scene_oid, duration, use_public} => {
+            let scene = match crate::sam::services::lifx::find_scene(scene_oid)? {
+                Some(scene) => scene,
+                None => return Err(format!("scene {} not found", scene_oid).into())
+            };
+            let service = crate::sam::services::lifx::get_lifx_service_db_obj()?;
+            let results = crate::sam::services::lifx::activate_scene(service.secret, &scene, *duration, *use_public)?;
+            let ok = results.results.iter().filter(|r| r.status == "ok").count();
+            return Ok(format!("activated scene {} ({}/{} lights ok)", scene.name, ok, results.results.len()));
+        },
+        AutomationAction::Tts{text} => {
+            crate::sam::services::tts::speak(text.clone())?;
+            return Ok(format!("said '{}'", text));
+        },
+        AutomationAction::Notify{message, human_oid} => {
+            let human_oids = match human_oid {
+                Some(human_oid) => vec![human_oid.clone()],
+                None => crate::sam::services::notifications::everyone()?
+            };
+            for human_oid in human_oids.iter() {
+                let mut notification = crate::sam::memory::Notification::new();
+                notification.message = message.clone();
+                notification.human_oid = human_oid.clone();
+                crate::sam::services::notifications::create(notification);
+            }
+            return Ok(format!("notified {} human(s)", human_oids.len()));
+        },
+        AutomationAction::HttpCall{method, url, body, headers} => {
+            let client = reqwest::blocking::Client::new();
+            let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes()).map_err(|e| format!("{}", e))?;
+            let mut request = client.request(method, url).timeout(Duration::from_secs(10));
+            for header in headers.iter() {
+                request = request.header(header.tag.as_str(), header.value.as_str());
+            }
+            match body {
+                Some(body) => request = request.body(body.clone()),
+                None => {}
+            }
+            let response = request.send()?;
+            return Ok(format!("{} returned {}", url, response.status()));
+        },
+        AutomationAction::StartRecording{thing_oid, seconds} => {
+            let mut pg_query = crate::sam::memory::PostgresQueries::default();
+            pg_query.queries.push(crate::sam::memory::PGCol::String(thing_oid.clone()));
+            pg_query.query_coulmns.push(format!("oid ="));
+            let things = crate::sam::memory::Thing::select(None, None, None, Some(pg_query))?;
+            if things.len() == 0 {
+                return Err(format!("thing {} not found", thing_oid).into());
+            }
+
+            // Recording blocks for the whole clip so it gets its own thread
+            let thing = things[0].clone();
+            let seconds = *seconds;
+            thread::spawn(move || {
+                match crate::sam::services::rtsp::record(thing, seconds) {
+                    Ok(_) => {},
+                    Err(e) => log::error!("failed to record: {}", e)
+                }
+            });
+            return Ok(format!("recording {} for {}s", thing_oid, seconds));
+        },
+        AutomationAction::ThingCommand{thing_oid, command} => {
+            let thing = match crate::sam::services::things::find(thing_oid)? {
+                Some(thing) => thing,
+                None => return Err(format!("thing {} not found", thing_oid).into())
+            };
+            let command: crate::sam::services::things::ThingCommand = serde_json::from_value(command.clone()).map_err(|e| format!("invalid thing command: {}", e))?;
+            crate::sam::services::things::command(&thing, &command)?;
+            return Ok(format!("sent {:?} to {}", command, thing.name));
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DryRunResult {
+    pub observation_oid: String,
+    pub timestamp: i64,
+    pub event_name: String,
+    pub conditions_passed: bool,
+    pub results: Vec<String>,
+}
+
+// Replays past observations through an automation without running any actions
+pub fn dry_run(automation: &Automation, limit: usize) -> Result<Vec<DryRunResult>, crate::sam::services::Error> {
+    let mut results: Vec<DryRunResult> = Vec::new();
+    let observations = crate::sam::memory::Observation::select_lite(Some(limit), None, Some(format!("timestamp DESC")), None)?;
+
+    for observation in observations {
+        let room_oid = observation.thing.clone().map(|t| t.room_oid);
+        let time = match Local.timestamp_opt(observation.timestamp, 0).single() {
+            Some(time) => time,
+            None => continue
+        };
+
+        // Rebuild the events the observation would have published
+        let mut events: Vec<Event> = Vec::new();
+        events.push(Event::ObservationRecorded{
+            observation: observation.clone(),
+            room_oid: room_oid.clone()
+        });
+        for human in observation.observation_humans.iter() {
+            events.push(Event::HumanIdentified{
+                human: human.clone(),
+                observation_oid: observation.oid.clone(),
+                thing_oid: observation.thing.clone().map(|t| t.oid),
+                room_oid: room_oid.clone(),
+                known: !crate::sam::services::sprec::clusters::is_unknown(human)
+            });
+        }
+
+        for event in events.iter() {
+            if automation.triggers.iter().any(|trigger| trigger_matches(trigger, event)) {
+                let log = execute(automation, event.name(), serde_json::Value::Null, room_oid.clone(), &time, true);
+                results.push(DryRunResult{
+                    observation_oid: observation.oid.clone(),
+                    timestamp: observation.timestamp,
+                    event_name: event.name().to_string(),
+                    conditions_passed: log.conditions_passed,
+                    results: log.results
+                });
+            }
+        }
+    }
+
+    return Ok(results);
+}
+
+// Inbound webhooks just become bus events so rules can trigger on them
+pub fn webhook(name: String, payload: serde_json::Value){
+    crate::sam::services::bus::publish(Event::WebhookReceived{
+        name: name,
+        payload: payload
+    });
+}
@@ -0,0 +1,209 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// bus.rs is the in-process publish/subscribe bus that connects services.
+// Modules publish typed events when something happens (an observation is
+// recorded, a file is stored, a light changes) and any number of subscribers
+// react to them without the publisher knowing they exist.
+//
+// Subscribers come in three flavours:
+//   subscribe(...)       - sync callback run on the publishing thread
+//   subscribe_async(...) - async callback spawned on the tokio runtime
+//   channel(...)         - std mpsc receiver for long running worker threads
+
+use serde::{Serialize, Deserialize};
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Event {
+    ObservationRecorded {
+        observation: crate::sam::memory::Observation,
+        room_oid: Option<String>,
+    },
+    HumanIdentified {
+        human: crate::sam::memory::Human,
+        observation_oid: String,
+        thing_oid: Option<String>,
+        room_oid: Option<String>,
+        known: bool,
+    },
+    ThingStateChanged {
+        thing_oid: Option<String>,
+        room_oid: Option<String>,
+        selector: String,
+        state: serde_json::Value,
+        // The full state before the change, null when it isn't known
+        #[serde(default)]
+        previous: serde_json::Value,
+    },
+    ThingDiscovered {
+        thing: crate::sam::memory::Thing,
+    },
+    FileStored {
+        file: crate::sam::memory::FileStorage,
+        human_oid: Option<String>,
+        sid: Option<String>,
+        source: String,
+    },
+    NotificationCreated {
+        notification: crate::sam::memory::Notification,
+    },
+    SettingChanged {
+        key: String,
+        values: Vec<String>,
+    },
+    JobProgress {
+        job: String,
+        progress: i64,
+        status: String,
+        human_oid: Option<String>,
+    },
+    WebhookReceived {
+        name: String,
+        payload: serde_json::Value,
+    },
+    // A human arrived (home = true) or left, as seen by their devices on the LAN
+    PresenceChanged {
+        human: crate::sam::memory::Human,
+        home: bool,
+        device_oid: Option<String>,
+        last_seen_at: Option<i64>,
+    },
+    // Something said to sam after the wake word, with the io brain's reply
+    VoiceCommand {
+        observation_oid: String,
+        thing_oid: Option<String>,
+        room_oid: Option<String>,
+        human_oid: Option<String>,
+        text: String,
+        reply: String,
+    },
+}
+impl Event {
+    // Short name used by metrics, logs and subscriber filters
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ObservationRecorded{..} => "ObservationRecorded",
+            Event::HumanIdentified{..} => "HumanIdentified",
+            Event::ThingStateChanged{..} => "ThingStateChanged",
+            Event::ThingDiscovered{..} => "ThingDiscovered",
+            Event::FileStored{..} => "FileStored",
+            Event::NotificationCreated{..} => "NotificationCreated",
+            Event::SettingChanged{..} => "SettingChanged",
+            Event::JobProgress{..} => "JobProgress",
+            Event::WebhookReceived{..} => "WebhookReceived",
+            Event::PresenceChanged{..} => "PresenceChanged",
+            Event::VoiceCommand{..} => "VoiceCommand",
+        }
+    }
+}
+
+type SyncHandler = Arc<dyn Fn(&Event) + Send + Sync>;
+type AsyncHandler = Arc<dyn Fn(Event) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+enum Subscriber {
+    Sync(String, SyncHandler),
+    Async(String, AsyncHandler),
+    Channel(String, mpsc::Sender<Event>),
+}
+
+fn subscribers() -> &'static Mutex<Vec<Subscriber>> {
+    static SUBSCRIBERS: OnceLock<Mutex<Vec<Subscriber>>> = OnceLock::new();
+    SUBSCRIBERS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+fn runtime() -> &'static OnceLock<tokio::runtime::Handle> {
+    static RUNTIME: OnceLock<tokio::runtime::Handle> = OnceLock::new();
+    &RUNTIME
+}
+
+// Must be called from inside the tokio runtime so async subscribers have somewhere to run
+pub fn init(){
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => {
+            let _ = runtime().set(handle);
+            log::info!("event bus started successfully");
+        },
+        Err(e) => {
+            log::error!("failed to initialize event bus: {}", e);
+        }
+    }
+}
+
+pub fn subscribe<F>(name: &str, handler: F) where F: Fn(&Event) + Send + Sync + 'static {
+    subscribers().lock().unwrap().push(Subscriber::Sync(name.to_string(), Arc::new(handler)));
+}
+
+pub fn subscribe_async<F, Fut>(name: &str, handler: F) where F: Fn(Event) -> Fut + Send + Sync + 'static, Fut: Future<Output = ()> + Send + 'static {
+    let handler: AsyncHandler = Arc::new(move |event| Box::pin(handler(event)));
+    subscribers().lock().unwrap().push(Subscriber::Async(name.to_string(), handler));
+}
+
+pub fn channel(name: &str) -> mpsc::Receiver<Event> {
+    let (tx, rx) = mpsc::channel();
+    subscribers().lock().unwrap().push(Subscriber::Channel(name.to_string(), tx));
+    return rx;
+}
+
+pub fn publish(event: Event){
+    // Copy the handlers out so a subscriber can publish without deadlocking
+    let mut sync_handlers: Vec<(String, SyncHandler)> = Vec::new();
+    let mut async_handlers: Vec<(String, AsyncHandler)> = Vec::new();
+    {
+        let mut subs = subscribers().lock().unwrap();
+
+        // Drop channel subscribers whose receiver has gone away
+        subs.retain(|sub| {
+            match sub {
+                Subscriber::Channel(name, tx) => {
+                    match tx.send(event.clone()) {
+                        Ok(_) => true,
+                        Err(_) => {
+                            log::info!("event bus channel '{}' closed", name);
+                            false
+                        }
+                    }
+                },
+                _ => true
+            }
+        });
+
+        for sub in subs.iter() {
+            match sub {
+                Subscriber::Sync(name, handler) => sync_handlers.push((name.clone(), handler.clone())),
+                Subscriber::Async(name, handler) => async_handlers.push((name.clone(), handler.clone())),
+                Subscriber::Channel(_, _) => {}
+            }
+        }
+    }
+
+    for (name, handler) in sync_handlers {
+        // A misbehaving subscriber shouldn't take down the publisher
+        let result = catch_unwind(AssertUnwindSafe(|| handler(&event)));
+        if result.is_err() {
+            log::error!("event bus subscriber '{}' panicked handling {}", name, event.name());
+        }
+    }
+
+    for (name, handler) in async_handlers {
+        match runtime().get() {
+            Some(handle) => {
+                handle.spawn(handler(event.clone()));
+            },
+            None => {
+                log::error!("event bus not initialized, dropping {} for '{}'", event.name(), name);
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// events.rs keeps a short in-memory history of live house activity
+// and wakes up any /api/events subscribers when something new happens.
+// Every event gets an increasing id so reconnecting clients can resume
+// with the Last-Event-ID header. Events are fed from the internal bus.
+
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// How many events are kept around for clients that reconnect
+const HISTORY_SIZE: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum LiveEventType {
+    #[allow(non_camel_case_types)]
+    observation_created,
+    #[allow(non_camel_case_types)]
+    notification_created,
+    #[allow(non_camel_case_types)]
+    thing_state_changed,
+    #[allow(non_camel_case_types)]
+    file_upload_finished,
+    #[allow(non_camel_case_types)]
+    job_progress,
+    #[allow(non_camel_case_types)]
+    presence_changed,
+    #[allow(non_camel_case_types)]
+    voice_command
+}
+impl std::fmt::Display for LiveEventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+impl std::str::FromStr for LiveEventType {
+    type Err = ();
+    fn from_str(input: &str) -> std::result::Result<LiveEventType, Self::Err> {
+        match input {
+            "observation_created"  => Ok(LiveEventType::observation_created),
+            "notification_created"  => Ok(LiveEventType::notification_created),
+            "thing_state_changed"  => Ok(LiveEventType::thing_state_changed),
+            "file_upload_finished"  => Ok(LiveEventType::file_upload_finished),
+            "job_progress"  => Ok(LiveEventType::job_progress),
+            "presence_changed"  => Ok(LiveEventType::presence_changed),
+            "voice_command"  => Ok(LiveEventType::voice_command),
+            _      => Err(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LiveEvent {
+    pub id: u64,
+    pub event_type: LiveEventType,
+    pub room_oid: Option<String>,
+    pub human_oid: Option<String>,
+    pub data: serde_json::Value,
+    pub timestamp: i64
+}
+
+// Filters requested by a subscriber, empty vectors match everything
+#[derive(Debug, Clone, Default)]
+pub struct LiveEventFilter {
+    pub event_types: Vec<LiveEventType>,
+    pub room_oids: Vec<String>,
+    pub human_oid: Option<String>
+}
+impl LiveEventFilter {
+    pub fn matches(&self, event: &LiveEvent) -> bool {
+        if self.event_types.len() > 0 && !self.event_types.contains(&event.event_type) {
+            return false;
+        }
+
+        if self.room_oids.len() > 0 {
+            match &event.room_oid {
+                Some(room_oid) => {
+                    if !self.room_oids.contains(room_oid) {
+                        return false;
+                    }
+                },
+                None => {
+                    return false;
+                }
+            }
+        }
+
+        // Events addressed to a specific human are only visible to that human
+        match &event.human_oid {
+            Some(human_oid) => {
+                if self.human_oid.as_ref() != Some(human_oid) {
+                    return false;
+                }
+            },
+            None => {}
+        }
+
+        return true;
+    }
+}
+
+struct EventLog {
+    next_id: u64,
+    history: VecDeque<LiveEvent>
+}
+
+struct EventHub {
+    log: Mutex<EventLog>,
+    signal: Condvar
+}
+
+fn hub() -> &'static EventHub {
+    static HUB: OnceLock<EventHub> = OnceLock::new();
+    HUB.get_or_init(|| {
+        EventHub {
+            log: Mutex::new(EventLog{
+                next_id: 1,
+                history: VecDeque::with_capacity(HISTORY_SIZE)
+            }),
+            signal: Condvar::new()
+        }
+    })
+}
+
+pub fn init(){
+    crate::sam::services::bus::subscribe("events", |event| {
+        use crate::sam::services::bus::Event;
+        match event {
+            Event::ObservationRecorded{observation, room_oid} => {
+                publish(LiveEventType::observation_created, room_oid.clone(), None, observation);
+            },
+            Event::NotificationCreated{notification} => {
+                publish(LiveEventType::notification_created, None, Some(notification.human_oid.clone()), notification);
+            },
+            Event::ThingStateChanged{room_oid, ..} => {
+                publish(LiveEventType::thing_state_changed, room_oid.clone(), None, event);
+            },
+            Event::FileStored{file, human_oid, ..} => {
+                publish(LiveEventType::file_upload_finished, None, human_oid.clone(), file);
+            },
+            Event::JobProgress{human_oid, ..} => {
+                publish(LiveEventType::job_progress, None, human_oid.clone(), event);
+            },
+            Event::PresenceChanged{human, ..} => {
+                publish(LiveEventType::presence_changed, None, Some(human.oid.clone()), event);
+            },
+            Event::VoiceCommand{room_oid, human_oid, ..} => {
+                publish(LiveEventType::voice_command, room_oid.clone(), human_oid.clone(), event);
+            },
+            _ => {}
+        }
+    });
+}
+
+pub fn publish<T: Serialize>(event_type: LiveEventType, room_oid: Option<String>, human_oid: Option<String>, data: &T) -> u64 {
+    let data = match serde_json::to_value(data) {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("failed to serialize live event: {}", e);
+            serde_json::Value::Null
+        }
+    };
+
+    let hub = hub();
+    let mut log = hub.log.lock().unwrap();
+
+    let event = LiveEvent{
+        id: log.next_id,
+        event_type: event_type,
+        room_oid: room_oid,
+        human_oid: human_oid,
+        data: data,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+    };
+    log.next_id = log.next_id + 1;
+
+    if log.history.len() >= HISTORY_SIZE {
+        log.history.pop_front();
+    }
+    log.history.push_back(event.clone());
+
+    hub.signal.notify_all();
+    return event.id;
+}
+
+// The id of the most recent event, new subscribers start from here
+pub fn last_id() -> u64 {
+    let log = hub().log.lock().unwrap();
+    return log.next_id - 1;
+}
+
+// Returns every event newer than last_id that matches the filter along with
+// the id the caller should resume from. Blocks for up to timeout when nothing
+// new is available.
+pub fn wait_since(last_id: u64, filter: &LiveEventFilter, timeout: Duration) -> (Vec<LiveEvent>, u64) {
+    let hub = hub();
+    let mut log = hub.log.lock().unwrap();
+
+    if log.next_id - 1 <= last_id {
+        let (guard, _) = hub.signal.wait_timeout_while(log, timeout, |l| l.next_id - 1 <= last_id).unwrap();
+        log = guard;
+    }
+
+    let mut events: Vec<LiveEvent> = Vec::new();
+    for event in log.history.iter() {
+        if event.id > last_id && filter.matches(event) {
+            events.push(event.clone());
+        }
+    }
+
+    let mut cursor = log.next_id - 1;
+    if cursor < last_id {
+        cursor = last_id;
+    }
+    return (events, cursor);
+}
@@ -10,10 +10,16 @@
 extern crate lifx_api_server;
 extern crate lifx_rs as lifx;
 
+pub mod lan;
+#[cfg(test)]
+pub mod simulator;
+pub mod sync;
+
 use online::check;
 use rouille::Request;
 use rouille::Response;
 use rouille::post_input;
+use serde::{Serialize, Deserialize};
 use std::thread;
 
 pub fn init_server(key: String) {
@@ -41,38 +47,31 @@ pub fn init_server(key: String) {
     }
 }
 
-pub fn ssync(){
-    thread::spawn(move || {
-        let mut pg_query = crate::sam::memory::PostgresQueries::default();
-        pg_query.queries.push(crate::sam::memory::PGCol::String(format!("lifx")));
-        pg_query.query_coulmns.push(format!("identifier ="));
-        let services = crate::sam::memory::Service::select(None, None, None, Some(pg_query));
-
-        match services {
-            Ok(services) => {
-                // crate::sam::services::lifx::init_server(services[0].secret.clone());
-     
-                crate::sam::services::lifx::sync(services[0].secret.clone());
-                
-            },
-            Err(e) => {
-                log::error!("{}", e);
-            }
-        }
-    });
-}
-
-
-
 pub fn get_lifx_service_db_obj() -> Result<crate::sam::memory::Service, crate::sam::services::Error>{
     let mut pg_query = crate::sam::memory::PostgresQueries::default();
     pg_query.queries.push(crate::sam::memory::PGCol::String(format!("lifx")));
     pg_query.query_coulmns.push(format!("identifier ="));
-    let service = crate::sam::memory::Service::select(None, None, None, Some(pg_query))?;
-    return Ok(service[0].clone());
+    let services = crate::sam::memory::Service::select(None, None, None, Some(pg_query))?;
+    return Ok(services.first().cloned().ok_or("lifx is not configured")?);
 }
 
 pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+    if request.url().starts_with("/api/services/lifx/scenes") {
+        return handle_scenes(request);
+    }
+
+    // GET returns the last sync summary, POST syncs now
+    if request.url() == "/api/services/lifx/sync" {
+        if request.method() == "POST" {
+            return Ok(Response::json(&sync::sync()?));
+        }
+        return Ok(Response::json(&sync::last_sync()));
+    }
+
+    if request.url() == "/api/services/lifx/lan/discover" {
+        return Ok(Response::json(&discover_lan()?));
+    }
+
     if request.url() == "/api/services/lifx/list_all" {
 
         match get_lifx_service_db_obj(){
@@ -132,34 +131,9 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
             power: String,
             use_public: String,
         })?;
-
-         
-
-
-
-        match get_lifx_service_db_obj(){
-            Ok(service) => {
-          
-                let mut public = false;
-                if input.use_public == "true"{
-                    public = true;
-                }
-
-                let objects = crate::sam::services::lifx::set(service.secret.clone(), input.selector.clone(), public, Some(input.power.clone()), None);
-
-
-                
-                return Ok(Response::json(&objects));
-            },
-            Err(e) => {
-                log::error!("{}", e);
-            }
-        }
-
-
-
-
-        return Ok(Response::empty_404());
+        let service = get_lifx_service_db_obj()?;
+        let results = set(service.secret.clone(), input.selector, input.use_public == "true", Some(input.power), None)?;
+        return Ok(Response::json(&results));
     }
 
     if request.url() == "/api/services/lifx/set_color" {
@@ -168,24 +142,106 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
             color: String,
             use_public: String
         })?;
+        let service = get_lifx_service_db_obj()?;
+        let results = set(service.secret.clone(), input.selector, input.use_public == "true", None, Some(input.color))?;
+        return Ok(Response::json(&results));
+    }
 
+    // Any combination of power, color, brightness, kelvin and infrared with a transition duration
+    if request.url() == "/api/services/lifx/state" && request.method() == "POST" {
+        let input = post_input!(request, {
+            selector: String,
+            power: Option<String>,
+            color: Option<String>,
+            brightness: Option<f64>,
+            kelvin: Option<i64>,
+            infrared: Option<f64>,
+            duration: Option<f64>,
+            use_public: Option<String>,
+        })?;
+        let change = ThingStateChange{
+            power: input.power.filter(|p| p.len() > 0),
+            color: input.color.filter(|c| c.len() > 0),
+            brightness: input.brightness,
+            kelvin: input.kelvin,
+            infrared: input.infrared,
+        };
+        let service = get_lifx_service_db_obj()?;
+        let public = input.use_public.map(|p| p == "true").unwrap_or(true);
+        let results = apply_state(service.secret.clone(), input.selector, public, &change, input.duration.unwrap_or(0.0))?;
+        return Ok(Response::json(&results));
+    }
 
-        match get_lifx_service_db_obj(){
-            Ok(service) => {
-                let mut public = false;
-                if input.use_public == "true"{
-                    public = true;
-                }
-    
-                crate::sam::services::lifx::set(service.secret.clone(), input.selector.clone(), public, None, Some(input.color.clone()));    
-            },
-            Err(e) => {
-                log::error!("{}", e);
-            }
-        }
+    if request.url() == "/api/services/lifx/toggle" && request.method() == "POST" {
+        let input = post_input!(request, {
+            selector: String,
+            duration: Option<f64>,
+            use_public: Option<String>,
+        })?;
+        let service = get_lifx_service_db_obj()?;
+        let public = input.use_public.map(|p| p == "true").unwrap_or(true);
+        let results = toggle(service.secret.clone(), input.selector, public, input.duration.unwrap_or(0.0))?;
+        return Ok(Response::json(&results));
+    }
 
+    // POST /api/services/lifx/effects/{breathe,pulse,morph,flame,off}
+    if request.url().starts_with("/api/services/lifx/effects/") && request.method() == "POST" {
+        let input = post_input!(request, {
+            selector: String,
+            color: Option<String>,
+            from_color: Option<String>,
+            period: Option<f64>,
+            cycles: Option<f64>,
+            persist: Option<String>,
+            power_on: Option<String>,
+            peak: Option<f64>,
+            palette: Option<String>,
+            duration: Option<f64>,
+            power_off: Option<String>,
+            use_public: Option<String>,
+        })?;
 
-        return Ok(Response::empty_404());
+        let power_on = input.power_on.map(|p| p == "true").unwrap_or(true);
+        let persist = input.persist.map(|p| p == "true").unwrap_or(false);
+        let effect = match request.url().replace("/api/services/lifx/effects/", "").as_str() {
+            "breathe" => LifxEffect::Breathe{
+                color: input.color.unwrap_or(format!("white")),
+                from_color: input.from_color.filter(|c| c.len() > 0),
+                period: input.period.unwrap_or(1.0),
+                cycles: input.cycles.unwrap_or(1.0),
+                persist: persist,
+                power_on: power_on,
+                peak: input.peak.unwrap_or(0.5),
+            },
+            "pulse" => LifxEffect::Pulse{
+                color: input.color.unwrap_or(format!("white")),
+                from_color: input.from_color.filter(|c| c.len() > 0),
+                period: input.period.unwrap_or(1.0),
+                cycles: input.cycles.unwrap_or(1.0),
+                persist: persist,
+                power_on: power_on,
+            },
+            "morph" => LifxEffect::Morph{
+                period: input.period.unwrap_or(5.0),
+                duration: input.duration,
+                palette: input.palette.map(|p| p.split(",").map(|c| c.trim().to_string()).filter(|c| c.len() > 0).collect()).unwrap_or(Vec::new()),
+                power_on: power_on,
+            },
+            "flame" => LifxEffect::Flame{
+                period: input.period.unwrap_or(5.0),
+                duration: input.duration,
+                power_on: power_on,
+            },
+            "off" => LifxEffect::Off{
+                power_off: input.power_off.map(|p| p == "true").unwrap_or(false),
+            },
+            _ => return Ok(Response::empty_404())
+        };
+
+        let service = get_lifx_service_db_obj()?;
+        let public = input.use_public.map(|p| p == "true").unwrap_or(true);
+        let results = run_effect(service.secret.clone(), input.selector, public, &effect)?;
+        return Ok(Response::json(&results));
     }
 
     
@@ -251,231 +307,838 @@ pub fn get(key: String, public: bool) -> Result<lifx::Lights, crate::sam::servic
 }
 
 
-pub fn set(key: String, selector: String, public: bool, power: Option<String>, color: Option<String>){
-    let mut api_endpoints: Vec<String> = Vec::new();
-    api_endpoints.push(select_lifx_endpoint(public));
-
-    let lifx_config = lifx::LifxConfig{
-        access_token: key.clone(),
-        api_endpoints: api_endpoints
+pub fn set(key: String, selector: String, public: bool, power: Option<String>, color: Option<String>) -> Result<LifxResults, crate::sam::services::Error> {
+    let change = ThingStateChange{
+        power: power,
+        color: color,
+        ..Default::default()
     };
+    return apply_state(key, selector, public, &change, 0.0);
+}
 
-    let mut state = lifx::State::new();
-    state.power = power;
-    state.color = color;
-
-    // Turn off all lights
-    match lifx::Light::set_state_by_selector(lifx_config.clone(), selector, state){
-        Ok(_) => {},
-        Err(e) => log::error!("failed to set lifx state: {:?}", e),
-    }
+// Like set but picks the cloud api when online and the local api server otherwise
+pub fn set_state(key: String, selector: String, power: Option<String>, color: Option<String>) -> Result<LifxResults, crate::sam::services::Error> {
+    return set(key, selector, check(Some(3)).is_ok(), power, color);
 }
 
-pub fn set_state(key: String, selector: String, power: Option<String>, color: Option<String>){
-    let mut api_endpoints: Vec<String> = Vec::new();
-    api_endpoints.push(get_lifx_endpoint());
+// What changed on a light. Also the input to apply_state, unset fields are left alone.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ThingStateChange {
+    pub power: Option<String>,
+    pub color: Option<String>,
+    #[serde(default)]
+    pub brightness: Option<f64>,
+    #[serde(default)]
+    pub kelvin: Option<i64>,
+    #[serde(default)]
+    pub infrared: Option<f64>,
+}
 
-    let lifx_config = lifx::LifxConfig{
-        access_token: key.clone(),
-        api_endpoints: api_endpoints
+// Maps a lifx selector (id:xxx, label:xxx) back onto a stored Thing
+pub fn thing_for_selector(selector: &str) -> Option<crate::sam::memory::Thing> {
+    let identifier = match selector.split_once(":") {
+        Some((_, value)) => value.to_string(),
+        None => selector.to_string()
     };
 
-    let mut state = lifx::State::new();
-    state.power = power;
-    state.color = color;
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("lifx")));
+    pg_query.query_coulmns.push(format!("thing_type ="));
+    match crate::sam::memory::Thing::select(None, None, None, Some(pg_query)){
+        Ok(things) => {
+            for thing in things{
+                if thing.online_identifiers.contains(&identifier) || thing.local_identifiers.contains(&identifier) {
+                    return Some(thing);
+                }
+            }
+        },
+        Err(e) => {
+            log::error!("{}", e);
+        }
+    }
+    return None;
+}
+
+pub fn publish_state_change(selector: String, power: Option<String>, color: Option<String>){
+    let change = ThingStateChange{
+        power: power,
+        color: color,
+        ..Default::default()
+    };
+    publish_thing_state(selector, &change);
+}
 
-    match lifx::Light::set_state_by_selector(lifx_config.clone(), selector, state){
-        Ok(_) => {},
-        Err(e) => log::error!("failed to set lifx state: {:?}", e),
+pub fn publish_thing_state(selector: String, change: &ThingStateChange){
+    let changes = serde_json::to_value(change).unwrap_or(serde_json::Value::Null);
+    match thing_for_selector(&selector) {
+        Some(thing) => {
+            crate::sam::services::things::cache::update(&thing, &changes, "push");
+        },
+        None => {
+            crate::sam::services::bus::publish(crate::sam::services::bus::Event::ThingStateChanged{
+                thing_oid: None,
+                room_oid: None,
+                selector: selector,
+                state: changes,
+                previous: serde_json::Value::Null,
+            });
+        }
     }
 }
 
 
-pub fn sync(key: String){
+// =================================================================
+// LIFX HTTP API
+// The public cloud api and lifx_api_server speak the same protocol
+// so these work against either endpoint.
+// =================================================================
 
-    let mut api_endpoints: Vec<String> = Vec::new();
-    api_endpoints.push(format!("https://api.lifx.com"));
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LifxColor {
+    pub hue: f64,
+    pub saturation: f64,
+    pub kelvin: i64,
+}
 
-    let lifx_config = lifx::LifxConfig{
-        access_token: key.clone(),
-        api_endpoints: api_endpoints
-    };
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LifxGroup {
+    pub id: String,
+    pub name: String,
+}
 
-    let _storable_thing_vec: Vec<crate::sam::memory::Thing> = Vec::new();
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LifxLight {
+    pub id: String,
+    #[serde(default)]
+    pub uuid: String,
+    pub label: String,
+    #[serde(default)]
+    pub connected: bool,
+    pub power: String,
+    #[serde(default)]
+    pub color: LifxColor,
+    #[serde(default)]
+    pub brightness: f64,
+    #[serde(default)]
+    pub group: LifxGroup,
+    #[serde(default)]
+    pub location: LifxGroup,
+    // Only known when the light was found on the lan
+    #[serde(default)]
+    pub ip_address: Option<String>,
+}
 
+// Outcome of a change for a single light
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LifxResult {
+    pub id: String,
+    #[serde(default)]
+    pub label: String,
+    pub status: String,
+}
 
-    let lights = lifx::Light::list_all(lifx_config.clone()).unwrap();
-    for light in lights{
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LifxResults {
+    pub results: Vec<LifxResult>,
+}
 
-        let mut thing = crate::sam::memory::Thing::new();
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LifxOperationResults {
+    results: Vec<LifxResult>,
+}
 
-        // =================================================================
-        // Sync Group/Location/Room/Name
-        // =================================================================
-        let location = light.location;
-        let group = light.group;
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LifxStatesResponse {
+    results: Vec<LifxOperationResults>,
+}
 
-        let mut loc = crate::sam::memory::Location::new();
-        loc.name = location.name.clone();
-        loc.save().unwrap();
+pub fn api_list(key: String, public: bool, selector: String) -> Result<Vec<LifxLight>, crate::sam::services::Error> {
+    let client = reqwest::blocking::Client::new();
+    let lights = client.get(format!("{}/v1/lights/{}", select_lifx_endpoint(public), selector))
+        .bearer_auth(key)
+        .timeout(std::time::Duration::from_secs(10))
+        .send()?
+        .error_for_status()?
+        .json::<Vec<LifxLight>>()?;
+    return Ok(lights);
+}
 
+// Applies many states at once, each state is a json object with its own selector
+pub fn api_set_states(key: String, public: bool, states: Vec<serde_json::Value>, duration: f64) -> Result<LifxResults, crate::sam::services::Error> {
+    let body = serde_json::json!({
+        "states": states,
+        "defaults": {
+            "duration": duration
+        }
+    });
 
+    let client = reqwest::blocking::Client::new();
+    let response = client.put(format!("{}/v1/lights/states", select_lifx_endpoint(public)))
+        .bearer_auth(key)
+        .timeout(std::time::Duration::from_secs(10))
+        .json(&body)
+        .send()?
+        .error_for_status()?
+        .json::<LifxStatesResponse>()?;
+
+    let mut results = LifxResults::default();
+    for operation in response.results {
+        results.results.extend(operation.results);
+    }
+    return Ok(results);
+}
 
-        let mut pg_query = crate::sam::memory::PostgresQueries::default();
-        pg_query.queries.push(crate::sam::memory::PGCol::String(location.name.clone()));
-        pg_query.query_coulmns.push(format!("name ilike"));
-
-        let matching_locations = crate::sam::memory::Location::select(None, None, None, Some(pg_query)).unwrap();
-        
-        
-        
-        
-        if matching_locations.len() > 0 {
-            for matching_location in matching_locations{
-                let mut room = crate::sam::memory::Room::new();
-                room.name = group.name.clone();
-                room.location_oid = matching_location.oid.clone();
-                room.save().unwrap();
+// Applies a state change by selector (all, id:, label:, group:, location:...) and
+// reports the outcome per light. Falls back to the lan when offline and the api is unreachable.
+pub fn apply_state(key: String, selector: String, public: bool, change: &ThingStateChange, duration: f64) -> Result<LifxResults, crate::sam::services::Error> {
+    let results = match api_set_state(key, public, selector.clone(), change, duration) {
+        Ok(results) => results,
+        Err(e) => {
+            if check(Some(3)).is_ok() {
+                return Err(e);
             }
+            log::info!("lifx api unavailable ({}), setting state over lan", e);
+            set_lan(selector, change, std::time::Duration::from_secs_f64(duration.max(0.0)))?
         }
+    };
 
-        // Get location oid
-        let mut pg_query = crate::sam::memory::PostgresQueries::default();
-        pg_query.queries.push(crate::sam::memory::PGCol::String(location.name.clone()));
-        pg_query.query_coulmns.push(format!("name ilike"));
-        let locations = crate::sam::memory::Location::select(None, None, None, Some(pg_query)).unwrap();
-        if locations.len() > 0 {
-            let location_oid = locations[0].oid.clone();
-              // Get room oid
-              let mut pg_query = crate::sam::memory::PostgresQueries::default();
-              pg_query.queries.push(crate::sam::memory::PGCol::String(location_oid.clone()));
-              pg_query.query_coulmns.push(format!("location_oid ="));
-              pg_query.queries.push(crate::sam::memory::PGCol::String(group.name.clone()));
-              pg_query.query_coulmns.push(format!(" AND name ilike"));
-            let rooms = crate::sam::memory::Room::select(None, None, None, Some(pg_query)).unwrap();
-            if rooms.len() > 0 {
-                thing.room_oid = rooms[0].oid.clone();
-            }
+    for result in results.results.iter() {
+        if result.status == "ok" {
+            publish_thing_state(format!("id:{}", result.id), change);
         }
+    }
+    return Ok(results);
+}
 
-        // =================================================================
-        // END Sync Group/Location/Room/Name
-        // =================================================================
+pub fn api_set_state(key: String, public: bool, selector: String, change: &ThingStateChange, duration: f64) -> Result<LifxResults, crate::sam::services::Error> {
+    let mut body = serde_json::json!({
+        "duration": duration
+    });
+    match &change.power {
+        Some(power) => body["power"] = serde_json::json!(power),
+        None => {}
+    }
+    // The api takes kelvin as part of the color string
+    let mut color: Vec<String> = Vec::new();
+    match &change.color {
+        Some(c) => color.push(c.clone()),
+        None => {}
+    }
+    match change.kelvin {
+        Some(kelvin) => color.push(format!("kelvin:{}", kelvin)),
+        None => {}
+    }
+    if color.len() > 0 {
+        body["color"] = serde_json::json!(color.join(" "));
+    }
+    match change.brightness {
+        Some(brightness) => body["brightness"] = serde_json::json!(brightness),
+        None => {}
+    }
+    match change.infrared {
+        Some(infrared) => body["infrared"] = serde_json::json!(infrared),
+        None => {}
+    }
 
-        let mut online_identifiers: Vec<String> = Vec::new();
-        online_identifiers.push(light.id.clone());
-        online_identifiers.push(light.uuid.clone());
-        online_identifiers.push(light.label.clone());
+    let client = reqwest::blocking::Client::new();
+    let results = client.put(format!("{}/v1/lights/{}/state", select_lifx_endpoint(public), selector))
+        .bearer_auth(key)
+        .timeout(std::time::Duration::from_secs(10))
+        .json(&body)
+        .send()?
+        .error_for_status()?
+        .json::<LifxResults>()?;
+    return Ok(results);
+}
 
-       
-        thing.name = light.label.clone();
-        thing.thing_type = "lifx".to_string();
-        thing.online_identifiers = online_identifiers.clone();
-        
-        let mut local_api_endpoints: Vec<String> = Vec::new();
-        local_api_endpoints.push(get_lifx_endpoint());
+pub fn toggle(key: String, selector: String, public: bool, duration: f64) -> Result<LifxResults, crate::sam::services::Error> {
+    let client = reqwest::blocking::Client::new();
+    let response = client.post(format!("{}/v1/lights/{}/toggle", select_lifx_endpoint(public), selector))
+        .bearer_auth(key.clone())
+        .timeout(std::time::Duration::from_secs(10))
+        .json(&serde_json::json!({"duration": duration}))
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.json::<LifxResults>());
+
+    match response {
+        Ok(results) => {
+            // The api doesn't say which way each light went, so look it up
+            match api_list(key, public, selector) {
+                Ok(lights) => {
+                    for light in lights {
+                        if results.results.iter().any(|r| r.id == light.id && r.status == "ok") {
+                            publish_state_change(format!("id:{}", light.id), Some(light.power), None);
+                        }
+                    }
+                },
+                Err(e) => log::error!("failed to read lifx power after toggle: {}", e)
+            }
+            return Ok(results);
+        },
+        Err(e) => {
+            if check(Some(3)).is_ok() {
+                return Err(e.into());
+            }
+            log::info!("lifx api unavailable ({}), toggling over lan", e);
+            return toggle_lan(selector, std::time::Duration::from_secs_f64(duration.max(0.0)));
+        }
+    }
+}
 
+// Mirrors the effect endpoints of the lifx http api, morph and flame need a tile or candle
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "effect", rename_all = "lowercase")]
+pub enum LifxEffect {
+    Breathe { color: String, from_color: Option<String>, period: f64, cycles: f64, persist: bool, power_on: bool, peak: f64 },
+    Pulse { color: String, from_color: Option<String>, period: f64, cycles: f64, persist: bool, power_on: bool },
+    Morph { period: f64, duration: Option<f64>, palette: Vec<String>, power_on: bool },
+    Flame { period: f64, duration: Option<f64>, power_on: bool },
+    Off { power_off: bool },
+}
+impl LifxEffect {
+    pub fn name(&self) -> &str {
+        match self {
+            LifxEffect::Breathe{..} => "breathe",
+            LifxEffect::Pulse{..} => "pulse",
+            LifxEffect::Morph{..} => "morph",
+            LifxEffect::Flame{..} => "flame",
+            LifxEffect::Off{..} => "off",
+        }
+    }
 
-        let local_config = lifx::LifxConfig{
-            access_token: key.clone(),
-            api_endpoints: local_api_endpoints
-        };
-        let xlocal_lights = lifx::Light::list_all(local_config.clone());
-        match xlocal_lights{
-            Ok(local_lights) => {
-                for local_light in local_lights{
-                    if local_light.label.clone() == light.label.clone() {
-                        let mut local_identifiers: Vec<String> = Vec::new();
-                        local_identifiers.push(local_light.id.clone());
-                        local_identifiers.push(local_light.uuid.clone());
-                        local_identifiers.push(local_light.label.clone());
-                        thing.local_identifiers = local_identifiers.clone();
+    fn body(&self) -> serde_json::Value {
+        let mut body = serde_json::Map::new();
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(fields)) => {
+                for (field, value) in fields {
+                    if field == "effect" || value.is_null() || value == serde_json::json!([]) {
+                        continue;
                     }
+                    body.insert(field, value);
                 }
             },
-            Err(er) => {
-                log::error!("{}", er);
+            _ => {}
+        }
+        return serde_json::Value::Object(body);
+    }
+}
+
+pub fn run_effect(key: String, selector: String, public: bool, effect: &LifxEffect) -> Result<LifxResults, crate::sam::services::Error> {
+    let client = reqwest::blocking::Client::new();
+    let response = client.post(format!("{}/v1/lights/{}/effects/{}", select_lifx_endpoint(public), selector, effect.name()))
+        .bearer_auth(key)
+        .timeout(std::time::Duration::from_secs(10))
+        .json(&effect.body())
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.json::<LifxResults>());
+
+    match response {
+        Ok(results) => return Ok(results),
+        Err(e) => {
+            if check(Some(3)).is_ok() {
+                return Err(e.into());
             }
+            log::info!("lifx api unavailable ({}), running {} over lan", e, effect.name());
+            return effect_lan(selector, effect);
         }
-        
+    }
+}
+
+// =================================================================
+// Scenes
+// =================================================================
+
+// Captures the current state of every light matching the selector
+pub fn snapshot_scene(key: String, selector: String, public: bool) -> Result<Vec<crate::sam::memory::LifxSceneState>, crate::sam::services::Error> {
+    let mut states: Vec<crate::sam::memory::LifxSceneState> = Vec::new();
+    for light in api_list(key, public, selector)? {
+        states.push(crate::sam::memory::LifxSceneState{
+            selector: format!("id:{}", light.id),
+            label: light.label,
+            power: light.power,
+            hue: light.color.hue,
+            saturation: light.color.saturation,
+            brightness: light.brightness,
+            kelvin: light.color.kelvin,
+        });
+    }
+    return Ok(states);
+}
 
+// Captures the other switchable things (http devices...) in a room
+pub fn snapshot_thing_states(room_oid: &str) -> Result<Vec<crate::sam::memory::SceneThingState>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(room_oid.to_string()));
+    pg_query.query_coulmns.push(format!("room_oid ="));
 
+    let mut states: Vec<crate::sam::memory::SceneThingState> = Vec::new();
+    for thing in crate::sam::memory::Thing::select(None, None, None, Some(pg_query))? {
+        if thing.thing_type == "lifx" || !crate::sam::services::things::capabilities(&thing).contains(&crate::sam::services::things::Capability::Switchable) {
+            continue;
+        }
+        let state = match crate::sam::services::things::cache::get(&thing.oid) {
+            Some(cached) => cached.state,
+            None => continue
+        };
+        states.push(crate::sam::memory::SceneThingState{
+            thing_oid: thing.oid.clone(),
+            name: thing.name.clone(),
+            power: state.power,
+            brightness: state.brightness,
+            color: state.color,
+        });
+    }
+    return Ok(states);
+}
 
-        let existing_things = crate::sam::memory::Thing::select(None, None, None, None).unwrap();
-        
-        let mut already_exists = false;
-        for existing_thing in existing_things{
-            if existing_thing.name == light.label {
-                already_exists = true;
+// Applies a scene's thing states with thing commands, reported like lifx results
+pub fn activate_thing_states(scene: &crate::sam::memory::LifxScene, duration: f64) -> Vec<LifxResult> {
+    let mut results: Vec<LifxResult> = Vec::new();
+    for state in scene.thing_states.iter() {
+        let thing = match crate::sam::services::things::find(&state.thing_oid) {
+            Ok(Some(thing)) => thing,
+            _ => {
+                results.push(LifxResult{ id: state.thing_oid.clone(), label: state.name.clone(), status: format!("not found") });
+                continue;
+            }
+        };
+        let capabilities = crate::sam::services::things::capabilities(&thing);
+        let mut commands: Vec<crate::sam::services::things::ThingCommand> = Vec::new();
+        let off = state.power.as_ref().map(|p| p == "off").unwrap_or(false);
+        if !off {
+            match &state.color {
+                Some(color) if capabilities.contains(&crate::sam::services::things::Capability::Color) => commands.push(crate::sam::services::things::ThingCommand::SetColor{ color: color.clone(), duration: Some(duration) }),
+                _ => {}
             }
+            match state.brightness {
+                Some(brightness) if capabilities.contains(&crate::sam::services::things::Capability::Dimmable) => commands.push(crate::sam::services::things::ThingCommand::SetBrightness{ brightness: brightness, duration: Some(duration) }),
+                _ => {}
+            }
+        }
+        match &state.power {
+            Some(power) => {
+                if power == "on" {
+                    commands.push(crate::sam::services::things::ThingCommand::TurnOn{ duration: Some(duration) });
+                } else {
+                    commands.push(crate::sam::services::things::ThingCommand::TurnOff{ duration: Some(duration) });
+                }
+            },
+            None => {}
+        }
 
-            for onlineid in thing.online_identifiers.clone(){
-                for extonlineid in existing_thing.online_identifiers.clone(){
-                    if onlineid == extonlineid{
-                        already_exists = true;
-                    }
+        let mut status = format!("ok");
+        for command in commands.iter() {
+            match crate::sam::services::things::command(&thing, command) {
+                Ok(_) => {},
+                Err(e) => {
+                    log::error!("scene {} failed on {}: {}", scene.name, thing.name, e);
+                    status = format!("{}", e);
                 }
             }
         }
+        results.push(LifxResult{ id: thing.oid.clone(), label: thing.name.clone(), status: status });
+    }
+    return results;
+}
 
-        if !already_exists{
-            thing.save().unwrap();
-        }
+pub fn activate_scene(key: String, scene: &crate::sam::memory::LifxScene, duration: f64, public: bool) -> Result<LifxResults, crate::sam::services::Error> {
+    if scene.states.len() == 0 {
+        return Ok(LifxResults{ results: activate_thing_states(scene, duration) });
+    }
 
+    let mut states: Vec<serde_json::Value> = Vec::new();
+    for state in scene.states.iter() {
+        states.push(serde_json::json!({
+            "selector": state.selector,
+            "power": state.power,
+            "color": format!("hue:{} saturation:{} kelvin:{}", state.hue, state.saturation, state.kelvin),
+            "brightness": state.brightness
+        }));
     }
 
-    sync_local(key.clone());
-    sync_local(key);
-}
+    let mut results = api_set_states(key, public, states, duration)?;
+
+    for state in scene.states.iter() {
+        let id = state.selector.replace("id:", "");
+        if results.results.iter().any(|r| r.id == id && r.status == "ok") {
+            publish_state_change(state.selector.clone(), Some(state.power.clone()), Some(format!("hue:{} saturation:{} kelvin:{}", state.hue, state.saturation, state.kelvin)));
+        }
+    }
+    results.results.extend(activate_thing_states(scene, duration));
 
+    return Ok(results);
+}
 
+pub fn find_scene(oid: &str) -> Result<Option<crate::sam::memory::LifxScene>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(oid.to_string()));
+    pg_query.query_coulmns.push(format!("oid ="));
+    let scenes = crate::sam::memory::LifxScene::select(None, None, None, Some(pg_query))?;
+    return Ok(scenes.first().cloned());
+}
 
-pub fn sync_local(key: String){
-    let mut api_endpoints: Vec<String> = Vec::new();
-    api_endpoints.push(get_lifx_endpoint());
+// Handles "activate scene movie night" / "activate movie night scene" style commands
+pub fn scene_command(input: &str) -> Option<String> {
+    let input = input.trim().to_lowercase();
+    let name = if input.starts_with("activate scene ") {
+        input.replacen("activate scene ", "", 1)
+    } else if input.starts_with("activate ") && input.ends_with(" scene") {
+        input.replacen("activate ", "", 1).trim_end_matches(" scene").to_string()
+    } else {
+        return None;
+    };
 
-    let lifx_config = lifx::LifxConfig{
-        access_token: key.clone(),
-        api_endpoints: api_endpoints
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(name.trim().to_string()));
+    pg_query.query_coulmns.push(format!("name ilike"));
+    let scenes = match crate::sam::memory::LifxScene::select(None, None, None, Some(pg_query)) {
+        Ok(scenes) => scenes,
+        Err(e) => {
+            log::error!("{}", e);
+            return Some(format!("I couldn't load your scenes."));
+        }
     };
+    if scenes.len() == 0 {
+        return Some(format!("I couldn't find a scene called {}.", name));
+    }
 
-    let _storable_thing_vec: Vec<crate::sam::memory::Thing> = Vec::new();
+    match get_lifx_service_db_obj() {
+        Ok(service) => {
+            let public = check(Some(3)).is_ok();
+            match activate_scene(service.secret.clone(), &scenes[0], 1.0, public) {
+                Ok(_) => return Some(format!("{} scene activated.", scenes[0].name)),
+                Err(e) => {
+                    log::error!("failed to activate scene: {}", e);
+                    return Some(format!("I couldn't activate {}.", scenes[0].name));
+                }
+            }
+        },
+        Err(e) => {
+            log::error!("{}", e);
+            return Some(format!("LIFX isn't set up yet."));
+        }
+    }
+}
 
-    let lights = lifx::Light::list_all(lifx_config.clone()).unwrap();
-    for light in lights{
+// GET/POST           /api/services/lifx/scenes (?room_oid= or ?location_oid=)
+// GET/PUT/DELETE     /api/services/lifx/scenes/{oid}
+// POST               /api/services/lifx/scenes/{oid}/activate
+pub fn handle_scenes(request: &Request) -> Result<Response, crate::sam::http::Error> {
+    let url = request.url().clone();
+    let split = url.split("/");
+    let vec = split.collect::<Vec<&str>>();
 
-        let mut local_identifiers: Vec<String> = Vec::new();
-        local_identifiers.push(light.id.clone());
-        local_identifiers.push(light.uuid.clone());
-        local_identifiers.push(light.label.clone());
+    if url == "/api/services/lifx/scenes" && request.method() == "GET" {
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        match request.get_param("room_oid") {
+            Some(room_oid) => {
+                pg_query.queries.push(crate::sam::memory::PGCol::String(room_oid));
+                pg_query.query_coulmns.push(format!("room_oid ="));
+            },
+            None => {}
+        }
+        match request.get_param("location_oid") {
+            Some(location_oid) => {
+                pg_query.queries.push(crate::sam::memory::PGCol::String(location_oid));
+                if pg_query.query_coulmns.len() == 0 {
+                    pg_query.query_coulmns.push(format!("location_oid ="));
+                } else {
+                    pg_query.query_coulmns.push(format!(" AND location_oid ="));
+                }
+            },
+            None => {}
+        }
+        let query = if pg_query.queries.len() > 0 { Some(pg_query) } else { None };
+        let scenes = crate::sam::memory::LifxScene::select(None, None, None, query)?;
+        return Ok(Response::json(&scenes));
+    }
 
-        let mut thing = crate::sam::memory::Thing::new();
-        thing.name = light.label.clone();
-        thing.thing_type = "lifx".to_string();
-        thing.local_identifiers = local_identifiers.clone();
-        
+    // Snapshot the lights matching selector into a new scene
+    if url == "/api/services/lifx/scenes" && request.method() == "POST" {
+        let input = post_input!(request, {
+            name: String,
+            selector: String,
+            room_oid: Option<String>,
+            location_oid: Option<String>,
+            use_public: Option<String>,
+        })?;
 
+        let public = input.use_public.map(|p| p == "true").unwrap_or(true);
 
-        let existing_things = crate::sam::memory::Thing::select(None, None, None, None).unwrap();
-        
-        let mut already_exists = false;
-        for existing_thing in existing_things{
-            if existing_thing.name == light.label {
-                already_exists = true;
+        let mut scene = crate::sam::memory::LifxScene::new();
+        scene.name = input.name;
+        scene.room_oid = input.room_oid.filter(|r| r.len() > 0);
+        scene.location_oid = input.location_oid.filter(|l| l.len() > 0);
+        // A room scene can be made of only non lifx things
+        scene.states = match get_lifx_service_db_obj() {
+            Ok(service) => snapshot_scene(service.secret.clone(), input.selector, public)?,
+            Err(e) => {
+                if scene.room_oid.is_none() {
+                    return Err(e.into());
+                }
+                Vec::new()
             }
+        };
+        match &scene.room_oid {
+            Some(room_oid) => scene.thing_states = snapshot_thing_states(room_oid)?,
+            None => {}
+        }
+        scene.save()?;
+        return Ok(Response::json(&scene));
+    }
 
-            for onlineid in thing.local_identifiers.clone(){
-                for extonlineid in existing_thing.local_identifiers.clone(){
-                    if onlineid == extonlineid{
-                        already_exists = true;
-                    }
+    if vec.len() > 5 {
+        let mut scene = match find_scene(vec[5])? {
+            Some(scene) => scene,
+            None => return Ok(Response::empty_404())
+        };
+
+        if url.ends_with("/activate") && request.method() == "POST" {
+            let input = post_input!(request, {
+                duration: Option<f64>,
+                use_public: Option<String>,
+            })?;
+            // Scenes of only non lifx things don't need lifx to be set up
+            if scene.states.len() == 0 {
+                return Ok(Response::json(&LifxResults{ results: activate_thing_states(&scene, input.duration.unwrap_or(1.0)) }));
+            }
+            let service = get_lifx_service_db_obj()?;
+            let public = input.use_public.map(|p| p == "true").unwrap_or(true);
+            let results = activate_scene(service.secret.clone(), &scene, input.duration.unwrap_or(1.0), public)?;
+            return Ok(Response::json(&results));
+        }
+
+        if vec.len() == 6 {
+            if request.method() == "GET" {
+                return Ok(Response::json(&scene));
+            }
+
+            // Rename, move or re-snapshot (when a selector is posted) a scene
+            if request.method() == "PUT" {
+                let input = post_input!(request, {
+                    name: Option<String>,
+                    room_oid: Option<String>,
+                    location_oid: Option<String>,
+                    selector: Option<String>,
+                    use_public: Option<String>,
+                })?;
+                match input.name {
+                    Some(name) => scene.name = name,
+                    None => {}
+                }
+                match input.room_oid {
+                    Some(room_oid) => scene.room_oid = Some(room_oid).filter(|r| r.len() > 0),
+                    None => {}
+                }
+                match input.location_oid {
+                    Some(location_oid) => scene.location_oid = Some(location_oid).filter(|l| l.len() > 0),
+                    None => {}
                 }
+                match input.selector {
+                    Some(selector) => {
+                        let service = get_lifx_service_db_obj()?;
+                        let public = input.use_public.map(|p| p == "true").unwrap_or(true);
+                        scene.states = snapshot_scene(service.secret.clone(), selector, public)?;
+                        match &scene.room_oid {
+                            Some(room_oid) => scene.thing_states = snapshot_thing_states(room_oid)?,
+                            None => {}
+                        }
+                    },
+                    None => {}
+                }
+                scene.updated_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+                scene.save()?;
+                return Ok(Response::json(&scene));
+            }
+
+            if request.method() == "DELETE" {
+                crate::sam::memory::LifxScene::destroy(scene.oid)?;
+                return Ok(Response::text("deleted"));
             }
         }
+    }
+
+    return Ok(Response::empty_404());
+}
+
+// =================================================================
+// LIFX LAN
+// =================================================================
 
-        if !already_exists{
-            thing.save().unwrap();
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LanDiscovery {
+    pub state: lan::LanLightState,
+    pub firmware: Option<lan::LanFirmware>,
+}
+
+pub fn discover_lan() -> Result<Vec<LanDiscovery>, crate::sam::services::Error> {
+    let client = lan::LanClient::new()?;
+    let mut found: Vec<LanDiscovery> = Vec::new();
+    for bulb in client.discover(std::time::Duration::from_secs(1))? {
+        match client.get_state(&bulb) {
+            Ok(state) => {
+                found.push(LanDiscovery{
+                    state: state,
+                    firmware: client.get_firmware(&bulb).ok(),
+                });
+            },
+            Err(e) => log::error!("{}", e)
         }
+    }
+    return Ok(found);
+}
+
+// Finds the lan bulbs for a selector (all, id:, label:, group:, location:). Known ids with a
+// stored ip address skip the discovery broadcast.
+pub fn lan_bulbs_for_selector(client: &lan::LanClient, selector: &str) -> Result<Vec<lan::LanBulb>, crate::sam::services::Error> {
+    match selector.split_once(":") {
+        Some(("id", id)) => {
+            match (lan::target_from_id(id), thing_for_selector(selector)) {
+                (Some(target), Some(thing)) => {
+                    match thing.ip_address.parse::<std::net::IpAddr>() {
+                        Ok(ip) => {
+                            return Ok(vec![lan::LanBulb{ target: target, address: std::net::SocketAddr::new(ip, lan::LIFX_PORT) }]);
+                        },
+                        Err(_) => {}
+                    }
+                },
+                _ => {}
+            }
+        },
+        _ => {}
+    }
 
+    let mut bulbs: Vec<lan::LanBulb> = Vec::new();
+    for bulb in client.discover(std::time::Duration::from_secs(1))? {
+        let matches = match selector.split_once(":") {
+            Some(("id", id)) => bulb.id() == id,
+            Some(("label", label)) => client.get_label(&bulb).map(|l| l.to_lowercase() == label.to_lowercase()).unwrap_or(false),
+            Some(("group_id", id)) => client.get_group(&bulb).map(|g| g.0 == id).unwrap_or(false),
+            Some(("group", label)) => client.get_group(&bulb).map(|g| g.1.to_lowercase() == label.to_lowercase()).unwrap_or(false),
+            Some(("location_id", id)) => client.get_location(&bulb).map(|l| l.0 == id).unwrap_or(false),
+            Some(("location", label)) => client.get_location(&bulb).map(|l| l.1.to_lowercase() == label.to_lowercase()).unwrap_or(false),
+            _ => selector == "all"
+        };
+        if matches {
+            bulbs.push(bulb);
+        }
     }
-}
\ No newline at end of file
+    return Ok(bulbs);
+}
+
+// Applies apply to every bulb matching the selector and collects a result for each
+fn for_each_lan_bulb<F>(selector: &str, apply: F) -> Result<LifxResults, crate::sam::services::Error> where F: Fn(&lan::LanClient, &lan::LanBulb) -> Result<(), crate::sam::services::Error> {
+    let client = lan::LanClient::new()?;
+    let mut results = LifxResults::default();
+
+    for bulb in lan_bulbs_for_selector(&client, selector)? {
+        let status = match apply(&client, &bulb) {
+            Ok(_) => format!("ok"),
+            Err(e) => format!("{}", e)
+        };
+        results.results.push(LifxResult{
+            id: bulb.id(),
+            label: client.get_label(&bulb).unwrap_or(String::new()),
+            status: status,
+        });
+    }
+    return Ok(results);
+}
+
+pub fn set_lan(selector: String, change: &ThingStateChange, duration: std::time::Duration) -> Result<LifxResults, crate::sam::services::Error> {
+    return for_each_lan_bulb(&selector, |client, bulb| {
+        match &change.power {
+            Some(power) => client.set_power(bulb, power == "on", duration)?,
+            None => {}
+        }
+
+        if change.color.is_some() || change.brightness.is_some() || change.kelvin.is_some() {
+            let mut hsbk = client.get_state(bulb)?.color;
+            match &change.color {
+                Some(color) => {
+                    hsbk = match lan::parse_color(color, hsbk) {
+                        Some(hsbk) => hsbk,
+                        None => return Err(format!("invalid color {}", color).into())
+                    };
+                },
+                None => {}
+            }
+            match change.brightness {
+                Some(brightness) => hsbk.brightness = (brightness.clamp(0.0, 1.0) * 65535.0).round() as u16,
+                None => {}
+            }
+            match change.kelvin {
+                Some(kelvin) => {
+                    hsbk.kelvin = kelvin.clamp(1500, 9000) as u16;
+                    hsbk.saturation = 0;
+                },
+                None => {}
+            }
+            client.set_color(bulb, hsbk, duration)?;
+        }
+
+        match change.infrared {
+            Some(infrared) => client.set_infrared(bulb, infrared)?,
+            None => {}
+        }
+        return Ok(());
+    });
+}
+
+pub fn toggle_lan(selector: String, duration: std::time::Duration) -> Result<LifxResults, crate::sam::services::Error> {
+    return for_each_lan_bulb(&selector, |client, bulb| {
+        let on = !client.get_power(bulb)?;
+        client.set_power(bulb, on, duration)?;
+        publish_state_change(format!("id:{}", bulb.id()), Some(if on { format!("on") } else { format!("off") }), None);
+        return Ok(());
+    });
+}
+
+// Breathe and pulse map onto the waveform message, the rest need tile or multizone messages
+pub fn effect_lan(selector: String, effect: &LifxEffect) -> Result<LifxResults, crate::sam::services::Error> {
+    return for_each_lan_bulb(&selector, |client, bulb| {
+        let (color, from_color, period, cycles, persist, power_on, skew_ratio, waveform) = match effect {
+            LifxEffect::Breathe{color, from_color, period, cycles, persist, power_on, peak} => {
+                // Peak is where in the period the color is brightest, skew ratio is the same thing centred on zero
+                let skew_ratio = ((peak.clamp(0.0, 1.0) * 65535.0) - 32768.0) as i16;
+                (color, from_color, period, cycles, persist, power_on, skew_ratio, lan::Waveform::Sine)
+            },
+            LifxEffect::Pulse{color, from_color, period, cycles, persist, power_on} => {
+                (color, from_color, period, cycles, persist, power_on, 0, lan::Waveform::Pulse)
+            },
+            LifxEffect::Off{power_off} => {
+                // Setting the current color again stops any running waveform
+                let current = client.get_state(bulb)?.color;
+                client.set_color(bulb, current, std::time::Duration::from_millis(0))?;
+                if *power_off {
+                    client.set_power(bulb, false, std::time::Duration::from_millis(0))?;
+                }
+                return Ok(());
+            },
+            _ => return Err(format!("{} is not supported over lan", effect.name()).into())
+        };
+
+        let mut current = client.get_state(bulb)?.color;
+        match from_color {
+            Some(from_color) => {
+                current = match lan::parse_color(from_color, current) {
+                    Some(hsbk) => hsbk,
+                    None => return Err(format!("invalid color {}", from_color).into())
+                };
+                client.set_color(bulb, current, std::time::Duration::from_millis(0))?;
+            },
+            None => {}
+        }
+        let target = match lan::parse_color(color, current) {
+            Some(hsbk) => hsbk,
+            None => return Err(format!("invalid color {}", color).into())
+        };
+        if *power_on {
+            client.set_power(bulb, true, std::time::Duration::from_millis(0))?;
+        }
+        client.set_waveform(bulb, !*persist, target, std::time::Duration::from_secs_f64(period.max(0.0)), *cycles as f32, skew_ratio, waveform)?;
+        return Ok(());
+    });
+}
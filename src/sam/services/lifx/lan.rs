@@ -0,0 +1,766 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// lan.rs speaks the LIFX LAN protocol (UDP 56700) directly to bulbs so
+// lights keep working when the internet (and the cloud api) is down.
+// https://lan.developer.lifx.com/docs/header-description
+//
+// Every packet is a 36 byte little endian header followed by a payload:
+//   frame          size u16 | protocol:12 addressable:1 tagged:1 origin:2 | source u32
+//   frame address  target u64 | reserved [6] | res_required:1 ack_required:1 | sequence u8
+//   protocol       reserved u64 | type u16 | reserved u16
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+pub const LIFX_PORT: u16 = 56700;
+pub const HEADER_SIZE: usize = 36;
+const PROTOCOL: u16 = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Hsbk {
+    pub hue: u16,
+    pub saturation: u16,
+    pub brightness: u16,
+    pub kelvin: u16,
+}
+impl Hsbk {
+    // Converts from the cloud api units (0-360, 0-1, 0-1, kelvin)
+    pub fn from_api(hue: f64, saturation: f64, brightness: f64, kelvin: u16) -> Hsbk {
+        Hsbk {
+            hue: ((hue.rem_euclid(360.0) / 360.0) * 65535.0).round() as u16,
+            saturation: (saturation.clamp(0.0, 1.0) * 65535.0).round() as u16,
+            brightness: (brightness.clamp(0.0, 1.0) * 65535.0).round() as u16,
+            kelvin: kelvin.clamp(1500, 9000),
+        }
+    }
+    pub fn hue_degrees(&self) -> f64 {
+        return (self.hue as f64 / 65535.0) * 360.0;
+    }
+    pub fn saturation_percent(&self) -> f64 {
+        return self.saturation as f64 / 65535.0;
+    }
+    pub fn brightness_percent(&self) -> f64 {
+        return self.brightness as f64 / 65535.0;
+    }
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.hue.to_le_bytes());
+        buf.extend_from_slice(&self.saturation.to_le_bytes());
+        buf.extend_from_slice(&self.brightness.to_le_bytes());
+        buf.extend_from_slice(&self.kelvin.to_le_bytes());
+    }
+    fn read(buf: &[u8]) -> Hsbk {
+        Hsbk {
+            hue: u16_at(buf, 0),
+            saturation: u16_at(buf, 2),
+            brightness: u16_at(buf, 4),
+            kelvin: u16_at(buf, 6),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Saw = 0,
+    Sine = 1,
+    HalfSine = 2,
+    Triangle = 3,
+    Pulse = 4,
+}
+impl Waveform {
+    fn from_u8(value: u8) -> Waveform {
+        match value {
+            0 => Waveform::Saw,
+            2 => Waveform::HalfSine,
+            3 => Waveform::Triangle,
+            4 => Waveform::Pulse,
+            _ => Waveform::Sine,
+        }
+    }
+}
+
+// Every message sam sends or understands, requests and responses alike
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Message {
+    GetService,
+    StateService { service: u8, port: u32 },
+    GetHostFirmware,
+    StateHostFirmware { build: u64, version_minor: u16, version_major: u16 },
+    GetPower,
+    SetPower { level: u16 },
+    StatePower { level: u16 },
+    GetLabel,
+    SetLabel { label: String },
+    StateLabel { label: String },
+    GetVersion,
+    StateVersion { vendor: u32, product: u32 },
+    Acknowledgement,
+    GetLocation,
+    StateLocation { location: String, label: String, updated_at: u64 },
+    GetGroup,
+    StateGroup { group: String, label: String, updated_at: u64 },
+    LightGet,
+    LightSetColor { color: Hsbk, duration: u32 },
+    LightSetWaveform { transient: bool, color: Hsbk, period: u32, cycles: f32, skew_ratio: i16, waveform: Waveform },
+    LightState { color: Hsbk, power: u16, label: String },
+    LightGetPower,
+    LightSetPower { level: u16, duration: u32 },
+    LightStatePower { level: u16 },
+    LightGetInfrared,
+    LightStateInfrared { brightness: u16 },
+    LightSetInfrared { brightness: u16 },
+    Unknown { message_type: u16 },
+}
+impl Message {
+    pub fn message_type(&self) -> u16 {
+        match self {
+            Message::GetService => 2,
+            Message::StateService{..} => 3,
+            Message::GetHostFirmware => 14,
+            Message::StateHostFirmware{..} => 15,
+            Message::GetPower => 20,
+            Message::SetPower{..} => 21,
+            Message::StatePower{..} => 22,
+            Message::GetLabel => 23,
+            Message::SetLabel{..} => 24,
+            Message::StateLabel{..} => 25,
+            Message::GetVersion => 32,
+            Message::StateVersion{..} => 33,
+            Message::Acknowledgement => 45,
+            Message::GetLocation => 48,
+            Message::StateLocation{..} => 50,
+            Message::GetGroup => 51,
+            Message::StateGroup{..} => 53,
+            Message::LightGet => 101,
+            Message::LightSetColor{..} => 102,
+            Message::LightSetWaveform{..} => 103,
+            Message::LightState{..} => 107,
+            Message::LightGetPower => 116,
+            Message::LightSetPower{..} => 117,
+            Message::LightStatePower{..} => 118,
+            Message::LightGetInfrared => 120,
+            Message::LightStateInfrared{..} => 121,
+            Message::LightSetInfrared{..} => 122,
+            Message::Unknown{message_type} => *message_type,
+        }
+    }
+
+    pub fn payload(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        match self {
+            Message::StateService{service, port} => {
+                buf.push(*service);
+                buf.extend_from_slice(&port.to_le_bytes());
+            },
+            Message::StateHostFirmware{build, version_minor, version_major} => {
+                buf.extend_from_slice(&build.to_le_bytes());
+                buf.extend_from_slice(&[0u8; 8]);
+                buf.extend_from_slice(&version_minor.to_le_bytes());
+                buf.extend_from_slice(&version_major.to_le_bytes());
+            },
+            Message::SetPower{level} | Message::StatePower{level} | Message::LightStatePower{level} | Message::LightStateInfrared{brightness: level} | Message::LightSetInfrared{brightness: level} => {
+                buf.extend_from_slice(&level.to_le_bytes());
+            },
+            Message::SetLabel{label} | Message::StateLabel{label} => {
+                write_string(&mut buf, label, 32);
+            },
+            Message::StateVersion{vendor, product} => {
+                buf.extend_from_slice(&vendor.to_le_bytes());
+                buf.extend_from_slice(&product.to_le_bytes());
+                buf.extend_from_slice(&[0u8; 4]);
+            },
+            Message::StateLocation{location: id, label, updated_at} | Message::StateGroup{group: id, label, updated_at} => {
+                buf.extend_from_slice(&hex_to_bytes(id, 16));
+                write_string(&mut buf, label, 32);
+                buf.extend_from_slice(&updated_at.to_le_bytes());
+            },
+            Message::LightSetColor{color, duration} => {
+                buf.push(0);
+                color.write(&mut buf);
+                buf.extend_from_slice(&duration.to_le_bytes());
+            },
+            Message::LightSetWaveform{transient, color, period, cycles, skew_ratio, waveform} => {
+                buf.push(0);
+                buf.push(*transient as u8);
+                color.write(&mut buf);
+                buf.extend_from_slice(&period.to_le_bytes());
+                buf.extend_from_slice(&cycles.to_le_bytes());
+                buf.extend_from_slice(&skew_ratio.to_le_bytes());
+                buf.push(*waveform as u8);
+            },
+            Message::LightState{color, power, label} => {
+                color.write(&mut buf);
+                buf.extend_from_slice(&[0u8; 2]);
+                buf.extend_from_slice(&power.to_le_bytes());
+                write_string(&mut buf, label, 32);
+                buf.extend_from_slice(&[0u8; 8]);
+            },
+            Message::LightSetPower{level, duration} => {
+                buf.extend_from_slice(&level.to_le_bytes());
+                buf.extend_from_slice(&duration.to_le_bytes());
+            },
+            _ => {}
+        }
+        return buf;
+    }
+
+    pub fn parse(message_type: u16, payload: &[u8]) -> Option<Message> {
+        let message = match message_type {
+            2 => Message::GetService,
+            3 => {
+                check_len(payload, 5)?;
+                Message::StateService{ service: payload[0], port: u32_at(payload, 1) }
+            },
+            14 => Message::GetHostFirmware,
+            15 => {
+                check_len(payload, 20)?;
+                Message::StateHostFirmware{ build: u64_at(payload, 0), version_minor: u16_at(payload, 16), version_major: u16_at(payload, 18) }
+            },
+            20 => Message::GetPower,
+            21 => {
+                check_len(payload, 2)?;
+                Message::SetPower{ level: u16_at(payload, 0) }
+            },
+            22 => {
+                check_len(payload, 2)?;
+                Message::StatePower{ level: u16_at(payload, 0) }
+            },
+            23 => Message::GetLabel,
+            24 => {
+                check_len(payload, 32)?;
+                Message::SetLabel{ label: read_string(&payload[0..32]) }
+            },
+            25 => {
+                check_len(payload, 32)?;
+                Message::StateLabel{ label: read_string(&payload[0..32]) }
+            },
+            32 => Message::GetVersion,
+            33 => {
+                check_len(payload, 8)?;
+                Message::StateVersion{ vendor: u32_at(payload, 0), product: u32_at(payload, 4) }
+            },
+            45 => Message::Acknowledgement,
+            48 => Message::GetLocation,
+            50 => {
+                check_len(payload, 56)?;
+                Message::StateLocation{ location: bytes_to_hex(&payload[0..16]), label: read_string(&payload[16..48]), updated_at: u64_at(payload, 48) }
+            },
+            51 => Message::GetGroup,
+            53 => {
+                check_len(payload, 56)?;
+                Message::StateGroup{ group: bytes_to_hex(&payload[0..16]), label: read_string(&payload[16..48]), updated_at: u64_at(payload, 48) }
+            },
+            101 => Message::LightGet,
+            102 => {
+                check_len(payload, 13)?;
+                Message::LightSetColor{ color: Hsbk::read(&payload[1..9]), duration: u32_at(payload, 9) }
+            },
+            103 => {
+                check_len(payload, 21)?;
+                Message::LightSetWaveform{
+                    transient: payload[1] != 0,
+                    color: Hsbk::read(&payload[2..10]),
+                    period: u32_at(payload, 10),
+                    cycles: f32::from_le_bytes([payload[14], payload[15], payload[16], payload[17]]),
+                    skew_ratio: i16::from_le_bytes([payload[18], payload[19]]),
+                    waveform: Waveform::from_u8(payload[20]),
+                }
+            },
+            107 => {
+                check_len(payload, 44)?;
+                Message::LightState{ color: Hsbk::read(&payload[0..8]), power: u16_at(payload, 10), label: read_string(&payload[12..44]) }
+            },
+            116 => Message::LightGetPower,
+            117 => {
+                check_len(payload, 6)?;
+                Message::LightSetPower{ level: u16_at(payload, 0), duration: u32_at(payload, 2) }
+            },
+            118 => {
+                check_len(payload, 2)?;
+                Message::LightStatePower{ level: u16_at(payload, 0) }
+            },
+            120 => Message::LightGetInfrared,
+            121 => {
+                check_len(payload, 2)?;
+                Message::LightStateInfrared{ brightness: u16_at(payload, 0) }
+            },
+            122 => {
+                check_len(payload, 2)?;
+                Message::LightSetInfrared{ brightness: u16_at(payload, 0) }
+            },
+            other => Message::Unknown{ message_type: other },
+        };
+        return Some(message);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub size: u16,
+    pub tagged: bool,
+    pub source: u32,
+    pub target: u64,
+    pub ack_required: bool,
+    pub res_required: bool,
+    pub sequence: u8,
+    pub message_type: u16,
+}
+
+pub fn encode(header: &Header, message: &Message) -> Vec<u8> {
+    let payload = message.payload();
+    let size = (HEADER_SIZE + payload.len()) as u16;
+
+    let mut packet: Vec<u8> = Vec::with_capacity(size as usize);
+    packet.extend_from_slice(&size.to_le_bytes());
+    let mut protocol = PROTOCOL | (1 << 12);
+    if header.tagged {
+        protocol = protocol | (1 << 13);
+    }
+    packet.extend_from_slice(&protocol.to_le_bytes());
+    packet.extend_from_slice(&header.source.to_le_bytes());
+    packet.extend_from_slice(&header.target.to_le_bytes());
+    packet.extend_from_slice(&[0u8; 6]);
+    let mut flags = 0u8;
+    if header.res_required {
+        flags = flags | 1;
+    }
+    if header.ack_required {
+        flags = flags | 2;
+    }
+    packet.push(flags);
+    packet.push(header.sequence);
+    packet.extend_from_slice(&[0u8; 8]);
+    packet.extend_from_slice(&message.message_type().to_le_bytes());
+    packet.extend_from_slice(&[0u8; 2]);
+    packet.extend_from_slice(&payload);
+    return packet;
+}
+
+pub fn decode(packet: &[u8]) -> Option<(Header, Message)> {
+    if packet.len() < HEADER_SIZE {
+        return None;
+    }
+    let size = u16_at(packet, 0);
+    if (size as usize) < HEADER_SIZE || (size as usize) > packet.len() {
+        return None;
+    }
+    let protocol = u16_at(packet, 2);
+    if protocol & 0x0fff != PROTOCOL {
+        return None;
+    }
+
+    let header = Header {
+        size: size,
+        tagged: protocol & (1 << 13) != 0,
+        source: u32_at(packet, 4),
+        target: u64_at(packet, 8),
+        res_required: packet[22] & 1 != 0,
+        ack_required: packet[22] & 2 != 0,
+        sequence: packet[23],
+        message_type: u16_at(packet, 32),
+    };
+    let message = Message::parse(header.message_type, &packet[HEADER_SIZE..size as usize])?;
+    return Some((header, message));
+}
+
+// A bulb found on the local network
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LanBulb {
+    pub target: u64,
+    pub address: SocketAddr,
+}
+impl LanBulb {
+    // Same format the cloud api uses for light ids (the mac address in hex)
+    pub fn id(&self) -> String {
+        return bytes_to_hex(&self.target.to_le_bytes()[0..6]);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LanLightState {
+    pub id: String,
+    pub ip_address: String,
+    pub label: String,
+    pub power: bool,
+    pub color: Hsbk,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LanFirmware {
+    pub build: u64,
+    pub version_major: u16,
+    pub version_minor: u16,
+}
+
+pub struct LanClient {
+    socket: Mutex<UdpSocket>,
+    source: u32,
+    sequence: Mutex<u8>,
+    broadcast: SocketAddr,
+    pub timeout: Duration,
+    pub retries: usize,
+}
+impl LanClient {
+    pub fn new() -> Result<LanClient, crate::sam::services::Error> {
+        return LanClient::with_broadcast(SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), LIFX_PORT));
+    }
+
+    // Discovery goes to this address instead of 255.255.255.255, used for the simulated bulb
+    pub fn with_broadcast(broadcast: SocketAddr) -> Result<LanClient, crate::sam::services::Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_broadcast(true)?;
+        let source: u32 = rand::random::<u32>().max(2);
+        return Ok(LanClient {
+            socket: Mutex::new(socket),
+            source: source,
+            sequence: Mutex::new(0),
+            broadcast: broadcast,
+            timeout: Duration::from_millis(500),
+            retries: 3,
+        });
+    }
+
+    fn next_sequence(&self) -> u8 {
+        let mut sequence = self.sequence.lock().unwrap();
+        *sequence = sequence.wrapping_add(1);
+        return *sequence;
+    }
+
+    pub fn discover(&self, wait: Duration) -> Result<Vec<LanBulb>, crate::sam::services::Error> {
+        let socket = self.socket.lock().unwrap();
+        let header = Header {
+            size: 0,
+            tagged: true,
+            source: self.source,
+            target: 0,
+            ack_required: false,
+            res_required: true,
+            sequence: self.next_sequence(),
+            message_type: 0,
+        };
+        socket.send_to(&encode(&header, &Message::GetService), self.broadcast)?;
+
+        let mut bulbs: Vec<LanBulb> = Vec::new();
+        let deadline = Instant::now() + wait;
+        let mut buf = [0u8; 1024];
+        while Instant::now() < deadline {
+            socket.set_read_timeout(Some(deadline.saturating_duration_since(Instant::now()).max(Duration::from_millis(1))))?;
+            match socket.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    match decode(&buf[..len]) {
+                        Some((reply, Message::StateService{service, port})) => {
+                            // Service 1 is UDP, anything else isn't for us
+                            if reply.source == self.source && service == 1 && !bulbs.iter().any(|b| b.target == reply.target) {
+                                bulbs.push(LanBulb{
+                                    target: reply.target,
+                                    address: SocketAddr::new(from.ip(), port as u16),
+                                });
+                            }
+                        },
+                        _ => {}
+                    }
+                },
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut {
+                        break;
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+        return Ok(bulbs);
+    }
+
+    // Sends a message and waits for the reply with the matching sequence, retrying on timeout
+    pub fn request(&self, bulb: &LanBulb, message: Message, ack_only: bool) -> Result<Message, crate::sam::services::Error> {
+        let socket = self.socket.lock().unwrap();
+        let sequence = self.next_sequence();
+        let header = Header {
+            size: 0,
+            tagged: false,
+            source: self.source,
+            target: bulb.target,
+            ack_required: ack_only,
+            res_required: !ack_only,
+            sequence: sequence,
+            message_type: 0,
+        };
+        let packet = encode(&header, &message);
+
+        let mut buf = [0u8; 1024];
+        for _ in 0..self.retries {
+            socket.send_to(&packet, bulb.address)?;
+            let deadline = Instant::now() + self.timeout;
+            while Instant::now() < deadline {
+                socket.set_read_timeout(Some(deadline.saturating_duration_since(Instant::now()).max(Duration::from_millis(1))))?;
+                match socket.recv_from(&mut buf) {
+                    Ok((len, _)) => {
+                        match decode(&buf[..len]) {
+                            Some((reply, response)) => {
+                                if reply.source != self.source || reply.sequence != sequence || reply.target != bulb.target {
+                                    continue;
+                                }
+                                if ack_only && response != Message::Acknowledgement {
+                                    continue;
+                                }
+                                if !ack_only && response == Message::Acknowledgement {
+                                    continue;
+                                }
+                                return Ok(response);
+                            },
+                            None => {}
+                        }
+                    },
+                    Err(e) => {
+                        if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut {
+                            break;
+                        }
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+        return Err(format!("lifx bulb {} did not respond", bulb.id()).into());
+    }
+
+    pub fn get_state(&self, bulb: &LanBulb) -> Result<LanLightState, crate::sam::services::Error> {
+        match self.request(bulb, Message::LightGet, false)? {
+            Message::LightState{color, power, label} => {
+                return Ok(LanLightState{
+                    id: bulb.id(),
+                    ip_address: bulb.address.ip().to_string(),
+                    label: label,
+                    power: power > 0,
+                    color: color,
+                });
+            },
+            other => return Err(format!("unexpected reply {:?}", other).into())
+        }
+    }
+
+    pub fn get_power(&self, bulb: &LanBulb) -> Result<bool, crate::sam::services::Error> {
+        match self.request(bulb, Message::GetPower, false)? {
+            Message::StatePower{level} => return Ok(level > 0),
+            other => return Err(format!("unexpected reply {:?}", other).into())
+        }
+    }
+
+    pub fn set_power(&self, bulb: &LanBulb, on: bool, duration: Duration) -> Result<(), crate::sam::services::Error> {
+        let level = if on { 65535 } else { 0 };
+        self.request(bulb, Message::LightSetPower{ level: level, duration: duration.as_millis() as u32 }, true)?;
+        return Ok(());
+    }
+
+    pub fn set_color(&self, bulb: &LanBulb, color: Hsbk, duration: Duration) -> Result<(), crate::sam::services::Error> {
+        self.request(bulb, Message::LightSetColor{ color: color, duration: duration.as_millis() as u32 }, true)?;
+        return Ok(());
+    }
+
+    pub fn set_waveform(&self, bulb: &LanBulb, transient: bool, color: Hsbk, period: Duration, cycles: f32, skew_ratio: i16, waveform: Waveform) -> Result<(), crate::sam::services::Error> {
+        self.request(bulb, Message::LightSetWaveform{
+            transient: transient,
+            color: color,
+            period: period.as_millis() as u32,
+            cycles: cycles,
+            skew_ratio: skew_ratio,
+            waveform: waveform,
+        }, true)?;
+        return Ok(());
+    }
+
+    pub fn get_label(&self, bulb: &LanBulb) -> Result<String, crate::sam::services::Error> {
+        match self.request(bulb, Message::GetLabel, false)? {
+            Message::StateLabel{label} => return Ok(label),
+            other => return Err(format!("unexpected reply {:?}", other).into())
+        }
+    }
+
+    // Infrared brightness from 0.0 to 1.0, only bulbs with infrared leds honour it
+    pub fn set_infrared(&self, bulb: &LanBulb, brightness: f64) -> Result<(), crate::sam::services::Error> {
+        let brightness = (brightness.clamp(0.0, 1.0) * 65535.0).round() as u16;
+        self.request(bulb, Message::LightSetInfrared{ brightness: brightness }, true)?;
+        return Ok(());
+    }
+
+    // Returns the (id, label) of the group the bulb belongs to
+    pub fn get_group(&self, bulb: &LanBulb) -> Result<(String, String), crate::sam::services::Error> {
+        match self.request(bulb, Message::GetGroup, false)? {
+            Message::StateGroup{group, label, ..} => return Ok((group, label)),
+            other => return Err(format!("unexpected reply {:?}", other).into())
+        }
+    }
+
+    // Returns the (id, label) of the location the bulb belongs to
+    pub fn get_location(&self, bulb: &LanBulb) -> Result<(String, String), crate::sam::services::Error> {
+        match self.request(bulb, Message::GetLocation, false)? {
+            Message::StateLocation{location, label, ..} => return Ok((location, label)),
+            other => return Err(format!("unexpected reply {:?}", other).into())
+        }
+    }
+
+    pub fn get_firmware(&self, bulb: &LanBulb) -> Result<LanFirmware, crate::sam::services::Error> {
+        match self.request(bulb, Message::GetHostFirmware, false)? {
+            Message::StateHostFirmware{build, version_minor, version_major} => {
+                return Ok(LanFirmware{ build: build, version_major: version_major, version_minor: version_minor });
+            },
+            other => return Err(format!("unexpected reply {:?}", other).into())
+        }
+    }
+}
+
+// Parses the color strings the cloud api accepts ("red", "#ff0000",
+// "hue:120 saturation:1.0 brightness:0.5 kelvin:3500") on top of the current color
+pub fn parse_color(color: &str, current: Hsbk) -> Option<Hsbk> {
+    let mut hue = current.hue_degrees();
+    let mut saturation = current.saturation_percent();
+    let mut brightness = current.brightness_percent();
+    let mut kelvin = current.kelvin;
+
+    for part in color.trim().to_lowercase().split_whitespace() {
+        match part {
+            "white" => { saturation = 0.0; },
+            "red" => { hue = 0.0; saturation = 1.0; },
+            "orange" => { hue = 36.0; saturation = 1.0; },
+            "yellow" => { hue = 60.0; saturation = 1.0; },
+            "green" => { hue = 120.0; saturation = 1.0; },
+            "cyan" => { hue = 180.0; saturation = 1.0; },
+            "blue" => { hue = 250.0; saturation = 1.0; },
+            "purple" => { hue = 280.0; saturation = 1.0; },
+            "pink" => { hue = 325.0; saturation = 1.0; },
+            _ => {
+                if part.starts_with("#") && part.len() == 7 {
+                    let r = u8::from_str_radix(&part[1..3], 16).ok()? as f64 / 255.0;
+                    let g = u8::from_str_radix(&part[3..5], 16).ok()? as f64 / 255.0;
+                    let b = u8::from_str_radix(&part[5..7], 16).ok()? as f64 / 255.0;
+                    let max = r.max(g).max(b);
+                    let min = r.min(g).min(b);
+                    let delta = max - min;
+                    hue = if delta == 0.0 {
+                        0.0
+                    } else if max == r {
+                        60.0 * (((g - b) / delta).rem_euclid(6.0))
+                    } else if max == g {
+                        60.0 * (((b - r) / delta) + 2.0)
+                    } else {
+                        60.0 * (((r - g) / delta) + 4.0)
+                    };
+                    saturation = if max == 0.0 { 0.0 } else { delta / max };
+                    brightness = max;
+                    continue;
+                }
+
+                let (key, value) = part.split_once(":")?;
+                match key {
+                    "hue" => hue = value.parse::<f64>().ok()?,
+                    "saturation" => saturation = value.parse::<f64>().ok()?,
+                    "brightness" => brightness = value.parse::<f64>().ok()?,
+                    "kelvin" => {
+                        kelvin = value.parse::<u16>().ok()?;
+                        saturation = 0.0;
+                    },
+                    _ => return None
+                }
+            }
+        }
+    }
+    return Some(Hsbk::from_api(hue, saturation, brightness, kelvin));
+}
+
+fn check_len(payload: &[u8], len: usize) -> Option<()> {
+    if payload.len() >= len {
+        return Some(());
+    }
+    return None;
+}
+
+fn u16_at(buf: &[u8], at: usize) -> u16 {
+    return u16::from_le_bytes([buf[at], buf[at + 1]]);
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    return u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[at..at + 8]);
+    return u64::from_le_bytes(bytes);
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str, len: usize) {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.truncate(len);
+    bytes.resize(len, 0);
+    buf.extend_from_slice(&bytes);
+}
+
+fn read_string(buf: &[u8]) -> String {
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    return String::from_utf8_lossy(&buf[..end]).to_string();
+}
+
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join("");
+}
+
+fn hex_to_bytes(hex: &str, len: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i + 2 <= hex.len() && bytes.len() < len {
+        // get, not a slice, so a multibyte char can't panic
+        bytes.push(hex.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()).unwrap_or(0));
+        i = i + 2;
+    }
+    bytes.resize(len, 0);
+    return bytes;
+}
+
+// Parses a light id (mac address in hex) back into a protocol target
+pub fn target_from_id(id: &str) -> Option<u64> {
+    if id.len() != 12 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = hex_to_bytes(id, 6);
+    bytes.resize(8, 0);
+    let mut target = [0u8; 8];
+    target.copy_from_slice(&bytes);
+    return Some(u64::from_le_bytes(target));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_round_trip_to_targets() {
+        let bulb = LanBulb{ target: 0x0000_5634_12d5_73d0, address: "127.0.0.1:56700".parse().unwrap() };
+        assert_eq!(bulb.id(), "d073d5123456");
+        assert_eq!(target_from_id(&bulb.id()), Some(bulb.target));
+        assert_eq!(target_from_id("D073D5123456"), Some(bulb.target));
+    }
+
+    #[test]
+    fn bad_ids_are_rejected() {
+        assert_eq!(target_from_id(""), None);
+        assert_eq!(target_from_id("d073d512345"), None);
+        assert_eq!(target_from_id("d073d512345g"), None);
+        // 12 bytes but not 12 hex digits
+        assert_eq!(target_from_id("d073d51234é"), None);
+        assert_eq!(target_from_id("ééééé12"), None);
+    }
+
+    #[test]
+    fn hex_to_bytes_never_panics() {
+        assert_eq!(hex_to_bytes("0aff", 2), vec![0x0a, 0xff]);
+        assert_eq!(hex_to_bytes("0a", 3), vec![0x0a, 0, 0]);
+        assert_eq!(hex_to_bytes("aéb", 2), vec![0, 0]);
+    }
+}
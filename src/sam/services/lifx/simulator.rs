@@ -0,0 +1,222 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// simulator.rs is a fake LIFX bulb that answers the LAN protocol on localhost.
+// It lets the lan client be tested without real hardware, only built for tests.
+
+use crate::sam::services::lifx::lan::{decode, encode, Header, Hsbk, Message};
+use serde::{Serialize, Deserialize};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimulatedState {
+    pub label: String,
+    pub power: u16,
+    pub color: Hsbk,
+    pub infrared: u16,
+    pub location: String,
+    pub location_label: String,
+    pub group: String,
+    pub group_label: String,
+    pub last_waveform: Option<Message>,
+}
+
+pub struct SimulatedBulb {
+    pub address: SocketAddr,
+    pub target: u64,
+    state: Arc<Mutex<SimulatedState>>,
+    running: Arc<AtomicBool>,
+}
+impl SimulatedBulb {
+    // Binds to 127.0.0.1 on a random port, point LanClient::with_broadcast at bulb.address
+    pub fn spawn(label: &str, target: u64) -> Result<SimulatedBulb, crate::sam::services::Error> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let address = socket.local_addr()?;
+
+        let state = Arc::new(Mutex::new(SimulatedState{
+            label: label.to_string(),
+            power: 0,
+            color: Hsbk{ hue: 0, saturation: 0, brightness: 65535, kelvin: 3500 },
+            infrared: 0,
+            location: format!("{:032x}", target),
+            location_label: format!("Home"),
+            group: format!("{:032x}", target + 1),
+            group_label: format!("Simulated"),
+            last_waveform: None,
+        }));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_state = state.clone();
+        let thread_running = running.clone();
+        thread::Builder::new().name(format!("lifx_simulator_{}", label)).spawn(move || {
+            let mut buf = [0u8; 1024];
+            while thread_running.load(Ordering::SeqCst) {
+                match socket.recv_from(&mut buf) {
+                    Ok((len, from)) => {
+                        match decode(&buf[..len]) {
+                            Some((header, message)) => {
+                                for (reply_header, reply) in respond(&header, message, target, address.port(), &thread_state) {
+                                    let _ = socket.send_to(&encode(&reply_header, &reply), from);
+                                }
+                            },
+                            None => {}
+                        }
+                    },
+                    Err(_) => {}
+                }
+            }
+        })?;
+
+        return Ok(SimulatedBulb{
+            address: address,
+            target: target,
+            state: state,
+            running: running,
+        });
+    }
+
+    pub fn state(&self) -> SimulatedState {
+        return self.state.lock().unwrap().clone();
+    }
+}
+impl Drop for SimulatedBulb {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+fn respond(header: &Header, message: Message, target: u64, port: u16, state: &Arc<Mutex<SimulatedState>>) -> Vec<(Header, Message)> {
+    // Untagged packets are only for the bulb they're addressed to
+    if !header.tagged && header.target != target {
+        return Vec::new();
+    }
+
+    let mut state = state.lock().unwrap();
+    let mut is_get = true;
+    match &message {
+        Message::SetPower{level} | Message::LightSetPower{level, ..} => {
+            state.power = *level;
+            is_get = false;
+        },
+        Message::LightSetInfrared{brightness} => {
+            state.infrared = *brightness;
+            is_get = false;
+        },
+        Message::SetLabel{label} => {
+            state.label = label.clone();
+            is_get = false;
+        },
+        Message::LightSetColor{color, ..} => {
+            state.color = *color;
+            is_get = false;
+        },
+        Message::LightSetWaveform{transient, color, ..} => {
+            if !transient {
+                state.color = *color;
+            }
+            state.last_waveform = Some(message.clone());
+            is_get = false;
+        },
+        _ => {}
+    }
+
+    let reply = match message {
+        Message::GetService => Some(Message::StateService{ service: 1, port: port as u32 }),
+        Message::GetHostFirmware => Some(Message::StateHostFirmware{ build: 1_600_000_000_000_000_000, version_minor: 70, version_major: 3 }),
+        Message::GetPower | Message::SetPower{..} => Some(Message::StatePower{ level: state.power }),
+        Message::GetLabel | Message::SetLabel{..} => Some(Message::StateLabel{ label: state.label.clone() }),
+        Message::GetVersion => Some(Message::StateVersion{ vendor: 1, product: 27 }),
+        Message::GetLocation => Some(Message::StateLocation{ location: state.location.clone(), label: state.location_label.clone(), updated_at: 0 }),
+        Message::GetGroup => Some(Message::StateGroup{ group: state.group.clone(), label: state.group_label.clone(), updated_at: 0 }),
+        Message::LightGet | Message::LightSetColor{..} | Message::LightSetWaveform{..} => Some(Message::LightState{ color: state.color, power: state.power, label: state.label.clone() }),
+        Message::LightGetPower | Message::LightSetPower{..} => Some(Message::LightStatePower{ level: state.power }),
+        Message::LightGetInfrared | Message::LightSetInfrared{..} => Some(Message::LightStateInfrared{ brightness: state.infrared }),
+        _ => None
+    };
+
+    let mut replies: Vec<(Header, Message)> = Vec::new();
+    let reply_header = Header{
+        size: 0,
+        tagged: false,
+        source: header.source,
+        target: target,
+        ack_required: false,
+        res_required: false,
+        sequence: header.sequence,
+        message_type: 0,
+    };
+    if header.ack_required {
+        replies.push((reply_header.clone(), Message::Acknowledgement));
+    }
+    // Get messages always answer, set messages only when asked to
+    match reply {
+        Some(reply) => {
+            if is_get || header.res_required {
+                replies.push((reply_header, reply));
+            }
+        },
+        None => {}
+    }
+    return replies;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sam::services::lifx::lan::{LanClient, Waveform};
+
+    #[test]
+    fn lan_client_against_simulated_bulb() {
+        let bulb = SimulatedBulb::spawn("Simulated Bulb", 0x0000_5634_12d5_73d0).unwrap();
+        let client = LanClient::with_broadcast(bulb.address).unwrap();
+
+        let found = client.discover(Duration::from_millis(500)).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].target, bulb.target);
+        let found = found[0].clone();
+
+        assert_eq!(client.get_label(&found).unwrap(), "Simulated Bulb");
+
+        client.set_power(&found, true, Duration::from_millis(0)).unwrap();
+        assert!(client.get_power(&found).unwrap());
+        assert_eq!(bulb.state().power, 65535);
+
+        let red = Hsbk::from_api(0.0, 1.0, 0.5, 3500);
+        client.set_color(&found, red, Duration::from_millis(250)).unwrap();
+        assert_eq!(client.get_state(&found).unwrap().color, red);
+
+        // A transient waveform doesn't change the color it returns to
+        client.set_waveform(&found, true, Hsbk::from_api(240.0, 1.0, 1.0, 3500), Duration::from_millis(1000), 3.0, 0, Waveform::Pulse).unwrap();
+        assert!(bulb.state().last_waveform.is_some());
+        assert_eq!(bulb.state().color, red);
+
+        client.set_infrared(&found, 0.5).unwrap();
+        assert_eq!(bulb.state().infrared, 32768);
+
+        assert_eq!(client.get_group(&found).unwrap().1, "Simulated");
+        assert_eq!(client.get_location(&found).unwrap().1, "Home");
+
+        let firmware = client.get_firmware(&found).unwrap();
+        assert_eq!((firmware.version_major, firmware.version_minor), (3, 70));
+    }
+
+    #[test]
+    fn bulbs_ignore_packets_for_other_targets() {
+        let bulb = SimulatedBulb::spawn("Other Bulb", 0x0000_0000_0000_0001).unwrap();
+        let client = LanClient::with_broadcast(bulb.address).unwrap();
+        let mut found = client.discover(Duration::from_millis(500)).unwrap().remove(0);
+        found.target = 0x0000_0000_0000_0002;
+        assert!(client.get_label(&found).is_err());
+    }
+}
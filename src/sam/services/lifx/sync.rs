@@ -0,0 +1,325 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// sync.rs reconciles lifx lights with Things, Rooms and Locations.
+//
+// Lights are matched by their stable lifx id (and uuid when the api has one),
+// lifx locations by Location.lifx_location_id and lifx groups by
+// Room.lifx_group_id, so running it again changes nothing unless something
+// changed in lifx. Renamed lights are renamed, moved lights change room and
+// lights that have disappeared are marked offline rather than deleted.
+
+use crate::sam::memory::{Location, Room, Thing};
+use crate::sam::services::lifx::{LifxColor, LifxGroup, LifxLight};
+use online::check;
+use serde::{Serialize, Deserialize};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LifxSyncSummary {
+    // cloud, local (lifx_api_server) or lan
+    pub source: String,
+    pub lights_seen: usize,
+    pub locations_created: Vec<String>,
+    pub rooms_created: Vec<String>,
+    pub things_created: Vec<String>,
+    pub things_updated: Vec<String>,
+    pub things_offline: Vec<String>,
+    pub synced_at: i64,
+}
+
+fn last_summary() -> &'static Mutex<Option<LifxSyncSummary>> {
+    static LAST_SUMMARY: OnceLock<Mutex<Option<LifxSyncSummary>>> = OnceLock::new();
+    LAST_SUMMARY.get_or_init(|| Mutex::new(None))
+}
+
+fn now() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+}
+
+pub fn init(){
+    crate::sam::services::scheduler::every("lifx_sync", SYNC_INTERVAL, || {
+        match sync() {
+            Ok(summary) => {
+                log::info!("lifx sync ({}): {} lights, {} created, {} updated, {} offline", summary.source, summary.lights_seen, summary.things_created.len(), summary.things_updated.len(), summary.things_offline.len());
+            },
+            Err(e) => log::error!("lifx sync failed: {}", e)
+        }
+    });
+}
+
+pub fn last_sync() -> Option<LifxSyncSummary> {
+    return last_summary().lock().unwrap().clone();
+}
+
+// Lists lights from the cloud when online and lifx_api_server otherwise,
+// falling back to the lan when neither answers or lifx isn't set up.
+pub fn sync() -> Result<LifxSyncSummary, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("lifx")));
+    pg_query.query_coulmns.push(format!("identifier ="));
+    let services = crate::sam::memory::Service::select(None, None, None, Some(pg_query))?;
+
+    let public = check(Some(3)).is_ok();
+    let listed = match services.first() {
+        Some(service) => {
+            match crate::sam::services::lifx::api_list(service.secret.clone(), public, format!("all")) {
+                Ok(lights) => Some(lights),
+                Err(e) => {
+                    log::error!("failed to list lifx lights: {}", e);
+                    None
+                }
+            }
+        },
+        None => None
+    };
+
+    let summary = match listed {
+        Some(lights) => reconcile(lights, !public, if public { "cloud" } else { "local" })?,
+        None => reconcile(lan_lights()?, true, "lan")?
+    };
+
+    *last_summary().lock().unwrap() = Some(summary.clone());
+    return Ok(summary);
+}
+
+// Describes the bulbs on the lan in the same shape as the http api
+pub fn lan_lights() -> Result<Vec<LifxLight>, crate::sam::services::Error> {
+    let client = crate::sam::services::lifx::lan::LanClient::new()?;
+    let mut lights: Vec<LifxLight> = Vec::new();
+    for bulb in client.discover(Duration::from_secs(1))? {
+        let state = match client.get_state(&bulb) {
+            Ok(state) => state,
+            Err(e) => {
+                log::error!("{}", e);
+                continue;
+            }
+        };
+        let group = client.get_group(&bulb).unwrap_or_default();
+        let location = client.get_location(&bulb).unwrap_or_default();
+
+        lights.push(LifxLight{
+            id: state.id,
+            uuid: String::new(),
+            label: state.label,
+            connected: true,
+            power: if state.power { format!("on") } else { format!("off") },
+            color: LifxColor{
+                hue: state.color.hue_degrees(),
+                saturation: state.color.saturation_percent(),
+                kelvin: state.color.kelvin as i64,
+            },
+            brightness: state.color.brightness_percent(),
+            group: LifxGroup{ id: group.0, name: group.1 },
+            location: LifxGroup{ id: location.0, name: location.1 },
+            ip_address: Some(state.ip_address),
+        });
+    }
+    return Ok(lights);
+}
+
+// Local is true when the lights came from lifx_api_server or the lan,
+// their ids are kept in local_identifiers instead of online_identifiers.
+pub fn reconcile(lights: Vec<LifxLight>, local: bool, source: &str) -> Result<LifxSyncSummary, crate::sam::services::Error> {
+    let mut summary = LifxSyncSummary::default();
+    summary.source = source.to_string();
+    summary.lights_seen = lights.len();
+    summary.synced_at = now();
+
+    let mut locations = Location::select(None, None, None, None)?;
+    let mut rooms = Room::select(None, None, None, None)?;
+
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("lifx")));
+    pg_query.query_coulmns.push(format!("thing_type ="));
+    let things = Thing::select(None, None, Some(format!("id ASC")), Some(pg_query))?;
+
+    let light_ids: Vec<String> = lights.iter().map(|l| l.id.clone()).collect();
+    let mut seen: Vec<String> = Vec::new();
+
+    for light in lights {
+        let location_oid = if light.location.id.len() > 0 {
+            Some(sync_location(&light.location, &mut locations, &mut summary)?)
+        } else {
+            None
+        };
+        let room_oid = match &location_oid {
+            Some(location_oid) if light.group.id.len() > 0 => Some(sync_room(&light.group, location_oid, &mut rooms, &mut summary)?),
+            _ => None
+        };
+
+        // Match on the stable ids first, labels only catch things that no listed light claims
+        let existing = things.iter().find(|t| !seen.contains(&t.oid) && has_identifier(t, &light))
+            .or_else(|| things.iter().find(|t| {
+                !seen.contains(&t.oid) && t.name == light.label && !t.online_identifiers.iter().chain(t.local_identifiers.iter()).any(|i| light_ids.contains(i))
+            }));
+
+        let mut thing = match existing {
+            Some(thing) => thing.clone(),
+            None => {
+                let mut thing = Thing::new();
+                thing.thing_type = format!("lifx");
+                thing
+            }
+        };
+        let before = thing.clone();
+        seen.push(thing.oid.clone());
+
+        let mut identifiers: Vec<String> = vec![light.id.clone()];
+        if light.uuid.len() > 0 {
+            identifiers.push(light.uuid.clone());
+        }
+        identifiers.push(light.label.clone());
+
+        thing.name = light.label.clone();
+        if local {
+            thing.local_identifiers = identifiers;
+        } else {
+            thing.online_identifiers = identifiers;
+        }
+        match room_oid {
+            Some(room_oid) => thing.room_oid = room_oid,
+            None => {}
+        }
+        match &light.ip_address {
+            Some(ip_address) => thing.ip_address = ip_address.clone(),
+            None => {}
+        }
+        thing.online = light.connected;
+        if light.connected {
+            thing.last_seen_at = Some(summary.synced_at);
+        }
+
+        let changed = thing.name != before.name
+            || thing.room_oid != before.room_oid
+            || thing.ip_address != before.ip_address
+            || thing.online_identifiers != before.online_identifiers
+            || thing.local_identifiers != before.local_identifiers
+            || thing.online != before.online;
+
+        if existing.is_none() {
+            thing.save()?;
+            summary.things_created.push(thing.name.clone());
+            crate::sam::services::bus::publish(crate::sam::services::bus::Event::ThingDiscovered{
+                thing: thing.clone()
+            });
+            continue;
+        }
+
+        // Only the synced columns are written so edits made in sam survive,
+        // a pass where nothing changed just moves last_seen_at
+        if changed {
+            thing.save_synced()?;
+            summary.things_updated.push(thing.name.clone());
+        } else if light.connected {
+            Thing::save_health(&thing.oid, true, thing.last_seen_at)?;
+        }
+        if thing.online != before.online {
+            if !thing.online {
+                summary.things_offline.push(thing.name.clone());
+            }
+            publish_online(&thing);
+        }
+    }
+
+    // Anything lifx no longer reports is offline
+    for thing in things.iter() {
+        if seen.contains(&thing.oid) || !thing.online {
+            continue;
+        }
+        let mut thing = thing.clone();
+        thing.online = false;
+        Thing::save_health(&thing.oid, false, None)?;
+        summary.things_offline.push(thing.name.clone());
+        publish_online(&thing);
+    }
+
+    return Ok(summary);
+}
+
+fn has_identifier(thing: &Thing, light: &LifxLight) -> bool {
+    let mut ids: Vec<&String> = vec![&light.id];
+    if light.uuid.len() > 0 {
+        ids.push(&light.uuid);
+    }
+    return ids.iter().any(|id| thing.online_identifiers.contains(id) || thing.local_identifiers.contains(id));
+}
+
+fn publish_online(thing: &Thing) {
+    crate::sam::services::things::cache::update(thing, &serde_json::json!({"online": thing.online}), "sync");
+}
+
+// One Location per lifx location. Unlinked locations with the same name are adopted.
+fn sync_location(lifx_location: &LifxGroup, locations: &mut Vec<Location>, summary: &mut LifxSyncSummary) -> Result<String, crate::sam::services::Error> {
+    match locations.iter_mut().find(|l| l.lifx_location_id.as_ref() == Some(&lifx_location.id)) {
+        Some(location) => {
+            if location.name != lifx_location.name {
+                location.name = lifx_location.name.clone();
+                location.updated_at = summary.synced_at;
+                location.save()?;
+            }
+            return Ok(location.oid.clone());
+        },
+        None => {}
+    }
+
+    let mut location = match locations.iter().find(|l| l.lifx_location_id.is_none() && l.name.to_lowercase() == lifx_location.name.to_lowercase()) {
+        Some(location) => location.clone(),
+        None => {
+            let mut location = Location::new();
+            location.name = lifx_location.name.clone();
+            summary.locations_created.push(location.name.clone());
+            location
+        }
+    };
+    location.lifx_location_id = Some(lifx_location.id.clone());
+    location.updated_at = summary.synced_at;
+    location.save()?;
+
+    locations.retain(|l| l.oid != location.oid);
+    locations.push(location.clone());
+    return Ok(location.oid);
+}
+
+// One Room per lifx group. Unlinked rooms in the same location with the same name are adopted.
+fn sync_room(group: &LifxGroup, location_oid: &str, rooms: &mut Vec<Room>, summary: &mut LifxSyncSummary) -> Result<String, crate::sam::services::Error> {
+    match rooms.iter_mut().find(|r| r.lifx_group_id.as_ref() == Some(&group.id)) {
+        Some(room) => {
+            if room.name != group.name || room.location_oid != location_oid {
+                room.name = group.name.clone();
+                room.location_oid = location_oid.to_string();
+                room.updated_at = summary.synced_at;
+                room.save()?;
+            }
+            return Ok(room.oid.clone());
+        },
+        None => {}
+    }
+
+    let mut room = match rooms.iter().find(|r| r.lifx_group_id.is_none() && r.location_oid == location_oid && r.name.to_lowercase() == group.name.to_lowercase()) {
+        Some(room) => room.clone(),
+        None => {
+            let mut room = Room::new();
+            room.name = group.name.clone();
+            room.location_oid = location_oid.to_string();
+            summary.rooms_created.push(room.name.clone());
+            room
+        }
+    };
+    room.lifx_group_id = Some(group.id.clone());
+    room.updated_at = summary.synced_at;
+    room.save()?;
+
+    rooms.retain(|r| r.oid != room.oid);
+    rooms.push(room.clone());
+    return Ok(room.oid);
+}
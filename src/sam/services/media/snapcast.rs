@@ -7,11 +7,22 @@
 // Developed by Caleb Mitchell Smith (PixelCoda)
 // Licensed under GPLv3....see LICENSE file.
 
-
+use serde::{Serialize, Deserialize};
 use std::fs::File;
-use std::io::{Write};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
 use std::path::{Path};
 use std::thread;
+use std::time::Duration;
+
+// The pipe source snapserver reads sam's own audio from, see configure
+pub const SAM_STREAM: &str = "samfifo";
+pub const SAM_FIFO: &str = "/tmp/snapfifo";
+// snapserver's default pipe sampleformat, 48000:16:2
+pub const FIFO_SAMPLE_RATE: u32 = 48000;
+pub const FIFO_CHANNELS: u32 = 2;
+
+const CONTROL_ADDRESS: &str = "127.0.0.1:1705";
 
 pub fn init(){
     // Attempt to re-install snapserver if it doesn't already exist
@@ -135,4 +146,126 @@ pub fn install_snapcast_server_amd64() -> std::io::Result<()> {
     crate::sam::tools::linux_cmd(format!("dpkg -i /opt/sam/tmp/snapserver.deb"));
     crate::sam::tools::linux_cmd(format!("service snapserver start"));
     return Ok(());
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Volume {
+    pub percent: i64,
+    pub muted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClientConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub volume: Volume,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClientHost {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Client {
+    pub id: String,
+    #[serde(default)]
+    pub connected: bool,
+    #[serde(default)]
+    pub config: ClientConfig,
+    #[serde(default)]
+    pub host: ClientHost,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Group {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    pub stream_id: String,
+    #[serde(default)]
+    pub muted: bool,
+    #[serde(default)]
+    pub clients: Vec<Client>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Stream {
+    pub id: String,
+    // idle, playing or unknown
+    #[serde(default)]
+    pub status: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerStatus {
+    pub groups: Vec<Group>,
+    pub streams: Vec<Stream>,
+}
+
+// snapserver's json-rpc control api, one json object per line over tcp
+pub struct Control {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    next_id: u64,
+}
+impl Control {
+    pub fn connect() -> Result<Control, crate::sam::services::Error> {
+        let address = match CONTROL_ADDRESS.to_socket_addrs()?.next() {
+            Some(address) => address,
+            None => return Err(format!("can't resolve {}", CONTROL_ADDRESS).into())
+        };
+        let stream = TcpStream::connect_timeout(&address, Duration::from_secs(3))?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let reader = BufReader::new(stream.try_clone()?);
+        return Ok(Control {
+            stream: stream,
+            reader: reader,
+            next_id: 1,
+        });
+    }
+
+    pub fn call(&mut self, method: &str, params: serde_json::Value) -> Result<serde_json::Value, crate::sam::services::Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id + 1;
+        let request = serde_json::json!({"id": id, "jsonrpc": "2.0", "method": method, "params": params});
+        self.stream.write_all(format!("{}\r\n", request).as_bytes())?;
+
+        // Notifications about other changes can arrive before the answer
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(format!("snapserver closed the connection").into());
+            }
+            let response: serde_json::Value = match serde_json::from_str(line.trim()) {
+                Ok(response) => response,
+                Err(_) => continue
+            };
+            if response["id"].as_u64() != Some(id) {
+                continue;
+            }
+            if !response["error"].is_null() {
+                return Err(format!("snapserver {} failed: {}", method, response["error"]).into());
+            }
+            return Ok(response["result"].clone());
+        }
+    }
+
+    pub fn status(&mut self) -> Result<ServerStatus, crate::sam::services::Error> {
+        let result = self.call("Server.GetStatus", serde_json::json!({}))?;
+        let status: ServerStatus = serde_json::from_value(result["server"].clone()).map_err(|e| format!("unexpected snapserver status: {}", e))?;
+        return Ok(status);
+    }
+
+    pub fn set_group_stream(&mut self, group_id: &str, stream_id: &str) -> Result<(), crate::sam::services::Error> {
+        self.call("Group.SetStream", serde_json::json!({"id": group_id, "stream_id": stream_id}))?;
+        return Ok(());
+    }
+
+    pub fn set_client_volume(&mut self, client_id: &str, volume: &Volume) -> Result<(), crate::sam::services::Error> {
+        self.call("Client.SetVolume", serde_json::json!({"id": client_id, "volume": {"percent": volume.percent, "muted": volume.muted}}))?;
+        return Ok(());
+    }
+}
@@ -68,6 +68,9 @@ pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Reques
 
         let id = request.get_param("id").unwrap();
 
+        let job = format!("youtube_download:{}", id);
+        publish_progress(&current_session, &job, 0, "starting");
+
         let tube_id = rustube::Id::from_string(id)?;
         let video = rustube::blocking::Video::from_id(tube_id.clone())?;
 
@@ -80,8 +83,12 @@ pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Reques
             .max_by_key(|stream| stream.quality_label).unwrap();
 
 
+        publish_progress(&current_session, &job, 10, "downloading");
+
         best_quality.blocking_download_to_dir("/opt/sam/tmp/youtube/downloads")?;
 
+        publish_progress(&current_session, &job, 80, "storing");
+
         let data = std::fs::read(format!("/opt/sam/tmp/youtube/downloads/{}.mp4", tube_id.clone())).expect("Unable to read file");
 
 
@@ -97,12 +104,17 @@ pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Reques
         file.storage_location_oid = format!("SQL");
         file.save()?;
 
+        publish_progress(&current_session, &job, 100, "done");
 
-        let mut notify = crate::sam::memory::Notification::new();
-        notify.message = format!("{}.mp4 finished downloading!", tube_id.clone());
-        notify.human_oid = current_session.human_oid;
-        notify.sid = current_session.sid;
-        notify.save()?;
+        // notifications picks this up and tells the owner the download finished
+        let mut live_file = file.clone();
+        live_file.file_data = None;
+        crate::sam::services::bus::publish(crate::sam::services::bus::Event::FileStored{
+            file: live_file,
+            human_oid: Some(current_session.human_oid.clone()),
+            sid: Some(current_session.sid.clone()),
+            source: format!("youtube"),
+        });
 
         let response = Response::text("done");
         return Ok(response);
@@ -140,4 +152,13 @@ pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Reques
     }
 
     return Ok(Response::empty_404());
-}
\ No newline at end of file
+}
+
+fn publish_progress(current_session: &crate::sam::memory::WebSessions, job: &str, progress: i64, status: &str){
+    crate::sam::services::bus::publish(crate::sam::services::bus::Event::JobProgress{
+        job: job.to_string(),
+        progress: progress,
+        status: status.to_string(),
+        human_oid: Some(current_session.human_oid.clone()),
+    });
+}
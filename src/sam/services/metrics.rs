@@ -0,0 +1,62 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// metrics.rs counts the events flowing through the internal bus
+// so the web ui can show what the house has been up to since boot.
+
+use rouille::Request;
+use rouille::Response;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EventMetric {
+    pub count: u64,
+    pub last_seen: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Metrics {
+    pub started_at: i64,
+    pub events: BTreeMap<String, EventMetric>,
+}
+
+fn metrics() -> &'static Mutex<Metrics> {
+    static METRICS: OnceLock<Mutex<Metrics>> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        Mutex::new(Metrics{
+            started_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+            events: BTreeMap::new(),
+        })
+    })
+}
+
+pub fn init(){
+    crate::sam::services::bus::subscribe("metrics", |event| record(event));
+}
+
+pub fn record(event: &crate::sam::services::bus::Event){
+    let mut metrics = metrics().lock().unwrap();
+    let metric = metrics.events.entry(event.name().to_string()).or_insert(EventMetric::default());
+    metric.count = metric.count + 1;
+    metric.last_seen = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+}
+
+pub fn snapshot() -> Metrics {
+    return metrics().lock().unwrap().clone();
+}
+
+pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+    if request.url() == "/api/services/metrics" && request.method() == "GET" {
+        return Ok(Response::json(&snapshot()));
+    }
+    return Ok(Response::empty_404());
+}
@@ -0,0 +1,668 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// mqtt.rs bridges the event bus to an MQTT broker so other home software
+// can follow and control sam. It's configured by a Service row with the
+// identifier mqtt: endpoint is the broker (mqtt://host:1883), key the client
+// id and username/password the broker login. Service settings topic_prefix
+// (sam), discovery_prefix (homeassistant) and discovery (true) are optional.
+//
+// Published (prefix/...):
+//   status                           online/offline, retained, offline is the will
+//   observations/{type}              observation summaries
+//   things/{oid}/state               the full ThingState json, retained
+//   things/{oid}/ha_state            the same state in home assistant's light schema, retained
+//   things/{oid}/availability        online/offline, retained
+//   things/{oid}/command/result      what a command returned
+//   notifications/{human_oid}        notifications
+//   presence/{human_oid}             home or away, retained
+// Subscribed:
+//   things/{oid}/command             a json ThingCommand
+//   things/{oid}/set                 home assistant light json ({"state": "ON", "brightness": 128})
+//   tts/say                          text to speak
+//   automations/{oid}/run            runs an automation, the payload is passed as the event
+//   {discovery_prefix}/status        discovery is republished when home assistant comes online
+
+pub mod client;
+pub mod packet;
+
+use crate::sam::memory::{Service, Thing};
+use crate::sam::services::mqtt::client::{MqttClient, MqttOptions};
+use crate::sam::services::mqtt::packet::{topic_matches, Packet, Will};
+use crate::sam::services::things::{Capability, ThingCommand};
+use rouille::Request;
+use rouille::Response;
+use serde::{Serialize, Deserialize};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MqttConfig {
+    pub endpoint: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic_prefix: String,
+    pub discovery_prefix: String,
+    pub discovery: bool,
+}
+impl MqttConfig {
+    pub fn from_service(service: &Service) -> MqttConfig {
+        let setting = |tag: &str| service.settings.iter().find(|s| s.tag == tag).map(|s| s.value.trim().to_string()).filter(|v| v.len() > 0);
+        return MqttConfig{
+            endpoint: if service.endpoint.trim().len() > 0 { service.endpoint.clone() } else { format!("localhost:1883") },
+            client_id: if service.key.trim().len() > 0 { service.key.clone() } else { format!("sam") },
+            username: Some(service.username.clone()).filter(|u| u.len() > 0),
+            password: Some(service.password.clone()).filter(|p| p.len() > 0),
+            topic_prefix: setting("topic_prefix").unwrap_or(format!("sam")).trim_end_matches("/").to_string(),
+            discovery_prefix: setting("discovery_prefix").unwrap_or(format!("homeassistant")).trim_end_matches("/").to_string(),
+            discovery: setting("discovery").map(|d| d != "false").unwrap_or(true),
+        };
+    }
+
+    pub fn options(&self) -> MqttOptions {
+        let mut options = MqttOptions::from_endpoint(&self.endpoint, &self.client_id);
+        options.username = self.username.clone();
+        options.password = self.password.clone();
+        options.will = Some(Will{
+            topic: format!("{}/status", self.topic_prefix),
+            payload: b"offline".to_vec(),
+            retain: true,
+        });
+        return options;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MqttStatus {
+    pub configured: bool,
+    pub connected: bool,
+    pub endpoint: Option<String>,
+    pub connected_at: Option<i64>,
+    pub last_error: Option<String>,
+    pub published: u64,
+    pub received: u64,
+}
+
+fn status() -> &'static Mutex<MqttStatus> {
+    static STATUS: OnceLock<Mutex<MqttStatus>> = OnceLock::new();
+    STATUS.get_or_init(|| Mutex::new(MqttStatus::default()))
+}
+
+fn now() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+}
+
+pub fn service() -> Result<Option<Service>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("mqtt")));
+    pg_query.query_coulmns.push(format!("identifier ="));
+    let services = Service::select(None, None, None, Some(pg_query))?;
+    return Ok(services.first().cloned());
+}
+
+// Keeps a connection to the broker, reconnecting after RETRY_INTERVAL when it drops
+pub fn init(){
+    let bridge_thread = thread::Builder::new().name(format!("mqtt")).spawn(move || {
+        loop {
+            let config = match service() {
+                Ok(Some(service)) => MqttConfig::from_service(&service),
+                Ok(None) => {
+                    status().lock().unwrap().configured = false;
+                    thread::sleep(RETRY_INTERVAL);
+                    continue;
+                },
+                Err(e) => {
+                    log::error!("{}", e);
+                    thread::sleep(RETRY_INTERVAL);
+                    continue;
+                }
+            };
+
+            {
+                let mut status = status().lock().unwrap();
+                status.configured = true;
+                status.endpoint = Some(config.endpoint.clone());
+            }
+
+            match run(&config) {
+                Ok(_) => {},
+                Err(e) => {
+                    log::error!("mqtt bridge to {}: {}", config.endpoint, e);
+                    status().lock().unwrap().last_error = Some(format!("{}", e));
+                }
+            }
+            status().lock().unwrap().connected = false;
+            thread::sleep(RETRY_INTERVAL);
+        }
+    });
+    match bridge_thread {
+        Ok(_) => {
+            log::info!("mqtt bridge started successfully");
+        },
+        Err(e) => {
+            log::error!("failed to initialize mqtt bridge: {}", e);
+        }
+    }
+}
+
+pub fn current_status() -> MqttStatus {
+    return status().lock().unwrap().clone();
+}
+
+// One connection, returns when it drops
+fn run(config: &MqttConfig) -> Result<(), crate::sam::services::Error> {
+    let client = MqttClient::connect(&config.options())?;
+    log::info!("mqtt bridge connected to {}", config.endpoint);
+    {
+        let mut status = status().lock().unwrap();
+        status.connected = true;
+        status.connected_at = Some(now());
+        status.last_error = None;
+    }
+
+    // Subscribe to the bus before announcing so nothing published in between is missed
+    let events = crate::sam::services::bus::channel("mqtt");
+
+    client.subscribe(&command_topics(config))?;
+    publish(&client, &format!("{}/status", config.topic_prefix), b"online", true)?;
+    if config.discovery {
+        publish_discovery(&client, config)?;
+    }
+    publish_states(&client, config)?;
+
+    loop {
+        match client.next(Duration::from_millis(250))? {
+            Some(Packet::Publish{topic, payload, ..}) => {
+                status().lock().unwrap().received += 1;
+                handle_message(&client, config, &topic, &payload);
+            },
+            Some(Packet::SubAck{codes, ..}) => {
+                if codes.iter().any(|c| *c == 0x80) {
+                    log::error!("mqtt broker refused some of sam's subscriptions");
+                }
+            },
+            _ => {}
+        }
+
+        loop {
+            match events.try_recv() {
+                Ok(event) => {
+                    for (topic, payload, retain) in event_messages(config, &event) {
+                        publish(&client, &topic, payload.as_bytes(), retain)?;
+                    }
+                    match &event {
+                        crate::sam::services::bus::Event::ThingDiscovered{thing} => {
+                            if config.discovery {
+                                for (topic, payload) in discovery_messages(config, thing) {
+                                    publish(&client, &topic, payload.as_bytes(), true)?;
+                                }
+                            }
+                        },
+                        _ => {}
+                    }
+                },
+                Err(_) => break
+            }
+        }
+
+        client.keep_alive()?;
+    }
+}
+
+fn publish(client: &MqttClient, topic: &str, payload: &[u8], retain: bool) -> Result<(), crate::sam::services::Error> {
+    client.publish(topic, payload, retain)?;
+    status().lock().unwrap().published += 1;
+    return Ok(());
+}
+
+pub fn command_topics(config: &MqttConfig) -> Vec<String> {
+    return vec![
+        format!("{}/things/+/command", config.topic_prefix),
+        format!("{}/things/+/set", config.topic_prefix),
+        format!("{}/tts/say", config.topic_prefix),
+        format!("{}/automations/+/run", config.topic_prefix),
+        format!("{}/status", config.discovery_prefix),
+    ];
+}
+
+// Maps bus events to (topic, payload, retain)
+pub fn event_messages(config: &MqttConfig, event: &crate::sam::services::bus::Event) -> Vec<(String, String, bool)> {
+    let prefix = &config.topic_prefix;
+    let mut messages: Vec<(String, String, bool)> = Vec::new();
+    match event {
+        crate::sam::services::bus::Event::ObservationRecorded{observation, room_oid} => {
+            let summary = serde_json::json!({
+                "oid": observation.oid,
+                "timestamp": observation.timestamp,
+                "observation_type": format!("{}", observation.observation_type),
+                "objects": observation.observation_objects,
+                "humans": observation.observation_humans.iter().map(|h| serde_json::json!({"oid": h.oid, "name": h.name})).collect::<Vec<serde_json::Value>>(),
+                "notes": observation.observation_notes,
+                "thing_oid": observation.thing.as_ref().map(|t| t.oid.clone()),
+                "room_oid": room_oid,
+            });
+            messages.push((format!("{}/observations/{}", prefix, format!("{}", observation.observation_type).to_lowercase()), summary.to_string(), false));
+        },
+        crate::sam::services::bus::Event::ThingStateChanged{thing_oid: Some(thing_oid), state, ..} => {
+            messages.push((format!("{}/things/{}/state", prefix, thing_oid), state.to_string(), true));
+            messages.push((format!("{}/things/{}/ha_state", prefix, thing_oid), ha_state(state).to_string(), true));
+            match state.get("online").and_then(|o| o.as_bool()) {
+                Some(online) => messages.push((format!("{}/things/{}/availability", prefix, thing_oid), availability(online), true)),
+                None => {}
+            }
+        },
+        crate::sam::services::bus::Event::NotificationCreated{notification} => {
+            messages.push((format!("{}/notifications/{}", prefix, notification.human_oid), serde_json::to_string(notification).unwrap_or_default(), false));
+        },
+        crate::sam::services::bus::Event::PresenceChanged{human, home, ..} => {
+            let state = if *home { "home" } else { "away" };
+            messages.push((format!("{}/presence/{}", prefix, human.oid), state.to_string(), true));
+        },
+        _ => {}
+    }
+    return messages;
+}
+
+fn availability(online: bool) -> String {
+    return if online { format!("online") } else { format!("offline") };
+}
+
+// ThingState json in home assistant's json light schema
+pub fn ha_state(state: &serde_json::Value) -> serde_json::Value {
+    let mut ha = serde_json::json!({});
+    match state.get("power").and_then(|p| p.as_str()) {
+        Some(power) => ha["state"] = serde_json::Value::String(if power == "on" { format!("ON") } else { format!("OFF") }),
+        None => {}
+    }
+    match state.get("brightness").and_then(|b| b.as_f64()) {
+        Some(brightness) => ha["brightness"] = serde_json::json!((brightness * 255.0).round() as i64),
+        None => {}
+    }
+    // Colors are "hue:120 saturation:0.5"
+    match state.get("color").and_then(|c| c.as_str()) {
+        Some(color) => {
+            let part = |name: &str| color.split_whitespace().find_map(|p| p.strip_prefix(&format!("{}:", name)).and_then(|v| v.parse::<f64>().ok()));
+            match (part("hue"), part("saturation")) {
+                (Some(hue), Some(saturation)) => {
+                    ha["color_mode"] = serde_json::json!("hs");
+                    ha["color"] = serde_json::json!({"h": hue, "s": saturation * 100.0});
+                },
+                _ => {}
+            }
+        },
+        None => {}
+    }
+    return ha;
+}
+
+// Home assistant light json from things/{oid}/set as thing commands
+pub fn ha_commands(payload: &serde_json::Value) -> Vec<ThingCommand> {
+    let duration = payload.get("transition").and_then(|t| t.as_f64());
+    let mut commands: Vec<ThingCommand> = Vec::new();
+    if payload.get("state").and_then(|s| s.as_str()).map(|s| s.eq_ignore_ascii_case("OFF")).unwrap_or(false) {
+        commands.push(ThingCommand::TurnOff{ duration });
+        return commands;
+    }
+    match payload.get("color") {
+        Some(color) => {
+            match (color.get("h").and_then(|h| h.as_f64()), color.get("s").and_then(|s| s.as_f64())) {
+                (Some(hue), Some(saturation)) => commands.push(ThingCommand::SetColor{ color: format!("hue:{} saturation:{}", hue, saturation / 100.0), duration }),
+                _ => {}
+            }
+        },
+        None => {}
+    }
+    match payload.get("color_temp").and_then(|c| c.as_f64()) {
+        Some(mireds) if mireds > 0.0 => commands.push(ThingCommand::SetColor{ color: format!("kelvin:{}", (1_000_000.0 / mireds).round() as i64), duration }),
+        _ => {}
+    }
+    match payload.get("brightness").and_then(|b| b.as_f64()) {
+        Some(brightness) => commands.push(ThingCommand::SetBrightness{ brightness: (brightness / 255.0).max(0.0).min(1.0), duration }),
+        None => {}
+    }
+    if commands.len() == 0 {
+        commands.push(ThingCommand::TurnOn{ duration });
+    }
+    return commands;
+}
+
+fn handle_message(client: &MqttClient, config: &MqttConfig, topic: &str, payload: &[u8]) {
+    let prefix = &config.topic_prefix;
+    let text = String::from_utf8_lossy(payload).to_string();
+    let levels: Vec<&str> = topic.split("/").collect();
+    let prefix_levels = prefix.split("/").count();
+
+    if topic == format!("{}/status", config.discovery_prefix) {
+        if text == "online" && config.discovery {
+            match publish_discovery(client, config).and_then(|_| publish_states(client, config)) {
+                Ok(_) => {},
+                Err(e) => log::error!("failed to republish mqtt discovery: {}", e)
+            }
+        }
+        return;
+    }
+
+    if topic == format!("{}/tts/say", prefix) {
+        match crate::sam::services::tts::speak(text) {
+            Ok(_) => {},
+            Err(e) => log::error!("mqtt tts failed: {}", e)
+        }
+        return;
+    }
+
+    if topic_matches(&format!("{}/automations/+/run", prefix), topic) {
+        let automation_oid = levels[prefix_levels + 1];
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(automation_oid.to_string()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        match crate::sam::memory::Automation::select(None, None, None, Some(pg_query)) {
+            Ok(automations) => {
+                match automations.first() {
+                    Some(automation) => {
+                        let event_json = serde_json::from_str(&text).unwrap_or(serde_json::json!({"payload": text}));
+                        let log = crate::sam::services::automations::execute(automation, "Mqtt", event_json, None, &chrono::Local::now(), false);
+                        let _ = publish(client, &format!("{}/automations/{}/result", prefix, automation_oid), serde_json::to_string(&log).unwrap_or_default().as_bytes(), false);
+                    },
+                    None => log::error!("mqtt asked to run unknown automation {}", automation_oid)
+                }
+            },
+            Err(e) => log::error!("{}", e)
+        }
+        return;
+    }
+
+    let is_command = topic_matches(&format!("{}/things/+/command", prefix), topic);
+    let is_set = topic_matches(&format!("{}/things/+/set", prefix), topic);
+    if !is_command && !is_set {
+        return;
+    }
+
+    let thing_oid = levels[prefix_levels + 1];
+    let thing = match crate::sam::services::things::find(thing_oid) {
+        Ok(Some(thing)) => thing,
+        Ok(None) => {
+            log::error!("mqtt command for unknown thing {}", thing_oid);
+            return;
+        },
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
+
+    let commands: Vec<ThingCommand> = if is_command {
+        match serde_json::from_str(&text) {
+            Ok(command) => vec![command],
+            Err(e) => {
+                let _ = publish(client, &format!("{}/things/{}/command/result", prefix, thing.oid), serde_json::json!({"error": format!("invalid command: {}", e)}).to_string().as_bytes(), false);
+                return;
+            }
+        }
+    } else {
+        match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(payload) => ha_commands(&payload),
+            // Plain ON / OFF payloads
+            Err(_) => ha_commands(&serde_json::json!({"state": text.trim()}))
+        }
+    };
+
+    for command in commands {
+        let result = match crate::sam::services::things::command(&thing, &command) {
+            Ok(result) => serde_json::json!({"command": command, "result": result}),
+            Err(e) => serde_json::json!({"command": command, "error": format!("{}", e)})
+        };
+        let _ = publish(client, &format!("{}/things/{}/command/result", prefix, thing.oid), result.to_string().as_bytes(), false);
+    }
+}
+
+// Home assistant discovery configs for a thing, (topic, payload)
+pub fn discovery_messages(config: &MqttConfig, thing: &Thing) -> Vec<(String, String)> {
+    let prefix = &config.topic_prefix;
+    let capabilities = crate::sam::services::things::capabilities(thing);
+    let device = serde_json::json!({
+        "identifiers": [format!("sam_{}", thing.oid)],
+        "name": thing.name,
+        "manufacturer": "Open Sam Foundation",
+        "model": thing.thing_type,
+    });
+    let availability_topic = format!("{}/things/{}/availability", prefix, thing.oid);
+    let mut messages: Vec<(String, String)> = Vec::new();
+
+    if capabilities.contains(&Capability::Switchable) {
+        let mut modes: Vec<&str> = Vec::new();
+        if capabilities.contains(&Capability::Color) {
+            modes.push("hs");
+        } else if capabilities.contains(&Capability::Dimmable) {
+            modes.push("brightness");
+        } else {
+            modes.push("onoff");
+        }
+        let light = serde_json::json!({
+            "name": serde_json::Value::Null,
+            "unique_id": format!("sam_{}_light", thing.oid),
+            "schema": "json",
+            "state_topic": format!("{}/things/{}/ha_state", prefix, thing.oid),
+            "command_topic": format!("{}/things/{}/set", prefix, thing.oid),
+            "availability_topic": availability_topic,
+            "brightness": capabilities.contains(&Capability::Dimmable),
+            "supported_color_modes": modes,
+            "device": device,
+        });
+        messages.push((format!("{}/light/sam_{}/config", config.discovery_prefix, thing.oid), light.to_string()));
+    }
+
+    let connectivity = serde_json::json!({
+        "name": "Online",
+        "unique_id": format!("sam_{}_online", thing.oid),
+        "device_class": "connectivity",
+        "state_topic": availability_topic,
+        "payload_on": "online",
+        "payload_off": "offline",
+        "device": device,
+    });
+    messages.push((format!("{}/binary_sensor/sam_{}_online/config", config.discovery_prefix, thing.oid), connectivity.to_string()));
+
+    return messages;
+}
+
+fn publish_discovery(client: &MqttClient, config: &MqttConfig) -> Result<(), crate::sam::services::Error> {
+    for thing in Thing::select(None, None, None, None)? {
+        for (topic, payload) in discovery_messages(config, &thing) {
+            publish(client, &topic, payload.as_bytes(), true)?;
+        }
+    }
+    return Ok(());
+}
+
+// Retained state for every thing sam already knows about
+fn publish_states(client: &MqttClient, config: &MqttConfig) -> Result<(), crate::sam::services::Error> {
+    for thing in Thing::select(None, None, None, None)? {
+        let prefix = &config.topic_prefix;
+        let online = match crate::sam::services::things::cache::get(&thing.oid) {
+            Some(cached) => {
+                let state = serde_json::to_value(&cached.state).unwrap_or(serde_json::Value::Null);
+                publish(client, &format!("{}/things/{}/state", prefix, thing.oid), state.to_string().as_bytes(), true)?;
+                publish(client, &format!("{}/things/{}/ha_state", prefix, thing.oid), ha_state(&state).to_string().as_bytes(), true)?;
+                cached.state.online
+            },
+            None => thing.online
+        };
+        publish(client, &format!("{}/things/{}/availability", prefix, thing.oid), availability(online).as_bytes(), true)?;
+    }
+    return Ok(());
+}
+
+// GET    /api/services/mqtt/status
+pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+    if request.url() == "/api/services/mqtt/status" && request.method() == "GET" {
+        return Ok(Response::json(&current_status()));
+    }
+
+    return Ok(Response::empty_404());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MqttConfig {
+        return MqttConfig{
+            endpoint: format!("localhost:1883"),
+            client_id: format!("sam_test"),
+            username: None,
+            password: None,
+            topic_prefix: format!("sam"),
+            discovery_prefix: format!("homeassistant"),
+            discovery: true,
+        };
+    }
+
+    fn commands(payload: serde_json::Value) -> serde_json::Value {
+        return serde_json::to_value(ha_commands(&payload)).unwrap();
+    }
+
+    #[test]
+    fn ha_state_maps_power_brightness_and_color() {
+        let state = serde_json::json!({"power": "on", "brightness": 0.5, "color": "hue:120 saturation:0.25"});
+        assert_eq!(ha_state(&state), serde_json::json!({
+            "state": "ON",
+            "brightness": 128,
+            "color_mode": "hs",
+            "color": {"h": 120.0, "s": 25.0},
+        }));
+        assert_eq!(ha_state(&serde_json::json!({"power": "off", "color": "kelvin:2700"})), serde_json::json!({"state": "OFF"}));
+        assert_eq!(ha_state(&serde_json::json!({})), serde_json::json!({}));
+    }
+
+    #[test]
+    fn ha_off_ignores_everything_else() {
+        assert_eq!(commands(serde_json::json!({"state": "OFF", "brightness": 10, "transition": 2})), serde_json::json!([
+            {"command": "turn_off", "duration": 2.0},
+        ]));
+    }
+
+    #[test]
+    fn ha_set_becomes_thing_commands() {
+        assert_eq!(commands(serde_json::json!({"state": "ON"})), serde_json::json!([
+            {"command": "turn_on", "duration": null},
+        ]));
+        assert_eq!(commands(serde_json::json!({"state": "ON", "color": {"h": 240, "s": 50}, "brightness": 255})), serde_json::json!([
+            {"command": "set_color", "color": "hue:240 saturation:0.5", "duration": null},
+            {"command": "set_brightness", "brightness": 1.0, "duration": null},
+        ]));
+        assert_eq!(commands(serde_json::json!({"color_temp": 250, "brightness": 300})), serde_json::json!([
+            {"command": "set_color", "color": "kelvin:4000", "duration": null},
+            {"command": "set_brightness", "brightness": 1.0, "duration": null},
+        ]));
+    }
+
+    #[test]
+    fn thing_state_is_published_retained() {
+        let event = crate::sam::services::bus::Event::ThingStateChanged{
+            thing_oid: Some(format!("lamp")),
+            room_oid: None,
+            selector: format!("id:lamp"),
+            state: serde_json::json!({"power": "on", "online": false}),
+            previous: serde_json::Value::Null,
+        };
+        assert_eq!(event_messages(&config(), &event), vec![
+            (format!("sam/things/lamp/state"), format!("{}", serde_json::json!({"power": "on", "online": false})), true),
+            (format!("sam/things/lamp/ha_state"), format!("{}", serde_json::json!({"state": "ON"})), true),
+            (format!("sam/things/lamp/availability"), format!("offline"), true),
+        ]);
+
+        // Selector changes without a thing have nowhere to go
+        let event = crate::sam::services::bus::Event::ThingStateChanged{
+            thing_oid: None,
+            room_oid: None,
+            selector: format!("all"),
+            state: serde_json::json!({"power": "on"}),
+            previous: serde_json::Value::Null,
+        };
+        assert_eq!(event_messages(&config(), &event).len(), 0);
+    }
+
+    #[test]
+    fn observations_carry_human_names_only() {
+        let mut human = crate::sam::memory::Human::new();
+        human.name = format!("Ada");
+        human.password = Some(format!("secret"));
+        let mut observation = crate::sam::memory::Observation::new();
+        observation.observation_type = crate::sam::memory::ObservationType::HEARD;
+        observation.observation_humans = vec![human.clone()];
+        let event = crate::sam::services::bus::Event::ObservationRecorded{ observation, room_oid: Some(format!("kitchen")) };
+
+        let messages = event_messages(&config(), &event);
+        assert_eq!(messages.len(), 1);
+        let (topic, payload, retain) = &messages[0];
+        assert_eq!(topic, "sam/observations/heard");
+        assert!(!retain);
+        let payload: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(payload["humans"], serde_json::json!([{"oid": human.oid, "name": "Ada"}]));
+        assert_eq!(payload["room_oid"], serde_json::json!("kitchen"));
+    }
+
+    #[test]
+    fn presence_and_notifications_go_to_the_human() {
+        let human = crate::sam::memory::Human::new();
+        let event = crate::sam::services::bus::Event::PresenceChanged{ human: human.clone(), home: true, device_oid: None, last_seen_at: None };
+        assert_eq!(event_messages(&config(), &event), vec![(format!("sam/presence/{}", human.oid), format!("home"), true)]);
+
+        let mut notification = crate::sam::memory::Notification::new();
+        notification.human_oid = human.oid.clone();
+        notification.message = format!("hello");
+        let event = crate::sam::services::bus::Event::NotificationCreated{ notification: notification.clone() };
+        assert_eq!(event_messages(&config(), &event), vec![
+            (format!("sam/notifications/{}", human.oid), serde_json::to_string(&notification).unwrap(), false),
+        ]);
+    }
+
+    fn wait_for<F>(client: &MqttClient, matches: F) -> bool where F: Fn(&Packet) -> bool {
+        for _ in 0..20 {
+            match client.next(Duration::from_millis(250)) {
+                Ok(Some(packet)) => {
+                    if matches(&packet) {
+                        return true;
+                    }
+                },
+                Ok(None) => {},
+                Err(_) => return false
+            }
+        }
+        return false;
+    }
+
+    // Needs a broker, e.g. mosquitto on localhost:1883: cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn round_trip_through_a_local_broker() {
+        let config = config();
+        let mut options = config.options();
+        options.will = None;
+        let client = MqttClient::connect(&options).unwrap();
+
+        let topic = format!("{}/test/{}", config.topic_prefix, now());
+        let packet_id = client.subscribe(&vec![topic.clone()]).unwrap();
+        assert!(wait_for(&client, |packet| match packet {
+            Packet::SubAck{packet_id: id, codes} => *id == packet_id && codes.iter().all(|c| *c != 0x80),
+            _ => false
+        }));
+
+        client.publish(&topic, b"ping", false).unwrap();
+        assert!(wait_for(&client, |packet| match packet {
+            Packet::Publish{topic: t, payload, ..} => *t == topic && payload == b"ping",
+            _ => false
+        }));
+        client.disconnect();
+    }
+}
@@ -0,0 +1,190 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// client.rs is a small blocking MQTT 3.1.1 client. Publishes are sent at
+// qos 0, subscriptions are made at qos 1 and incoming qos 1 messages are
+// acknowledged by the reader thread before they're handed over.
+
+use crate::sam::services::mqtt::packet::{encode, read, Packet, Will};
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct MqttOptions {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub keep_alive: u16,
+    pub will: Option<Will>,
+}
+impl MqttOptions {
+    // Accepts host, host:port, mqtt://host or mqtt://host:port
+    pub fn from_endpoint(endpoint: &str, client_id: &str) -> MqttOptions {
+        let address = endpoint.trim().trim_start_matches("mqtt://").trim_start_matches("tcp://").trim_end_matches("/");
+        let (host, port) = match address.rsplit_once(":") {
+            Some((host, port)) => (host.to_string(), port.parse::<u16>().unwrap_or(1883)),
+            None => (address.to_string(), 1883)
+        };
+        return MqttOptions{
+            host: if host.len() > 0 { host } else { format!("localhost") },
+            port: port,
+            client_id: client_id.to_string(),
+            username: None,
+            password: None,
+            keep_alive: 30,
+            will: None,
+        };
+    }
+}
+
+pub struct MqttClient {
+    writer: Arc<Mutex<TcpStream>>,
+    incoming: Mutex<mpsc::Receiver<Result<Packet, String>>>,
+    next_packet_id: AtomicU16,
+    last_sent: Mutex<Instant>,
+    keep_alive: Duration,
+}
+impl MqttClient {
+    pub fn connect(options: &MqttOptions) -> Result<MqttClient, crate::sam::services::Error> {
+        let address = match format!("{}:{}", options.host, options.port).to_socket_addrs()?.next() {
+            Some(address) => address,
+            None => return Err(format!("can't resolve {}", options.host).into())
+        };
+        let mut stream = TcpStream::connect_timeout(&address, Duration::from_secs(5))?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        stream.write_all(&encode(&Packet::Connect{
+            client_id: options.client_id.clone(),
+            username: options.username.clone(),
+            password: options.password.clone(),
+            keep_alive: options.keep_alive,
+            will: options.will.clone(),
+        }))?;
+
+        match read(&mut stream)? {
+            Some(Packet::ConnAck{code, ..}) => {
+                if code != 0 {
+                    return Err(format!("broker refused the connection: {}", connack_reason(code)).into());
+                }
+            },
+            other => return Err(format!("expected CONNACK, got {:?}", other).into())
+        }
+        stream.set_read_timeout(None)?;
+
+        let writer = Arc::new(Mutex::new(stream.try_clone()?));
+        let (tx, rx) = mpsc::channel();
+        let ack_writer = writer.clone();
+        thread::Builder::new().name(format!("mqtt_reader")).spawn(move || {
+            loop {
+                match read(&mut stream) {
+                    Ok(Some(packet)) => {
+                        match &packet {
+                            Packet::Publish{qos, packet_id: Some(packet_id), ..} if *qos > 0 => {
+                                let _ = ack_writer.lock().unwrap().write_all(&encode(&Packet::PubAck{ packet_id: *packet_id }));
+                            },
+                            _ => {}
+                        }
+                        if tx.send(Ok(packet)).is_err() {
+                            return;
+                        }
+                    },
+                    Ok(None) => {},
+                    Err(e) => {
+                        let _ = tx.send(Err(format!("{}", e)));
+                        return;
+                    }
+                }
+            }
+        })?;
+
+        return Ok(MqttClient{
+            writer: writer,
+            incoming: Mutex::new(rx),
+            next_packet_id: AtomicU16::new(1),
+            last_sent: Mutex::new(Instant::now()),
+            keep_alive: Duration::from_secs(options.keep_alive as u64),
+        });
+    }
+
+    fn send(&self, packet: &Packet) -> Result<(), crate::sam::services::Error> {
+        self.writer.lock().unwrap().write_all(&encode(packet))?;
+        *self.last_sent.lock().unwrap() = Instant::now();
+        return Ok(());
+    }
+
+    fn packet_id(&self) -> u16 {
+        // 0 isn't a valid packet id
+        let id = self.next_packet_id.fetch_add(1, Ordering::SeqCst);
+        if id == 0 {
+            return self.next_packet_id.fetch_add(1, Ordering::SeqCst);
+        }
+        return id;
+    }
+
+    pub fn publish(&self, topic: &str, payload: &[u8], retain: bool) -> Result<(), crate::sam::services::Error> {
+        return self.send(&Packet::Publish{
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos: 0,
+            retain: retain,
+            packet_id: None,
+        });
+    }
+
+    // The SUBACK arrives through next() like any other packet
+    pub fn subscribe(&self, topics: &[String]) -> Result<u16, crate::sam::services::Error> {
+        let packet_id = self.packet_id();
+        self.send(&Packet::Subscribe{
+            packet_id: packet_id,
+            topics: topics.iter().map(|t| (t.clone(), 1)).collect(),
+        })?;
+        return Ok(packet_id);
+    }
+
+    // Waits up to timeout for a packet, Ok(None) when nothing arrived
+    pub fn next(&self, timeout: Duration) -> Result<Option<Packet>, crate::sam::services::Error> {
+        match self.incoming.lock().unwrap().recv_timeout(timeout) {
+            Ok(Ok(packet)) => return Ok(Some(packet)),
+            Ok(Err(e)) => return Err(format!("mqtt connection lost: {}", e).into()),
+            Err(mpsc::RecvTimeoutError::Timeout) => return Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(format!("mqtt connection closed").into())
+        }
+    }
+
+    // Sends a PINGREQ when nothing else has been sent for half the keep alive
+    pub fn keep_alive(&self) -> Result<(), crate::sam::services::Error> {
+        let idle = self.last_sent.lock().unwrap().elapsed();
+        if self.keep_alive.as_secs() > 0 && idle >= self.keep_alive / 2 {
+            return self.send(&Packet::PingReq);
+        }
+        return Ok(());
+    }
+
+    pub fn disconnect(&self) {
+        let _ = self.send(&Packet::Disconnect);
+        let _ = self.writer.lock().unwrap().shutdown(std::net::Shutdown::Both);
+    }
+}
+
+pub fn connack_reason(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown reason"
+    }
+}
@@ -0,0 +1,345 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// packet.rs encodes and decodes the MQTT 3.1.1 packets sam needs.
+// http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/mqtt-v3.1.1.html
+
+use std::io::Read;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Connect {
+        client_id: String,
+        username: Option<String>,
+        password: Option<String>,
+        keep_alive: u16,
+        will: Option<Will>,
+    },
+    ConnAck { session_present: bool, code: u8 },
+    // packet_id is only set for qos 1
+    Publish { topic: String, payload: Vec<u8>, qos: u8, retain: bool, packet_id: Option<u16> },
+    PubAck { packet_id: u16 },
+    Subscribe { packet_id: u16, topics: Vec<(String, u8)> },
+    SubAck { packet_id: u16, codes: Vec<u8> },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+fn push_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn push_bytes(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+fn push_length(buf: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length = length / 128;
+        if length > 0 {
+            byte = byte | 0x80;
+        }
+        buf.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+pub fn encode(packet: &Packet) -> Vec<u8> {
+    let mut body: Vec<u8> = Vec::new();
+    let header: u8 = match packet {
+        Packet::Connect{client_id, username, password, keep_alive, will} => {
+            push_string(&mut body, "MQTT");
+            body.push(4);
+            let mut flags: u8 = 0x02; // clean session
+            match will {
+                Some(will) => {
+                    flags = flags | 0x04;
+                    if will.retain {
+                        flags = flags | 0x20;
+                    }
+                },
+                None => {}
+            }
+            if username.is_some() {
+                flags = flags | 0x80;
+            }
+            if password.is_some() {
+                flags = flags | 0x40;
+            }
+            body.push(flags);
+            body.extend_from_slice(&keep_alive.to_be_bytes());
+            push_string(&mut body, client_id);
+            match will {
+                Some(will) => {
+                    push_string(&mut body, &will.topic);
+                    push_bytes(&mut body, &will.payload);
+                },
+                None => {}
+            }
+            match username {
+                Some(username) => push_string(&mut body, username),
+                None => {}
+            }
+            match password {
+                Some(password) => push_string(&mut body, password),
+                None => {}
+            }
+            0x10
+        },
+        Packet::ConnAck{session_present, code} => {
+            body.push(if *session_present { 1 } else { 0 });
+            body.push(*code);
+            0x20
+        },
+        Packet::Publish{topic, payload, qos, retain, packet_id} => {
+            push_string(&mut body, topic);
+            if *qos > 0 {
+                body.extend_from_slice(&packet_id.unwrap_or(1).to_be_bytes());
+            }
+            body.extend_from_slice(payload);
+            0x30 | (qos << 1) | (if *retain { 1 } else { 0 })
+        },
+        Packet::PubAck{packet_id} => {
+            body.extend_from_slice(&packet_id.to_be_bytes());
+            0x40
+        },
+        Packet::Subscribe{packet_id, topics} => {
+            body.extend_from_slice(&packet_id.to_be_bytes());
+            for (topic, qos) in topics {
+                push_string(&mut body, topic);
+                body.push(*qos);
+            }
+            0x82
+        },
+        Packet::SubAck{packet_id, codes} => {
+            body.extend_from_slice(&packet_id.to_be_bytes());
+            body.extend_from_slice(codes);
+            0x90
+        },
+        Packet::PingReq => 0xC0,
+        Packet::PingResp => 0xD0,
+        Packet::Disconnect => 0xE0,
+    };
+
+    let mut buf: Vec<u8> = vec![header];
+    push_length(&mut buf, body.len());
+    buf.extend_from_slice(&body);
+    return buf;
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    position: usize,
+}
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let value = *self.buf.get(self.position)?;
+        self.position = self.position + 1;
+        return Some(value);
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        return Some(((self.u8()? as u16) << 8) | self.u8()? as u16);
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let length = self.u16()? as usize;
+        let value = self.buf.get(self.position..self.position + length)?.to_vec();
+        self.position = self.position + length;
+        return Some(value);
+    }
+
+    fn string(&mut self) -> Option<String> {
+        return String::from_utf8(self.bytes()?).ok();
+    }
+
+    fn rest(&mut self) -> Vec<u8> {
+        let value = self.buf[self.position.min(self.buf.len())..].to_vec();
+        self.position = self.buf.len();
+        return value;
+    }
+}
+
+// Decodes the body of a packet whose fixed header has already been read
+pub fn decode(header: u8, body: &[u8]) -> Option<Packet> {
+    let mut reader = Reader{ buf: body, position: 0 };
+    let packet = match header >> 4 {
+        1 => {
+            if reader.string()? != "MQTT" {
+                return None;
+            }
+            let _level = reader.u8()?;
+            let flags = reader.u8()?;
+            let keep_alive = reader.u16()?;
+            let client_id = reader.string()?;
+            let will = if flags & 0x04 > 0 {
+                Some(Will{ topic: reader.string()?, payload: reader.bytes()?, retain: flags & 0x20 > 0 })
+            } else {
+                None
+            };
+            let username = if flags & 0x80 > 0 { Some(reader.string()?) } else { None };
+            let password = if flags & 0x40 > 0 { Some(reader.string()?) } else { None };
+            Packet::Connect{ client_id, username, password, keep_alive, will }
+        },
+        2 => Packet::ConnAck{ session_present: reader.u8()? & 1 > 0, code: reader.u8()? },
+        3 => {
+            let qos = (header >> 1) & 0x03;
+            let topic = reader.string()?;
+            let packet_id = if qos > 0 { Some(reader.u16()?) } else { None };
+            Packet::Publish{ topic, payload: reader.rest(), qos, retain: header & 1 > 0, packet_id }
+        },
+        4 => Packet::PubAck{ packet_id: reader.u16()? },
+        8 => {
+            let packet_id = reader.u16()?;
+            let mut topics: Vec<(String, u8)> = Vec::new();
+            while reader.position < body.len() {
+                topics.push((reader.string()?, reader.u8()?));
+            }
+            Packet::Subscribe{ packet_id, topics }
+        },
+        9 => Packet::SubAck{ packet_id: reader.u16()?, codes: reader.rest() },
+        12 => Packet::PingReq,
+        13 => Packet::PingResp,
+        14 => Packet::Disconnect,
+        _ => return None
+    };
+    return Some(packet);
+}
+
+// Reads one packet, None means the packet type isn't one sam handles
+pub fn read<R: Read>(stream: &mut R) -> std::io::Result<Option<Packet>> {
+    let mut header = [0u8; 1];
+    stream.read_exact(&mut header)?;
+
+    let mut length: usize = 0;
+    let mut multiplier: usize = 1;
+    loop {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte)?;
+        length = length + (byte[0] & 0x7F) as usize * multiplier;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        multiplier = multiplier * 128;
+        if multiplier > 128 * 128 * 128 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed remaining length"));
+        }
+    }
+
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body)?;
+    return Ok(decode(header[0], &body));
+}
+
+// MQTT topic filter matching with + and # wildcards
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let filter: Vec<&str> = filter.split("/").collect();
+    let topic: Vec<&str> = topic.split("/").collect();
+    for (i, level) in filter.iter().enumerate() {
+        if *level == "#" {
+            return true;
+        }
+        match topic.get(i) {
+            Some(part) => {
+                if *level != "+" && level != part {
+                    return false;
+                }
+            },
+            None => return false
+        }
+    }
+    return filter.len() == topic.len();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip(packet: Packet) {
+        let bytes = encode(&packet);
+        assert_eq!(read(&mut Cursor::new(bytes)).unwrap(), Some(packet));
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        round_trip(Packet::Connect{
+            client_id: format!("sam"),
+            username: Some(format!("user")),
+            password: Some(format!("pass")),
+            keep_alive: 30,
+            will: Some(Will{ topic: format!("sam/status"), payload: b"offline".to_vec(), retain: true }),
+        });
+        round_trip(Packet::Connect{ client_id: format!("sam"), username: None, password: None, keep_alive: 0, will: None });
+        round_trip(Packet::ConnAck{ session_present: true, code: 0 });
+        round_trip(Packet::Publish{ topic: format!("sam/tts/say"), payload: b"hello".to_vec(), qos: 0, retain: true, packet_id: None });
+        round_trip(Packet::Publish{ topic: format!("sam/things/a/set"), payload: b"ON".to_vec(), qos: 1, retain: false, packet_id: Some(7) });
+        round_trip(Packet::PubAck{ packet_id: 7 });
+        round_trip(Packet::Subscribe{ packet_id: 3, topics: vec![(format!("sam/things/+/command"), 1), (format!("homeassistant/status"), 1)] });
+        round_trip(Packet::SubAck{ packet_id: 3, codes: vec![1, 0x80] });
+        round_trip(Packet::PingReq);
+        round_trip(Packet::PingResp);
+        round_trip(Packet::Disconnect);
+    }
+
+    #[test]
+    fn connect_matches_the_spec() {
+        let bytes = encode(&Packet::Connect{ client_id: format!("a"), username: None, password: None, keep_alive: 60, will: None });
+        assert_eq!(bytes, vec![0x10, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 60, 0, 1, b'a']);
+    }
+
+    #[test]
+    fn long_bodies_use_several_length_bytes() {
+        let payload = vec![b'x'; 200];
+        let bytes = encode(&Packet::Publish{ topic: format!("t"), payload: payload.clone(), qos: 0, retain: false, packet_id: None });
+        // 3 bytes of topic and 200 of payload, 203 = 0xCB 0x01
+        assert_eq!(&bytes[..2], &[0x30, 0xCB]);
+        assert_eq!(bytes[2], 0x01);
+        assert_eq!(bytes.len(), 3 + 203);
+        round_trip(Packet::Publish{ topic: format!("t"), payload: vec![0u8; 20_000], qos: 0, retain: false, packet_id: None });
+    }
+
+    #[test]
+    fn bad_input_is_rejected() {
+        // Five continuation bytes is more than the spec allows
+        assert!(read(&mut Cursor::new(vec![0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01])).is_err());
+        // Shorter than its length says
+        assert!(read(&mut Cursor::new(vec![0x30, 10, 0, 1, b't'])).is_err());
+        // A topic running past the end of the body
+        assert_eq!(decode(0x30, &[0, 9, b't']), None);
+        assert_eq!(decode(0x10, &[0, 4, b'H', b'T', b'T', b'P']), None);
+        // Unsubscribe isn't something sam reads
+        assert_eq!(decode(0xA2, &[0, 1]), None);
+    }
+
+    #[test]
+    fn topic_filters() {
+        assert!(topic_matches("sam/things/+/set", "sam/things/lamp/set"));
+        assert!(!topic_matches("sam/things/+/set", "sam/things/lamp/command"));
+        assert!(!topic_matches("sam/things/+/set", "sam/things/lamp/set/extra"));
+        assert!(!topic_matches("sam/things/+/set", "sam/things/set"));
+        assert!(topic_matches("sam/#", "sam/things/lamp/state"));
+        assert!(topic_matches("#", "anything"));
+        assert!(topic_matches("sam/tts/say", "sam/tts/say"));
+        assert!(!topic_matches("sam/tts/say", "sam/tts"));
+    }
+}
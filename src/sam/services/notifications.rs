@@ -16,6 +16,52 @@ use rouille::Response;
 use serde::{Serialize, Deserialize};
 use rouille::post_input;
 
+// Listens on the event bus for things the owner should hear about
+pub fn init(){
+    crate::sam::services::bus::subscribe("notifications", |event| {
+        match event {
+            crate::sam::services::bus::Event::FileStored{file, human_oid, sid, source} => {
+                if source == "youtube" {
+                    match human_oid {
+                        Some(human_oid) => {
+                            let mut notify = crate::sam::memory::Notification::new();
+                            notify.message = format!("{} finished downloading!", file.file_name);
+                            notify.human_oid = human_oid.clone();
+                            notify.sid = sid.clone().unwrap_or(String::new());
+                            create(notify);
+                        },
+                        None => {}
+                    }
+                }
+            },
+            _ => {}
+        }
+    });
+}
+
+// Saves a notification and lets the rest of sam know about it
+pub fn create(notification: crate::sam::memory::Notification) -> Option<crate::sam::memory::Notification> {
+    match notification.save(){
+        Ok(notification) => {
+            crate::sam::services::bus::publish(crate::sam::services::bus::Event::NotificationCreated{
+                notification: notification.clone()
+            });
+            return Some(notification);
+        },
+        Err(e) => {
+            log::error!("failed to save notification: {}", e);
+            return None;
+        }
+    }
+}
+
+// Who hears about things no one human asked for. Unknown voice clusters are
+// humans too but there is nobody to tell.
+pub fn everyone() -> Result<Vec<String>, crate::sam::services::Error> {
+    let humans = crate::sam::memory::Human::select(None, None, None, None)?;
+    return Ok(humans.into_iter().filter(|h| !crate::sam::services::sprec::clusters::is_unknown(h)).map(|h| h.oid).collect());
+}
+
 pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
     
     
@@ -78,9 +124,15 @@ pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Reques
             notification.message = input.message;
             notification.sid = current_session.sid;
             notification.human_oid = current_session.human_oid;
-            notification.save().unwrap();
 
-            return Ok(Response::json(&notification));
+            match create(notification) {
+                Some(notification) => {
+                    return Ok(Response::json(&notification));
+                },
+                None => {
+                    return Ok(Response::text("failed to save notification").with_status_code(500));
+                }
+            }
 
         }
 
@@ -0,0 +1,369 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// presence.rs works out who is home from their devices on the LAN. Each
+// PresenceDevice ties a human to a MAC address, hostname or IP. Every
+// presence_scan_seconds the neighbour table (ip neigh, or /proc/net/arp) is
+// read and devices without a fresh entry are pinged at their last known ip.
+// A human arrives once a device has been seen for presence_arrive_seconds and
+// leaves once none of their devices have been seen for presence_away_seconds.
+// Phones sleep their wifi, so the away timer is deliberately long.
+// Transitions publish PresenceChanged on the bus.
+
+use crate::sam::memory::{Human, PresenceDevice};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::net::ToSocketAddrs;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_SCAN_SECONDS: u64 = 30;
+const DEFAULT_ARRIVE_SECONDS: u64 = 0;
+const DEFAULT_AWAY_SECONDS: u64 = 600;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PresenceState {
+    pub human_oid: String,
+    pub name: String,
+    pub home: bool,
+    // When home last flipped, None until the first transition since startup
+    pub changed_at: Option<i64>,
+    pub last_seen_at: Option<i64>,
+    // The device that was seen last
+    pub device_oid: Option<String>,
+    // Start of the current run of sightings, used to debounce arrivals
+    pub seen_since: Option<i64>,
+    pub checked_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Neighbour {
+    pub ip: String,
+    pub mac: String,
+    // REACHABLE, STALE, DELAY... from ip neigh, COMPLETE from /proc/net/arp
+    pub state: String,
+}
+impl Neighbour {
+    // Entries the kernel confirmed recently, anything else has to answer a ping
+    pub fn fresh(&self) -> bool {
+        return ["REACHABLE", "DELAY", "PROBE", "PERMANENT"].contains(&self.state.as_str());
+    }
+}
+
+fn states() -> &'static Mutex<HashMap<String, PresenceState>> {
+    static STATES: OnceLock<Mutex<HashMap<String, PresenceState>>> = OnceLock::new();
+    STATES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn last_scan() -> &'static Mutex<i64> {
+    static LAST_SCAN: OnceLock<Mutex<i64>> = OnceLock::new();
+    LAST_SCAN.get_or_init(|| Mutex::new(0))
+}
+
+fn now() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+}
+
+pub fn init(){
+    crate::sam::services::scheduler::every("presence_scan", Duration::from_secs(5), scan_due);
+}
+
+pub fn state(human_oid: &str) -> Option<PresenceState> {
+    return states().lock().unwrap().get(human_oid).cloned();
+}
+
+pub fn all() -> Vec<PresenceState> {
+    return states().lock().unwrap().values().cloned().collect();
+}
+
+// Humans without devices aren't tracked, callers fall back to observations for them
+pub fn is_tracked(human_oid: &str) -> bool {
+    return states().lock().unwrap().contains_key(human_oid);
+}
+
+pub fn is_home(human_oid: &str) -> bool {
+    return state(human_oid).map(|s| s.home).unwrap_or(false);
+}
+
+pub fn devices(human_oid: &str) -> Result<Vec<PresenceDevice>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(human_oid.to_string()));
+    pg_query.query_coulmns.push(format!("human_oid ="));
+    return Ok(PresenceDevice::select(None, None, Some(format!("name ASC")), Some(pg_query))?);
+}
+
+// Lowercase and colon separated, so aa-bb-cc-dd-ee-ff and AA:BB:... match
+pub fn normalize_mac(mac: &str) -> Option<String> {
+    let mac = mac.trim().to_lowercase().replace("-", ":");
+    let parts: Vec<&str> = mac.split(":").collect();
+    if parts.len() != 6 || parts.iter().any(|p| p.len() != 2 || u8::from_str_radix(p, 16).is_err()) {
+        return None;
+    }
+    return Some(mac);
+}
+
+// Checks and cleans up a device before it's saved
+pub fn validate(device: &mut PresenceDevice) -> Result<(), String> {
+    device.identifier = device.identifier.trim().to_string();
+    match device.identifier_type.as_str() {
+        "mac" => {
+            match normalize_mac(&device.identifier) {
+                Some(mac) => device.identifier = mac,
+                None => return Err(format!("{} is not a mac address", device.identifier))
+            }
+        },
+        "ip" => {
+            if device.identifier.parse::<std::net::IpAddr>().is_err() {
+                return Err(format!("{} is not an ip address", device.identifier));
+            }
+        },
+        "hostname" => {
+            if device.identifier.len() == 0 || device.identifier.contains(" ") {
+                return Err(format!("{} is not a hostname", device.identifier));
+            }
+        },
+        other => return Err(format!("unknown identifier type {}, use mac, hostname or ip", other))
+    }
+    return Ok(());
+}
+
+// ip neigh is preferred since it says how fresh an entry is
+pub fn neighbours() -> Vec<Neighbour> {
+    match std::process::Command::new("ip").args(["neigh", "show"]).output() {
+        Ok(output) if output.status.success() => {
+            return parse_ip_neigh(&String::from_utf8_lossy(&output.stdout));
+        },
+        _ => {}
+    }
+    match std::fs::read_to_string("/proc/net/arp") {
+        Ok(arp) => return parse_proc_arp(&arp),
+        Err(e) => {
+            log::error!("failed to read the arp table: {}", e);
+            return Vec::new();
+        }
+    }
+}
+
+// 192.168.1.20 dev wlan0 lladdr aa:bb:cc:dd:ee:ff REACHABLE
+pub fn parse_ip_neigh(output: &str) -> Vec<Neighbour> {
+    let mut neighbours: Vec<Neighbour> = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let mac = match fields.iter().position(|f| *f == "lladdr").and_then(|i| fields.get(i + 1)) {
+            Some(mac) => mac,
+            None => continue
+        };
+        match (fields.first(), normalize_mac(mac)) {
+            (Some(ip), Some(mac)) => {
+                neighbours.push(Neighbour{
+                    ip: ip.to_string(),
+                    mac: mac,
+                    state: fields.last().unwrap_or(&"").to_uppercase(),
+                });
+            },
+            _ => {}
+        }
+    }
+    return neighbours;
+}
+
+// IP address  HW type  Flags  HW address  Mask  Device
+pub fn parse_proc_arp(arp: &str) -> Vec<Neighbour> {
+    let mut neighbours: Vec<Neighbour> = Vec::new();
+    for line in arp.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            continue;
+        }
+        // 0x2 is a completed entry, 0x0 one that never answered
+        if fields[2] == "0x0" {
+            continue;
+        }
+        match normalize_mac(fields[3]) {
+            Some(mac) => {
+                neighbours.push(Neighbour{
+                    ip: fields[0].to_string(),
+                    mac: mac,
+                    state: format!("COMPLETE"),
+                });
+            },
+            None => {}
+        }
+    }
+    return neighbours;
+}
+
+fn resolve(hostname: &str) -> Option<String> {
+    match format!("{}:0", hostname).to_socket_addrs() {
+        Ok(mut addrs) => addrs.next().map(|a| a.ip().to_string()),
+        Err(_) => None
+    }
+}
+
+// Returns the ip the device answered from
+pub fn detect(device: &PresenceDevice, neighbours: &Vec<Neighbour>) -> Option<String> {
+    match device.identifier_type.as_str() {
+        "mac" => {
+            let entry = neighbours.iter().find(|n| n.mac == device.identifier);
+            match entry {
+                Some(entry) if entry.fresh() => return Some(entry.ip.clone()),
+                _ => {}
+            }
+            let ip = match entry.map(|e| e.ip.clone()).or(device.last_ip.clone()) {
+                Some(ip) => ip,
+                None => return None
+            };
+            if crate::sam::services::things::ping(&ip).is_err() {
+                return None;
+            }
+            // The ping refreshed the entry, make sure the ip still belongs to this device
+            match neighbours_for(&ip).iter().find(|n| n.mac == device.identifier) {
+                Some(_) => return Some(ip),
+                None => return None
+            }
+        },
+        "hostname" => {
+            let ip = resolve(&device.identifier)?;
+            match crate::sam::services::things::ping(&ip) {
+                Ok(_) => return Some(ip),
+                Err(_) => return None
+            }
+        },
+        _ => {
+            let fresh = neighbours.iter().any(|n| n.ip == device.identifier && n.fresh());
+            if fresh || crate::sam::services::things::ping(&device.identifier).is_ok() {
+                return Some(device.identifier.clone());
+            }
+            return None;
+        }
+    }
+}
+
+fn neighbours_for(ip: &str) -> Vec<Neighbour> {
+    return neighbours().into_iter().filter(|n| n.ip == ip).collect();
+}
+
+pub fn scan_due(){
+    let every = crate::sam::services::things::setting_seconds("presence_scan_seconds", DEFAULT_SCAN_SECONDS) as i64;
+    {
+        let mut last = last_scan().lock().unwrap();
+        if now() - *last < every {
+            return;
+        }
+        *last = now();
+    }
+    match scan() {
+        Ok(_) => {},
+        Err(e) => log::error!("presence scan failed: {}", e)
+    }
+}
+
+// Checks every device once and handles arrivals and departures
+pub fn scan() -> Result<Vec<PresenceState>, crate::sam::services::Error> {
+    let all_devices = PresenceDevice::select(None, None, None, None)?;
+    let mut by_human: HashMap<String, Vec<PresenceDevice>> = HashMap::new();
+    for device in all_devices {
+        by_human.entry(device.human_oid.clone()).or_insert(Vec::new()).push(device);
+    }
+
+    // Humans whose last device was removed stop being tracked
+    states().lock().unwrap().retain(|human_oid, _| by_human.contains_key(human_oid));
+
+    let arrive_seconds = crate::sam::services::things::setting_seconds("presence_arrive_seconds", DEFAULT_ARRIVE_SECONDS) as i64;
+    let away_seconds = crate::sam::services::things::setting_seconds("presence_away_seconds", DEFAULT_AWAY_SECONDS) as i64;
+    let table = neighbours();
+
+    let mut results: Vec<PresenceState> = Vec::new();
+    for (human_oid, devices) in by_human {
+        let human = match find_human(&human_oid)? {
+            Some(human) => human,
+            None => continue
+        };
+
+        let mut seen: Option<String> = None;
+        for device in devices.iter() {
+            match detect(device, &table) {
+                Some(ip) => {
+                    let mut device = device.clone();
+                    device.last_ip = Some(ip);
+                    device.last_seen_at = Some(now());
+                    device.updated_at = now();
+                    device.save()?;
+                    if seen.is_none() {
+                        seen = Some(device.oid.clone());
+                    }
+                },
+                None => {}
+            }
+        }
+
+        let timestamp = now();
+        let mut state = match state(&human_oid) {
+            Some(state) => state,
+            None => {
+                // Pick up where the last run left off without announcing anything
+                let last_seen_at = devices.iter().filter_map(|d| d.last_seen_at).max();
+                PresenceState{
+                    human_oid: human_oid.clone(),
+                    name: human.name.clone(),
+                    home: last_seen_at.map(|l| timestamp - l < away_seconds).unwrap_or(false),
+                    changed_at: None,
+                    last_seen_at: last_seen_at,
+                    device_oid: None,
+                    seen_since: None,
+                    checked_at: timestamp,
+                }
+            }
+        };
+        state.name = human.name.clone();
+        state.checked_at = timestamp;
+
+        let was_home = state.home;
+        match &seen {
+            Some(device_oid) => {
+                state.device_oid = Some(device_oid.clone());
+                state.last_seen_at = Some(timestamp);
+                let since = *state.seen_since.get_or_insert(timestamp);
+                if !state.home && timestamp - since >= arrive_seconds {
+                    state.home = true;
+                }
+            },
+            None => {
+                state.seen_since = None;
+                let gone_for = state.last_seen_at.map(|l| timestamp - l).unwrap_or(away_seconds);
+                if state.home && gone_for >= away_seconds {
+                    state.home = false;
+                }
+            }
+        }
+
+        if state.home != was_home {
+            state.changed_at = Some(timestamp);
+            log::info!("{} {}", human.name, if state.home { "arrived home" } else { "left home" });
+            crate::sam::services::bus::publish(crate::sam::services::bus::Event::PresenceChanged{
+                human: human.clone(),
+                home: state.home,
+                device_oid: state.device_oid.clone(),
+                last_seen_at: state.last_seen_at,
+            });
+        }
+
+        states().lock().unwrap().insert(human_oid.clone(), state.clone());
+        results.push(state);
+    }
+    return Ok(results);
+}
+
+fn find_human(oid: &str) -> Result<Option<Human>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(oid.to_string()));
+    pg_query.query_coulmns.push(format!("oid ="));
+    let humans = Human::select(None, None, None, Some(pg_query))?;
+    return Ok(humans.first().cloned());
+}
@@ -10,8 +10,11 @@
 
 
 
+use std::collections::HashMap;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, OnceLock};
 use std::thread;
-use std::path::Path;
+use std::time::Duration;
 
 // TO
 
@@ -21,43 +24,15 @@ pub fn init(){
     // Initialize RTSP Cameras
     // TODO - Customizable Port and Path
     thread::spawn(move || {
-        let mut pg_query = crate::sam::memory::PostgresQueries::default();
-        pg_query.queries.push(crate::sam::memory::PGCol::String(format!("rtsp")));
-        pg_query.query_coulmns.push(format!("thing_type ="));
-        let rtsp_things = crate::sam::memory::Thing::select(None, None, None, Some(pg_query));
-
-        match rtsp_things {
+        match crate::sam::services::things::of_type("rtsp") {
             Ok(things) => {
-   
-     
                 for thing in things{
-
-                    // Convert RTSP to /streams http api
-                    let rtsp_http_thing = thing.clone();
-                    thread::spawn(move || {
-                        let rtsp_address = format!("rtsp://{}:{}@{}:554/cam/realmonitor?channel=1&subtype=0", rtsp_http_thing.username, rtsp_http_thing.password, rtsp_http_thing.ip_address);
-                        let script = crate::sam::services::rtsp::gen_rtsp_to_http_stream_script(rtsp_address, rtsp_http_thing.oid);
-                        crate::sam::tools::linux_cmd(script);
-                    });
-
-
-                    // Convert RTSP streams to wav files for sam to parse
-                    let rtsp_wav_thing = thing.clone();
-                    thread::spawn(move || {
-                        let rtsp_address = format!("rtsp://{}:{}@{}:554/cam/realmonitor?channel=1&subtype=0", rtsp_wav_thing.username, rtsp_wav_thing.password, rtsp_wav_thing.ip_address);
-                        let script = crate::sam::services::rtsp::gen_rtsp_to_wav_script(rtsp_address, rtsp_wav_thing.oid);
-                        crate::sam::tools::linux_cmd(script);
-                    });
-
-
-          
-                    
+                    start_streams(thing);
 
                     // TODO - Perform Deep Learning on RTSP streams and log observations
 
                     // TODO - Record slected RTSP streams to a network location
                 }
-                
             },
             Err(e) => {
                 log::error!("{}", e);
@@ -66,31 +41,141 @@ pub fn init(){
     });
 }
 
+pub fn rtsp_address(thing: &crate::sam::memory::Thing) -> String {
+    return format!("rtsp://{}:{}@{}:554/cam/realmonitor?channel=1&subtype=0", thing.username, thing.password, thing.ip_address);
+}
+
+fn workers() -> &'static Mutex<HashMap<String, Vec<Child>>> {
+    static WORKERS: OnceLock<Mutex<HashMap<String, Vec<Child>>>> = OnceLock::new();
+    WORKERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Starts the ffmpeg workers for a camera, replacing any that are already running
+pub fn start_streams(thing: crate::sam::memory::Thing){
+    stop_streams(&thing.oid);
+
+    let scripts = vec![
+        // Convert RTSP to /streams http api
+        gen_rtsp_to_http_stream_script(rtsp_address(&thing), thing.oid.clone()),
+    ];
+
+    let mut children: Vec<Child> = Vec::new();
+    for script in scripts {
+        match Command::new("sh").arg("-c").arg(script).stdout(Stdio::null()).stderr(Stdio::null()).spawn() {
+            Ok(child) => children.push(child),
+            Err(e) => log::error!("failed to start ffmpeg for {}: {}", thing.name, e)
+        }
+    }
+    workers().lock().unwrap().insert(thing.oid.clone(), children);
+
+    // Feed the camera's audio into the sound pipeline
+    crate::sam::services::sound::pipeline::start_rtsp(&thing);
+}
+
+pub fn stop_streams(thing_oid: &str){
+    crate::sam::services::sound::pipeline::stop(thing_oid);
+    let children = workers().lock().unwrap().remove(thing_oid);
+    match children {
+        Some(children) => {
+            for mut child in children {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        },
+        None => {}
+    }
+}
+
+// False when any ffmpeg worker of the camera, or its audio capture, has exited
+pub fn streams_running(thing_oid: &str) -> bool {
+    if !crate::sam::services::sound::pipeline::running(thing_oid) {
+        return false;
+    }
+    match workers().lock().unwrap().get_mut(thing_oid) {
+        Some(children) => children.len() > 0 && children.iter_mut().all(|c| match c.try_wait() {
+            Ok(None) => true,
+            _ => false
+        }),
+        None => false
+    }
+}
+
+// Checks the camera accepts connections on the rtsp port
+pub fn probe(thing: &crate::sam::memory::Thing) -> bool {
+    let address = format!("{}:554", thing.ip_address);
+    match std::net::ToSocketAddrs::to_socket_addrs(&address) {
+        Ok(mut addresses) => {
+            match addresses.next() {
+                Some(address) => std::net::TcpStream::connect_timeout(&address, std::time::Duration::from_secs(3)).is_ok(),
+                None => false
+            }
+        },
+        Err(_) => false
+    }
+}
+
 pub fn gen_rtsp_to_http_stream_script(address: String, identifier: String) -> String{
     let mut script = format!("#!/bin/bash\n");
     script = format!("{}VIDSOURCE=\"{}\"\n", script, address);
     script = format!("{}AUDIO_OPTS=\"-c:a aac -b:a 160000 -ac 2\"\n", script);
     script = format!("{}VIDEO_OPTS=\"-s 854x480 -c:v libx264 -b:v 800000\"\n", script);
     script = format!("{}OUTPUT_HLS=\"-hls_time 10 -hls_list_size 10 -start_number 1\"\n", script);
-    script = format!("{}ffmpeg -i \"$VIDSOURCE\" -y $AUDIO_OPTS $VIDEO_OPTS $OUTPUT_HLS /opt/sam/streams/{}.m3u8", script, identifier);
+    script = format!("{}exec ffmpeg -i \"$VIDSOURCE\" -y $AUDIO_OPTS $VIDEO_OPTS $OUTPUT_HLS /opt/sam/streams/{}.m3u8", script, identifier);
     return script;
 }
 
-pub fn gen_rtsp_to_wav_script(address: String, identifier: String) -> String{
-    let p = format!("/opt/sam/tmp/sound/{}", identifier);
-    if !Path::new(p.as_str()).exists(){
-        crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/sound/{}", identifier));
-        crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/sound/{}/s1", identifier));
-        crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/sound/{}/s2", identifier));
-        crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/sound/{}/s3", identifier));
-    }
+// Records a clip from an rtsp thing and stores it under Recordings
+pub fn record(thing: crate::sam::memory::Thing, seconds: i64) -> Result<crate::sam::memory::FileStorage, crate::sam::services::Error> {
+    let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let file_name = format!("{}-{}.mp4", thing.oid, timestamp);
+    let path = format!("/opt/sam/tmp/recordings/{}", file_name);
 
+    ffmpeg(&thing, &["-y", "-i", &rtsp_address(&thing), "-t", &seconds.to_string(), "-c", "copy", &path], Duration::from_secs(seconds.max(0) as u64 + 30))?;
 
-    let mut script = format!("#!/bin/bash\n");
-    script = format!("{}VIDSOURCE=\"{}\"\n", script, address);
-    script = format!("{}ffmpeg -i \"$VIDSOURCE\" -f segment -segment_time 1 -reset_timestamps 1 -strftime 1 -map 0:a /opt/sam/tmp/sound/{}/s1/%Y%m%d-%H%M%S.wav", script, identifier);
-    return script;
+    return store_recording(file_name, format!("video/mp4"), path);
+}
+
+// Grabs a single frame from an rtsp thing and stores it under Recordings
+pub fn snapshot(thing: crate::sam::memory::Thing) -> Result<crate::sam::memory::FileStorage, crate::sam::services::Error> {
+    let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let file_name = format!("{}-{}.jpg", thing.oid, timestamp);
+    let path = format!("/opt/sam/tmp/recordings/{}", file_name);
+
+    ffmpeg(&thing, &["-y", "-rtsp_transport", "tcp", "-i", &rtsp_address(&thing), "-frames:v", "1", &path], Duration::from_secs(30))?;
+
+    return store_recording(file_name, format!("image/jpeg"), path);
 }
 
+// Runs ffmpeg without a shell. Errors name the thing, never the url, it
+// carries the camera's password.
+fn ffmpeg(thing: &crate::sam::memory::Thing, args: &[&str], timeout: Duration) -> Result<(), crate::sam::services::Error> {
+    std::fs::create_dir_all("/opt/sam/tmp/recordings")?;
+    let mut command = Command::new("ffmpeg");
+    command.args(["-hide_banner", "-loglevel", "error"]).args(args);
+    match crate::sam::services::stt::run(&mut command, timeout) {
+        Ok(_) => return Ok(()),
+        Err(e) => return Err(format!("ffmpeg failed for {}: {}", thing.name, e).into())
+    }
+}
 
+fn store_recording(file_name: String, file_type: String, path: String) -> Result<crate::sam::memory::FileStorage, crate::sam::services::Error> {
+    let mut file = crate::sam::memory::FileStorage::new();
+    file.file_name = file_name;
+    file.file_type = file_type;
+    file.file_data = Some(std::fs::read(path.clone())?);
+    file.file_folder_tree = Some(vec![format!("Recordings")]);
+    file.storage_location_oid = format!("SQL");
+    file.save()?;
+    std::fs::remove_file(path)?;
+
+    let mut live = file.clone();
+    live.file_data = None;
+    crate::sam::services::bus::publish(crate::sam::services::bus::Event::FileStored{
+        file: live,
+        human_oid: None,
+        sid: None,
+        source: format!("recording"),
+    });
 
+    return Ok(file);
+}
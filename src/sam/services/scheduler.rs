@@ -0,0 +1,377 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// scheduler.rs runs everything in sam that happens on a timer.
+//
+// Persistent jobs (reminders, recurring announcements, scheduled automations)
+// live in the scheduled_jobs table so they survive restarts. Runs that were
+// missed while sam was down are handled by the job's MissedRunPolicy.
+//
+// Internal jobs are registered from code with every(...) or cron(...) and
+// replace the old ad hoc loop {} threads. Each internal job gets its own
+// thread so a slow stage can't hold up the others.
+
+use chrono::{Local, TimeZone};
+use crate::sam::memory::{MissedRunPolicy, ScheduledJob, ScheduledTask};
+use serde::{Serialize, Deserialize};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Jobs this late are treated as missed rather than just due
+const GRACE_SECONDS: i64 = 60;
+
+// Upper bound on catch-up runs for the run_all policy
+const MAX_CATCH_UP_RUNS: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InternalJobStatus {
+    pub name: String,
+    pub schedule: String,
+    pub run_count: u64,
+    pub panic_count: u64,
+    pub last_run_at: Option<i64>,
+    pub last_duration_ms: Option<u128>,
+}
+
+fn internal_jobs() -> &'static Mutex<Vec<InternalJobStatus>> {
+    static INTERNAL_JOBS: OnceLock<Mutex<Vec<InternalJobStatus>>> = OnceLock::new();
+    INTERNAL_JOBS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+fn now() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+}
+
+pub fn init(){
+    let scheduler_thread = thread::Builder::new().name("scheduler".to_string()).spawn(move || {
+        loop {
+            run_due_jobs();
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+    match scheduler_thread{
+        Ok(_) => {
+            log::info!("scheduler started successfully");
+        },
+        Err(e) => {
+            log::error!("failed to initialize scheduler: {}", e);
+        }
+    }
+}
+
+// Runs handler every interval, measured from the end of the previous run
+pub fn every<F>(name: &str, interval: Duration, handler: F) where F: Fn() + Send + Sync + 'static {
+    let index = register(name, format!("every {}ms", interval.as_millis()));
+    spawn_internal(name, move || {
+        loop {
+            run_internal(index, &handler);
+            thread::sleep(interval);
+        }
+    });
+}
+
+// Runs handler on a cron schedule in local time
+pub fn cron<F>(name: &str, expression: &str, handler: F) where F: Fn() + Send + Sync + 'static {
+    let schedule = match crate::sam::tools::cron::CronSchedule::parse(expression) {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            log::error!("failed to schedule {}: invalid cron expression '{}': {}", name, expression, e);
+            return;
+        }
+    };
+    let index = register(name, format!("cron {}", expression));
+    let job_name = name.to_string();
+    spawn_internal(name, move || {
+        loop {
+            match schedule.next_after(&Local::now()) {
+                Some(next) => {
+                    let wait = (next - Local::now()).to_std().unwrap_or(Duration::from_secs(0));
+                    thread::sleep(wait);
+                    run_internal(index, &handler);
+                },
+                None => {
+                    log::error!("{} has no upcoming runs, stopping", job_name);
+                    return;
+                }
+            }
+        }
+    });
+}
+
+pub fn internal_status() -> Vec<InternalJobStatus> {
+    return internal_jobs().lock().unwrap().clone();
+}
+
+fn register(name: &str, schedule: String) -> usize {
+    let mut jobs = internal_jobs().lock().unwrap();
+    jobs.push(InternalJobStatus{
+        name: name.to_string(),
+        schedule: schedule,
+        run_count: 0,
+        panic_count: 0,
+        last_run_at: None,
+        last_duration_ms: None,
+    });
+    return jobs.len() - 1;
+}
+
+fn spawn_internal<F>(name: &str, body: F) where F: FnOnce() + Send + 'static {
+    let internal_thread = thread::Builder::new().name(name.to_string()).spawn(body);
+    match internal_thread{
+        Ok(_) => {
+            log::info!("{} scheduled successfully", name);
+        },
+        Err(e) => {
+            log::error!("failed to schedule {}: {}", name, e);
+        }
+    }
+}
+
+fn run_internal<F>(index: usize, handler: &F) where F: Fn() {
+    let started = std::time::Instant::now();
+    // A panicking run shouldn't kill the job, it just tries again next time
+    let result = catch_unwind(AssertUnwindSafe(|| handler()));
+
+    let mut jobs = internal_jobs().lock().unwrap();
+    let job = &mut jobs[index];
+    job.run_count = job.run_count + 1;
+    job.last_run_at = Some(now());
+    job.last_duration_ms = Some(started.elapsed().as_millis());
+    if result.is_err() {
+        job.panic_count = job.panic_count + 1;
+        log::error!("scheduled job {} panicked", job.name);
+    }
+}
+
+// Computes when a job should first run, or None if it has no valid schedule
+pub fn first_run(job: &ScheduledJob, from: i64) -> Option<i64> {
+    match (&job.run_at, &job.cron, &job.interval_seconds) {
+        (Some(run_at), _, _) => Some(*run_at),
+        (None, Some(expression), _) => next_cron(expression, from),
+        (None, None, Some(interval)) => {
+            if *interval > 0 {
+                Some(from + interval)
+            } else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+fn next_cron(expression: &str, after: i64) -> Option<i64> {
+    let schedule = crate::sam::tools::cron::CronSchedule::parse(expression).ok()?;
+    let after = Local.timestamp_opt(after, 0).single()?;
+    return schedule.next_after(&after).map(|next| next.timestamp());
+}
+
+// Every scheduled occurrence in (after, until], capped at MAX_CATCH_UP_RUNS
+fn occurrences(job: &ScheduledJob, after: i64, until: i64) -> Vec<i64> {
+    let mut runs: Vec<i64> = Vec::new();
+    let mut cursor = after;
+    while runs.len() < MAX_CATCH_UP_RUNS {
+        let next = match (&job.cron, &job.interval_seconds) {
+            (Some(expression), _) => next_cron(expression, cursor),
+            (None, Some(interval)) if *interval > 0 => Some(cursor + interval),
+            _ => None
+        };
+        match next {
+            Some(next) if next <= until => {
+                runs.push(next);
+                cursor = next;
+            },
+            _ => break
+        }
+    }
+    return runs;
+}
+
+// How many times a due job runs this pass, one-shot jobs only ever run once
+pub fn due_runs(job: &ScheduledJob, current: i64) -> usize {
+    if current - job.next_run_at <= GRACE_SECONDS {
+        return 1;
+    }
+    match job.missed_run_policy {
+        MissedRunPolicy::skip => 0,
+        MissedRunPolicy::run_once => 1,
+        MissedRunPolicy::run_all => {
+            if job.run_at.is_some() {
+                1
+            } else {
+                1 + occurrences(job, job.next_run_at, current).len()
+            }
+        }
+    }
+}
+
+fn due_jobs() -> Vec<ScheduledJob> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::Boolean(true));
+    pg_query.query_coulmns.push(format!("enabled ="));
+    pg_query.queries.push(crate::sam::memory::PGCol::BigNumber(now()));
+    pg_query.query_coulmns.push(format!(" AND next_run_at <="));
+    match ScheduledJob::select(None, None, Some(format!("next_run_at ASC")), Some(pg_query)) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            log::error!("failed to load scheduled jobs: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+pub fn run_due_jobs(){
+    for mut job in due_jobs() {
+        let current = now();
+        let late = current - job.next_run_at > GRACE_SECONDS;
+        let runs = due_runs(&job, current);
+
+        if late {
+            log::info!("scheduled job {} missed its run at {}, policy {} runs it {} time(s)", job.name, job.next_run_at, job.missed_run_policy, runs);
+        }
+
+        for _ in 0..runs {
+            match run_task(&job) {
+                Ok(_) => job.last_error = None,
+                Err(e) => {
+                    log::error!("scheduled job {} failed: {}", job.name, e);
+                    job.last_error = Some(format!("{}", e));
+                }
+            }
+            job.run_count = job.run_count + 1;
+            job.last_run_at = Some(current);
+        }
+
+        if job.run_at.is_some() {
+            // One-shot jobs are kept around disabled so they show up in history
+            job.enabled = false;
+        } else {
+            match &job.cron {
+                Some(expression) => {
+                    match next_cron(expression, current) {
+                        Some(next) => job.next_run_at = next,
+                        None => job.enabled = false
+                    }
+                },
+                None => {
+                    let interval = job.interval_seconds.unwrap_or(0);
+                    if interval > 0 {
+                        // Stay aligned with the original schedule
+                        while job.next_run_at <= current {
+                            job.next_run_at = job.next_run_at + interval;
+                        }
+                    } else {
+                        job.enabled = false;
+                    }
+                }
+            }
+        }
+        job.updated_at = current;
+
+        match job.save() {
+            Ok(_) => {},
+            Err(e) => log::error!("failed to save scheduled job {}: {}", job.oid, e)
+        }
+    }
+}
+
+pub fn run_task(job: &ScheduledJob) -> Result<(), crate::sam::services::Error> {
+    match &job.task {
+        ScheduledTask::Notify{message} => {
+            let human_oid = match &job.human_oid {
+                Some(human_oid) => human_oid.clone(),
+                None => return Err(format!("notify jobs need an owner").into())
+            };
+            let mut notification = crate::sam::memory::Notification::new();
+            notification.message = message.clone();
+            notification.human_oid = human_oid;
+            match crate::sam::services::notifications::create(notification) {
+                Some(_) => return Ok(()),
+                None => return Err(format!("failed to create notification").into())
+            }
+        },
+        ScheduledTask::Speak{text} => {
+            return crate::sam::services::tts::speak(text.clone());
+        },
+        ScheduledTask::RunAutomation{automation_oid} => {
+            let mut pg_query = crate::sam::memory::PostgresQueries::default();
+            pg_query.queries.push(crate::sam::memory::PGCol::String(automation_oid.clone()));
+            pg_query.query_coulmns.push(format!("oid ="));
+            let automations = crate::sam::memory::Automation::select(None, None, None, Some(pg_query))?;
+            if automations.len() == 0 {
+                return Err(format!("automation {} not found", automation_oid).into());
+            }
+            crate::sam::services::automations::execute(&automations[0], "Scheduled", serde_json::json!({"job": job.oid}), None, &Local::now(), false);
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2023-01-02 00:00:00 UTC
+    const START: i64 = 1672617600;
+
+    fn job(policy: MissedRunPolicy) -> ScheduledJob {
+        let mut job = ScheduledJob::new();
+        job.missed_run_policy = policy;
+        job.interval_seconds = Some(3600);
+        job.next_run_at = START;
+        return job;
+    }
+
+    #[test]
+    fn on_time_jobs_run_once() {
+        for policy in [MissedRunPolicy::skip, MissedRunPolicy::run_once, MissedRunPolicy::run_all] {
+            assert_eq!(due_runs(&job(policy.clone()), START), 1);
+            assert_eq!(due_runs(&job(policy), START + GRACE_SECONDS), 1);
+        }
+    }
+
+    #[test]
+    fn missed_runs_follow_the_policy() {
+        // Down for 3 and a half hours, the runs at +1h, +2h and +3h were missed too
+        let current = START + 3 * 3600 + 1800;
+        assert_eq!(due_runs(&job(MissedRunPolicy::skip), current), 0);
+        assert_eq!(due_runs(&job(MissedRunPolicy::run_once), current), 1);
+        assert_eq!(due_runs(&job(MissedRunPolicy::run_all), current), 4);
+    }
+
+    #[test]
+    fn one_shot_jobs_run_at_most_once() {
+        let mut one_shot = job(MissedRunPolicy::run_all);
+        one_shot.interval_seconds = None;
+        one_shot.run_at = Some(START);
+        assert_eq!(due_runs(&one_shot, START + 86400), 1);
+        one_shot.missed_run_policy = MissedRunPolicy::skip;
+        assert_eq!(due_runs(&one_shot, START + 86400), 0);
+    }
+
+    #[test]
+    fn catching_up_is_capped() {
+        let mut busy = job(MissedRunPolicy::run_all);
+        busy.interval_seconds = Some(1);
+        assert_eq!(due_runs(&busy, START + 86400), 1 + MAX_CATCH_UP_RUNS);
+    }
+
+    #[test]
+    fn cron_jobs_catch_up_every_occurrence() {
+        let mut cron = job(MissedRunPolicy::run_all);
+        cron.interval_seconds = None;
+        cron.cron = Some(format!("* * * * *"));
+        assert_eq!(occurrences(&cron, START, START + 600), (1..=10).map(|m| START + m * 60).collect::<Vec<i64>>());
+        assert_eq!(due_runs(&cron, START + 600), 11);
+        cron.cron = Some(format!("not cron"));
+        assert_eq!(due_runs(&cron, START + 600), 1);
+        assert_eq!(first_run(&cron, START), None);
+    }
+}
@@ -7,9 +7,28 @@
 // Developed by Caleb Mitchell Smith (PixelCoda)
 // Licensed under GPLv3....see LICENSE file.
 
+// socket.rs runs the echo server on port 2794 and the event socket, which
+// forwards everything published on the bus to a signed in browser. The event
+// socket is authenticated by the session cookie like any other request:
+//
+//   GET /api/socket
+//
+// Every bus event is sent as json tagged with its "type". Events that belong
+// to another human are skipped and thing credentials are blanked. Sam sends
+// {"type": "ping"} when the bus has been quiet for a while.
+
+use crate::sam::services::bus::Event as BusEvent;
+use rouille::websocket::Websocket;
+use rouille::Request;
+use rouille::Response;
 use simple_websockets::{Event, Responder};
 use std::collections::HashMap;
+use std::sync::mpsc::RecvTimeoutError;
 use std::thread;
+use std::time::Duration;
+
+// A closed socket is only noticed when something is sent to it
+const PING_SECONDS: u64 = 30;
 
 pub fn init() {
     thread::spawn(move || {
@@ -34,11 +53,83 @@ pub fn init() {
                 Event::Message(client_id, message) => {
                     log::info!("WSS Received a message from client #{}: {:?}", client_id, message);
                     // retrieve this client's `Responder`:
-                    let responder = clients.get(&client_id).unwrap();
-                    // echo the message back:
-                    responder.send(message);
+                    match clients.get(&client_id) {
+                        Some(responder) => {
+                            // echo the message back:
+                            responder.send(message);
+                        },
+                        None => {}
+                    }
                 },
             }
         }
     });
-}
\ No newline at end of file
+}
+
+pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+    let (response, websocket) = match rouille::websocket::start(request, None::<&str>) {
+        Ok(started) => started,
+        Err(e) => return Ok(Response::text(format!("expected a websocket: {:?}", e)).with_status_code(400))
+    };
+
+    // Subscribe now so nothing published while the socket opens is missed
+    let events = crate::sam::services::bus::channel(&format!("socket_{}", current_session.oid));
+    let spawned = thread::Builder::new().name(format!("event_socket_{}", current_session.oid)).spawn(move || {
+        match websocket.recv() {
+            Ok(socket) => forward(socket, events, &current_session.human_oid),
+            Err(_) => {}
+        }
+    });
+    match spawned {
+        Ok(_) => {},
+        Err(e) => log::error!("failed to start event socket: {}", e)
+    }
+
+    return Ok(response);
+}
+
+// Runs until the socket closes, dropping the receiver unsubscribes from the bus
+fn forward(mut socket: Websocket, events: std::sync::mpsc::Receiver<BusEvent>, human_oid: &str){
+    loop {
+        let message = match events.recv_timeout(Duration::from_secs(PING_SECONDS)) {
+            Ok(event) => match visible(event, human_oid) {
+                Some(event) => match serde_json::to_string(&event) {
+                    Ok(json) => json,
+                    Err(e) => {
+                        log::error!("failed to serialize bus event for the event socket: {}", e);
+                        continue;
+                    }
+                },
+                None => continue
+            },
+            Err(RecvTimeoutError::Timeout) => serde_json::json!({"type": "ping"}).to_string(),
+            Err(RecvTimeoutError::Disconnected) => return
+        };
+        if socket.send_text(&message).is_err() {
+            return;
+        }
+    }
+}
+
+// What a human's socket may see of an event
+fn visible(event: BusEvent, human_oid: &str) -> Option<BusEvent> {
+    let owner = match &event {
+        BusEvent::NotificationCreated{notification} => Some(notification.human_oid.clone()),
+        BusEvent::FileStored{human_oid, ..} => human_oid.clone(),
+        BusEvent::JobProgress{human_oid, ..} => human_oid.clone(),
+        BusEvent::VoiceCommand{human_oid, ..} => human_oid.clone(),
+        _ => None
+    };
+    match owner {
+        Some(owner) if owner != human_oid => return None,
+        _ => {}
+    }
+    match event {
+        BusEvent::ThingDiscovered{mut thing} => {
+            thing.username = String::new();
+            thing.password = String::new();
+            return Some(BusEvent::ThingDiscovered{thing: thing});
+        },
+        event => return Some(event)
+    }
+}
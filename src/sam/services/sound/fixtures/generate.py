@@ -0,0 +1,127 @@
+# Builds the synthetic vad fixtures and expected.json, run from this directory:
+#   python3 generate.py
+#
+# Speech is a harmonic voice (f0 around 140Hz) shaped by vowel formants that
+# change every syllable, with a 4Hz syllable envelope. Expected utterances are
+# where the segmenter should cut them: PRE_ROLL before the speech starts, the
+# onset frames after it, and HANGOVER plus HANG after it ends.
+
+import json
+import math
+import random
+import struct
+import wave
+
+RATE = 16000
+PRE_ROLL_MS = 300
+ONSET_MS = 60
+TAIL_MS = 150 + 900
+
+random.seed(7)
+
+VOWELS = [(700, 1200, 2600), (400, 2000, 2700), (300, 900, 2300), (600, 1700, 2500)]
+# The wake phrase is its own vowel sequence so it can't be mistaken for other speech
+PHRASE = [(250, 2300, 3000), (750, 1100, 2500), (350, 800, 2200)]
+
+
+def room_tone(seconds, sigma):
+    return [random.gauss(0, sigma) for _ in range(int(seconds * RATE))]
+
+
+def speech(seconds, peak, vowels=VOWELS, pitch=140):
+    n = int(seconds * RATE)
+    out = []
+    phase = 0.0
+    for i in range(n):
+        t = i / RATE
+        f0 = pitch + 15 * math.sin(2 * math.pi * 3 * t)
+        phase += 2 * math.pi * f0 / RATE
+        f1, f2, f3 = vowels[int(t * 4) % len(vowels)]
+        value = 0.0
+        for k in range(1, 26):
+            hz = k * f0
+            if hz > 4000:
+                break
+            weight = (math.exp(-((hz - f1) / 150) ** 2) + 0.6 * math.exp(-((hz - f2) / 200) ** 2)
+                      + 0.3 * math.exp(-((hz - f3) / 300) ** 2) + 0.02)
+            value += weight * math.sin(k * phase)
+        syllable = 0.625 - 0.375 * math.cos(2 * math.pi * 4 * t)
+        edge = min(1.0, t / 0.02, (seconds - t) / 0.02)
+        out.append(value * syllable * edge)
+    top = max(abs(v) for v in out)
+    return [v / top * peak for v in out]
+
+
+def fan(seconds, sigma, start):
+    out = []
+    last = 0.0
+    for i in range(int(seconds * RATE)):
+        last = 0.7 * last + 0.3 * random.gauss(0, 1)
+        out.append(last * sigma if i >= start * RATE else 0.0)
+    return out
+
+
+def slam(seconds, peak):
+    out = []
+    for i in range(int(seconds * RATE)):
+        t = i / RATE
+        decay = math.exp(-t / 0.06)
+        out.append(peak * decay * (0.7 * random.uniform(-1, 1) + 0.3 * math.sin(2 * math.pi * 80 * t)))
+    return out
+
+
+def mix(base, part, at):
+    start = int(at * RATE)
+    for i, v in enumerate(part):
+        if start + i < len(base):
+            base[start + i] += v
+    return base
+
+
+def write(name, samples):
+    with wave.open(name, "wb") as w:
+        w.setnchannels(1)
+        w.setsampwidth(2)
+        w.setframerate(RATE)
+        w.writeframes(b"".join(struct.pack("<h", max(-32768, min(32767, int(round(s))))) for s in samples))
+
+
+def expected(start, end):
+    return [int(start * 1000) - PRE_ROLL_MS + ONSET_MS, int(end * 1000) + TAIL_MS]
+
+
+fixtures = []
+
+clip = mix(room_tone(4.0, 40), speech(1.5, 1500), 1.0)
+write("quiet_speaker.wav", clip)
+fixtures.append({"file": "quiet_speaker.wav", "description": "soft voice peaking at 1500, far below the old 14000 gate",
+                 "utterances": [expected(1.0, 2.5)]})
+
+clip = mix(room_tone(4.0, 40), slam(0.6, 30000), 1.5)
+write("door_slam.wav", clip)
+fixtures.append({"file": "door_slam.wav", "description": "a door slam peaking at 30000 is not speech", "utterances": []})
+
+clip = mix(mix(room_tone(6.5, 60), speech(1.0, 8000), 0.5), speech(1.2, 6000), 3.5)
+write("two_utterances.wav", clip)
+fixtures.append({"file": "two_utterances.wav", "description": "two sentences two seconds apart stay separate",
+                 "utterances": [expected(0.5, 1.5), expected(3.5, 4.7)]})
+
+clip = mix(mix(room_tone(6.0, 40), fan(6.0, 1500, 0.5), 0.0), speech(1.5, 12000), 2.5)
+write("fan_noise.wav", clip)
+fixtures.append({"file": "fan_noise.wav", "description": "a fan turns on at 0.5s, speech over it at 2.5s",
+                 "utterances": [expected(2.5, 4.0)]})
+
+write("room_tone.wav", room_tone(3.0, 50))
+fixtures.append({"file": "room_tone.wav", "description": "nothing but room tone", "utterances": []})
+
+# Wake word fixtures aren't segmented, wake.rs reads them directly
+write("wake_template.wav", mix(room_tone(1.2, 40), speech(0.75, 8000, PHRASE), 0.2))
+
+clip = mix(mix(room_tone(3.0, 40), speech(0.75, 6000, PHRASE, 150), 0.3), speech(1.5, 6000), 1.2)
+write("wake_command.wav", clip)
+
+write("wake_other.wav", mix(room_tone(3.0, 40), speech(2.0, 6000), 0.3))
+
+with open("expected.json", "w") as f:
+    json.dump(fixtures, f, indent=4)
+    f.write("\n")
@@ -0,0 +1,750 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// pipeline.rs streams audio from its sources to observations in memory.
+// Every source (an rtsp camera's audio, a browser microphone) gets its own
+// capture and segmenter threads, recognition is shared:
+//
+//   capture -> frames -> segmenter -> utterances -> recognizer pool -> observe
+//
+// Frames are 16kHz mono pcm in FRAME_MS chunks. The frame channel is bounded
+// and blocking, so a segmenter that falls behind pushes back on its capture
+// (and on ffmpeg). The utterance queue is bounded and shared by every source,
+// when recognition can't keep up new utterances are dropped and counted
+// instead of letting live audio fall further and further behind. Only
+// finalized utterances are written to disk, as the wav whisper and sprec read.
+//
+// Sources started with start_streaming also get their transcripts back, a
+// partial one every PARTIAL_MS while someone is still talking (only when the
+// recognizers are idle) and a final one for every utterance.
+
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SAMPLE_RATE: u32 = 16000;
+pub const FRAME_MS: i64 = 30;
+pub const FRAME_SAMPLES: usize = (SAMPLE_RATE as i64 * FRAME_MS / 1000) as usize;
+
+// About two seconds of audio
+const FRAME_QUEUE: usize = 64;
+const UTTERANCE_QUEUE: usize = 8;
+
+// Speech kept from before the gate opened so first syllables aren't clipped
+const PRE_ROLL_MS: i64 = 300;
+// Silence allowed inside one utterance
+const HANG_MS: i64 = 900;
+const MIN_UTTERANCE_MS: i64 = 300;
+const MAX_UTTERANCE_MS: i64 = 30000;
+const PARTIAL_MS: i64 = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AudioSource {
+    // thing oid for cameras, web_{session oid}_{ms} for browsers
+    pub id: String,
+    pub thing_oid: Option<String>,
+    pub web_session: Option<crate::sam::memory::WebSessions>,
+}
+impl AudioSource {
+    pub fn thing(thing: &crate::sam::memory::Thing) -> AudioSource {
+        AudioSource {
+            id: thing.oid.clone(),
+            thing_oid: Some(thing.oid.clone()),
+            web_session: None,
+        }
+    }
+
+    // Every socket is its own source, a session can have a few tabs open
+    pub fn web(session: &crate::sam::memory::WebSessions) -> AudioSource {
+        AudioSource {
+            id: format!("web_{}_{}", session.oid, now_ms()),
+            thing_oid: None,
+            web_session: Some(session.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioFrame {
+    pub samples: Vec<i16>,
+    // Audio time in milliseconds since the epoch
+    pub timestamp_ms: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Utterance {
+    pub source: AudioSource,
+    pub samples: Vec<i16>,
+    pub started_at_ms: i64,
+    pub ended_at_ms: i64,
+    // Said to sam after the wake word, goes to the io brain
+    pub command: bool,
+    pub wake_word: Option<crate::sam::services::sound::wake::WakeMatch>,
+    // Still being spoken, only transcribed for start_streaming sources
+    pub partial: bool,
+    pub transcripts: Option<Sender<Transcript>>,
+}
+impl Utterance {
+    pub fn duration_ms(&self) -> i64 {
+        return self.samples.len() as i64 * 1000 / SAMPLE_RATE as i64;
+    }
+
+    pub fn write_wav(&self, path: &str) -> Result<(), crate::sam::services::Error> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for sample in self.samples.iter() {
+            writer.write_sample(*sample)?;
+        }
+        writer.finalize()?;
+        return Ok(());
+    }
+
+    // write_wav, in memory
+    pub fn wav(&self) -> Result<Vec<u8>, crate::sam::services::Error> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut wav: Vec<u8> = Vec::new();
+        let mut writer = hound::WavWriter::new(std::io::Cursor::new(&mut wav), spec)?;
+        for sample in self.samples.iter() {
+            writer.write_sample(*sample)?;
+        }
+        writer.finalize()?;
+        return Ok(wav);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Transcript {
+    // partial or final
+    pub kind: String,
+    pub text: String,
+    pub started_at_ms: i64,
+    pub ended_at_ms: i64,
+    pub observation_oid: Option<String>,
+    pub human_oid: Option<String>,
+    pub command: bool,
+    pub reply: Option<String>,
+    // The stt engine that produced the text
+    pub engine: Option<String>,
+}
+impl Transcript {
+    pub fn new(utterance: &Utterance) -> Transcript {
+        Transcript {
+            kind: if utterance.partial { format!("partial") } else { format!("final") },
+            text: String::new(),
+            started_at_ms: utterance.started_at_ms,
+            ended_at_ms: utterance.ended_at_ms,
+            observation_oid: None,
+            human_oid: None,
+            command: utterance.command,
+            reply: None,
+            engine: None,
+        }
+    }
+}
+
+// Decides frame by frame whether someone is talking
+pub trait SpeechDetector: Send {
+    fn is_speech(&mut self, frame: &AudioFrame) -> bool;
+}
+
+// Groups frames into utterances using a SpeechDetector
+pub struct Segmenter {
+    source: AudioSource,
+    detector: Box<dyn SpeechDetector>,
+    pre_roll: VecDeque<AudioFrame>,
+    current: Vec<i16>,
+    started_at_ms: Option<i64>,
+    last_speech_ms: i64,
+    transcripts: Option<Sender<Transcript>>,
+    next_partial_ms: i64,
+}
+impl Segmenter {
+    pub fn new(source: AudioSource, detector: Box<dyn SpeechDetector>) -> Segmenter {
+        Segmenter {
+            source: source,
+            detector: detector,
+            pre_roll: VecDeque::new(),
+            current: Vec::new(),
+            started_at_ms: None,
+            last_speech_ms: 0,
+            transcripts: None,
+            next_partial_ms: PARTIAL_MS,
+        }
+    }
+
+    // Utterances carry the sender so their transcripts find their way back
+    pub fn with_transcripts(mut self, transcripts: Sender<Transcript>) -> Segmenter {
+        self.transcripts = Some(transcripts);
+        return self;
+    }
+
+    // Feeds one frame, returns an utterance when one ends
+    pub fn push(&mut self, frame: AudioFrame) -> Option<Utterance> {
+        let speech = self.detector.is_speech(&frame);
+        let end_ms = frame.timestamp_ms + frame.samples.len() as i64 * 1000 / SAMPLE_RATE as i64;
+
+        match self.started_at_ms {
+            None => {
+                if !speech {
+                    self.pre_roll.push_back(frame);
+                    while self.pre_roll.len() as i64 * FRAME_MS > PRE_ROLL_MS {
+                        self.pre_roll.pop_front();
+                    }
+                    return None;
+                }
+                let started_at_ms = self.pre_roll.front().map(|f| f.timestamp_ms).unwrap_or(frame.timestamp_ms);
+                for buffered in self.pre_roll.drain(..) {
+                    self.current.extend_from_slice(&buffered.samples);
+                }
+                self.current.extend_from_slice(&frame.samples);
+                self.started_at_ms = Some(started_at_ms);
+                self.last_speech_ms = end_ms;
+                return None;
+            },
+            Some(started_at_ms) => {
+                self.current.extend_from_slice(&frame.samples);
+                if speech {
+                    self.last_speech_ms = end_ms;
+                }
+                if end_ms - self.last_speech_ms >= HANG_MS || end_ms - started_at_ms >= MAX_UTTERANCE_MS {
+                    return self.finish(end_ms);
+                }
+                if self.transcripts.is_some() && speech && end_ms - started_at_ms >= self.next_partial_ms {
+                    self.next_partial_ms = end_ms - started_at_ms + PARTIAL_MS;
+                    return Some(Utterance {
+                        source: self.source.clone(),
+                        samples: self.current.clone(),
+                        started_at_ms: started_at_ms,
+                        ended_at_ms: end_ms,
+                        command: false,
+                        wake_word: None,
+                        partial: true,
+                        transcripts: self.transcripts.clone(),
+                    });
+                }
+                return None;
+            }
+        }
+    }
+
+    // Ends whatever is in progress, used when a source stops
+    pub fn flush(&mut self) -> Option<Utterance> {
+        let end_ms = self.last_speech_ms;
+        return self.finish(end_ms);
+    }
+
+    fn finish(&mut self, end_ms: i64) -> Option<Utterance> {
+        let started_at_ms = self.started_at_ms.take()?;
+        let utterance = Utterance {
+            source: self.source.clone(),
+            samples: std::mem::take(&mut self.current),
+            started_at_ms: started_at_ms,
+            ended_at_ms: end_ms,
+            command: false,
+            wake_word: None,
+            partial: false,
+            transcripts: self.transcripts.clone(),
+        };
+        self.pre_roll.clear();
+        self.next_partial_ms = PARTIAL_MS;
+        if utterance.duration_ms() < MIN_UTTERANCE_MS {
+            return None;
+        }
+        return Some(utterance);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SourceStatus {
+    pub source: AudioSource,
+    pub running: bool,
+    pub started_at: i64,
+    pub frames: u64,
+    pub utterances: u64,
+    // Utterances dropped because the recognizers were busy
+    pub dropped: u64,
+    // Utterances dropped because no wake word came first
+    pub ignored: u64,
+    pub wake_words: u64,
+}
+
+#[derive(Default)]
+struct SourceStats {
+    frames: AtomicU64,
+    utterances: AtomicU64,
+    dropped: AtomicU64,
+    ignored: AtomicU64,
+    wake_words: AtomicU64,
+}
+
+struct SourceHandle {
+    source: AudioSource,
+    stop: Arc<AtomicBool>,
+    child: Option<Child>,
+    threads: Vec<JoinHandle<()>>,
+    stats: Arc<SourceStats>,
+    started_at: i64,
+}
+
+// Splits pcm into frames and hands them to a source's segmenter
+pub struct FrameSender {
+    tx: SyncSender<AudioFrame>,
+    stop: Arc<AtomicBool>,
+    stats: Arc<SourceStats>,
+    pending: Vec<i16>,
+    started_at_ms: i64,
+    samples_sent: i64,
+}
+impl FrameSender {
+    // Blocks while the segmenter is behind, errors once the source is stopped
+    pub fn send(&mut self, samples: &[i16]) -> Result<(), crate::sam::services::Error> {
+        if self.stop.load(Ordering::SeqCst) {
+            return Err(format!("audio source stopped").into());
+        }
+        self.pending.extend_from_slice(samples);
+        while self.pending.len() >= FRAME_SAMPLES {
+            let frame = AudioFrame {
+                samples: self.pending.drain(..FRAME_SAMPLES).collect(),
+                timestamp_ms: self.started_at_ms + self.samples_sent * 1000 / SAMPLE_RATE as i64,
+            };
+            self.samples_sent = self.samples_sent + FRAME_SAMPLES as i64;
+            match self.tx.send(frame) {
+                Ok(_) => {
+                    self.stats.frames.fetch_add(1, Ordering::Relaxed);
+                },
+                Err(_) => return Err(format!("audio source stopped").into())
+            }
+        }
+        return Ok(());
+    }
+
+    // Little endian s16 bytes, as ffmpeg -f s16le writes them
+    pub fn send_bytes(&mut self, bytes: &[u8]) -> Result<(), crate::sam::services::Error> {
+        let samples: Vec<i16> = bytes.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+        return self.send(&samples);
+    }
+}
+
+fn sources() -> &'static Mutex<HashMap<String, SourceHandle>> {
+    static SOURCES: OnceLock<Mutex<HashMap<String, SourceHandle>>> = OnceLock::new();
+    SOURCES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn utterance_queue() -> &'static OnceLock<SyncSender<(Utterance, Arc<SourceStats>)>> {
+    static QUEUE: OnceLock<SyncSender<(Utterance, Arc<SourceStats>)>> = OnceLock::new();
+    &QUEUE
+}
+
+// Utterances waiting for or being recognized
+fn busy() -> &'static AtomicUsize {
+    static BUSY: AtomicUsize = AtomicUsize::new(0);
+    &BUSY
+}
+
+pub fn now_ms() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+}
+
+// Starts the recognizer pool, sound_workers (1) threads share the queue
+pub fn init(){
+    let (tx, rx) = mpsc::sync_channel::<(Utterance, Arc<SourceStats>)>(UTTERANCE_QUEUE);
+    if utterance_queue().set(tx).is_err() {
+        return;
+    }
+    let rx = Arc::new(Mutex::new(rx));
+    let workers = crate::sam::services::things::setting_seconds("sound_workers", 1);
+    for i in 0..workers {
+        let rx = rx.clone();
+        let spawned = thread::Builder::new().name(format!("sound_recognizer_{}", i)).spawn(move || {
+            recognizer(rx);
+        });
+        match spawned {
+            Ok(_) => {},
+            Err(e) => log::error!("failed to start sound recognizer: {}", e)
+        }
+    }
+    log::info!("sound pipeline started with {} recognizer(s)", workers);
+}
+
+fn recognizer(rx: Arc<Mutex<Receiver<(Utterance, Arc<SourceStats>)>>>){
+    loop {
+        // A worker that panicked mustn't take the others down with it
+        let next = rx.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        match next {
+            Ok((utterance, stats)) => {
+                if !utterance.partial {
+                    stats.utterances.fetch_add(1, Ordering::Relaxed);
+                }
+                match catch_unwind(AssertUnwindSafe(|| recognize(&utterance))) {
+                    Ok(Ok(_)) => {},
+                    Ok(Err(e)) => log::error!("failed to recognize utterance from {}: {}", utterance.source.id, e),
+                    Err(_) => log::error!("recognizer panicked on an utterance from {}", utterance.source.id)
+                }
+                busy().fetch_sub(1, Ordering::SeqCst);
+            },
+            Err(_) => return
+        }
+    }
+}
+
+// Writes finalized utterances out for whisper and sprec, then observes what was said
+pub fn recognize(utterance: &Utterance) -> Result<(), crate::sam::services::Error> {
+    let mut transcript = Transcript::new(utterance);
+    if utterance.partial {
+        // Partials are only shown to the speaker and stay in memory, sprec waits for the whole utterance
+        match utterance.wav().and_then(|wav| crate::sam::services::stt::transcribe_wav(&wav)) {
+            Ok(transcription) => {
+                transcript.text = transcription.text;
+                transcript.engine = Some(transcription.engine);
+            },
+            Err(e) => log::error!("stt failed for a partial utterance from {}: {}", utterance.source.id, e)
+        }
+    } else {
+        let dir = format!("/opt/sam/tmp/sound/{}", utterance.source.id);
+        std::fs::create_dir_all(&dir)?;
+        let path = format!("{}/{}.final.wav", dir, utterance.started_at_ms);
+        utterance.write_wav(&path)?;
+
+        match crate::sam::services::stt::process(path.clone()) {
+            Ok(prediction) => {
+                if prediction.stt.trim().len() > 0 {
+                    let text = prediction.stt.clone();
+                    transcript.engine = Some(prediction.engine.clone());
+                    transcript.text = text.trim().to_string();
+                    match crate::sam::services::sound::observe(prediction, &path, &utterance.source) {
+                        Ok(observation) => {
+                            transcript.observation_oid = Some(observation.oid.clone());
+                            transcript.human_oid = known_human(&observation);
+                            if utterance.command {
+                                transcript.reply = command(utterance, &text, observation);
+                            }
+                        },
+                        Err(e) => log::error!("failed to observe {}: {}", path, e)
+                    }
+                }
+            },
+            Err(e) => log::error!("stt failed for {}: {}", path, e)
+        }
+        std::fs::remove_file(&path)?;
+    }
+
+    match &utterance.transcripts {
+        Some(transcripts) => {
+            // The socket may already be gone
+            let _ = transcripts.send(transcript);
+        },
+        None => {}
+    }
+
+    return Ok(());
+}
+
+fn known_human(observation: &crate::sam::memory::Observation) -> Option<String> {
+    return observation.observation_humans.iter().find(|h| !crate::sam::services::sprec::clusters::is_unknown(h)).map(|h| h.oid.clone());
+}
+
+// Hands what was said after the wake word to the io brain, returns its reply
+fn command(utterance: &Utterance, text: &str, observation: crate::sam::memory::Observation) -> Option<String> {
+    let phrase = crate::sam::services::sound::wake::config().phrase.clone();
+    let text = crate::sam::services::sound::wake::strip_phrase(text, &phrase);
+    if text.len() == 0 {
+        return None;
+    }
+    let reply = crate::sam::http::api::io::process(&text);
+    log::info!("voice command from {}: {} -> {}", utterance.source.id, text, reply.text);
+
+    crate::sam::services::bus::publish(crate::sam::services::bus::Event::VoiceCommand {
+        observation_oid: observation.oid.clone(),
+        thing_oid: utterance.source.thing_oid.clone(),
+        room_oid: observation.thing.as_ref().map(|t| t.room_oid.clone()),
+        human_oid: known_human(&observation),
+        text: text,
+        reply: reply.text.clone(),
+    });
+    return Some(reply.text);
+}
+
+// Registers a source and starts its segmenter, audio is pushed with the returned sender
+pub fn start(source: AudioSource, detector: Box<dyn SpeechDetector>) -> FrameSender {
+    return spawn(source, detector, None);
+}
+
+// Like start, transcripts come back on the receiver. It disconnects once the
+// sender is dropped and everything the source heard has been recognized.
+pub fn start_streaming(source: AudioSource, detector: Box<dyn SpeechDetector>) -> (FrameSender, Receiver<Transcript>) {
+    let (tx, rx) = mpsc::channel::<Transcript>();
+    return (spawn(source, detector, Some(tx)), rx);
+}
+
+fn spawn(source: AudioSource, detector: Box<dyn SpeechDetector>, transcripts: Option<Sender<Transcript>>) -> FrameSender {
+    stop(&source.id);
+
+    let (tx, rx) = mpsc::sync_channel::<AudioFrame>(FRAME_QUEUE);
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let stats = Arc::new(SourceStats::default());
+
+    let mut threads: Vec<JoinHandle<()>> = Vec::new();
+    let thread_stats = stats.clone();
+    let mut segmenter = Segmenter::new(source.clone(), detector);
+    match transcripts {
+        Some(transcripts) => segmenter = segmenter.with_transcripts(transcripts),
+        None => {}
+    }
+    let mut listener = crate::sam::services::sound::wake::Listener::new(&source);
+    let spawned = thread::Builder::new().name(format!("sound_segmenter_{}", source.id)).spawn(move || {
+        // Ends when every FrameSender has been dropped
+        for frame in rx.iter() {
+            match segmenter.push(frame) {
+                Some(utterance) => listen(&mut listener, utterance, &thread_stats),
+                None => {}
+            }
+        }
+        match segmenter.flush() {
+            Some(utterance) => listen(&mut listener, utterance, &thread_stats),
+            None => {}
+        }
+    });
+    match spawned {
+        Ok(handle) => threads.push(handle),
+        Err(e) => log::error!("failed to start segmenter for {}: {}", source.id, e)
+    }
+
+    sources().lock().unwrap().insert(source.id.clone(), SourceHandle {
+        source: source,
+        stop: stop_flag.clone(),
+        child: None,
+        threads: threads,
+        stats: stats.clone(),
+        started_at: now_ms() / 1000,
+    });
+
+    return FrameSender {
+        tx: tx,
+        stop: stop_flag,
+        stats: stats,
+        pending: Vec::new(),
+        started_at_ms: now_ms(),
+        samples_sent: 0,
+    };
+}
+
+// Only what follows the wake word, or everything from continuous things, is recognized
+fn listen(listener: &mut crate::sam::services::sound::wake::Listener, utterance: Utterance, stats: &Arc<SourceStats>){
+    if utterance.partial {
+        // Best effort, only when nothing else is waiting
+        if busy().load(Ordering::SeqCst) == 0 {
+            enqueue(utterance, stats);
+        }
+        return;
+    }
+    let wake_words = listener.wake_words;
+    let heard = listener.hear(utterance);
+    let woke = listener.wake_words > wake_words;
+    if woke {
+        stats.wake_words.fetch_add(1, Ordering::Relaxed);
+    }
+    match heard {
+        Some(utterance) => enqueue(utterance, stats),
+        None => {
+            if !woke {
+                stats.ignored.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn enqueue(utterance: Utterance, stats: &Arc<SourceStats>){
+    let queue = match utterance_queue().get() {
+        Some(queue) => queue,
+        None => {
+            log::error!("sound pipeline isn't initialized, dropping utterance from {}", utterance.source.id);
+            return;
+        }
+    };
+    busy().fetch_add(1, Ordering::SeqCst);
+    match queue.try_send((utterance, stats.clone())) {
+        Ok(_) => {},
+        Err(TrySendError::Full((utterance, _))) => {
+            busy().fetch_sub(1, Ordering::SeqCst);
+            stats.dropped.fetch_add(1, Ordering::Relaxed);
+            log::warn!("recognizers are busy, dropped {}ms utterance from {}", utterance.duration_ms(), utterance.source.id);
+        },
+        Err(TrySendError::Disconnected(_)) => {
+            busy().fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+// Captures a thing's rtsp audio with ffmpeg, decoded straight to pcm on stdout
+pub fn start_rtsp(thing: &crate::sam::memory::Thing){
+    let source = AudioSource::thing(thing);
+    let mut sender = start(source.clone(), detector_for(Some(&thing.oid)));
+
+    let spawned = Command::new("ffmpeg")
+        .args(["-nostdin", "-loglevel", "error", "-i", crate::sam::services::rtsp::rtsp_address(thing).as_str()])
+        .args(["-vn", "-ac", "1", "-ar", SAMPLE_RATE.to_string().as_str(), "-f", "s16le", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            log::error!("failed to start audio capture for {}: {}", thing.name, e);
+            stop(&source.id);
+            return;
+        }
+    };
+    let mut stdout = match child.stdout.take() {
+        Some(stdout) => stdout,
+        None => {
+            log::error!("audio capture for {} has no stdout", thing.name);
+            let _ = child.kill();
+            let _ = child.wait();
+            stop(&source.id);
+            return;
+        }
+    };
+
+    let capture = thread::Builder::new().name(format!("sound_capture_{}", source.id)).spawn(move || {
+        let mut buffer = [0u8; FRAME_SAMPLES * 2];
+        loop {
+            match stdout.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    // An odd read leaves half a sample behind, read the other byte before sending
+                    let mut n = n;
+                    if n % 2 == 1 {
+                        match stdout.read_exact(&mut buffer[n..n + 1]) {
+                            Ok(_) => n = n + 1,
+                            Err(_) => break
+                        }
+                    }
+                    if sender.send_bytes(&buffer[..n]).is_err() {
+                        break;
+                    }
+                },
+                Err(e) => {
+                    log::error!("audio capture read failed: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut sources = sources().lock().unwrap();
+    match sources.get_mut(&source.id) {
+        Some(handle) => {
+            handle.child = Some(child);
+            match capture {
+                Ok(capture) => handle.threads.push(capture),
+                Err(e) => log::error!("failed to start audio capture thread for {}: {}", source.id, e)
+            }
+        },
+        None => {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// Voice activity detection tuned by the thing's microphone_threshold
+pub fn detector_for(thing_oid: Option<&str>) -> Box<dyn SpeechDetector> {
+    let threshold = crate::sam::services::sound::vad::threshold_for(thing_oid);
+    return Box::new(crate::sam::services::sound::vad::Vad::new(SAMPLE_RATE, threshold));
+}
+
+// Stops a source, what it had already heard is still recognized
+pub fn stop(source_id: &str){
+    let handle = sources().lock().unwrap().remove(source_id);
+    match handle {
+        Some(mut handle) => {
+            handle.stop.store(true, Ordering::SeqCst);
+            match handle.child.as_mut() {
+                Some(child) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                },
+                None => {}
+            }
+            // A browser source's segmenter ends when its socket drops the sender
+            if handle.child.is_some() {
+                for thread in handle.threads.drain(..) {
+                    let _ = thread.join();
+                }
+            }
+        },
+        None => {}
+    }
+}
+
+// False once a source's capture or segmenter has exited
+pub fn running(source_id: &str) -> bool {
+    match sources().lock().unwrap().get_mut(source_id) {
+        Some(handle) => {
+            let threads_alive = handle.threads.len() > 0 && handle.threads.iter().all(|t| !t.is_finished());
+            let child_alive = match handle.child.as_mut() {
+                Some(child) => match child.try_wait() {
+                    Ok(None) => true,
+                    _ => false
+                },
+                None => true
+            };
+            threads_alive && child_alive
+        },
+        None => false
+    }
+}
+
+pub fn status() -> Vec<SourceStatus> {
+    let ids: Vec<String> = sources().lock().unwrap().keys().cloned().collect();
+    let mut statuses: Vec<SourceStatus> = Vec::new();
+    for id in ids {
+        let is_running = running(&id);
+        match sources().lock().unwrap().get(&id) {
+            Some(handle) => {
+                // Session ids are as good as a login
+                let mut source = handle.source.clone();
+                match source.web_session.as_mut() {
+                    Some(session) => session.sid = String::new(),
+                    None => {}
+                }
+                statuses.push(SourceStatus {
+                    source: source,
+                    running: is_running,
+                    started_at: handle.started_at,
+                    frames: handle.stats.frames.load(Ordering::Relaxed),
+                    utterances: handle.stats.utterances.load(Ordering::Relaxed),
+                    dropped: handle.stats.dropped.load(Ordering::Relaxed),
+                    ignored: handle.stats.ignored.load(Ordering::Relaxed),
+                    wake_words: handle.stats.wake_words.load(Ordering::Relaxed),
+                });
+            },
+            None => {}
+        }
+    }
+    return statuses;
+}
@@ -0,0 +1,235 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// stream.rs takes a browser microphone over a websocket and feeds it to the
+// sound pipeline, the socket is authenticated by the session cookie like any
+// other request:
+//
+//   GET /api/services/sound/stream?format=pcm&rate=48000
+//
+// Binary messages are audio. format=pcm (the default) is little endian s16
+// mono at rate (16000), format=opus is whatever MediaRecorder produces (webm
+// or ogg). Anything but 16kHz pcm is decoded by ffmpeg. Text messages are
+// json, {"type": "stop"} ends the stream.
+//
+// Sam answers with {"type": "ready"}, then a {"type": "partial"} or
+// {"type": "final"} Transcript as each is recognized, {"type": "error"} when
+// something breaks and {"type": "done"} once the last utterance is through.
+
+use rouille::websocket::{Message, Websocket};
+use rouille::Request;
+use rouille::Response;
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::sam::services::sound::pipeline::{AudioSource, FrameSender, Transcript, FRAME_SAMPLES, SAMPLE_RATE};
+
+// How long a stopped stream waits for its last transcripts
+const FINISH_SECONDS: u64 = 60;
+
+pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+    let format = request.get_param("format").unwrap_or(format!("pcm"));
+    if format != "pcm" && format != "opus" {
+        return Ok(Response::text(format!("unknown format {}, use pcm or opus", format)).with_status_code(400));
+    }
+    let rate = match request.get_param("rate") {
+        Some(rate) => match rate.parse::<u32>() {
+            Ok(rate) if rate >= 8000 && rate <= 96000 => rate,
+            _ => return Ok(Response::text(format!("rate should be 8000 to 96000")).with_status_code(400))
+        },
+        None => SAMPLE_RATE
+    };
+
+    let (response, websocket) = match rouille::websocket::start(request, None::<&str>) {
+        Ok(started) => started,
+        Err(e) => return Ok(Response::text(format!("expected a websocket: {:?}", e)).with_status_code(400))
+    };
+
+    let spawned = thread::Builder::new().name(format!("sound_stream_{}", current_session.oid)).spawn(move || {
+        match websocket.recv() {
+            Ok(socket) => stream(socket, current_session, &format, rate),
+            Err(_) => {}
+        }
+    });
+    match spawned {
+        Ok(_) => {},
+        Err(e) => log::error!("failed to start sound stream: {}", e)
+    }
+
+    return Ok(response);
+}
+
+fn send(socket: &mut Websocket, message: serde_json::Value){
+    // A closed socket only means nobody is left to read it
+    let _ = socket.send_text(&message.to_string());
+}
+
+fn send_transcript(socket: &mut Websocket, transcript: Transcript){
+    let mut message = serde_json::to_value(&transcript).unwrap_or(serde_json::json!({}));
+    message["type"] = serde_json::Value::String(transcript.kind.clone());
+    send(socket, message);
+}
+
+fn stream(mut socket: Websocket, session: crate::sam::memory::WebSessions, format: &str, rate: u32){
+    let source = AudioSource::web(&session);
+    let (sender, transcripts) = crate::sam::services::sound::pipeline::start_streaming(source.clone(), crate::sam::services::sound::pipeline::detector_for(None));
+    let mut decoder = match Decoder::new(&source, sender, format, rate) {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            send(&mut socket, serde_json::json!({"type": "error", "message": format!("{}", e)}));
+            crate::sam::services::sound::pipeline::stop(&source.id);
+            return;
+        }
+    };
+    log::info!("browser microphone {} connected", source.id);
+    send(&mut socket, serde_json::json!({"type": "ready", "source": source.id, "sample_rate": SAMPLE_RATE}));
+
+    loop {
+        let message = match socket.next() {
+            Some(message) => message,
+            None => break
+        };
+        match message {
+            Message::Binary(data) => {
+                match decoder.write(&data) {
+                    Ok(_) => {},
+                    Err(e) => {
+                        send(&mut socket, serde_json::json!({"type": "error", "message": format!("{}", e)}));
+                        break;
+                    }
+                }
+            },
+            Message::Text(text) => {
+                let parsed: serde_json::Value = serde_json::from_str(&text).unwrap_or(serde_json::Value::Null);
+                if parsed["type"].as_str() == Some("stop") {
+                    break;
+                }
+            }
+        }
+
+        // Browsers send audio every few dozen milliseconds, that's often enough to answer
+        while let Ok(transcript) = transcripts.try_recv() {
+            send_transcript(&mut socket, transcript);
+        }
+    }
+
+    decoder.finish();
+    finish(&mut socket, &transcripts);
+    crate::sam::services::sound::pipeline::stop(&source.id);
+    log::info!("browser microphone {} disconnected", source.id);
+}
+
+// The last utterance is only recognized once the segmenter has flushed it
+fn finish(socket: &mut Websocket, transcripts: &Receiver<Transcript>){
+    let deadline = Instant::now() + Duration::from_secs(FINISH_SECONDS);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match transcripts.recv_timeout(remaining) {
+            Ok(transcript) => send_transcript(socket, transcript),
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => {
+                send(socket, serde_json::json!({"type": "done"}));
+                break;
+            }
+        }
+    }
+}
+
+enum Decoder {
+    // 16kHz pcm goes straight to the pipeline
+    Pcm(FrameSender),
+    Ffmpeg {
+        child: Child,
+        stdin: Option<ChildStdin>,
+        capture: JoinHandle<()>,
+    },
+}
+impl Decoder {
+    fn new(source: &AudioSource, sender: FrameSender, format: &str, rate: u32) -> Result<Decoder, crate::sam::services::Error> {
+        if format == "pcm" && rate == SAMPLE_RATE {
+            return Ok(Decoder::Pcm(sender));
+        }
+
+        let mut command = Command::new("ffmpeg");
+        command.args(["-nostdin", "-loglevel", "error", "-fflags", "nobuffer"]);
+        if format == "pcm" {
+            command.args(["-f", "s16le", "-ac", "1", "-ar", rate.to_string().as_str()]);
+        }
+        command.args(["-i", "pipe:0", "-vn", "-ac", "1", "-ar", SAMPLE_RATE.to_string().as_str(), "-f", "s16le", "-"]);
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn()?;
+        let stdin = child.stdin.take();
+        let mut stdout = match child.stdout.take() {
+            Some(stdout) => stdout,
+            None => return Err(format!("ffmpeg has no stdout").into())
+        };
+
+        let mut sender = sender;
+        let capture = thread::Builder::new().name(format!("sound_decoder_{}", source.id)).spawn(move || {
+            let mut buffer = [0u8; FRAME_SAMPLES * 2];
+            loop {
+                match stdout.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let mut n = n;
+                        if n % 2 == 1 {
+                            match stdout.read_exact(&mut buffer[n..n + 1]) {
+                                Ok(_) => n = n + 1,
+                                Err(_) => break
+                            }
+                        }
+                        if sender.send_bytes(&buffer[..n]).is_err() {
+                            break;
+                        }
+                    },
+                    Err(_) => break
+                }
+            }
+        })?;
+
+        return Ok(Decoder::Ffmpeg {
+            child: child,
+            stdin: stdin,
+            capture: capture,
+        });
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), crate::sam::services::Error> {
+        match self {
+            Decoder::Pcm(sender) => {
+                if data.len() % 2 == 1 {
+                    return Err(format!("pcm messages should be whole s16 samples").into());
+                }
+                return sender.send_bytes(data);
+            },
+            Decoder::Ffmpeg{stdin, ..} => {
+                match stdin.as_mut() {
+                    Some(stdin) => stdin.write_all(data)?,
+                    None => {}
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    // Drops the sender, ffmpeg gets end of file and drains first
+    fn finish(self){
+        match self {
+            Decoder::Pcm(sender) => drop(sender),
+            Decoder::Ffmpeg{mut child, stdin, capture} => {
+                drop(stdin);
+                let _ = capture.join();
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// vad.rs decides which frames are speech. Each frame is windowed and run
+// through an fft, a frame counts as speech-like when:
+//   - its energy is margin_db above the running noise floor
+//   - most of that energy sits in the voice band (300-3400Hz)
+//   - the voice band is harmonic rather than flat like a slam or a fan
+// The noise floor follows quiet frames quickly and loud ones slowly, so
+// a fan turning on stops counting as speech within a second or so. Speech
+// starts after ONSET_FRAMES speech-like frames in a row and survives dips
+// shorter than HANGOVER_FRAMES.
+//
+// Sensitivity comes from the microphone_threshold setting. The first plain
+// number is the default, thing_oid:number entries override it per Thing.
+// The old gate used it as a peak amplitude, it is now read as
+// 20*log10(threshold/5600) dB above the noise floor, so the default of 14000
+// is 8dB and halving it makes the detector 6dB more sensitive.
+//
+// fixtures/ holds synthetic wavs (generate.py) with the utterances the
+// segmenter should cut from them (expected.json), the tests below check them.
+
+use serde::{Serialize, Deserialize};
+
+use crate::sam::services::sound::pipeline::{AudioFrame, SpeechDetector, SAMPLE_RATE};
+
+pub const DEFAULT_THRESHOLD: f64 = 14000.0;
+
+const ONSET_FRAMES: usize = 3;
+const HANGOVER_FRAMES: usize = 5;
+const VOICE_LOW_HZ: f64 = 300.0;
+const VOICE_HIGH_HZ: f64 = 3400.0;
+const MIN_VOICE_RATIO: f64 = 0.5;
+const MAX_FLATNESS: f64 = 0.35;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FrameFeatures {
+    pub energy_db: f64,
+    // Share of the energy inside the voice band
+    pub voice_ratio: f64,
+    // Spectral flatness of the voice band, 0 for a pure tone and ~0.56 for white noise
+    pub flatness: f64,
+}
+
+pub struct Vad {
+    sample_rate: u32,
+    margin_db: f64,
+    noise_floor_db: Option<f64>,
+    run: usize,
+    hangover: usize,
+    active: bool,
+}
+impl Vad {
+    pub fn new(sample_rate: u32, threshold: f64) -> Vad {
+        Vad {
+            sample_rate: sample_rate,
+            margin_db: margin_db(threshold),
+            noise_floor_db: None,
+            run: 0,
+            hangover: 0,
+            active: false,
+        }
+    }
+
+    pub fn noise_floor_db(&self) -> Option<f64> {
+        return self.noise_floor_db;
+    }
+
+    pub fn is_speech_samples(&mut self, samples: &[i16]) -> bool {
+        let features = features(samples, self.sample_rate);
+        let floor = *self.noise_floor_db.get_or_insert(features.energy_db);
+
+        let speech_like = features.energy_db - floor >= self.margin_db
+            && features.voice_ratio >= MIN_VOICE_RATIO
+            && features.flatness <= MAX_FLATNESS;
+
+        // Quiet frames pull the floor down fast and noise pushes it up slowly.
+        // Inside speech, pauses between syllables only nudge it, and the very
+        // slow drift keeps a constant hum from counting as speech forever.
+        let rate = if features.energy_db < floor {
+            0.3
+        } else if speech_like || self.active {
+            0.002
+        } else {
+            0.05
+        };
+        self.noise_floor_db = Some(floor + (features.energy_db - floor) * rate);
+
+        self.run = if speech_like { self.run + 1 } else { 0 };
+        if speech_like && (self.active || self.run >= ONSET_FRAMES) {
+            self.active = true;
+            self.hangover = HANGOVER_FRAMES;
+            return true;
+        }
+        if self.active && self.hangover > 0 {
+            self.hangover = self.hangover - 1;
+            return true;
+        }
+        self.active = false;
+        return false;
+    }
+}
+impl SpeechDetector for Vad {
+    fn is_speech(&mut self, frame: &AudioFrame) -> bool {
+        return self.is_speech_samples(&frame.samples);
+    }
+}
+
+pub fn margin_db(threshold: f64) -> f64 {
+    if threshold <= 0.0 {
+        return margin_db(DEFAULT_THRESHOLD);
+    }
+    return (20.0 * (threshold / 5600.0).log10()).max(3.0).min(30.0);
+}
+
+// microphone_threshold for a thing, see the top of the file
+pub fn threshold_for(thing_oid: Option<&str>) -> f64 {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("microphone_threshold")));
+    pg_query.query_coulmns.push(format!("key ="));
+    let values = match crate::sam::memory::Setting::select(None, None, None, Some(pg_query)) {
+        Ok(settings) => settings.first().map(|s| s.values.clone()).unwrap_or(Vec::new()),
+        Err(e) => {
+            log::error!("failed to load setting microphone_threshold: {}", e);
+            Vec::new()
+        }
+    };
+    return parse_threshold(&values, thing_oid);
+}
+
+pub fn parse_threshold(values: &Vec<String>, thing_oid: Option<&str>) -> f64 {
+    let mut threshold = DEFAULT_THRESHOLD;
+    for value in values.iter() {
+        match value.split_once(":") {
+            Some((oid, number)) => {
+                if Some(oid.trim()) == thing_oid {
+                    match number.trim().parse::<f64>() {
+                        Ok(number) => return number,
+                        Err(_) => {}
+                    }
+                }
+            },
+            None => {
+                match value.trim().parse::<f64>() {
+                    Ok(number) => threshold = number,
+                    Err(_) => {}
+                }
+            }
+        }
+    }
+    return threshold;
+}
+
+pub fn features(samples: &[i16], sample_rate: u32) -> FrameFeatures {
+    let energy = samples.iter().map(|s| (*s as f64) * (*s as f64)).sum::<f64>() / samples.len().max(1) as f64;
+
+    let size = samples.len().max(2).next_power_of_two();
+    let mut re: Vec<f64> = vec![0.0; size];
+    let mut im: Vec<f64> = vec![0.0; size];
+    let n = samples.len();
+    for (i, sample) in samples.iter().enumerate() {
+        // Hann window
+        let w = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (n.max(2) - 1) as f64).cos();
+        re[i] = *sample as f64 * w;
+    }
+    fft(&mut re, &mut im);
+
+    let bin_hz = sample_rate as f64 / size as f64;
+    let mut total = 0.0;
+    let mut voice = 0.0;
+    let mut log_sum = 0.0;
+    let mut voice_bins = 0;
+    // Skip dc, it says nothing about speech
+    for k in 1..size / 2 {
+        let power = re[k] * re[k] + im[k] * im[k];
+        total = total + power;
+        let hz = k as f64 * bin_hz;
+        if hz >= VOICE_LOW_HZ && hz <= VOICE_HIGH_HZ {
+            voice = voice + power;
+            log_sum = log_sum + (power + 1e-9).ln();
+            voice_bins = voice_bins + 1;
+        }
+    }
+
+    let flatness = if voice_bins > 0 && voice > 0.0 {
+        (log_sum / voice_bins as f64).exp() / (voice / voice_bins as f64)
+    } else {
+        1.0
+    };
+
+    return FrameFeatures {
+        energy_db: 10.0 * (energy + 1.0).log10(),
+        voice_ratio: if total > 0.0 { voice / total } else { 0.0 },
+        flatness: flatness,
+    };
+}
+
+// In place radix-2 fft, the length must be a power of two
+pub fn fft(re: &mut Vec<f64>, im: &mut Vec<f64>) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j = j ^ bit;
+            bit = bit >> 1;
+        }
+        j = j | bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f64::consts::PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] = re[a] + tr;
+                im[a] = im[a] + ti;
+            }
+        }
+        len = len << 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sam::services::sound::pipeline::{AudioSource, Segmenter, FRAME_SAMPLES};
+
+    // How far a boundary may drift from the fixture's expectation
+    const TOLERANCE_MS: i64 = 150;
+
+    #[derive(Deserialize)]
+    struct FixtureExpectation {
+        file: String,
+        description: String,
+        utterances: Vec<(i64, i64)>,
+    }
+
+    fn fixture_path(file: &str) -> String {
+        return format!("{}/src/sam/services/sound/fixtures/{}", env!("CARGO_MANIFEST_DIR"), file);
+    }
+
+    // Runs a whole clip through a fresh Vad and Segmenter, returns (start_ms, end_ms) per utterance
+    fn segment(samples: &[i16], threshold: f64) -> Vec<(i64, i64)> {
+        let source = AudioSource {
+            id: format!("fixture"),
+            thing_oid: None,
+            web_session: None,
+        };
+        let mut segmenter = Segmenter::new(source, Box::new(Vad::new(SAMPLE_RATE, threshold)));
+        let mut utterances: Vec<(i64, i64)> = Vec::new();
+        for (i, chunk) in samples.chunks(FRAME_SAMPLES).enumerate() {
+            let frame = AudioFrame {
+                samples: chunk.to_vec(),
+                timestamp_ms: i as i64 * FRAME_SAMPLES as i64 * 1000 / SAMPLE_RATE as i64,
+            };
+            match segmenter.push(frame) {
+                Some(utterance) => utterances.push((utterance.started_at_ms, utterance.ended_at_ms)),
+                None => {}
+            }
+        }
+        match segmenter.flush() {
+            Some(utterance) => utterances.push((utterance.started_at_ms, utterance.ended_at_ms)),
+            None => {}
+        }
+        return utterances;
+    }
+
+    // Segments a fixture at the default threshold and compares the boundaries with expected.json
+    fn check(file: &str) {
+        let expected = std::fs::read_to_string(fixture_path("expected.json")).unwrap();
+        let expectations: Vec<FixtureExpectation> = serde_json::from_str(&expected).unwrap();
+        let expectation = expectations.into_iter().find(|e| e.file == file).expect("fixture missing from expected.json");
+
+        let reader = hound::WavReader::open(fixture_path(file)).unwrap();
+        assert_eq!((reader.spec().sample_rate, reader.spec().channels), (SAMPLE_RATE, 1), "{} must be 16kHz mono", file);
+        let samples = reader.into_samples::<i16>().collect::<Result<Vec<i16>, hound::Error>>().unwrap();
+        let found = segment(&samples, DEFAULT_THRESHOLD);
+
+        assert_eq!(found.len(), expectation.utterances.len(), "{} ({}): found {:?}", file, expectation.description, found);
+        for (f, e) in found.iter().zip(expectation.utterances.iter()) {
+            assert!((f.0 - e.0).abs() <= TOLERANCE_MS && (f.1 - e.1).abs() <= TOLERANCE_MS, "{} ({}): found {:?}, expected {:?}", file, expectation.description, f, e);
+        }
+    }
+
+    #[test]
+    fn quiet_speaker() {
+        check("quiet_speaker.wav");
+    }
+
+    #[test]
+    fn door_slam() {
+        check("door_slam.wav");
+    }
+
+    #[test]
+    fn two_utterances() {
+        check("two_utterances.wav");
+    }
+
+    #[test]
+    fn fan_noise() {
+        check("fan_noise.wav");
+    }
+
+    #[test]
+    fn room_tone() {
+        check("room_tone.wav");
+    }
+}
@@ -0,0 +1,702 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// wake.rs listens for the wake word before anything is transcribed.
+// WakeWordTemplates are recordings of the phrase. Every utterance the vad
+// cuts is turned into mfcc frames and its opening is compared against each
+// template with subsequence dtw, which costs a few milliseconds. A match
+// arms the source: the rest of that utterance, and anything said within
+// wake_word_listen_seconds, is transcribed and handed to the io brain.
+// Everything else is dropped without being transcribed, unless the thing is
+// listed in continuous_transcription. Until a template is recorded for the
+// phrase every source is continuous, nothing could wake sam otherwise.
+//
+// Settings:
+//   wake_word_phrase          which templates are used (sam)
+//   wake_word_sensitivity     0-100 (50), higher accepts looser matches
+//   wake_word_listen_seconds  how long a source stays armed (8)
+//   continuous_transcription  thing oids that transcribe everything, or all
+
+use serde::{Serialize, Deserialize};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::sam::services::sound::pipeline::{Utterance, SAMPLE_RATE};
+
+pub const DEFAULT_PHRASE: &str = "sam";
+const DEFAULT_SENSITIVITY: f64 = 50.0;
+const DEFAULT_LISTEN_SECONDS: u64 = 8;
+
+// Typical dtw distance between two recordings of the same phrase, used
+// until a second template gives a real one
+const DEFAULT_BASELINE: f64 = 8.0;
+
+// 25ms frames every 10ms
+const MFCC_FRAME: usize = 400;
+const MFCC_HOP: usize = 160;
+const MFCC_FFT: usize = 512;
+const MEL_FILTERS: usize = 26;
+const CEPSTRA: usize = 12;
+// Frames the cepstral mean is taken over
+const CMN_FRAMES: usize = 60;
+
+// The phrase has to start within the first second of an utterance
+const MAX_START_FRAMES: usize = 100;
+// Anything shorter left after the phrase is just the tail of the phrase
+const MIN_COMMAND_MS: i64 = 300;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WakeMatch {
+    pub template_oid: String,
+    pub distance: f64,
+    pub threshold: f64,
+    pub start_ms: i64,
+    pub end_ms: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WakeConfig {
+    pub phrase: String,
+    pub sensitivity: f64,
+    pub listen_ms: i64,
+    pub continuous: Vec<String>,
+    pub templates: Vec<(String, Vec<Vec<f32>>)>,
+    pub baseline: f64,
+}
+impl WakeConfig {
+    pub fn threshold(&self) -> f64 {
+        // 0 accepts only what is as close as the templates are to each other, 100 three times that
+        return self.baseline * (1.0 + self.sensitivity.max(0.0).min(100.0) / 50.0);
+    }
+
+    pub fn is_continuous(&self, thing_oid: Option<&str>) -> bool {
+        if self.templates.len() == 0 {
+            return true;
+        }
+        return self.continuous.iter().any(|c| c == "all" || Some(c.as_str()) == thing_oid);
+    }
+}
+
+fn cache() -> &'static Mutex<Option<Arc<WakeConfig>>> {
+    static CACHE: OnceLock<Mutex<Option<Arc<WakeConfig>>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(None))
+}
+
+pub fn init(){
+    crate::sam::services::bus::subscribe("wake_word", |event| {
+        match event {
+            crate::sam::services::bus::Event::SettingChanged{key, ..} => {
+                if key.starts_with("wake_word_") || key == "continuous_transcription" {
+                    reload();
+                }
+            },
+            _ => {}
+        }
+    });
+}
+
+// Drops the cached settings and templates, the next utterance loads them again
+pub fn reload(){
+    *cache().lock().unwrap() = None;
+}
+
+pub fn config() -> Arc<WakeConfig> {
+    match cache().lock().unwrap().as_ref() {
+        Some(config) => return config.clone(),
+        None => {}
+    }
+    let config = Arc::new(load());
+    *cache().lock().unwrap() = Some(config.clone());
+    return config;
+}
+
+fn setting_values(key: &str) -> Vec<String> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(key.to_string()));
+    pg_query.query_coulmns.push(format!("key ="));
+    match crate::sam::memory::Setting::select(None, None, None, Some(pg_query)) {
+        Ok(settings) => settings.first().map(|s| s.values.clone()).unwrap_or(Vec::new()),
+        Err(e) => {
+            log::error!("failed to load setting {}: {}", key, e);
+            Vec::new()
+        }
+    }
+}
+
+fn load() -> WakeConfig {
+    let phrase = setting_values("wake_word_phrase").first().map(|p| normalize_phrase(p)).filter(|p| p.len() > 0).unwrap_or(DEFAULT_PHRASE.to_string());
+    let sensitivity = setting_values("wake_word_sensitivity").first().and_then(|s| s.trim().parse::<f64>().ok()).unwrap_or(DEFAULT_SENSITIVITY);
+    let listen_seconds = crate::sam::services::things::setting_seconds("wake_word_listen_seconds", DEFAULT_LISTEN_SECONDS);
+
+    let templates: Vec<(String, Vec<Vec<f32>>)> = match templates(&phrase) {
+        Ok(templates) => templates.into_iter().filter(|t| t.features.len() > 0).map(|t| (t.oid, t.features)).collect(),
+        Err(e) => {
+            log::error!("failed to load wake word templates: {}", e);
+            Vec::new()
+        }
+    };
+    if templates.len() == 0 {
+        log::warn!("no wake word templates recorded for \"{}\", everything heard will be transcribed", phrase);
+    }
+
+    return WakeConfig {
+        phrase: phrase,
+        sensitivity: sensitivity,
+        listen_ms: listen_seconds as i64 * 1000,
+        continuous: setting_values("continuous_transcription").iter().map(|c| c.trim().to_string()).filter(|c| c.len() > 0).collect(),
+        baseline: baseline(&templates),
+        templates: templates,
+    };
+}
+
+pub fn normalize_phrase(phrase: &str) -> String {
+    return phrase.trim().to_lowercase().split_whitespace().collect::<Vec<&str>>().join(" ");
+}
+
+pub fn templates(phrase: &str) -> Result<Vec<crate::sam::memory::WakeWordTemplate>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(phrase.to_string()));
+    pg_query.query_coulmns.push(format!("phrase ="));
+    return Ok(crate::sam::memory::WakeWordTemplate::select(None, None, Some(format!("created_at ASC")), Some(pg_query))?);
+}
+
+// Mean distance between every pair of templates
+fn baseline(templates: &Vec<(String, Vec<Vec<f32>>)>) -> f64 {
+    let mut distances: Vec<f64> = Vec::new();
+    for (i, a) in templates.iter().enumerate() {
+        for b in templates.iter().skip(i + 1) {
+            match find(&a.1, &b.1, 1) {
+                Some((distance, _, _)) => distances.push(distance),
+                None => {}
+            }
+        }
+    }
+    if distances.len() == 0 {
+        return DEFAULT_BASELINE;
+    }
+    return distances.iter().sum::<f64>() / distances.len() as f64;
+}
+
+// Trims silence around a recording of the phrase, the loudest frame minus 30dB counts as sound
+pub fn trim(samples: &[i16]) -> Vec<i16> {
+    let frame = MFCC_HOP;
+    let energies: Vec<f64> = samples.chunks(frame).map(|c| {
+        10.0 * (c.iter().map(|s| (*s as f64) * (*s as f64)).sum::<f64>() / c.len() as f64 + 1.0).log10()
+    }).collect();
+    let loudest = energies.iter().cloned().fold(0.0, f64::max);
+    let first = energies.iter().position(|e| *e >= loudest - 30.0).unwrap_or(0);
+    let last = energies.iter().rposition(|e| *e >= loudest - 30.0).unwrap_or(energies.len().saturating_sub(1));
+    let start = first * frame;
+    let end = ((last + 1) * frame).min(samples.len());
+    return samples[start..end].to_vec();
+}
+
+pub fn mel(hz: f64) -> f64 {
+    return 2595.0 * (1.0 + hz / 700.0).log10();
+}
+
+pub fn mel_to_hz(mel: f64) -> f64 {
+    return 700.0 * (10f64.powf(mel / 2595.0) - 1.0);
+}
+
+// Triangular filters from 20Hz to 7600Hz over the fft bins
+fn filterbank() -> &'static Vec<Vec<(usize, f64)>> {
+    static FILTERS: OnceLock<Vec<Vec<(usize, f64)>>> = OnceLock::new();
+    FILTERS.get_or_init(|| {
+        let low = mel(20.0);
+        let high = mel(7600.0);
+        let points: Vec<f64> = (0..MEL_FILTERS + 2).map(|i| {
+            let hz = mel_to_hz(low + (high - low) * i as f64 / (MEL_FILTERS + 1) as f64);
+            hz * MFCC_FFT as f64 / SAMPLE_RATE as f64
+        }).collect();
+        let mut filters: Vec<Vec<(usize, f64)>> = Vec::new();
+        for m in 1..=MEL_FILTERS {
+            let mut weights: Vec<(usize, f64)> = Vec::new();
+            for k in 0..=MFCC_FFT / 2 {
+                let bin = k as f64;
+                let weight = if bin >= points[m - 1] && bin <= points[m] {
+                    (bin - points[m - 1]) / (points[m] - points[m - 1]).max(1e-9)
+                } else if bin > points[m] && bin <= points[m + 1] {
+                    (points[m + 1] - bin) / (points[m + 1] - points[m]).max(1e-9)
+                } else {
+                    0.0
+                };
+                if weight > 0.0 {
+                    weights.push((k, weight));
+                }
+            }
+            filters.push(weights);
+        }
+        filters
+    })
+}
+
+// 12 mel cepstra per 10ms with the mean removed, so the microphone matters less
+pub fn mfcc(samples: &[i16]) -> Vec<Vec<f32>> {
+    let mut frames: Vec<Vec<f64>> = Vec::new();
+    let mut loudness: Vec<f64> = Vec::new();
+    if samples.len() < MFCC_FRAME {
+        return Vec::new();
+    }
+    let mut start = 0;
+    while start + MFCC_FRAME <= samples.len() {
+        let mut re: Vec<f64> = vec![0.0; MFCC_FFT];
+        let mut im: Vec<f64> = vec![0.0; MFCC_FFT];
+        for i in 0..MFCC_FRAME {
+            let previous = if start + i > 0 { samples[start + i - 1] as f64 } else { 0.0 };
+            // Pre-emphasis, then a hamming window
+            let value = samples[start + i] as f64 - 0.97 * previous;
+            let w = 0.54 - 0.46 * (2.0 * std::f64::consts::PI * i as f64 / (MFCC_FRAME - 1) as f64).cos();
+            re[i] = value * w;
+        }
+        crate::sam::services::sound::vad::fft(&mut re, &mut im);
+        let power: Vec<f64> = (0..=MFCC_FFT / 2).map(|k| (re[k] * re[k] + im[k] * im[k]) / MFCC_FFT as f64).collect();
+
+        let energies: Vec<f64> = filterbank().iter().map(|filter| {
+            (filter.iter().map(|(k, w)| power[*k] * w).sum::<f64>() + 1e-10).ln()
+        }).collect();
+
+        // DCT-II, c0 is loudness and is left out
+        let cepstra: Vec<f64> = (1..=CEPSTRA).map(|c| {
+            energies.iter().enumerate().map(|(m, e)| {
+                e * (std::f64::consts::PI * c as f64 * (m as f64 + 0.5) / MEL_FILTERS as f64).cos()
+            }).sum::<f64>()
+        }).collect();
+        frames.push(cepstra);
+        loudness.push((power.iter().sum::<f64>() + 1e-10).ln());
+        start = start + MFCC_HOP;
+    }
+
+    // The mean is taken over voiced frames (within 20dB of the loudest) in a
+    // window around each frame, so the words after the phrase don't shift it
+    let loudest = loudness.iter().cloned().fold(f64::MIN, f64::max);
+    let voiced: Vec<bool> = loudness.iter().map(|l| *l >= loudest - 4.6).collect();
+    let mut normalized: Vec<Vec<f32>> = Vec::new();
+    for i in 0..frames.len() {
+        let from = i.saturating_sub(CMN_FRAMES / 2);
+        let to = (i + CMN_FRAMES / 2).min(frames.len());
+        let mut mean = vec![0.0; CEPSTRA];
+        let mut count = 0.0;
+        for j in from..to {
+            if voiced[j] {
+                for c in 0..CEPSTRA {
+                    mean[c] = mean[c] + frames[j][c];
+                }
+                count = count + 1.0;
+            }
+        }
+        normalized.push((0..CEPSTRA).map(|c| (frames[i][c] - mean[c] / f64::max(count, 1.0)) as f32).collect());
+    }
+    return normalized;
+}
+
+fn distance(a: &Vec<f32>, b: &Vec<f32>) -> f64 {
+    return a.iter().zip(b.iter()).map(|(x, y)| ((x - y) * (x - y)) as f64).sum::<f64>().sqrt();
+}
+
+// Subsequence dtw, the template may start in the first max_start frames and end anywhere.
+// Returns the length normalized distance and the first and last matched frame.
+pub fn find(template: &Vec<Vec<f32>>, frames: &Vec<Vec<f32>>, max_start: usize) -> Option<(f64, usize, usize)> {
+    let m = template.len();
+    let n = frames.len();
+    if m == 0 || n == 0 {
+        return None;
+    }
+
+    // (cost, path length, start frame) for the previous and current template frame
+    let mut previous: Vec<(f64, usize, usize)> = vec![(f64::INFINITY, 0, 0); n];
+    let mut current: Vec<(f64, usize, usize)> = vec![(f64::INFINITY, 0, 0); n];
+    for i in 0..m {
+        for j in 0..n {
+            let d = distance(&template[i], &frames[j]);
+            let mut best: (f64, usize, usize) = (f64::INFINITY, 0, 0);
+            if i == 0 && j < max_start.max(1) {
+                best = (0.0, 0, j);
+            }
+            let candidates = [
+                if i > 0 && j > 0 { Some(previous[j - 1]) } else { None },
+                if i > 0 { Some(previous[j]) } else { None },
+                if j > 0 { Some(current[j - 1]) } else { None },
+            ];
+            for candidate in candidates.iter().flatten() {
+                if candidate.0 < best.0 {
+                    best = *candidate;
+                }
+            }
+            current[j] = (best.0 + d, best.1 + 1, best.2);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    let mut result: Option<(f64, usize, usize)> = None;
+    for (j, cell) in previous.iter().enumerate() {
+        if !cell.0.is_finite() {
+            continue;
+        }
+        // Squashing or stretching the phrase more than twice over isn't a match
+        let span = j + 1 - cell.2;
+        if span * 2 < m || span > m * 2 {
+            continue;
+        }
+        let normalized = cell.0 / cell.1 as f64;
+        if result.map(|r| normalized < r.0).unwrap_or(true) {
+            result = Some((normalized, cell.2, j));
+        }
+    }
+    return result;
+}
+
+// Looks for the phrase at the start of the samples
+pub fn detect(config: &WakeConfig, samples: &[i16]) -> Option<WakeMatch> {
+    if config.templates.len() == 0 {
+        return None;
+    }
+    let longest = config.templates.iter().map(|t| t.1.len()).max().unwrap_or(0);
+    let window = ((MAX_START_FRAMES + longest * 2) * MFCC_HOP + MFCC_FRAME).min(samples.len());
+    let frames = mfcc(&samples[..window]);
+
+    let threshold = config.threshold();
+    let mut best: Option<WakeMatch> = None;
+    for (oid, template) in config.templates.iter() {
+        match find(template, &frames, MAX_START_FRAMES) {
+            Some((distance, start, end)) => {
+                if best.as_ref().map(|b| distance < b.distance).unwrap_or(true) {
+                    best = Some(WakeMatch {
+                        template_oid: oid.clone(),
+                        distance: distance,
+                        threshold: threshold,
+                        start_ms: (start * MFCC_HOP) as i64 * 1000 / SAMPLE_RATE as i64,
+                        end_ms: (end * MFCC_HOP + MFCC_FRAME) as i64 * 1000 / SAMPLE_RATE as i64,
+                    });
+                }
+            },
+            None => {}
+        }
+    }
+    return best.filter(|b| b.distance <= b.threshold);
+}
+
+// Drops the phrase from the start of a transcript, "Sam, lights on" becomes "lights on"
+pub fn strip_phrase(text: &str, phrase: &str) -> String {
+    let cleaned = text.trim();
+    let phrase = normalize_phrase(phrase);
+    // Only near the start and only the whole word, samantha stays samantha
+    let end = cleaned.char_indices().take(5)
+        .find_map(|(start, _)| phrase_len(&cleaned[start..], &phrase).map(|len| start + len))
+        .filter(|end| !cleaned[*end..].chars().next().map(|c| c.is_alphanumeric()).unwrap_or(false));
+    match end {
+        Some(end) => {
+            let rest = &cleaned[end..];
+            return rest.trim_start_matches(|c: char| c == ',' || c == '.' || c == '!' || c == '?' || c.is_whitespace()).to_string();
+        },
+        None => return cleaned.to_string()
+    }
+}
+
+// Bytes of text that spell the (lowercase) phrase, compared a char at a time
+// since lowercasing can change how many bytes a char takes
+fn phrase_len(text: &str, phrase: &str) -> Option<usize> {
+    let mut wanted = phrase.chars().peekable();
+    for (i, c) in text.char_indices() {
+        if wanted.peek().is_none() {
+            return Some(i);
+        }
+        for lower in c.to_lowercase() {
+            if wanted.next() != Some(lower) {
+                return None;
+            }
+        }
+    }
+    return if wanted.peek().is_none() { Some(text.len()) } else { None };
+}
+
+// How much speech the vad hears after the cut, the whole utterance primes its noise floor
+fn speech_ms(samples: &[i16], cut: usize, threshold: f64) -> i64 {
+    let mut vad = crate::sam::services::sound::vad::Vad::new(SAMPLE_RATE, threshold);
+    let frame = crate::sam::services::sound::pipeline::FRAME_SAMPLES;
+    let mut speech = 0;
+    for (i, chunk) in samples.chunks(frame).enumerate() {
+        if vad.is_speech_samples(chunk) && i * frame >= cut {
+            speech = speech + chunk.len();
+        }
+    }
+    return speech as i64 * 1000 / SAMPLE_RATE as i64;
+}
+
+// Decides, per source, which utterances get transcribed
+pub struct Listener {
+    thing_oid: Option<String>,
+    // A browser microphone is opened on purpose, everything it hears is transcribed
+    browser: bool,
+    armed_until_ms: i64,
+    // The thing's microphone_threshold, loaded the first time the phrase is heard
+    vad_threshold: Option<f64>,
+    pub wake_words: u64,
+}
+impl Listener {
+    pub fn new(source: &crate::sam::services::sound::pipeline::AudioSource) -> Listener {
+        Listener {
+            thing_oid: source.thing_oid.clone(),
+            browser: source.web_session.is_some(),
+            armed_until_ms: 0,
+            vad_threshold: None,
+            wake_words: 0,
+        }
+    }
+
+    // None drops the utterance unheard
+    pub fn hear(&mut self, utterance: Utterance) -> Option<Utterance> {
+        return self.hear_with(&config(), utterance);
+    }
+
+    fn hear_with(&mut self, config: &WakeConfig, mut utterance: Utterance) -> Option<Utterance> {
+        let continuous = self.browser || config.is_continuous(self.thing_oid.as_deref());
+
+        if utterance.started_at_ms < self.armed_until_ms {
+            self.armed_until_ms = utterance.ended_at_ms + config.listen_ms;
+            utterance.command = true;
+            return Some(utterance);
+        }
+
+        match detect(config, &utterance.samples) {
+            Some(found) => {
+                log::info!("wake word heard from {} ({:.1}/{:.1})", utterance.source.id, found.distance, found.threshold);
+                self.armed_until_ms = utterance.ended_at_ms + config.listen_ms;
+                self.wake_words = self.wake_words + 1;
+                let cut = ((found.end_ms * SAMPLE_RATE as i64 / 1000) as usize).min(utterance.samples.len());
+                let thing_oid = self.thing_oid.clone();
+                let threshold = *self.vad_threshold.get_or_insert_with(|| crate::sam::services::sound::vad::threshold_for(thing_oid.as_deref()));
+                if speech_ms(&utterance.samples, cut, threshold) < MIN_COMMAND_MS {
+                    // Just the wake word, the command follows in the next utterance
+                    if continuous {
+                        return Some(utterance);
+                    }
+                    return None;
+                }
+                let rest = utterance.samples.split_off(cut);
+                utterance.started_at_ms = utterance.started_at_ms + found.end_ms;
+                utterance.samples = rest;
+                utterance.wake_word = Some(found);
+                utterance.command = true;
+                return Some(utterance);
+            },
+            None => {
+                if continuous {
+                    return Some(utterance);
+                }
+                return None;
+            }
+        }
+    }
+}
+
+// Decodes any audio ffmpeg understands to 16kHz mono samples
+pub fn decode(audio: &[u8]) -> Result<Vec<i16>, crate::sam::services::Error> {
+    std::fs::create_dir_all("/opt/sam/tmp/sound")?;
+    let path = format!("/opt/sam/tmp/sound/wake_{}", crate::sam::services::sound::pipeline::now_ms());
+    std::fs::write(&path, audio)?;
+    let output = std::process::Command::new("ffmpeg")
+        .args(["-nostdin", "-loglevel", "error", "-i", path.as_str()])
+        .args(["-vn", "-ac", "1", "-ar", SAMPLE_RATE.to_string().as_str(), "-f", "s16le", "-"])
+        .output();
+    std::fs::remove_file(&path)?;
+    let output = output?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg couldn't decode the audio: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    return Ok(output.stdout.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect());
+}
+
+fn wav(samples: &[i16]) -> Result<Vec<u8>, crate::sam::services::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    {
+        let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+        for sample in samples.iter() {
+            writer.write_sample(*sample)?;
+        }
+        writer.finalize()?;
+    }
+    return Ok(cursor.into_inner());
+}
+
+// Saves a recording of the current phrase as a new template
+pub fn enroll(audio: &[u8], human_oid: Option<String>) -> Result<crate::sam::memory::WakeWordTemplate, crate::sam::services::Error> {
+    let samples = trim(&decode(audio)?);
+    let duration_ms = samples.len() as i64 * 1000 / SAMPLE_RATE as i64;
+    if duration_ms < 200 || duration_ms > 3000 {
+        return Err(format!("a wake word recording should be 0.2 to 3 seconds of speech, got {}ms", duration_ms).into());
+    }
+
+    let mut template = crate::sam::memory::WakeWordTemplate::new();
+    template.phrase = config().phrase.clone();
+    template.human_oid = human_oid;
+    template.features = mfcc(&samples);
+    template.audio = Some(wav(&samples)?);
+    template.duration_ms = duration_ms;
+    template.save()?;
+    reload();
+    return Ok(template);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WakeTest {
+    pub phrase: String,
+    pub templates: usize,
+    pub threshold: f64,
+    // The closest template, whether or not it passed
+    pub closest: Option<f64>,
+    pub heard: Option<WakeMatch>,
+}
+
+// Runs a recording through detection, for tuning wake_word_sensitivity
+pub fn test(audio: &[u8]) -> Result<WakeTest, crate::sam::services::Error> {
+    let samples = decode(audio)?;
+    let config = config();
+    let frames = mfcc(&samples);
+    let closest = config.templates.iter().filter_map(|(_, template)| find(template, &frames, MAX_START_FRAMES)).map(|f| f.0).fold(None, |best: Option<f64>, d| Some(best.map(|b| b.min(d)).unwrap_or(d)));
+    return Ok(WakeTest {
+        phrase: config.phrase.clone(),
+        templates: config.templates.len(),
+        threshold: config.threshold(),
+        closest: closest,
+        heard: detect(&config, &samples),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sam::services::sound::pipeline::AudioSource;
+
+    fn fixture(file: &str) -> Vec<i16> {
+        let path = format!("{}/src/sam/services/sound/fixtures/{}", env!("CARGO_MANIFEST_DIR"), file);
+        let reader = hound::WavReader::open(path).unwrap();
+        assert_eq!((reader.spec().sample_rate, reader.spec().channels), (SAMPLE_RATE, 1), "{} must be 16kHz mono", file);
+        return reader.into_samples::<i16>().collect::<Result<Vec<i16>, hound::Error>>().unwrap();
+    }
+
+    // One template, recorded like the enrollment endpoint does
+    fn wake_config(templates: bool) -> WakeConfig {
+        let mut config = WakeConfig {
+            phrase: format!("sam"),
+            sensitivity: DEFAULT_SENSITIVITY,
+            listen_ms: DEFAULT_LISTEN_SECONDS as i64 * 1000,
+            continuous: Vec::new(),
+            templates: Vec::new(),
+            baseline: DEFAULT_BASELINE,
+        };
+        if templates {
+            config.templates.push((format!("template"), mfcc(&trim(&fixture("wake_template.wav")))));
+        }
+        return config;
+    }
+
+    fn listener(thing_oid: &str) -> Listener {
+        let mut listener = Listener::new(&AudioSource {
+            id: thing_oid.to_string(),
+            thing_oid: Some(thing_oid.to_string()),
+            web_session: None,
+        });
+        listener.vad_threshold = Some(crate::sam::services::sound::vad::DEFAULT_THRESHOLD);
+        return listener;
+    }
+
+    fn utterance(file: &str, started_at_ms: i64) -> Utterance {
+        let samples = fixture(file);
+        let ended_at_ms = started_at_ms + samples.len() as i64 * 1000 / SAMPLE_RATE as i64;
+        return Utterance {
+            source: AudioSource {
+                id: format!("camera"),
+                thing_oid: Some(format!("camera")),
+                web_session: None,
+            },
+            samples: samples,
+            started_at_ms: started_at_ms,
+            ended_at_ms: ended_at_ms,
+            command: false,
+            wake_word: None,
+            partial: false,
+            transcripts: None,
+        };
+    }
+
+    #[test]
+    fn mfcc_frames() {
+        assert_eq!(mfcc(&vec![0; MFCC_FRAME - 1]).len(), 0);
+        let frames = mfcc(&fixture("wake_template.wav"));
+        assert_eq!(frames.len(), (SAMPLE_RATE as usize * 6 / 5 - MFCC_FRAME) / MFCC_HOP + 1);
+        assert!(frames.iter().all(|f| f.len() == CEPSTRA && f.iter().all(|c| c.is_finite())));
+    }
+
+    #[test]
+    fn finds_the_template_in_a_longer_clip() {
+        let config = wake_config(true);
+        let (distance, start, end) = find(&config.templates[0].1, &mfcc(&fixture("wake_command.wav")), MAX_START_FRAMES).unwrap();
+        assert!(distance <= config.threshold(), "distance {} over {}", distance, config.threshold());
+        // The phrase is at 0.3s to 1.05s
+        assert!((start as i64 - 30).abs() <= 10, "started at frame {}", start);
+        assert!((end as i64 - 105).abs() <= 15, "ended at frame {}", end);
+    }
+
+    #[test]
+    fn rejects_other_speech() {
+        let config = wake_config(true);
+        assert!(detect(&config, &fixture("wake_command.wav")).is_some());
+        assert!(detect(&config, &fixture("wake_other.wav")).is_none());
+        assert!(detect(&wake_config(false), &fixture("wake_command.wav")).is_none());
+    }
+
+    #[test]
+    fn hear_keeps_the_command_after_the_phrase() {
+        let config = wake_config(true);
+        let mut listener = listener("camera");
+        let heard = listener.hear_with(&config, utterance("wake_command.wav", 10_000)).unwrap();
+        assert!(heard.command);
+        assert_eq!(listener.wake_words, 1);
+        let found = heard.wake_word.unwrap();
+        assert_eq!(heard.started_at_ms, 10_000 + found.end_ms);
+        assert_eq!(heard.samples.len(), fixture("wake_command.wav").len() - (found.end_ms * SAMPLE_RATE as i64 / 1000) as usize);
+
+        // Still armed, the next thing said is a command too
+        let next = listener.hear_with(&config, utterance("wake_other.wav", 14_000)).unwrap();
+        assert!(next.command && next.wake_word.is_none());
+    }
+
+    #[test]
+    fn hear_drops_speech_without_the_phrase() {
+        let mut config = wake_config(true);
+        assert!(listener("camera").hear_with(&config, utterance("wake_other.wav", 0)).is_none());
+
+        config.continuous.push(format!("camera"));
+        let heard = listener("camera").hear_with(&config, utterance("wake_other.wav", 0)).unwrap();
+        assert!(!heard.command);
+
+        // Nothing to listen for yet, so everything is heard
+        let heard = listener("camera").hear_with(&wake_config(false), utterance("wake_other.wav", 0)).unwrap();
+        assert!(!heard.command);
+    }
+
+    #[test]
+    fn strip_phrase_edges() {
+        assert_eq!(strip_phrase("Sam, turn on the lights", "sam"), "turn on the lights");
+        assert_eq!(strip_phrase("  hey SAM! what time is it", "Hey  Sam"), "what time is it");
+        assert_eq!(strip_phrase("samantha is here", "sam"), "samantha is here");
+        assert_eq!(strip_phrase("I told sam", "sam"), "I told sam");
+        assert_eq!(strip_phrase("sam", "sam"), "");
+        assert_eq!(strip_phrase("", "sam"), "");
+        // Lowercasing İ takes more bytes, offsets from a lowercased copy would cut mid char
+        assert_eq!(strip_phrase("İ sam, lights", "sam"), "lights");
+        assert_eq!(strip_phrase("İstanbul sam", "sam"), "İstanbul sam");
+    }
+}
@@ -7,178 +7,1036 @@
 // Developed by Caleb Mitchell Smith (PixelCoda)
 // Licensed under GPLv3....see LICENSE file.
 
-// use std::thread;
+// sprec.rs recognizes who is speaking. Each clip becomes a speaker embedding,
+// a vector where clips of the same voice land close together. A TorchScript
+// speaker model at /opt/sam/models/sprec/speaker.pt (80 log mel filterbanks
+// in, one embedding out, an exported ECAPA-TDNN for example) is run through
+// tch when it's there, otherwise the statistics of the clip's mel cepstra
+// are used.
+//
+// Humans are enrolled a clip at a time, nothing is retrained. A clip belongs
+// to the human whose mean embedding is the most similar, unless that is under
+// sprec_threshold or another human is within sprec_margin, then the speaker
+// is Unknown.
+//
+//   GET /api/services/sprec
+//   POST /api/services/sprec/enroll (human_oid, audio_data or observation_oid)
+//   POST /api/services/sprec/identify (audio_data)
+//   DELETE /api/services/sprec/humans/{oid}
+//   POST /api/services/sprec/build
+//   GET /api/services/sprec/clusters
+//   POST /api/services/sprec/clusters/{id} (human_oid, or name for a new human)
 
-use std::thread;
-use std::fs::File;
-use std::io::{Write};
-use std::path::Path;
-use std::str::FromStr;
+pub mod clusters;
+
+use rouille::post_input;
+use rouille::Request;
+use rouille::Response;
 use serde::{Serialize, Deserialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
 
-pub fn init(){
+use crate::sam::services::sound::pipeline::SAMPLE_RATE;
+use crate::sam::services::sound::wake;
+
+const MODEL_PATH: &str = "/opt/sam/models/sprec/speaker.pt";
+const FRAME: usize = 400;
+const HOP: usize = 160;
+const FFT: usize = 512;
+const FILTERS: usize = 80;
+const CEPSTRA: usize = 24;
+// Less voiced speech than this says too little about the voice
+const MIN_SPEECH_MS: i64 = 400;
+// Per human, the oldest are dropped so a profile follows a changing voice
+const MAX_EMBEDDINGS: usize = 50;
+const DEFAULT_MARGIN: f64 = 0.02;
+// About -50dBFS
+const QUIET_RMS: f64 = 100.0;
+const MIN_PITCH: usize = 70;
+const MAX_PITCH: usize = 400;
+const PITCH_WEIGHT: f64 = 12.0;
+const WORDS_PER_CLIP: f64 = 9.0;
+const PRIOR_CLIPS: f64 = 4.0;
+// Observations read at a time by build
+const BUILD_PAGE: i64 = 100;
+
+pub trait SpeakerEncoder: Send + Sync {
+    // Stored with every embedding, embeddings from another encoder are ignored
+    fn name(&self) -> String;
+    fn default_threshold(&self) -> f64;
+    // frames are the starts of the frames with speech in them, see speech_frames
+    fn embed(&self, samples: &[i16], frames: &Vec<usize>) -> Result<Vec<f32>, crate::sam::services::Error>;
+
+    // 0 to 1, how much an embedding sounds like a human
+    fn similarity(&self, _profiles: &Profiles, profile: &Profile, embedding: &Vec<f32>) -> f64 {
+        return cosine(&profile.mean, embedding).max(0.0);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SprecPrediction {
+    // A human oid, or Unknown
+    pub human: String,
+    // Similarity to the closest human, 0 to 1
+    pub confidence: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Profile {
+    pub human_oid: String,
+    pub clips: usize,
+    #[serde(skip)]
+    pub mean: Vec<f32>,
+}
 
-    // thread::Builder::new().name("sprec_build".to_string()).spawn(move || {
-    //     // loop{
-    //     //     crate::sam::tools::linux_cmd(format!("python3 /opt/sam/scripts/sprec/build.py"));
-    //     //     sleep(Duration::from_millis(100000));
-    //     // }
-    // });
-    
+pub struct Profiles {
+    pub encoder: Arc<dyn SpeakerEncoder>,
+    pub threshold: f64,
+    pub margin: f64,
+    pub profiles: Vec<Profile>,
+    // How far each dimension moves between clips of the same human, pooled
+    // over everyone, and how many clips that is from
+    pub spread: Vec<f64>,
+    pub degrees: usize,
 }
 
+// Mean and spread of liftered mel cepstra over the speech, then the median
+// pitch. The cepstra carry the shape of the vocal tract but also what was
+// said, so a clip is compared dimension by dimension against how much the
+// same human's clips vary, and pitch counts for more.
+pub struct StatsEncoder;
+
+impl SpeakerEncoder for StatsEncoder {
+    fn name(&self) -> String {
+        format!("cepstral-stats-1")
+    }
 
-pub fn build(){
-    thread::spawn(move || {
+    fn default_threshold(&self) -> f64 {
+        0.3
+    }
 
-        let mut pg_query = crate::sam::memory::PostgresQueries::default();
-        pg_query.queries.push(crate::sam::memory::PGCol::String(format!("HEARD")));
-        pg_query.query_coulmns.push(format!("observation_type ="));
-
-        
-        let observations = crate::sam::memory::Observation::select(None, None, None, Some(pg_query)).unwrap();
-        
-
-        
-        for observation in observations{
-            log::info!("observation_humans: {:?}", observation.observation_humans);
-            for human in observation.observation_humans{
-                if !Path::new(format!("/opt/sam/scripts/sprec/audio/{}", human.oid).as_str()).exists(){
-                    std::fs::create_dir(format!("/opt/sam/scripts/sprec/audio/{}", human.oid).as_str()).unwrap();
+    fn embed(&self, samples: &[i16], frames: &Vec<usize>) -> Result<Vec<f32>, crate::sam::services::Error> {
+        let cepstra: Vec<Vec<f64>> = fbank(samples, frames).iter().map(|energies| {
+            (1..=CEPSTRA).map(|c| {
+                let value: f64 = energies.iter().enumerate().map(|(m, e)| {
+                    *e as f64 * (std::f64::consts::PI * c as f64 * (m as f64 + 0.5) / FILTERS as f64).cos()
+                }).sum();
+                // Sinusoidal lifter, the higher cepstra are small but just as telling
+                value * (1.0 + CEPSTRA as f64 / 2.0 * (std::f64::consts::PI * c as f64 / CEPSTRA as f64).sin())
+            }).collect()
+        }).collect();
+
+        let count = cepstra.len() as f64;
+        let mut embedding: Vec<f32> = Vec::new();
+        let means: Vec<f64> = (0..CEPSTRA).map(|c| cepstra.iter().map(|f| f[c]).sum::<f64>() / count).collect();
+        for c in 0..CEPSTRA {
+            embedding.push(means[c] as f32);
+        }
+        for c in 0..CEPSTRA {
+            let variance = cepstra.iter().map(|f| (f[c] - means[c]).powi(2)).sum::<f64>() / count;
+            embedding.push(variance.sqrt() as f32);
+        }
+
+        let mut pitches: Vec<f64> = frames.iter().filter_map(|start| pitch(samples, *start)).map(|f0| f0.ln()).collect();
+        pitches.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        embedding.push(pitches.get(pitches.len() / 2).cloned().unwrap_or(0.0) as f32);
+        return Ok(embedding);
+    }
+
+    fn similarity(&self, profiles: &Profiles, profile: &Profile, embedding: &Vec<f32>) -> f64 {
+        if profile.mean.len() != embedding.len() || embedding.len() != 2 * CEPSTRA + 1 {
+            return 0.0;
+        }
+        let mut distance = 0.0;
+        let mut weights = 0.0;
+        for d in 0..embedding.len() {
+            // A few clips are too few to measure the spread, so it starts
+            // out as expected: what was said moves a cepstral mean by its
+            // spread over the frames shared between the words, the spreads
+            // themselves by a fifth and pitch by about 5%
+            let expected = if d < CEPSTRA {
+                (embedding[CEPSTRA + d] + profile.mean[CEPSTRA + d]) as f64 / 2.0 / WORDS_PER_CLIP.sqrt()
+            } else if d < 2 * CEPSTRA {
+                (embedding[d] + profile.mean[d]) as f64 / 2.0 * 0.2
+            } else {
+                0.05
+            };
+            let spread = ((profiles.spread.get(d).cloned().unwrap_or(0.0).powi(2) * profiles.degrees as f64 + expected.powi(2) * PRIOR_CLIPS) / (profiles.degrees as f64 + PRIOR_CLIPS)).sqrt().max(1e-3);
+            let weight = if d == embedding.len() - 1 { PITCH_WEIGHT } else { 1.0 };
+            distance = distance + weight * ((embedding[d] - profile.mean[d]) as f64 / spread).powi(2);
+            weights = weights + weight;
+        }
+        return (-distance / weights / 2.0).exp();
+    }
+}
+
+// A TorchScript model taking [1, frames, 80] mean normalized log mel filterbanks
+pub struct TorchEncoder {
+    pub name: String,
+    module: Mutex<tch::CModule>,
+}
+impl TorchEncoder {
+    pub fn load(path: &str) -> Result<TorchEncoder, crate::sam::services::Error> {
+        let module = tch::CModule::load(path)?;
+        // The size tells a replaced model apart, its embeddings won't compare
+        let size = std::fs::metadata(path)?.len();
+        return Ok(TorchEncoder {
+            name: format!("torchscript-{}", size),
+            module: Mutex::new(module),
+        });
+    }
+}
+
+impl SpeakerEncoder for TorchEncoder {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn default_threshold(&self) -> f64 {
+        0.5
+    }
+
+    fn embed(&self, samples: &[i16], frames: &Vec<usize>) -> Result<Vec<f32>, crate::sam::services::Error> {
+        let frames = fbank(samples, frames);
+        let count = frames.len();
+        let mut means = vec![0.0f32; FILTERS];
+        for frame in frames.iter() {
+            for m in 0..FILTERS {
+                means[m] = means[m] + frame[m] / count as f32;
+            }
+        }
+        let flat: Vec<f32> = frames.iter().flat_map(|frame| frame.iter().zip(means.iter()).map(|(e, mean)| e - mean)).collect();
+        let input = tch::Tensor::of_slice(&flat).view([1, count as i64, FILTERS as i64]);
+
+        let module = match self.module.lock() {
+            Ok(module) => module,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        let output = tch::no_grad(|| module.forward_ts(&[input]))?;
+        return Ok(Vec::<f32>::from(&output.flatten(0, -1)));
+    }
+}
+
+pub fn init(){
+    crate::sam::services::bus::subscribe("sprec", |event| {
+        match event {
+            crate::sam::services::bus::Event::SettingChanged{key, ..} => {
+                if key.starts_with("sprec_") {
+                    invalidate();
                 }
+            },
+            _ => {}
+        }
+    });
+}
+
+fn encoder_cache() -> &'static Mutex<Option<Arc<dyn SpeakerEncoder>>> {
+    static ENCODER: OnceLock<Mutex<Option<Arc<dyn SpeakerEncoder>>>> = OnceLock::new();
+    ENCODER.get_or_init(|| Mutex::new(None))
+}
 
-                std::fs::write(format!("/opt/sam/scripts/sprec/audio/{}/{}.wav", human.oid, observation.oid).as_str(), observation.observation_file.clone().unwrap()).unwrap();
+pub fn encoder() -> Arc<dyn SpeakerEncoder> {
+    let mut cache = match encoder_cache().lock() {
+        Ok(cache) => cache,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    match cache.as_ref() {
+        Some(encoder) => return encoder.clone(),
+        None => {}
+    }
+    let encoder: Arc<dyn SpeakerEncoder> = if Path::new(MODEL_PATH).exists() {
+        match TorchEncoder::load(MODEL_PATH) {
+            Ok(encoder) => Arc::new(encoder),
+            Err(e) => {
+                log::error!("failed to load speaker model {}: {}", MODEL_PATH, e);
+                Arc::new(StatsEncoder)
             }
         }
+    } else {
+        Arc::new(StatsEncoder)
+    };
+    log::info!("sprec is using the {} encoder", encoder.name());
+    *cache = Some(encoder.clone());
+    return encoder;
+}
+
+fn profiles_cache() -> &'static Mutex<Option<Arc<Profiles>>> {
+    static PROFILES: OnceLock<Mutex<Option<Arc<Profiles>>>> = OnceLock::new();
+    PROFILES.get_or_init(|| Mutex::new(None))
+}
+
+// Drops the cached encoder and profiles, for when a new model is dropped in
+pub fn reload(){
+    match encoder_cache().lock() {
+        Ok(mut cache) => *cache = None,
+        Err(poisoned) => *poisoned.into_inner() = None
+    }
+    invalidate();
+}
 
+fn invalidate(){
+    match profiles_cache().lock() {
+        Ok(mut cache) => *cache = None,
+        Err(poisoned) => *poisoned.into_inner() = None
+    }
+}
 
+pub fn profiles() -> Result<Arc<Profiles>, crate::sam::services::Error> {
+    match profiles_cache().lock() {
+        Ok(cache) => match cache.as_ref() {
+            Some(profiles) => return Ok(profiles.clone()),
+            None => {}
+        },
+        Err(_) => {}
+    }
 
-        crate::sam::tools::linux_cmd(format!("python3 /opt/sam/scripts/sprec/build.py"));
+    let encoder = encoder();
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(encoder.name()));
+    pg_query.query_coulmns.push(format!("encoder ="));
+    let unknown = clusters::unknown_humans()?;
+    let embeddings: Vec<crate::sam::memory::SpeakerEmbedding> = crate::sam::memory::SpeakerEmbedding::select(None, None, None, Some(pg_query))?
+        .into_iter()
+        .filter(|e| !unknown.contains(&e.human_oid))
+        .collect();
+    let threshold = setting_f64("sprec_threshold").unwrap_or(encoder.default_threshold());
+    let margin = setting_f64("sprec_margin").unwrap_or(DEFAULT_MARGIN);
+    let profiles = Arc::new(Profiles::build(encoder, &embeddings, threshold, margin));
 
-    });
+    match profiles_cache().lock() {
+        Ok(mut cache) => *cache = Some(profiles.clone()),
+        Err(_) => {}
+    }
+    return Ok(profiles);
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct SprecPrediction {
-    pub human: String,
-    pub confidence: f64,
+fn setting_f64(key: &str) -> Option<f64> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(key.to_string()));
+    pg_query.query_coulmns.push(format!("key ="));
+    match crate::sam::memory::Setting::select(None, None, None, Some(pg_query)) {
+        Ok(settings) => settings.first().and_then(|s| s.values.first()).and_then(|v| v.trim().parse::<f64>().ok()),
+        Err(e) => {
+            log::error!("failed to load setting {}: {}", key, e);
+            None
+        }
+    }
 }
 
-pub fn predict(file_path: &str) -> Result<SprecPrediction, crate::sam::services::Error>{
-    if Path::new("/opt/sam/scripts/sprec/test.wav").exists(){
-        std::fs::remove_file("/opt/sam/scripts/sprec/test.wav")?;
+impl Profiles {
+    // One mean per human, and the spread of their clips around it
+    pub fn build(encoder: Arc<dyn SpeakerEncoder>, embeddings: &Vec<crate::sam::memory::SpeakerEmbedding>, threshold: f64, margin: f64) -> Profiles {
+        let mut humans: Vec<(String, Vec<&Vec<f32>>)> = Vec::new();
+        let size = embeddings.first().map(|e| e.embedding.len()).unwrap_or(0);
+        for embedding in embeddings.iter().filter(|e| e.embedding.len() == size) {
+            match humans.iter_mut().find(|(oid, _)| *oid == embedding.human_oid) {
+                Some((_, clips)) => clips.push(&embedding.embedding),
+                None => humans.push((embedding.human_oid.clone(), vec![&embedding.embedding]))
+            }
+        }
+
+        let mut profiles: Vec<Profile> = Vec::new();
+        let mut variance = vec![0.0; size];
+        let mut degrees = 0;
+        for (human_oid, clips) in humans.into_iter() {
+            let mean: Vec<f64> = (0..size).map(|d| clips.iter().map(|c| c[d] as f64).sum::<f64>() / clips.len() as f64).collect();
+            for clip in clips.iter() {
+                for d in 0..size {
+                    variance[d] = variance[d] + (clip[d] as f64 - mean[d]).powi(2);
+                }
+            }
+            degrees = degrees + clips.len() - 1;
+            profiles.push(Profile {
+                human_oid: human_oid,
+                clips: clips.len(),
+                mean: mean.iter().map(|m| *m as f32).collect(),
+            });
+        }
+
+        let spread: Vec<f64> = variance.iter().map(|v| (v / degrees.max(1) as f64).sqrt()).collect();
+
+        return Profiles {
+            encoder: encoder,
+            threshold: threshold,
+            margin: margin,
+            profiles: profiles,
+            spread: spread,
+            degrees: degrees,
+        };
     }
-    std::fs::copy(file_path, "/opt/sam/scripts/sprec/test.wav")?;
-    let result = crate::sam::tools::cmd(format!("python3 /opt/sam/scripts/sprec/predict.py"));
+}
 
-    let mut split = result.split(":::::");
-    let vec = split.collect::<Vec<&str>>();
+pub fn cosine(a: &Vec<f32>, b: &Vec<f32>) -> f64 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f64 = a.iter().zip(b.iter()).map(|(x, y)| (*x as f64) * (*y as f64)).sum();
+    let lengths = a.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt() * b.iter().map(|y| (*y as f64).powi(2)).sum::<f64>().sqrt();
+    if lengths == 0.0 {
+        return 0.0;
+    }
+    return dot / lengths;
+}
 
-    let sprec = SprecPrediction{
-        human: vec[1].to_string(),
-        confidence: f64::from_str(vec[2])?,
+pub fn identify(profiles: &Profiles, embedding: &Vec<f32>) -> SprecPrediction {
+    let mut scores: Vec<(f64, &String)> = profiles.profiles.iter().map(|p| (profiles.encoder.similarity(profiles, p, embedding), &p.human_oid)).collect();
+    scores.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    let best = match scores.first() {
+        Some(best) => best,
+        None => return SprecPrediction { human: format!("Unknown"), confidence: 0.0 }
     };
+    let close_second = scores.get(1).map(|second| best.0 - second.0 < profiles.margin).unwrap_or(false);
+    if best.0 < profiles.threshold || close_second {
+        return SprecPrediction { human: format!("Unknown"), confidence: best.0 };
+    }
+    return SprecPrediction { human: best.1.clone(), confidence: best.0 };
+}
+
+// Triangular filters from 20Hz to 7600Hz over the fft bins
+fn filterbank() -> &'static Vec<Vec<(usize, f64)>> {
+    static FILTERBANK: OnceLock<Vec<Vec<(usize, f64)>>> = OnceLock::new();
+    FILTERBANK.get_or_init(|| {
+        let low = wake::mel(20.0);
+        let high = wake::mel(7600.0);
+        let points: Vec<f64> = (0..FILTERS + 2).map(|i| {
+            wake::mel_to_hz(low + (high - low) * i as f64 / (FILTERS + 1) as f64) * FFT as f64 / SAMPLE_RATE as f64
+        }).collect();
+        (1..=FILTERS).map(|m| {
+            (0..=FFT / 2).filter_map(|k| {
+                let bin = k as f64;
+                let weight = if bin >= points[m - 1] && bin <= points[m] {
+                    (bin - points[m - 1]) / (points[m] - points[m - 1]).max(1e-9)
+                } else if bin > points[m] && bin <= points[m + 1] {
+                    (points[m + 1] - bin) / (points[m + 1] - points[m]).max(1e-9)
+                } else {
+                    0.0
+                };
+                if weight > 0.0 { Some((k, weight)) } else { None }
+            }).collect()
+        }).collect()
+    })
+}
+
+// Starts of the frames worth listening to, within 20dB of the loudest and
+// louder than a quiet room, silence and hum say nothing about the speaker
+pub fn speech_frames(samples: &[i16]) -> Vec<usize> {
+    let mut energies: Vec<(usize, f64)> = Vec::new();
+    let mut start = 0;
+    while start + FRAME <= samples.len() {
+        let energy = samples[start..start + FRAME].iter().map(|s| (*s as f64) * (*s as f64)).sum::<f64>() / FRAME as f64;
+        energies.push((start, energy));
+        start = start + HOP;
+    }
+    let loudest = energies.iter().map(|(_, e)| *e).fold(0.0, f64::max);
+    return energies.into_iter()
+        .filter(|(_, e)| *e >= loudest / 100.0 && *e >= QUIET_RMS * QUIET_RMS)
+        .map(|(start, _)| start)
+        .collect();
+}
+
+// 80 log mel energies for each frame
+pub fn fbank(samples: &[i16], frames: &Vec<usize>) -> Vec<Vec<f32>> {
+    return frames.iter().map(|start| {
+        let mut re: Vec<f64> = vec![0.0; FFT];
+        let mut im: Vec<f64> = vec![0.0; FFT];
+        for i in 0..FRAME {
+            let previous = if start + i > 0 { samples[start + i - 1] as f64 } else { 0.0 };
+            let w = 0.54 - 0.46 * (2.0 * std::f64::consts::PI * i as f64 / (FRAME - 1) as f64).cos();
+            re[i] = (samples[start + i] as f64 - 0.97 * previous) * w;
+        }
+        crate::sam::services::sound::vad::fft(&mut re, &mut im);
+        let power: Vec<f64> = (0..=FFT / 2).map(|k| (re[k] * re[k] + im[k] * im[k]) / FFT as f64).collect();
+        filterbank().iter().map(|filter| {
+            (filter.iter().map(|(k, w)| power[*k] * w).sum::<f64>() + 1e-10).ln() as f32
+        }).collect()
+    }).collect();
+}
+
+// Pitch around a frame from its autocorrelation, None when it isn't periodic.
+// The window holds two of the longest periods and is low passed so a strong
+// formant doesn't pass for the pitch.
+pub fn pitch(samples: &[i16], start: usize) -> Option<f64> {
+    let longest = SAMPLE_RATE as usize / MIN_PITCH;
+    let end = (start + 2 * longest).min(samples.len());
+    let mut low = 0.0;
+    let mut lower = 0.0;
+    let frame: Vec<f64> = samples[start..end].iter().map(|s| {
+        low = low + (*s as f64 - low) * 0.27;
+        lower = lower + (low - lower) * 0.27;
+        lower
+    }).collect();
+    if frame.len() < 2 * longest {
+        return None;
+    }
+
+    let correlations: Vec<(usize, f64)> = (SAMPLE_RATE as usize / MAX_PITCH..=longest).map(|lag| {
+        let mut product = 0.0;
+        let mut head = 0.0;
+        let mut tail = 0.0;
+        for i in 0..longest {
+            product = product + frame[i] * frame[i + lag];
+            head = head + frame[i] * frame[i];
+            tail = tail + frame[i + lag] * frame[i + lag];
+        }
+        (lag, product / (head * tail).sqrt().max(1e-9))
+    }).collect();
+    let best = correlations.iter().map(|(_, r)| *r).fold(f64::MIN, f64::max);
+    if best < 0.7 {
+        return None;
+    }
+    // Multiples of the period correlate about as well, the first peak is the pitch
+    for n in 1..correlations.len() - 1 {
+        let (lag, r) = correlations[n];
+        if r >= best * 0.9 && r >= correlations[n - 1].1 && r >= correlations[n + 1].1 {
+            return Some(SAMPLE_RATE as f64 / lag as f64);
+        }
+    }
+    return None;
+}
+
+// 16kHz mono samples from a wav, anything else goes through ffmpeg
+pub fn samples(file_path: &str) -> Result<Vec<i16>, crate::sam::services::Error> {
+    match hound::WavReader::open(file_path) {
+        Ok(reader) => {
+            let spec = reader.spec();
+            if spec.sample_rate == SAMPLE_RATE && spec.channels == 1 && spec.bits_per_sample == 16 && spec.sample_format == hound::SampleFormat::Int {
+                return Ok(reader.into_samples::<i16>().collect::<Result<Vec<i16>, hound::Error>>()?);
+            }
+        },
+        Err(_) => {}
+    }
+    return wake::decode(&std::fs::read(file_path)?);
+}
+
+pub fn embed(encoder: &dyn SpeakerEncoder, samples: &[i16]) -> Result<(Vec<f32>, i64), crate::sam::services::Error> {
+    let frames = speech_frames(samples);
+    let speech_ms = (frames.len() * HOP * 1000 / SAMPLE_RATE as usize) as i64;
+    if speech_ms < MIN_SPEECH_MS {
+        return Err(format!("not enough speech to recognize a voice ({}ms)", speech_ms).into());
+    }
+    return Ok((encoder.embed(samples, &frames)?, speech_ms));
+}
+
+pub fn predict(file_path: &str) -> Result<SprecPrediction, crate::sam::services::Error> {
+    let profiles = profiles()?;
+    let samples = samples(file_path)?;
+    match embed(profiles.encoder.as_ref(), &samples) {
+        Ok((embedding, _)) => return Ok(identify(&profiles, &embedding)),
+        Err(e) => {
+            log::info!("sprec can't tell who is speaking in {}: {}", file_path, e);
+            return Ok(SprecPrediction { human: format!("Unknown"), confidence: 0.0 });
+        }
+    }
+}
+
+// Adds a clip to a human's voice
+pub fn enroll(human_oid: &str, samples: &[i16], observation_oid: Option<String>) -> Result<crate::sam::memory::SpeakerEmbedding, crate::sam::services::Error> {
+    let speaker_embedding = remember(human_oid, samples, observation_oid)?;
+    trim(human_oid)?;
+    invalidate();
+    return Ok(speaker_embedding);
+}
+
+// Keeps the voice of a clip without touching the profiles, unknown humans'
+// voices are only kept to be clustered, see sprec/clusters.rs
+pub fn remember(human_oid: &str, samples: &[i16], observation_oid: Option<String>) -> Result<crate::sam::memory::SpeakerEmbedding, crate::sam::services::Error> {
+    let (embedding, speech_ms) = embed(encoder().as_ref(), samples)?;
+    return keep(human_oid, embedding, speech_ms, observation_oid);
+}
+
+// Stores an embedding made by the current encoder
+pub fn keep(human_oid: &str, embedding: Vec<f32>, speech_ms: i64, observation_oid: Option<String>) -> Result<crate::sam::memory::SpeakerEmbedding, crate::sam::services::Error> {
+    let mut speaker_embedding = crate::sam::memory::SpeakerEmbedding::new();
+    speaker_embedding.human_oid = human_oid.to_string();
+    speaker_embedding.observation_oid = observation_oid;
+    speaker_embedding.encoder = encoder().name();
+    speaker_embedding.embedding = embedding;
+    speaker_embedding.speech_ms = speech_ms;
+    speaker_embedding.save()?;
+    return Ok(speaker_embedding);
+}
+
+// Drops a human's oldest clips past MAX_EMBEDDINGS
+pub fn trim(human_oid: &str) -> Result<(), crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(human_oid.to_string()));
+    pg_query.query_coulmns.push(format!("human_oid ="));
+    let embeddings = crate::sam::memory::SpeakerEmbedding::select(None, None, Some(format!("created_at DESC")), Some(pg_query))?;
+    for old in embeddings.into_iter().skip(MAX_EMBEDDINGS) {
+        crate::sam::memory::SpeakerEmbedding::destroy(old.oid)?;
+    }
+    return Ok(());
+}
+
+pub fn forget(human_oid: &str) -> Result<usize, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(human_oid.to_string()));
+    pg_query.query_coulmns.push(format!("human_oid ="));
+    let embeddings = crate::sam::memory::SpeakerEmbedding::select(None, None, None, Some(pg_query))?;
+    let count = embeddings.len();
+    for embedding in embeddings.into_iter() {
+        crate::sam::memory::SpeakerEmbedding::destroy(embedding.oid)?;
+    }
+    invalidate();
+    return Ok(count);
+}
+
+// Enrolls the HEARD observations of known humans, or only of human_oid, that
+// the current encoder hasn't seen yet. A full build drops embeddings from
+// older encoders first. Observations are read a page at a time without their
+// audio, which is only fetched for the ones being enrolled.
+pub fn build(human_oid: Option<String>){
+    let spawned = thread::Builder::new().name("sprec_build".to_string()).spawn(move || {
+        let encoder = encoder();
+        if human_oid.is_none() {
+            let stale = crate::sam::memory::SpeakerEmbedding::select(None, None, None, None).map(|embeddings| {
+                embeddings.into_iter().filter(|e| e.encoder != encoder.name()).collect::<Vec<crate::sam::memory::SpeakerEmbedding>>()
+            });
+            match stale {
+                Ok(stale) => {
+                    for embedding in stale.into_iter() {
+                        let _ = crate::sam::memory::SpeakerEmbedding::destroy(embedding.oid);
+                    }
+                },
+                Err(e) => log::error!("sprec build failed: {}", e)
+            }
+        }
 
-    return Ok(sprec);
+        let unknown = match clusters::unknown_humans() {
+            Ok(unknown) => unknown,
+            Err(e) => {
+                log::error!("sprec build failed: {}", e);
+                return;
+            }
+        };
+        let mut enrolled = 0;
+        let mut after_id = 0;
+        loop {
+            let observations = match crate::sam::memory::Observation::unembedded(after_id, BUILD_PAGE, human_oid.as_deref()) {
+                Ok(observations) => observations,
+                Err(e) => {
+                    log::error!("sprec build failed: {}", e);
+                    return;
+                }
+            };
+            if observations.len() == 0 {
+                break;
+            }
+            for (id, observation_oid, human_oids) in observations.into_iter() {
+                after_id = id;
+                let human = match &human_oid {
+                    Some(human_oid) => human_oid.clone(),
+                    None => match human_oids.into_iter().find(|h| !unknown.contains(h)) {
+                        Some(human) => human,
+                        None => continue
+                    }
+                };
+                match observation_samples(&observation_oid) {
+                    Ok(samples) => match enroll(&human, &samples, Some(observation_oid.clone())) {
+                        Ok(_) => enrolled = enrolled + 1,
+                        Err(e) => log::info!("skipped observation {}: {}", observation_oid, e)
+                    },
+                    Err(e) => log::info!("skipped observation {}: {}", observation_oid, e)
+                }
+            }
+        }
+        log::info!("sprec build enrolled {} observations", enrolled);
+    });
+    match spawned {
+        Ok(_) => {},
+        Err(e) => log::error!("failed to start sprec build: {}", e)
+    }
 }
 
+// Observation::select leaves the audio out, it's fetched on its own
+fn observation_samples(observation_oid: &str) -> Result<Vec<i16>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(observation_oid.to_string()));
+    pg_query.query_coulmns.push(format!("oid ="));
+    let observations = crate::sam::memory::Observation::select(None, None, None, Some(pg_query))?;
+    let audio = match observations.first().and_then(|o| o.observation_file.clone()) {
+        Some(audio) => audio,
+        None => return Err(format!("observation {} has no audio", observation_oid).into())
+    };
+    return wake::decode(&audio);
+}
 
 pub fn install() -> std::io::Result<()> {
-    let data = include_bytes!("../../../scripts/sprec/build.py");
+    std::fs::create_dir_all("/opt/sam/models/sprec")?;
+    Ok(())
+}
 
-    let mut pos = 0;
-    let mut buffer = File::create("/opt/sam/scripts/sprec/build.py")?;
+pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+    if request.url() == "/api/services/sprec" {
+        let profiles = profiles()?;
+        return Ok(Response::json(&serde_json::json!({
+            "encoder": profiles.encoder.name(),
+            "threshold": profiles.threshold,
+            "margin": profiles.margin,
+            "humans": profiles.profiles,
+        })));
+    }
+
+    if request.url() == "/api/services/sprec/enroll" && request.method() == "POST" {
+        let input = post_input!(request, {
+            human_oid: String,
+            audio_data: Option<rouille::input::post::BufferedFile>,
+            observation_oid: Option<String>,
+        })?;
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(input.human_oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        if crate::sam::memory::Human::select(None, None, None, Some(pg_query))?.len() == 0 {
+            return Ok(Response::empty_404());
+        }
 
-    while pos < data.len() {
-        let bytes_written = buffer.write(&data[pos..])?;
-        pos += bytes_written;
+        let samples = match (input.audio_data, &input.observation_oid) {
+            (Some(audio), _) => wake::decode(&audio.data),
+            (None, Some(observation_oid)) => observation_samples(observation_oid),
+            (None, None) => return Ok(Response::text("audio_data or observation_oid is required").with_status_code(400))
+        };
+        match samples.and_then(|samples| enroll(&input.human_oid, &samples, input.observation_oid.clone())) {
+            Ok(embedding) => return Ok(Response::json(&serde_json::json!({
+                "oid": embedding.oid,
+                "human_oid": embedding.human_oid,
+                "speech_ms": embedding.speech_ms,
+                "encoder": embedding.encoder,
+            }))),
+            Err(e) => return Ok(Response::text(format!("{}", e)).with_status_code(400))
+        }
     }
 
-    let data = include_bytes!("../../../scripts/sprec/predict.py");
+    if request.url() == "/api/services/sprec/identify" && request.method() == "POST" {
+        let input = post_input!(request, {
+            audio_data: rouille::input::post::BufferedFile,
+        })?;
+        let profiles = profiles()?;
+        let embedded = wake::decode(&input.audio_data.data)
+            .and_then(|samples| embed(profiles.encoder.as_ref(), &samples));
+        match embedded {
+            Ok((embedding, _)) => return Ok(Response::json(&identify(&profiles, &embedding))),
+            Err(e) => return Ok(Response::text(format!("{}", e)).with_status_code(400))
+        }
+    }
 
-    let mut pos = 0;
-    let mut buffer = File::create("/opt/sam/scripts/sprec/predict.py")?;
+    if request.url().starts_with("/api/services/sprec/humans/") && request.method() == "DELETE" {
+        let url = request.url().clone();
+        let split = url.split("/");
+        let vec = split.collect::<Vec<&str>>();
+        let removed = forget(vec[5])?;
+        return Ok(Response::json(&serde_json::json!({"removed": removed})));
+    }
 
-    while pos < data.len() {
-        let bytes_written = buffer.write(&data[pos..])?;
-        pos += bytes_written;
+    if request.url() == "/api/services/sprec/clusters" && request.method() == "GET" {
+        return Ok(Response::json(&clusters::clusters()?));
     }
 
-    let data = include_bytes!("../../../scripts/sprec/requirements.txt");
+    if request.url().starts_with("/api/services/sprec/clusters/") && request.method() == "POST" {
+        let url = request.url().clone();
+        let split = url.split("/");
+        let vec = split.collect::<Vec<&str>>();
+        if !clusters::clusters()?.iter().any(|c| c.id == vec[5]) {
+            return Ok(Response::empty_404());
+        }
+        let input = post_input!(request, {
+            human_oid: Option<String>,
+            name: Option<String>,
+        })?;
 
-    let mut pos = 0;
-    let mut buffer = File::create("/opt/sam/scripts/sprec/requirements.txt")?;
+        let human_oid = match (input.human_oid.filter(|h| h.len() > 0), input.name.map(|n| n.trim().to_string()).filter(|n| n.len() > 0)) {
+            (Some(human_oid), _) => human_oid,
+            (None, Some(name)) => {
+                let mut human = crate::sam::memory::Human::new();
+                human.name = name;
+                human.save()?;
+                human.oid
+            },
+            (None, None) => return Ok(Response::text("human_oid or name is required").with_status_code(400))
+        };
+        match clusters::assign(vec[5], &human_oid) {
+            Ok(Some(human)) => {
+                // Their older clips may not have been kept, the build picks those up
+                build(Some(human_oid.clone()));
+                return Ok(Response::json(&human));
+            },
+            Ok(None) => return Ok(Response::empty_404()),
+            Err(e) => return Ok(Response::text(format!("{}", e)).with_status_code(400))
+        }
+    }
 
-    while pos < data.len() {
-        let bytes_written = buffer.write(&data[pos..])?;
-        pos += bytes_written;
+    if request.url() == "/api/services/sprec/build" && request.method() == "POST" {
+        reload();
+        build(None);
+        return Ok(Response::text("building"));
     }
 
-    let data = include_bytes!("../../../scripts/sprec/model.h5");
+    return Ok(Response::empty_404());
+}
 
-    let mut pos = 0;
-    let mut buffer = File::create("/opt/sam/scripts/sprec/model.h5")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    while pos < data.len() {
-        let bytes_written = buffer.write(&data[pos..])?;
-        pos += bytes_written;
+    // A fixed lcg so every run hears the same voices
+    struct Rng(u64);
+    impl Rng {
+        fn next(&mut self) -> f64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            return ((self.0 >> 11) as f64) / ((1u64 << 53) as f64);
+        }
+        fn gauss(&mut self) -> f64 {
+            let u = self.next().max(1e-12);
+            let v = self.next();
+            return (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos();
+        }
     }
 
-    let data = include_bytes!("../../../scripts/sprec/labels.pickle");
+    // f0 is the pitch, tract scales the formants (shorter tracts are higher)
+    #[derive(Clone, Copy)]
+    struct Voice {
+        f0: f64,
+        tract: f64,
+        tilt: f64,
+    }
 
-    let mut pos = 0;
-    let mut buffer = File::create("/opt/sam/scripts/sprec/labels.pickle")?;
+    const VOWELS: [(f64, f64, f64); 8] = [(270.0, 2290.0, 3010.0), (390.0, 1990.0, 2550.0), (530.0, 1840.0, 2480.0), (660.0, 1720.0, 2410.0), (730.0, 1090.0, 2440.0), (570.0, 840.0, 2410.0), (440.0, 1020.0, 2240.0), (300.0, 870.0, 2240.0)];
 
-    while pos < data.len() {
-        let bytes_written = buffer.write(&data[pos..])?;
-        pos += bytes_written;
+    // A random sentence of vowels and fricatives in a voice, between half second pauses
+    fn utterance(voice: Voice, seconds: f64, gain: f64, rng: &mut Rng) -> Vec<i16> {
+        let rate = SAMPLE_RATE as f64;
+        let mut out: Vec<f64> = vec![0.0; 8000];
+        let mut phase = 0.0;
+        let mut total = 0.0;
+        while total < seconds {
+            let ms = 80.0 + rng.next() * 200.0;
+            let n = (ms * rate / 1000.0) as usize;
+            let fricative = rng.next() < 0.2;
+            let (f1, f2, f3) = VOWELS[(rng.next() * 8.0) as usize % 8];
+            let (f1, f2, f3) = (f1 * voice.tract, f2 * voice.tract, f3 * voice.tract);
+            let drift = 1.0 + 0.1 * rng.gauss();
+            for i in 0..n {
+                let t = i as f64 / n as f64;
+                let edge = (t / 0.15).min((1.0 - t) / 0.15).min(1.0);
+                let pitch = voice.f0 * drift * (1.0 + 0.04 * (t * 5.0).sin());
+                phase = phase + 2.0 * std::f64::consts::PI * pitch / rate;
+                let mut sample = 0.0;
+                if fricative {
+                    sample = 0.25 * rng.gauss();
+                } else {
+                    for k in 1..60 {
+                        let hz = k as f64 * pitch;
+                        if hz > 7000.0 {
+                            break;
+                        }
+                        let formants = (-((hz - f1) / 90.0).powi(2)).exp() + 0.6 * (-((hz - f2) / 120.0).powi(2)).exp() + 0.35 * (-((hz - f3) / 180.0).powi(2)).exp() + 0.03;
+                        sample = sample + formants * (k as f64).powf(-voice.tilt) * (k as f64 * phase).sin();
+                    }
+                }
+                out.push(sample * edge);
+            }
+            out.extend((0..((rng.next() * 1600.0) as usize)).map(|_| 0.0));
+            total = total + ms / 1000.0;
+        }
+        out.extend((0..8000).map(|_| 0.0));
+        let top = out.iter().fold(0.0f64, |a, b| a.max(b.abs()));
+        return out.iter().map(|s| (s / top * gain + rng.gauss() * 40.0).max(-32768.0).min(32767.0) as i16).collect();
     }
 
-    let data = include_bytes!("../../../scripts/sprec/audio/Unknown.zip");
+    fn voices() -> Vec<Voice> {
+        return vec![
+            Voice { f0: 100.0, tract: 1.15, tilt: 1.2 },
+            Voice { f0: 145.0, tract: 0.95, tilt: 0.7 },
+            Voice { f0: 210.0, tract: 0.85, tilt: 1.0 },
+            Voice { f0: 260.0, tract: 1.05, tilt: 0.6 },
+        ];
+    }
 
-    let mut pos = 0;
-    let mut buffer = File::create("/opt/sam/scripts/sprec/audio/Unknown.zip")?;
+    fn embedding(human_oid: &str, values: Vec<f32>) -> crate::sam::memory::SpeakerEmbedding {
+        let mut embedding = crate::sam::memory::SpeakerEmbedding::new();
+        embedding.human_oid = human_oid.to_string();
+        embedding.embedding = values;
+        return embedding;
+    }
 
-    while pos < data.len() {
-        let bytes_written = buffer.write(&data[pos..])?;
-        pos += bytes_written;
+    // Three clips of each voice, human oids are h0, h1...
+    fn enroll(voices: &Vec<Voice>, rng: &mut Rng) -> Profiles {
+        let mut embeddings = Vec::new();
+        for (n, voice) in voices.iter().enumerate() {
+            for _ in 0..3 {
+                let samples = utterance(*voice, 2.5, 6000.0 + rng.next() * 8000.0, rng);
+                embeddings.push(embedding(&format!("h{}", n), embed(&StatsEncoder, &samples).unwrap().0));
+            }
+        }
+        return Profiles::build(Arc::new(StatsEncoder), &embeddings, StatsEncoder.default_threshold(), DEFAULT_MARGIN);
     }
 
+    fn fixture(file: &str) -> Vec<i16> {
+        let path = format!("{}/src/sam/services/sprec/fixtures/{}", env!("CARGO_MANIFEST_DIR"), file);
+        let reader = hound::WavReader::open(path).unwrap();
+        assert_eq!((reader.spec().sample_rate, reader.spec().channels), (SAMPLE_RATE, 1), "{} must be 16kHz mono", file);
+        return reader.into_samples::<i16>().collect::<Result<Vec<i16>, hound::Error>>().unwrap();
+    }
 
-    let data = include_bytes!("../../../scripts/sprec/noise/other.zip");
+    #[test]
+    fn speech_frames_skip_silence_and_room_noise() {
+        let mut rng = Rng(1);
+        assert_eq!(speech_frames(&vec![0i16; 32000]).len(), 0);
+        let room: Vec<i16> = (0..32000).map(|_| (rng.gauss() * 60.0) as i16).collect();
+        assert_eq!(speech_frames(&room).len(), 0);
 
-    let mut pos = 0;
-    let mut buffer = File::create("/opt/sam/scripts/sprec/noise/other.zip")?;
+        // A second of tone between a second of silence either side
+        let mut samples = vec![0i16; 16000];
+        samples.extend((0..16000).map(|i| ((i as f64 * 2.0 * std::f64::consts::PI * 200.0 / 16000.0).sin() * 8000.0) as i16));
+        samples.extend(vec![0i16; 16000]);
+        let frames = speech_frames(&samples);
+        assert!(frames.len() >= 95, "{} frames", frames.len());
+        assert!(frames.iter().all(|start| *start + FRAME > 16000 && *start < 32000));
+    }
 
-    while pos < data.len() {
-        let bytes_written = buffer.write(&data[pos..])?;
-        pos += bytes_written;
+    #[test]
+    fn too_little_speech_is_not_embedded() {
+        let mut rng = Rng(2);
+        assert!(embed(&StatsEncoder, &vec![0i16; 32000]).is_err());
+        assert!(embed(&StatsEncoder, &utterance(voices()[0], 0.1, 8000.0, &mut rng)).is_err());
     }
 
-    let data = include_bytes!("../../../scripts/sprec/noise/_background_noise_.zip");
+    #[test]
+    fn pitch_follows_the_fundamental() {
+        for f0 in [90.0, 145.0, 210.0, 300.0] {
+            // Harmonics falling off like a voice, the second louder than the first
+            let samples: Vec<i16> = (0..8000).map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let wave: f64 = (1..20).map(|k| (if k == 2 { 1.5 } else { 1.0 }) / k as f64 * (2.0 * std::f64::consts::PI * k as f64 * f0 * t).sin()).sum();
+                (wave * 4000.0) as i16
+            }).collect();
+            let heard = pitch(&samples, 1600).unwrap();
+            assert!((heard / f0 - 1.0).abs() < 0.03, "heard {:.0}Hz for {:.0}Hz", heard, f0);
+        }
+        // Noise has no pitch
+        let mut rng = Rng(3);
+        let noise: Vec<i16> = (0..8000).map(|_| (rng.gauss() * 4000.0) as i16).collect();
+        assert_eq!(pitch(&noise, 1600), None);
+    }
 
-    let mut pos = 0;
-    let mut buffer = File::create("/opt/sam/scripts/sprec/noise/_background_noise_.zip")?;
+    #[test]
+    fn stats_embeddings_end_with_the_pitch() {
+        let mut rng = Rng(3);
+        let mut pitches: Vec<f64> = Vec::new();
+        for voice in voices() {
+            let (embedding, speech_ms) = embed(&StatsEncoder, &utterance(voice, 2.0, 8000.0, &mut rng)).unwrap();
+            assert_eq!(embedding.len(), 2 * CEPSTRA + 1);
+            assert!(speech_ms >= MIN_SPEECH_MS);
+            let heard = (embedding[2 * CEPSTRA] as f64).exp();
+            // The voices wander by about 10% between syllables
+            assert!((heard / voice.f0 - 1.0).abs() < 0.25, "heard {:.0}Hz for {:.0}Hz", heard, voice.f0);
+            pitches.push(heard);
+        }
+        assert!(pitches.windows(2).all(|w| w[0] < w[1]), "{:?}", pitches);
+    }
 
-    while pos < data.len() {
-        let bytes_written = buffer.write(&data[pos..])?;
-        pos += bytes_written;
+    #[test]
+    fn profiles_are_built_per_human() {
+        let embeddings = vec![
+            embedding("a", vec![1.0, 0.0]),
+            embedding("a", vec![3.0, 0.0]),
+            embedding("b", vec![0.0, 4.0]),
+            // Another encoder's size is left out
+            embedding("c", vec![1.0, 2.0, 3.0]),
+        ];
+        let profiles = Profiles::build(Arc::new(StatsEncoder), &embeddings, 0.3, 0.02);
+        assert_eq!(profiles.profiles.len(), 2);
+        assert_eq!(profiles.profiles[0].human_oid, "a");
+        assert_eq!(profiles.profiles[0].clips, 2);
+        assert_eq!(profiles.profiles[0].mean, vec![2.0, 0.0]);
+        assert_eq!(profiles.profiles[1].mean, vec![0.0, 4.0]);
+        // Only a's two clips say anything about the spread
+        assert_eq!(profiles.degrees, 1);
+        assert!((profiles.spread[0] - 2f64.sqrt()).abs() < 1e-9);
+        assert_eq!(profiles.spread[1], 0.0);
     }
 
+    #[test]
+    fn similarity_prefers_the_same_voice() {
+        let mut rng = Rng(4);
+        let voices = voices();
+        let profiles = enroll(&voices, &mut rng);
+        for (n, voice) in voices.iter().enumerate() {
+            let (clip, _) = embed(&StatsEncoder, &utterance(*voice, 2.0, 9000.0, &mut rng)).unwrap();
+            let own = StatsEncoder.similarity(&profiles, &profiles.profiles[n], &clip);
+            for (m, other) in profiles.profiles.iter().enumerate() {
+                if m != n {
+                    assert!(own > StatsEncoder.similarity(&profiles, other, &clip), "voice {} sounds more like {}", n, m);
+                }
+            }
+        }
+    }
 
-    crate::sam::tools::extract_zip("/opt/sam/scripts/sprec/audio/Unknown.zip", format!("/opt/sam/scripts/sprec/audio/"));
-    crate::sam::tools::extract_zip("/opt/sam/scripts/sprec/noise/other.zip", format!("/opt/sam/scripts/sprec/noise/"));
-    crate::sam::tools::extract_zip("/opt/sam/scripts/sprec/noise/_background_noise_.zip", format!("/opt/sam/scripts/sprec/noise/"));
+    #[test]
+    fn enrolled_voices_are_identified() {
+        let mut rng = Rng(5);
+        let voices = voices();
+        let profiles = enroll(&voices, &mut rng);
+        for (n, voice) in voices.iter().enumerate() {
+            for _ in 0..3 {
+                let (clip, _) = embed(&StatsEncoder, &utterance(*voice, 1.5 + rng.next(), 4000.0 + rng.next() * 12000.0, &mut rng)).unwrap();
+                let prediction = identify(&profiles, &clip);
+                assert_eq!(prediction.human, format!("h{}", n), "confidence {:.2}", prediction.confidence);
+            }
+        }
+    }
 
-    crate::sam::tools::linux_cmd(format!("rm -rf /opt/sam/scripts/sprec/audio/Unknown.zip"));
-    crate::sam::tools::linux_cmd(format!("rm -rf /opt/sam/scripts/sprec/noise/other.zip"));
-    crate::sam::tools::linux_cmd(format!("rm -rf /opt/sam/scripts/sprec/noise/_background_noise_.zip"));
+    #[test]
+    fn unmatched_voices_are_unknown() {
+        let mut rng = Rng(6);
+        // Only the two lower voices are enrolled
+        let profiles = enroll(&voices()[..2].to_vec(), &mut rng);
+        let strangers = vec![
+            Voice { f0: 300.0, tract: 0.8, tilt: 0.7 },
+            Voice { f0: 230.0, tract: 0.9, tilt: 1.4 },
+        ];
+        for stranger in strangers {
+            for _ in 0..3 {
+                let (clip, _) = embed(&StatsEncoder, &utterance(stranger, 2.0, 9000.0, &mut rng)).unwrap();
+                let prediction = identify(&profiles, &clip);
+                assert_eq!(prediction.human, "Unknown", "{:.0}Hz was {} ({:.2})", stranger.f0, prediction.human, prediction.confidence);
+            }
+        }
+    }
 
+    #[test]
+    fn nobody_enrolled_or_too_close_to_call_is_unknown() {
+        let mut rng = Rng(7);
+        let (clip, _) = embed(&StatsEncoder, &utterance(voices()[0], 2.0, 8000.0, &mut rng)).unwrap();
+        let empty = Profiles::build(Arc::new(StatsEncoder), &Vec::new(), 0.3, DEFAULT_MARGIN);
+        assert_eq!(identify(&empty, &clip).human, "Unknown");
 
-    log::info!("Installing requirements for sprec....");
-    crate::sam::tools::linux_cmd(format!("pip3 install -r /opt/sam/scripts/sprec/requirements.txt"));
-    
-    log::info!("Building initial sprec model...");
-    build();
-    Ok(())
-}
\ No newline at end of file
+        // Two humans with the same voice, neither wins by the margin
+        let twins = Profiles::build(Arc::new(StatsEncoder), &vec![embedding("a", clip.clone()), embedding("b", clip.clone())], 0.3, DEFAULT_MARGIN);
+        let prediction = identify(&twins, &clip);
+        assert_eq!(prediction.human, "Unknown");
+        assert!(prediction.confidence > 0.9);
+    }
+
+    #[test]
+    fn recorded_speakers() {
+        let speakers = ["low", "middle", "high"];
+        let mut embeddings = Vec::new();
+        for speaker in speakers.iter() {
+            for n in 0..3 {
+                let (values, _) = embed(&StatsEncoder, &fixture(&format!("{}_enroll_{}.wav", speaker, n))).unwrap();
+                embeddings.push(embedding(speaker, values));
+            }
+        }
+        let profiles = Profiles::build(Arc::new(StatsEncoder), &embeddings, StatsEncoder.default_threshold(), DEFAULT_MARGIN);
+
+        for speaker in speakers.iter() {
+            let (clip, _) = embed(&StatsEncoder, &fixture(&format!("{}_test.wav", speaker))).unwrap();
+            let prediction = identify(&profiles, &clip);
+            assert_eq!(&prediction.human, speaker, "confidence {:.2}", prediction.confidence);
+        }
+        let (clip, _) = embed(&StatsEncoder, &fixture("stranger.wav")).unwrap();
+        let prediction = identify(&profiles, &clip);
+        assert_eq!(prediction.human, "Unknown", "stranger was {} ({:.2})", prediction.human, prediction.confidence);
+    }
+}
@@ -0,0 +1,264 @@
+// ███████     █████     ███    ███
+// ██         ██   ██    ████  ████
+// ███████    ███████    ██ ████ ██
+//      ██    ██   ██    ██  ██  ██
+// ███████ ██ ██   ██ ██ ██      ██ ██
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// Voices sprec didn't recognize. An utterance nobody was recognized in joins
+// the Unknown human that sounds most like it, or starts a new one (see
+// unknown_voice), and its embedding is kept. Here those unknown humans are
+// grouped by voice so they can be named once. Assigning a cluster merges its
+// unknown humans into an existing or new human, whose voice then includes
+// their clips. Clips with too little speech all go to one placeholder human
+// without an embedding, so it isn't in any cluster, nor are unknown humans
+// heard before sprec kept their voices.
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+
+use crate::sam::services::sprec::{Profile, Profiles};
+
+// Holds the clips of unknown speakers that were too short to cluster
+const PLACEHOLDER: &str = "Unknown speaker";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cluster {
+    // The oid of its first unknown human
+    pub id: String,
+    pub human_oids: Vec<String>,
+    pub observation_oids: Vec<String>,
+    pub clips: usize,
+    pub first_heard_at: i64,
+    pub last_heard_at: i64,
+}
+
+// Everything that points at a human, moved over when humans are merged
+fn human_tables() -> Vec<String> {
+    return vec![
+        crate::sam::memory::SpeakerEmbedding::sql_table_name(),
+        crate::sam::memory::WakeWordTemplate::sql_table_name(),
+        crate::sam::memory::HumanFaceEncoding::sql_table_name(),
+        crate::sam::memory::PresenceDevice::sql_table_name(),
+        crate::sam::memory::Notification::sql_table_name(),
+        crate::sam::memory::ScheduledJob::sql_table_name(),
+    ];
+}
+
+pub fn is_unknown(human: &crate::sam::memory::Human) -> bool {
+    return human.name.to_lowercase().starts_with("unknown");
+}
+
+pub fn unknown_humans() -> Result<HashSet<String>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("unknown%")));
+    pg_query.query_coulmns.push(format!("name ilike"));
+    let humans = crate::sam::memory::Human::select(None, None, None, Some(pg_query))?;
+    return Ok(humans.into_iter().map(|h| h.oid).collect());
+}
+
+fn human(oid: &str) -> Result<Option<crate::sam::memory::Human>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(oid.to_string()));
+    pg_query.query_coulmns.push(format!("oid ="));
+    return Ok(crate::sam::memory::Human::select(None, None, None, Some(pg_query))?.into_iter().next());
+}
+
+// Where a voice sprec didn't recognize goes: the unknown human who sounds the
+// most like it if that passes sprec_threshold, otherwise a new one. The clip's
+// embedding and speech_ms come back to be kept once the observation is saved,
+// None when there's too little speech and it went to the placeholder.
+pub fn unknown_voice(file_path: &str) -> Result<(crate::sam::memory::Human, Option<(Vec<f32>, i64)>), crate::sam::services::Error> {
+    let profiles = crate::sam::services::sprec::profiles()?;
+    let voice = crate::sam::services::sprec::samples(file_path).and_then(|samples| {
+        crate::sam::services::sprec::embed(profiles.encoder.as_ref(), &samples)
+    });
+    let (embedding, speech_ms) = match voice {
+        Ok(voice) => voice,
+        Err(e) => {
+            log::info!("no unknown voice to keep in {}: {}", file_path, e);
+            return Ok((placeholder()?, None));
+        }
+    };
+
+    let voices = Profiles::build(profiles.encoder.clone(), &unknown_embeddings(&profiles)?, profiles.threshold, profiles.margin);
+    let mut best: Option<(&Profile, f64)> = None;
+    for voice in voices.profiles.iter() {
+        let similarity = profiles.encoder.similarity(&profiles, voice, &embedding);
+        if similarity >= profiles.threshold && best.map(|(_, b)| similarity > b).unwrap_or(true) {
+            best = Some((voice, similarity));
+        }
+    }
+    match best {
+        Some((voice, _)) => match human(&voice.human_oid)? {
+            Some(human) => return Ok((human, Some((embedding, speech_ms)))),
+            None => {}
+        },
+        None => {}
+    }
+
+    let mut human = crate::sam::memory::Human::new();
+    human.name = format!("Unknown");
+    human.save()?;
+    return Ok((human, Some((embedding, speech_ms))));
+}
+
+// The one human for clips too short to tell a voice from, made when first needed
+fn placeholder() -> Result<crate::sam::memory::Human, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(PLACEHOLDER.to_string()));
+    pg_query.query_coulmns.push(format!("name ="));
+    match crate::sam::memory::Human::select(Some(1), None, Some(format!("id ASC")), Some(pg_query))?.into_iter().next() {
+        Some(human) => return Ok(human),
+        None => {}
+    }
+    let mut human = crate::sam::memory::Human::new();
+    human.name = PLACEHOLDER.to_string();
+    human.save()?;
+    return Ok(human);
+}
+
+// Embeddings of unknown humans from the current encoder, oldest first
+fn unknown_embeddings(profiles: &Profiles) -> Result<Vec<crate::sam::memory::SpeakerEmbedding>, crate::sam::services::Error> {
+    let unknown = unknown_humans()?;
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(profiles.encoder.name()));
+    pg_query.query_coulmns.push(format!("encoder ="));
+    let embeddings: Vec<crate::sam::memory::SpeakerEmbedding> = crate::sam::memory::SpeakerEmbedding::select(None, None, Some(format!("created_at ASC")), Some(pg_query))?
+        .into_iter()
+        .filter(|e| unknown.contains(&e.human_oid))
+        .collect();
+    return Ok(embeddings);
+}
+
+// Unknown humans in the order they were heard, each joins the cluster that
+// sounds most like them if it passes sprec_threshold, otherwise starts one
+pub fn clusters() -> Result<Vec<Cluster>, crate::sam::services::Error> {
+    let profiles = crate::sam::services::sprec::profiles()?;
+    let embeddings = unknown_embeddings(&profiles)?;
+    return Ok(group(&profiles, &embeddings));
+}
+
+pub fn group(profiles: &Profiles, embeddings: &Vec<crate::sam::memory::SpeakerEmbedding>) -> Vec<Cluster> {
+    // One voice per unknown human first, usually that's a single clip
+    let voices = Profiles::build(profiles.encoder.clone(), &embeddings, profiles.threshold, profiles.margin);
+
+    let mut clusters: Vec<(Cluster, Vec<Profile>)> = Vec::new();
+    for voice in voices.profiles.into_iter() {
+        let clips: Vec<&crate::sam::memory::SpeakerEmbedding> = embeddings.iter().filter(|e| e.human_oid == voice.human_oid).collect();
+        let mut best: Option<(usize, f64)> = None;
+        for (n, (_, members)) in clusters.iter().enumerate() {
+            let similarity = profiles.encoder.similarity(profiles, &centroid(members), &voice.mean);
+            if similarity >= profiles.threshold && best.map(|(_, b)| similarity > b).unwrap_or(true) {
+                best = Some((n, similarity));
+            }
+        }
+
+        let heard: Vec<i64> = clips.iter().map(|c| c.created_at).collect();
+        let first = heard.iter().cloned().min().unwrap_or(0);
+        let last = heard.iter().cloned().max().unwrap_or(0);
+        let observation_oids: Vec<String> = clips.iter().filter_map(|c| c.observation_oid.clone()).collect();
+        match best {
+            Some((n, _)) => {
+                let (cluster, members) = &mut clusters[n];
+                cluster.human_oids.push(voice.human_oid.clone());
+                cluster.observation_oids.extend(observation_oids);
+                cluster.clips = cluster.clips + voice.clips;
+                cluster.first_heard_at = cluster.first_heard_at.min(first);
+                cluster.last_heard_at = cluster.last_heard_at.max(last);
+                members.push(voice);
+            },
+            None => {
+                clusters.push((Cluster {
+                    id: voice.human_oid.clone(),
+                    human_oids: vec![voice.human_oid.clone()],
+                    observation_oids: observation_oids,
+                    clips: voice.clips,
+                    first_heard_at: first,
+                    last_heard_at: last,
+                }, vec![voice]));
+            }
+        }
+    }
+
+    let mut clusters: Vec<Cluster> = clusters.into_iter().map(|(cluster, _)| cluster).collect();
+    clusters.sort_by(|a, b| b.clips.cmp(&a.clips).then(b.last_heard_at.cmp(&a.last_heard_at)));
+    return clusters;
+}
+
+// The voices of a cluster as one, each clip counting the same
+fn centroid(members: &Vec<Profile>) -> Profile {
+    let size = members.first().map(|m| m.mean.len()).unwrap_or(0);
+    let clips: usize = members.iter().map(|m| m.clips).sum();
+    let mean: Vec<f32> = (0..size).map(|d| {
+        (members.iter().map(|m| m.mean[d] as f64 * m.clips as f64).sum::<f64>() / clips.max(1) as f64) as f32
+    }).collect();
+    return Profile {
+        human_oid: members.first().map(|m| m.human_oid.clone()).unwrap_or(String::new()),
+        clips: clips,
+        mean: mean,
+    };
+}
+
+// Names a cluster, its unknown humans become the given human
+pub fn assign(cluster_id: &str, human_oid: &str) -> Result<Option<crate::sam::memory::Human>, crate::sam::services::Error> {
+    let cluster = match clusters()?.into_iter().find(|c| c.id == cluster_id) {
+        Some(cluster) => cluster,
+        None => return Ok(None)
+    };
+    let mut merged = None;
+    for unknown_oid in cluster.human_oids.iter() {
+        merged = Some(merge(unknown_oid, human_oid)?);
+    }
+    return Ok(merged);
+}
+
+// Folds one human into another. Observations, voices, faces, devices,
+// notifications and jobs move over, counts are redone and missing details are filled in.
+pub fn merge(from_oid: &str, into_oid: &str) -> Result<crate::sam::memory::Human, crate::sam::services::Error> {
+    if from_oid == into_oid {
+        return Err(format!("can't merge a human into themselves").into());
+    }
+    let from = match human(from_oid)? {
+        Some(from) => from,
+        None => return Err(format!("human {} not found", from_oid).into())
+    };
+    let mut into = match human(into_oid)? {
+        Some(into) => into,
+        None => return Err(format!("human {} not found", into_oid).into())
+    };
+
+    crate::sam::memory::Observation::replace_human(from_oid, into_oid)?;
+    for table in human_tables() {
+        crate::sam::memory::Config::reassign_human(table, from_oid, into_oid)?;
+    }
+
+    // into keeps its own authorization_level, merging never grants access
+    if is_unknown(&into) && !is_unknown(&from) {
+        into.name = from.name.clone();
+    }
+    if into.email.is_none() {
+        into.email = from.email.clone();
+    }
+    if into.phone_number.is_none() {
+        into.phone_number = from.phone_number.clone();
+    }
+    if into.tts_speaker_id.is_none() && into.tts_style_wav.is_none() {
+        into.tts_speaker_id = from.tts_speaker_id.clone();
+        into.tts_style_wav = from.tts_style_wav.clone();
+    }
+    // save only writes what's newer than the stored row
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    into.updated_at = now.max(into.updated_at + 1);
+    into.save()?;
+    crate::sam::memory::Human::destroy(from.oid.clone())?;
+    // Their observations are all into's now
+    crate::sam::memory::Human::recount(into_oid)?;
+
+    crate::sam::services::sprec::trim(into_oid)?;
+    crate::sam::services::sprec::invalidate();
+    log::info!("merged human {} into {}", from_oid, into_oid);
+    return Ok(human(into_oid)?.unwrap_or(into));
+}
@@ -0,0 +1,82 @@
+# Builds the recorded voice fixtures for the sprec tests, run from this directory:
+#   python3 generate.py
+#
+# Every speaker reads a few sentences of random syllables in their own voice:
+# a harmonic source at their pitch, tilted by their voice quality and shaped
+# by vowel formants scaled to their vocal tract, with breathy consonants in
+# between. The enroll clips are what a human would record when they're added,
+# the test clip is something they say later. stranger.wav is nobody enrolled.
+
+import math
+import random
+import struct
+import wave
+
+RATE = 16000
+
+random.seed(11)
+
+# (f1, f2, f3) of an average adult tract
+VOWELS = [(270, 2290, 3010), (390, 1990, 2550), (530, 1840, 2480), (660, 1720, 2410),
+          (730, 1090, 2440), (570, 840, 2410), (440, 1020, 2240), (300, 870, 2240)]
+
+# name: (pitch, tract, tilt)
+SPEAKERS = {
+    "low": (105, 1.12, 1.1),
+    "middle": (160, 0.97, 0.8),
+    "high": (235, 0.86, 0.9),
+}
+STRANGER = (290, 0.8, 0.6)
+
+
+def sentence(voice, seconds, peak):
+    pitch, tract, tilt = voice
+    out = [0.0] * (RATE // 2)
+    phase = 0.0
+    spoken = 0.0
+    while spoken < seconds:
+        ms = random.uniform(90, 260)
+        n = int(ms * RATE / 1000)
+        consonant = random.random() < 0.2
+        f1, f2, f3 = (f * tract for f in random.choice(VOWELS))
+        drift = 1.0 + random.gauss(0, 0.08)
+        syllable = []
+        for i in range(n):
+            t = i / n
+            edge = min(1.0, t / 0.12, (1.0 - t) / 0.12)
+            f0 = pitch * drift * (1.0 + 0.03 * math.sin(2 * math.pi * 4 * t))
+            phase += 2 * math.pi * f0 / RATE
+            value = 0.0
+            if consonant:
+                value = 0.3 * random.gauss(0, 1)
+            else:
+                k = 1
+                while k * f0 < 7000:
+                    hz = k * f0
+                    envelope = (math.exp(-((hz - f1) / 100) ** 2) + 0.6 * math.exp(-((hz - f2) / 130) ** 2)
+                                + 0.35 * math.exp(-((hz - f3) / 190) ** 2) + 0.03)
+                    value += envelope * k ** -tilt * math.sin(k * phase)
+                    k += 1
+            syllable.append(value * edge)
+        out.extend(syllable)
+        out.extend(0.0 for _ in range(int(random.uniform(0, 0.1) * RATE)))
+        spoken += ms / 1000
+    out.extend(0.0 for _ in range(RATE // 2))
+    top = max(abs(v) for v in out)
+    return [v / top * peak + random.gauss(0, 40) for v in out]
+
+
+def write(name, samples):
+    with wave.open(name, "wb") as w:
+        w.setnchannels(1)
+        w.setsampwidth(2)
+        w.setframerate(RATE)
+        w.writeframes(b"".join(struct.pack("<h", max(-32768, min(32767, int(round(s))))) for s in samples))
+
+
+for name, voice in SPEAKERS.items():
+    for n in range(3):
+        write("%s_enroll_%d.wav" % (name, n), sentence(voice, 2.5, random.uniform(6000, 14000)))
+    write("%s_test.wav" % name, sentence(voice, 2.0, random.uniform(4000, 16000)))
+
+write("stranger.wav", sentence(STRANGER, 2.0, 9000))
@@ -41,25 +41,11 @@ pub fn init(){
 }
 
 pub fn init_cache(){
-    let cache_thread = thread::Builder::new().name("cache".to_string()).spawn(move || {
-        loop{
-            crate::sam::memory::FileStorage::cache_all();
-            thread::sleep(Duration::from_millis(4000));
-        }
-    });
-    
-    match cache_thread{
-        Ok(_) => {
-            log::info!("cache started successfully");
-        },
-        Err(e) => {
-            log::error!("failed to initialize cache: {}", e);
-        }
-    }
+    crate::sam::services::scheduler::every("cache", Duration::from_millis(4000), || crate::sam::memory::FileStorage::cache_all());
 }
 
 
-pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
+pub fn handle(current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
     if request.url() == "/api/services/storage/locations" {
 
         if request.method() == "GET" {
@@ -108,6 +94,16 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
             file.storage_location_oid = format!("SQL");
             file.save()?;
 
+            // Let the rest of sam know without sending the file data around
+            let mut live = file.clone();
+            live.file_data = None;
+            crate::sam::services::bus::publish(crate::sam::services::bus::Event::FileStored{
+                file: live,
+                human_oid: Some(current_session.human_oid.clone()),
+                sid: Some(current_session.sid.clone()),
+                source: format!("upload"),
+            });
+
             return Ok(Response::json(&file));
         }
     }
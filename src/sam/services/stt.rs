@@ -8,9 +8,14 @@
 // Licensed under GPLv3....see LICENSE file.
 
 
-// TODO - Ability to use multiple stt servers
-// Cloud -> Internal Cloud -> Localhost
-// TODO - Don't start docker unless localhost has been called
+// stt.rs turns speech into text through a chain of engines. Each engine is
+// tried in the order of the stt_engines setting (whisper,remote,tiny by
+// default) until one answers within stt_{engine}_timeout_seconds, engines
+// that aren't installed or configured are skipped. Transcriptions record
+// which engine answered, how long it took and how sure it was.
+
+pub mod remote;
+pub mod whisper;
 
 use rouille::Request;
 use rouille::Response;
@@ -18,46 +23,244 @@ use rouille::post_input;
 use serde::{Serialize, Deserialize};
 use std::fs::File;
 use std::io::Write;
-use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::io::Read;
 use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub trait SttEngine: Send + Sync {
+    fn name(&self) -> &'static str;
+    // Engines that aren't installed or configured are left out of the chain
+    fn available(&self) -> bool {
+        true
+    }
+    fn default_timeout(&self) -> Duration;
+    fn transcribe(&self, file_path: &str, timeout: Duration) -> Result<Transcription, crate::sam::services::Error>;
+    // A 16kHz mono wav held in memory, engines that can't take one are skipped
+    fn transcribe_wav(&self, _wav: &[u8], _timeout: Duration) -> Result<Transcription, crate::sam::services::Error> {
+        return Err(format!("{} can't transcribe from memory", self.name()).into());
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Transcription {
+    pub text: String,
+    pub engine: String,
+    pub latency_ms: i64,
+    // 0 to 1, None when the engine doesn't say
+    pub confidence: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EngineStats {
+    pub engine: String,
+    pub available: bool,
+    pub timeout_seconds: u64,
+    pub calls: u64,
+    pub failures: u64,
+    pub average_latency_ms: i64,
+    pub last_error: Option<String>,
+}
+
+pub fn engines() -> &'static Vec<Box<dyn SttEngine>> {
+    static ENGINES: OnceLock<Vec<Box<dyn SttEngine>>> = OnceLock::new();
+    ENGINES.get_or_init(|| vec![
+        Box::new(whisper::WhisperEngine::local()),
+        Box::new(remote::RemoteEngine),
+        Box::new(whisper::WhisperEngine::tiny()),
+    ])
+}
+
+pub fn engine_for(name: &str) -> Option<&'static dyn SttEngine> {
+    return engines().iter().find(|e| e.name() == name).map(|e| e.as_ref());
+}
+
+fn stats() -> &'static Mutex<HashMap<String, EngineStats>> {
+    static STATS: OnceLock<Mutex<HashMap<String, EngineStats>>> = OnceLock::new();
+    STATS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn record(engine: &str, result: &Result<Transcription, crate::sam::services::Error>){
+    let mut stats = match stats().lock() {
+        Ok(stats) => stats,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    let entry = stats.entry(engine.to_string()).or_default();
+    entry.calls = entry.calls + 1;
+    match result {
+        Ok(transcription) => {
+            let succeeded = (entry.calls - entry.failures) as i64;
+            entry.average_latency_ms = entry.average_latency_ms + (transcription.latency_ms - entry.average_latency_ms) / succeeded;
+        },
+        Err(e) => {
+            entry.failures = entry.failures + 1;
+            entry.last_error = Some(format!("{}", e));
+        }
+    }
+}
+
+// The engines in the order they're tried, unknown names in the setting are ignored
+pub fn chain() -> Vec<&'static dyn SttEngine> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("stt_engines")));
+    pg_query.query_coulmns.push(format!("key ="));
+    let names: Vec<String> = match crate::sam::memory::Setting::select(None, None, None, Some(pg_query)) {
+        Ok(settings) => settings.first().map(|s| s.values.iter()
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().to_lowercase())
+            .filter(|v| v.len() > 0)
+            .collect()).unwrap_or_default(),
+        Err(e) => {
+            log::error!("failed to load setting stt_engines: {}", e);
+            Vec::new()
+        }
+    };
+    if names.len() == 0 {
+        return engines().iter().map(|e| e.as_ref()).collect();
+    }
+    return names.iter().filter_map(|name| engine_for(name)).collect();
+}
+
+pub fn timeout(engine: &dyn SttEngine) -> Duration {
+    let key = format!("stt_{}_timeout_seconds", engine.name());
+    return Duration::from_secs(crate::sam::services::things::setting_seconds(&key, engine.default_timeout().as_secs()));
+}
+
+// Tries each engine in turn, the first to answer wins
+pub fn transcribe(file_path: &str) -> Result<Transcription, crate::sam::services::Error> {
+    return first_answer(file_path, |engine| engine.transcribe(file_path, timeout(engine)));
+}
+
+// transcribe, for audio that shouldn't be written to disk (partial utterances)
+pub fn transcribe_wav(wav: &[u8]) -> Result<Transcription, crate::sam::services::Error> {
+    return first_answer("an in-memory wav", |engine| engine.transcribe_wav(wav, timeout(engine)));
+}
+
+fn first_answer<F>(what: &str, transcribe: F) -> Result<Transcription, crate::sam::services::Error> where F: Fn(&dyn SttEngine) -> Result<Transcription, crate::sam::services::Error> {
+    let mut errors: Vec<String> = Vec::new();
+    for engine in chain() {
+        if !engine.available() {
+            continue;
+        }
+        let started = Instant::now();
+        let mut result = transcribe(engine);
+        match result.as_mut() {
+            Ok(transcription) => transcription.latency_ms = started.elapsed().as_millis() as i64,
+            Err(_) => {}
+        }
+        record(engine.name(), &result);
+        match result {
+            Ok(transcription) => return Ok(transcription),
+            Err(e) => {
+                log::warn!("stt engine {} failed for {}: {}", engine.name(), what, e);
+                errors.push(format!("{}: {}", engine.name(), e));
+            }
+        }
+    }
+    if errors.len() == 0 {
+        return Err(format!("no stt engine is available").into());
+    }
+    return Err(format!("every stt engine failed ({})", errors.join(", ")).into());
+}
+
+pub fn engine_stats() -> Vec<EngineStats> {
+    let stats = match stats().lock() {
+        Ok(stats) => stats.clone(),
+        Err(poisoned) => poisoned.into_inner().clone()
+    };
+    return chain().iter().map(|engine| {
+        let mut entry = stats.get(engine.name()).cloned().unwrap_or_default();
+        entry.engine = engine.name().to_string();
+        entry.available = engine.available();
+        entry.timeout_seconds = timeout(*engine).as_secs();
+        entry
+    }).collect();
+}
+
+// Runs a command to completion and returns its stdout, killing it once the timeout passes
+pub fn run(command: &mut Command, timeout: Duration) -> Result<String, crate::sam::services::Error> {
+    return run_with_input(command, None, timeout);
+}
+
+// run, with input written to the command's stdin
+pub fn run_with_input(command: &mut Command, input: Option<Vec<u8>>, timeout: Duration) -> Result<String, crate::sam::services::Error> {
+    let stdin = if input.is_some() { Stdio::piped() } else { Stdio::null() };
+    let mut child = command.stdin(stdin).stdout(Stdio::piped()).stderr(Stdio::null()).spawn()?;
+    match (child.stdin.take(), input) {
+        (Some(mut stdin), Some(input)) => {
+            thread::spawn(move || {
+                let _ = stdin.write_all(&input);
+            });
+        },
+        _ => {}
+    }
+    let mut stdout = match child.stdout.take() {
+        Some(stdout) => stdout,
+        None => return Err(format!("no stdout").into())
+    };
+    // Read on the side so a chatty process can't fill the pipe and stall
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.read_to_end(&mut output);
+        String::from_utf8_lossy(&output).to_string()
+    });
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait()? {
+            Some(status) => {
+                let output = reader.join().unwrap_or_default();
+                if !status.success() {
+                    return Err(format!("exited with {}", status).into());
+                }
+                return Ok(output);
+            },
+            None => {
+                if Instant::now() >= deadline {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(format!("timed out after {}ms", timeout.as_millis()).into());
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct STTPrediction {
     pub stt: String,
     pub human: String,
     pub confidence: f64,
+    pub engine: String,
+    pub latency_ms: i64,
+    pub stt_confidence: Option<f64>,
 }
 
-// TODO - Fallback to STT api if whisper fails
-// TODO - Return defult unknown if sprec fails
 pub fn process(file_path: String) -> Result<STTPrediction, crate::sam::services::Error> {
-    let whisper = crate::sam::services::stt::whisper(file_path.clone())?;
-    let sprec = crate::sam::services::sprec::predict(&file_path)?;
+    let transcription = transcribe(&file_path)?;
+    // Not knowing who spoke shouldn't lose what they said
+    let sprec = match crate::sam::services::sprec::predict(&file_path) {
+        Ok(sprec) => sprec,
+        Err(e) => {
+            log::error!("sprec failed on {}: {}", file_path, e);
+            crate::sam::services::sprec::SprecPrediction { human: format!("Unknown"), confidence: 0.0 }
+        }
+    };
 
     return Ok(STTPrediction{
-        stt: whisper,
+        stt: transcription.text,
         human: sprec.human,
         confidence: sprec.confidence,
+        engine: transcription.engine,
+        latency_ms: transcription.latency_ms,
+        stt_confidence: transcription.confidence,
     });
 }
 
-// /opt/sam/bin/whisper -m /opt/sam/models/ggml-* -f ./output.wav -otxt
-pub fn whisper(file_path: String) -> Result<String, crate::sam::services::Error> {
-   
-    crate::sam::tools::linux_cmd(format!("ffmpeg -i {} -ar 16000 -ac 1 -c:a pcm_s16le {}.16.wav", file_path, file_path));
-
-    crate::sam::tools::linux_cmd(format!("/opt/sam/bin/whisper -m /opt/sam/models/ggml-large.bin -f {}.16.wav -otxt", file_path));
-    
-    let data = std::fs::read_to_string(format!("{}.16.wav.txt", file_path).as_str())?;
-
-
-    std::fs::remove_file(format!("{}.16.wav", file_path).as_str())?;
-    std::fs::remove_file(format!("{}.16.wav.txt", file_path).as_str())?;
-
-    return Ok(data);
-}
-
 pub fn patch_whisper_wts(file_path: String) -> Result<(), crate::sam::services::Error>{
     let mut data = std::fs::read_to_string(format!("{}", file_path).as_str())?;
     data = data.replace("ffmpeg", "/opt/sam/bin/ffmpeg").replace("/System/Library/Fonts/Supplemental/Courier New Bold.ttf","/opt/sam/fonts/courier.ttf");
@@ -77,6 +280,10 @@ pub fn install() -> std::io::Result<()> {
         crate::sam::tools::linux_cmd(format!("wget -O /opt/sam/models/ggml-large.bin https://huggingface.co/datasets/ggerganov/whisper.cpp/resolve/main/ggml-large.bin"));
     }
 
+    if !Path::new("/opt/sam/models/ggml-tiny.en.bin").exists(){
+        crate::sam::tools::linux_cmd(format!("wget -O /opt/sam/models/ggml-tiny.en.bin https://huggingface.co/datasets/ggerganov/whisper.cpp/resolve/main/ggml-tiny.en.bin"));
+    }
+
     let data = include_bytes!("../../../packages/whisper/main-amd64");
     let mut pos = 0;
     let mut buffer = File::create("/opt/sam/bin/whisper")?;
@@ -132,16 +339,28 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
         file.write_all(&data.audio_data.data).unwrap();
 
 
-        let mut idk = crate::sam::services::stt::upload(tmp_file_path).unwrap();
+        let transcription = transcribe(&tmp_file_path);
+        let _ = std::fs::remove_file(&tmp_file_path);
+        let transcription = match transcription {
+            Ok(transcription) => transcription,
+            Err(e) => return Ok(Response::text(format!("{}", e)).with_status_code(503))
+        };
+        let mut idk = STTReply{
+            text: transcription.text,
+            time: transcription.latency_ms as f64 / 1000.0,
+            response_type: None,
+            engine: Some(transcription.engine),
+            confidence: transcription.confidence,
+        };
 
         // TODO - Spawn thread to store audio/text files as an observation.
         // TODO - Spawn sprec thread to identify speaker.
-        // TODO - Spawn thread to process sam brain.py. (maybe, might execute in js runtime instead)
-        
-        
-        // If idk.text contains "sam" then redirect request to io api
-        if idk.text.contains("sam") {
-            return Ok(Response::redirect_303(format!("/api/io?input={}", idk.text.replace("sam ", ""))));
+
+        // Clips that start with the wake word are redirected to the io api
+        let phrase = crate::sam::services::sound::wake::config().phrase.clone();
+        let command = crate::sam::services::sound::wake::strip_phrase(&idk.text, &phrase);
+        if command.len() > 0 && command != idk.text.trim() {
+            return Ok(Response::redirect_303(format!("/api/io?input={}", crate::sam::services::things::http::encode(&command))));
         }
 
         idk.response_type = Some(format!("stt"));
@@ -149,57 +368,21 @@ pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Reque
         return Ok(Response::json(&idk));
     }
 
+    if request.url() == "/api/services/stt/engines" {
+        return Ok(Response::json(&engine_stats()));
+    }
+
 
 
     
     return Ok(Response::empty_404());
 }
 
-pub fn init(){
-
-    let stt_thread = thread::Builder::new().name("stt".to_string()).spawn(move || {
-        crate::sam::tools::linux_cmd(format!("docker run -p 8002:8000 p0indexter/stt"));
-    });
-    match stt_thread{
-        Ok(_) => {
-            log::info!("stt server started successfully");
-        },
-        Err(e) => {
-            log::error!("failed to initialize stt server: {}", e);
-        }
-    }
-}
-
-
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct STTReply {
     pub text: String,
     pub time: f64,
     pub response_type: Option<String>,
+    pub engine: Option<String>,
+    pub confidence: Option<f64>,
 }
-
-
-// TODO - Use foundation stt public server first, fallback to local server after 2 seconds for offline avaliablity
-// TODO - Find another method besided multipart....too many dependencies
-pub fn upload(_tmp_file_path: String) -> Result<STTReply, crate::sam::services::Error> {
-
-    return Ok(STTReply{
-        text: String::new(),
-        time: 0.0,
-        response_type: None,
-    });
-
-    // let form = reqwest::blocking::multipart::Form::new().file("speech", tmp_file_path.as_str())?;
-
-
-    // let client = reqwest::blocking::Client::new();
-
-    // Ok(client.post(format!("https://stt.opensam.foundation/api/v1/stt"))
-    // .multipart(form)
-    // .send()?.json()?)
-
-    // Ok(client.post(format!("http://localhost:8002/api/v1/stt"))
-    //     .multipart(form)
-    //     .send()?.json()?)
-}
\ No newline at end of file
@@ -0,0 +1,82 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// An stt server somewhere else, configured by a Service row with the
+// identifier stt: endpoint is the url the wav is posted to (as audio/wav),
+// key is sent as a bearer token and username/password as basic auth when
+// set. The server answers with json, {"text": "...", "confidence": 0.93},
+// confidence is optional and transcript is accepted in place of text.
+
+use crate::sam::services::stt::{SttEngine, Transcription};
+use std::time::Duration;
+
+pub struct RemoteEngine;
+
+pub fn service() -> Result<Option<crate::sam::memory::Service>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("stt")));
+    pg_query.query_coulmns.push(format!("identifier ="));
+    let services = crate::sam::memory::Service::select(None, None, None, Some(pg_query))?;
+    return Ok(services.first().cloned());
+}
+
+impl SttEngine for RemoteEngine {
+    fn name(&self) -> &'static str {
+        "remote"
+    }
+
+    fn available(&self) -> bool {
+        match service() {
+            Ok(Some(service)) => service.endpoint.trim().len() > 0,
+            _ => false
+        }
+    }
+
+    fn default_timeout(&self) -> Duration {
+        return Duration::from_secs(10);
+    }
+
+    fn transcribe(&self, file_path: &str, timeout: Duration) -> Result<Transcription, crate::sam::services::Error> {
+        return self.transcribe_wav(&std::fs::read(file_path)?, timeout);
+    }
+
+    fn transcribe_wav(&self, wav: &[u8], timeout: Duration) -> Result<Transcription, crate::sam::services::Error> {
+        let service = match service()? {
+            Some(service) => service,
+            None => return Err(format!("no stt service configured").into())
+        };
+
+        let client = reqwest::blocking::Client::builder().timeout(timeout).build()?;
+        let mut request = client.post(service.endpoint.trim())
+            .header("Content-Type", "audio/wav")
+            .body(wav.to_vec());
+        if service.key.len() > 0 {
+            request = request.bearer_auth(&service.key);
+        } else if service.username.len() > 0 {
+            request = request.basic_auth(&service.username, Some(&service.password));
+        }
+
+        let response = request.send()?;
+        if !response.status().is_success() {
+            return Err(format!("stt server answered {}", response.status()).into());
+        }
+        let body: serde_json::Value = response.json()?;
+        let text = match body["text"].as_str().or(body["transcript"].as_str()) {
+            Some(text) => text.trim().to_string(),
+            None => return Err(format!("stt server answered without text").into())
+        };
+
+        return Ok(Transcription {
+            text: text,
+            engine: self.name().to_string(),
+            latency_ms: 0,
+            confidence: body["confidence"].as_f64(),
+        });
+    }
+}
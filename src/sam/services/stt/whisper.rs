@@ -0,0 +1,175 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// whisper.cpp at /opt/sam/bin/whisper with a ggml model from /opt/sam/models.
+// The same binary backs two engines: whisper runs stt_whisper_model (large),
+// tiny runs stt_tiny_model (tiny.en) on two threads so it stays usable on a
+// busy or small cpu. Models are downloaded by stt::install.
+//
+// The bundled whisper.cpp only reports token probabilities through -pc, which
+// colors every token by p^3 in ten steps, confidence is read back from those.
+
+use crate::sam::services::stt::{SttEngine, Transcription};
+use std::process::Command;
+use std::time::Duration;
+
+const WHISPER_BIN: &str = "/opt/sam/bin/whisper";
+
+// The colors whisper.cpp picks from, least to most confident
+const CONFIDENCE_COLORS: [&str; 10] = ["196", "202", "208", "214", "220", "226", "190", "154", "118", "82"];
+
+pub struct WhisperEngine {
+    pub name: &'static str,
+    pub model_setting: &'static str,
+    pub default_model: &'static str,
+    pub threads: Option<u32>,
+}
+impl WhisperEngine {
+    pub fn local() -> WhisperEngine {
+        WhisperEngine {
+            name: "whisper",
+            model_setting: "stt_whisper_model",
+            default_model: "large",
+            threads: None,
+        }
+    }
+
+    pub fn tiny() -> WhisperEngine {
+        WhisperEngine {
+            name: "tiny",
+            model_setting: "stt_tiny_model",
+            default_model: "tiny.en",
+            threads: Some(2),
+        }
+    }
+
+    pub fn model_path(&self) -> String {
+        return model_path(self.model_setting, self.default_model);
+    }
+}
+
+impl SttEngine for WhisperEngine {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn available(&self) -> bool {
+        return std::path::Path::new(WHISPER_BIN).exists() && std::path::Path::new(&self.model_path()).exists();
+    }
+
+    fn default_timeout(&self) -> Duration {
+        return Duration::from_secs(if self.threads.is_some() { 30 } else { 60 });
+    }
+
+    fn transcribe(&self, file_path: &str, timeout: Duration) -> Result<Transcription, crate::sam::services::Error> {
+        let started = std::time::Instant::now();
+        let converted = format!("{}.{}.16.wav", file_path, self.name);
+        crate::sam::services::stt::run(Command::new("ffmpeg").args(["-nostdin", "-loglevel", "error", "-y", "-i", file_path, "-ar", "16000", "-ac", "1", "-c:a", "pcm_s16le", converted.as_str()]), timeout)?;
+
+        let mut command = Command::new(WHISPER_BIN);
+        command.args(["-m", self.model_path().as_str(), "-f", converted.as_str(), "-otxt", "-nt", "-pc"]);
+        match self.threads {
+            Some(threads) => {
+                command.args(["-t", threads.to_string().as_str()]);
+            },
+            None => {}
+        }
+        let output = crate::sam::services::stt::run(&mut command, timeout.saturating_sub(started.elapsed()));
+
+        let text = std::fs::read_to_string(format!("{}.txt", converted));
+        let _ = std::fs::remove_file(&converted);
+        let _ = std::fs::remove_file(format!("{}.txt", converted));
+
+        return Ok(Transcription {
+            text: text?.trim().to_string(),
+            engine: self.name.to_string(),
+            latency_ms: 0,
+            confidence: confidence(&output?),
+        });
+    }
+
+    // The bundled whisper.cpp reads a wav from stdin with -f - and prints the text
+    fn transcribe_wav(&self, wav: &[u8], timeout: Duration) -> Result<Transcription, crate::sam::services::Error> {
+        let mut command = Command::new(WHISPER_BIN);
+        command.args(["-m", self.model_path().as_str(), "-f", "-", "-nt", "-pc"]);
+        match self.threads {
+            Some(threads) => {
+                command.args(["-t", threads.to_string().as_str()]);
+            },
+            None => {}
+        }
+        let output = crate::sam::services::stt::run_with_input(&mut command, Some(wav.to_vec()), timeout)?;
+
+        return Ok(Transcription {
+            text: uncolored(&output).trim().to_string(),
+            engine: self.name.to_string(),
+            latency_ms: 0,
+            confidence: confidence(&output),
+        });
+    }
+}
+
+// ggml-{model}.bin, falls back to the default when the setting isn't a plain model name
+pub fn model_path(setting: &str, default: &str) -> String {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(setting.to_string()));
+    pg_query.query_coulmns.push(format!("key ="));
+    let configured = match crate::sam::memory::Setting::select(None, None, None, Some(pg_query)) {
+        Ok(settings) => settings.first().and_then(|s| s.values.first().cloned()),
+        Err(e) => {
+            log::error!("failed to load setting {}: {}", setting, e);
+            None
+        }
+    };
+    let model = configured.map(|m| m.trim().to_string())
+        .filter(|m| m.len() > 0 && m.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_'))
+        .unwrap_or(default.to_string());
+    return format!("/opt/sam/models/ggml-{}.bin", model);
+}
+
+// stdout without the -pc escape codes
+fn uncolored(stdout: &str) -> String {
+    let mut text = String::new();
+    let mut chars = stdout.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // ESC [ ... m
+            while let Some(c) = chars.next() {
+                if c == 'm' {
+                    break;
+                }
+            }
+        } else {
+            text.push(c);
+        }
+    }
+    return text;
+}
+
+// Mean token probability from the -pc color codes, None when nothing was colored
+fn confidence(stdout: &str) -> Option<f64> {
+    let mut total = 0.0;
+    let mut tokens = 0;
+    for part in stdout.split("\x1b[38;5;").skip(1) {
+        let code: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+        match CONFIDENCE_COLORS.iter().position(|c| *c == code) {
+            Some(step) => {
+                // The middle of the step, undoing the cube
+                let cubed = (step as f64 + 0.5) / CONFIDENCE_COLORS.len() as f64;
+                total = total + cubed.powf(1.0 / 3.0);
+                tokens = tokens + 1;
+            },
+            None => {}
+        }
+    }
+    if tokens == 0 {
+        return None;
+    }
+    return Some(total / tokens as f64);
+}
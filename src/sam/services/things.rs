@@ -0,0 +1,236 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// things.rs is the device layer. Every Thing.thing_type is handled by a
+// ThingDriver that knows what the device can do, how to find it, how to
+// read its state and how to send it commands. Adding a device type means
+// adding a driver module under things/ and registering it in drivers().
+
+pub mod cache;
+pub mod health;
+pub mod http;
+pub mod lifx;
+pub mod rtsp;
+
+use crate::sam::memory::Thing;
+use serde::{Serialize, Deserialize};
+use std::sync::OnceLock;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    Switchable,
+    Dimmable,
+    Color,
+    Camera,
+    Microphone,
+    Speaker,
+    Sensor,
+}
+
+// Commands posted to /api/things/{oid}/command, e.g. {"command": "set_brightness", "brightness": 0.5}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ThingCommand {
+    TurnOn { #[serde(default)] duration: Option<f64> },
+    TurnOff { #[serde(default)] duration: Option<f64> },
+    Toggle { #[serde(default)] duration: Option<f64> },
+    SetBrightness { brightness: f64, #[serde(default)] duration: Option<f64> },
+    SetColor { color: String, #[serde(default)] duration: Option<f64> },
+    Record { seconds: i64 },
+    Snapshot,
+}
+impl ThingCommand {
+    // The capability a thing needs before the command is sent to its driver
+    pub fn capability(&self) -> Capability {
+        match self {
+            ThingCommand::TurnOn{..} | ThingCommand::TurnOff{..} | ThingCommand::Toggle{..} => Capability::Switchable,
+            ThingCommand::SetBrightness{..} => Capability::Dimmable,
+            ThingCommand::SetColor{..} => Capability::Color,
+            ThingCommand::Record{..} | ThingCommand::Snapshot => Capability::Camera,
+        }
+    }
+}
+
+// What a driver reports about a thing, fields a device doesn't have are left empty
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ThingState {
+    pub online: bool,
+    pub power: Option<String>,
+    pub brightness: Option<f64>,
+    pub color: Option<String>,
+    pub kelvin: Option<i64>,
+    #[serde(default)]
+    pub details: serde_json::Value,
+}
+
+pub trait ThingDriver: Send + Sync {
+    // The Thing.thing_type this driver handles
+    fn thing_type(&self) -> &'static str;
+
+    fn capabilities(&self, thing: &Thing) -> Vec<Capability>;
+
+    // Starts any background workers the driver needs, called once at startup
+    fn init(&self) {}
+
+    // Looks for devices, saves new or changed ones and returns every known Thing of this type
+    fn discover(&self) -> Result<Vec<Thing>, crate::sam::services::Error>;
+
+    fn state(&self, thing: &Thing) -> Result<ThingState, crate::sam::services::Error>;
+
+    // Runs a command and returns a driver specific json result
+    fn command(&self, thing: &Thing, command: &ThingCommand) -> Result<serde_json::Value, crate::sam::services::Error>;
+
+    // Ok when the device answers, defaults to pinging its ip address
+    fn health_check(&self, thing: &Thing) -> Result<(), crate::sam::services::Error> {
+        return ping(&thing.ip_address);
+    }
+
+    // False when a background worker serving the thing has died
+    fn workers_alive(&self, _thing: &Thing) -> bool {
+        true
+    }
+
+    // Starts the thing's background workers again, stopping any that are left
+    fn restart_workers(&self, _thing: &Thing) {}
+
+    // How often the state cache polls the thing, None uses thing_poll_seconds and 0 turns polling off
+    fn poll_seconds(&self, _thing: &Thing) -> Option<u64> {
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DriverInfo {
+    pub thing_type: String,
+    pub capabilities: Vec<Capability>,
+}
+
+pub fn drivers() -> &'static Vec<Box<dyn ThingDriver>> {
+    static DRIVERS: OnceLock<Vec<Box<dyn ThingDriver>>> = OnceLock::new();
+    DRIVERS.get_or_init(|| vec![
+        Box::new(http::HttpDriver),
+        Box::new(lifx::LifxDriver),
+        Box::new(rtsp::RtspDriver),
+    ])
+}
+
+pub fn driver_for(thing_type: &str) -> Option<&'static dyn ThingDriver> {
+    return drivers().iter().find(|d| d.thing_type() == thing_type).map(|d| d.as_ref());
+}
+
+pub fn init(){
+    for driver in drivers().iter() {
+        log::info!("starting {} driver", driver.thing_type());
+        driver.init();
+    }
+    cache::init();
+    health::init();
+}
+
+pub fn driver_info() -> Vec<DriverInfo> {
+    return drivers().iter().map(|d| DriverInfo{
+        thing_type: d.thing_type().to_string(),
+        capabilities: d.capabilities(&Thing::new()),
+    }).collect();
+}
+
+// Runs discovery on every driver, a failing driver doesn't stop the others
+pub fn discover() -> Vec<Thing> {
+    let mut things: Vec<Thing> = Vec::new();
+    for driver in drivers().iter() {
+        match driver.discover() {
+            Ok(found) => things.extend(found),
+            Err(e) => log::error!("{} discovery failed: {}", driver.thing_type(), e)
+        }
+    }
+    return things;
+}
+
+pub fn capabilities(thing: &Thing) -> Vec<Capability> {
+    match driver_for(&thing.thing_type) {
+        Some(driver) => driver.capabilities(thing),
+        None => Vec::new()
+    }
+}
+
+pub fn state(thing: &Thing) -> Result<ThingState, crate::sam::services::Error> {
+    match driver_for(&thing.thing_type) {
+        Some(driver) => driver.state(thing),
+        None => Err(format!("no driver for thing type {}", thing.thing_type).into())
+    }
+}
+
+pub fn command(thing: &Thing, command: &ThingCommand) -> Result<serde_json::Value, crate::sam::services::Error> {
+    let driver = match driver_for(&thing.thing_type) {
+        Some(driver) => driver,
+        None => return Err(format!("no driver for thing type {}", thing.thing_type).into())
+    };
+    if !driver.capabilities(thing).contains(&command.capability()) {
+        return Err(format!("{} is missing the {:?} capability", thing.name, command.capability()).into());
+    }
+    return driver.command(thing, command);
+}
+
+pub fn find(oid: &str) -> Result<Option<Thing>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(oid.to_string()));
+    pg_query.query_coulmns.push(format!("oid ="));
+    let things = Thing::select(None, None, None, Some(pg_query))?;
+    return Ok(things.first().cloned());
+}
+
+pub fn of_type(thing_type: &str) -> Result<Vec<Thing>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(thing_type.to_string()));
+    pg_query.query_coulmns.push(format!("thing_type ="));
+    return Ok(Thing::select(None, None, None, Some(pg_query))?);
+}
+
+// Things are addressed by their first known identifier, falling back to the name for hand made things
+pub fn selector(thing: &Thing) -> String {
+    match thing.online_identifiers.iter().chain(thing.local_identifiers.iter()).find(|i| i.len() > 0) {
+        Some(id) => format!("id:{}", id),
+        None => format!("label:{}", thing.name)
+    }
+}
+
+// Reads a number of seconds from a setting, used for poll and check intervals.
+// 0 falls back to the default too, see setting_number where 0 means something.
+pub fn setting_seconds(key: &str, default: u64) -> u64 {
+    match setting_number(key) {
+        Some(seconds) if seconds > 0 => seconds,
+        _ => default
+    }
+}
+
+// None when the setting is missing or isn't a number
+pub fn setting_number(key: &str) -> Option<u64> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(key.to_string()));
+    pg_query.query_coulmns.push(format!("key ="));
+    match crate::sam::memory::Setting::select(None, None, None, Some(pg_query)) {
+        Ok(settings) => settings.first().and_then(|s| s.values.first()).and_then(|v| v.trim().parse::<u64>().ok()),
+        Err(e) => {
+            log::error!("failed to load setting {}: {}", key, e);
+            None
+        }
+    }
+}
+
+pub fn ping(ip_address: &str) -> Result<(), crate::sam::services::Error> {
+    if ip_address.trim().len() == 0 {
+        return Err(format!("no ip address").into());
+    }
+    let output = std::process::Command::new("ping").args(["-c", "1", "-W", "2", ip_address.trim()]).output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    return Err(format!("{} did not answer a ping", ip_address).into());
+}
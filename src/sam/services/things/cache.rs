@@ -0,0 +1,226 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// cache.rs keeps the latest known state of every Thing in memory so the api
+// and dashboards never have to ask a device. Drivers push partial changes
+// through update(...) and the poller asks each driver for the rest every
+// thing_poll_seconds (60), at 0 only things with their own interval are
+// polled. Every change is written to thing_state_history and published on
+// the bus together with the previous state.
+
+use crate::sam::memory::{Thing, ThingStateHistory};
+use crate::sam::services::things::ThingState;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Fields that count as a change, details are kept but not compared
+const TRACKED: [&str; 5] = ["online", "power", "brightness", "color", "kelvin"];
+
+const DEFAULT_POLL_SECONDS: u64 = 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedState {
+    pub thing_oid: String,
+    pub state: ThingState,
+    pub previous: Option<ThingState>,
+    pub source: String,
+    // When the state last changed
+    pub updated_at: i64,
+    pub polled_at: Option<i64>,
+}
+
+fn cache() -> &'static Mutex<HashMap<String, CachedState>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, CachedState>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn now() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+}
+
+pub fn init(){
+    warm();
+    crate::sam::services::scheduler::every("thing_state_poll", Duration::from_secs(5), poll_due);
+}
+
+pub fn get(thing_oid: &str) -> Option<CachedState> {
+    return cache().lock().unwrap().get(thing_oid).cloned();
+}
+
+pub fn all() -> Vec<CachedState> {
+    return cache().lock().unwrap().values().cloned().collect();
+}
+
+// Seeds the cache from history so a restart isn't mistaken for a change
+fn warm(){
+    let things = match Thing::select(None, None, None, None) {
+        Ok(things) => things,
+        Err(e) => {
+            log::error!("failed to warm thing state cache: {}", e);
+            return;
+        }
+    };
+
+    for thing in things {
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(thing.oid.clone()));
+        pg_query.query_coulmns.push(format!("thing_oid ="));
+        match ThingStateHistory::select(Some(1), None, Some(format!("timestamp DESC")), Some(pg_query)) {
+            Ok(rows) => {
+                match rows.first() {
+                    Some(row) => {
+                        cache().lock().unwrap().insert(thing.oid.clone(), CachedState{
+                            thing_oid: thing.oid.clone(),
+                            state: serde_json::from_value(row.state.clone()).unwrap_or_default(),
+                            previous: serde_json::from_value(row.previous.clone()).ok(),
+                            source: row.source.clone(),
+                            updated_at: row.timestamp,
+                            polled_at: None,
+                        });
+                    },
+                    None => {}
+                }
+            },
+            Err(e) => log::error!("{}", e)
+        }
+    }
+}
+
+// Merges changes (any ThingState fields as a json object, nulls are ignored)
+// into the cached state of a thing. Returns the cached state afterwards.
+pub fn update(thing: &Thing, changes: &serde_json::Value, source: &str) -> CachedState {
+    let timestamp = now();
+
+    let (cached, old, changed) = {
+        let mut map = cache().lock().unwrap();
+        let existing = map.get(&thing.oid).cloned();
+        let old = existing.as_ref().map(|c| c.state.clone());
+
+        let mut merged = serde_json::to_value(old.clone().unwrap_or_default()).unwrap_or(serde_json::json!({}));
+        // A device that reports anything is reachable unless it says otherwise
+        merged["online"] = serde_json::Value::Bool(true);
+        match changes.as_object() {
+            Some(fields) => {
+                for (field, value) in fields {
+                    if !value.is_null() {
+                        merged[field.as_str()] = value.clone();
+                    }
+                }
+            },
+            None => {}
+        }
+        let state: ThingState = serde_json::from_value(merged.clone()).unwrap_or_default();
+
+        let before = serde_json::to_value(old.clone().unwrap_or_default()).unwrap_or(serde_json::Value::Null);
+        let changed: Vec<String> = TRACKED.iter()
+            .filter(|field| old.is_none() || before.get(**field) != merged.get(**field))
+            .filter(|field| merged.get(**field).map(|v| !v.is_null()).unwrap_or(false))
+            .map(|field| field.to_string())
+            .collect();
+
+        let cached = CachedState{
+            thing_oid: thing.oid.clone(),
+            state: state,
+            previous: if changed.len() > 0 { old.clone() } else { existing.as_ref().and_then(|c| c.previous.clone()) },
+            source: source.to_string(),
+            updated_at: if changed.len() > 0 { timestamp } else { existing.as_ref().map(|c| c.updated_at).unwrap_or(timestamp) },
+            polled_at: if source == "poll" { Some(timestamp) } else { existing.as_ref().and_then(|c| c.polled_at) },
+        };
+        map.insert(thing.oid.clone(), cached.clone());
+        (cached, old, changed)
+    };
+
+    if changed.len() == 0 {
+        return cached;
+    }
+
+    let mut stored_state = cached.state.clone();
+    stored_state.details = serde_json::Value::Null;
+    let mut history = ThingStateHistory::new();
+    history.thing_oid = thing.oid.clone();
+    history.state = serde_json::to_value(&stored_state).unwrap_or(serde_json::Value::Null);
+    history.previous = match &old {
+        Some(old) => {
+            let mut old = old.clone();
+            old.details = serde_json::Value::Null;
+            serde_json::to_value(&old).unwrap_or(serde_json::Value::Null)
+        },
+        None => serde_json::Value::Null
+    };
+    history.changed = changed;
+    history.source = source.to_string();
+    history.timestamp = timestamp;
+    match history.save() {
+        Ok(_) => {},
+        Err(e) => log::error!("failed to save state history for {}: {}", thing.name, e)
+    }
+
+    // The first poll of a thing sam hasn't seen before isn't news
+    if old.is_some() || source != "poll" {
+        crate::sam::services::bus::publish(crate::sam::services::bus::Event::ThingStateChanged{
+            thing_oid: Some(thing.oid.clone()),
+            room_oid: Some(thing.room_oid.clone()),
+            selector: crate::sam::services::things::selector(thing),
+            state: history.state.clone(),
+            previous: history.previous.clone(),
+        });
+    }
+
+    return cached;
+}
+
+// Polls every thing with a driver whose cached state is older than thing_poll_seconds,
+// or the driver's own interval for the thing
+pub fn poll_due(){
+    let interval = crate::sam::services::things::setting_number("thing_poll_seconds").unwrap_or(DEFAULT_POLL_SECONDS) as i64;
+    let things = match Thing::select(None, None, None, None) {
+        Ok(things) => things,
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
+
+    for thing in things {
+        let driver = match crate::sam::services::things::driver_for(&thing.thing_type) {
+            Some(driver) => driver,
+            None => continue
+        };
+        let interval = match driver.poll_seconds(&thing) {
+            Some(0) => continue,
+            Some(seconds) => seconds as i64,
+            None if interval == 0 => continue,
+            None => interval
+        };
+        match get(&thing.oid).and_then(|c| c.polled_at) {
+            Some(polled_at) => {
+                if now() - polled_at < interval {
+                    continue;
+                }
+            },
+            None => {}
+        }
+        poll(&thing);
+    }
+}
+
+pub fn poll(thing: &Thing) -> CachedState {
+    match crate::sam::services::things::state(thing) {
+        Ok(state) => {
+            let changes = serde_json::to_value(&state).unwrap_or(serde_json::Value::Null);
+            return update(thing, &changes, "poll");
+        },
+        Err(e) => {
+            log::error!("failed to poll {}: {}", thing.name, e);
+            return update(thing, &serde_json::json!({"online": false}), "poll");
+        }
+    }
+}
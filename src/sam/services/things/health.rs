@@ -0,0 +1,214 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// health.rs checks every Thing with its driver's health_check (ping, rtsp
+// probe, lifx query) every health_check_seconds, or {thing_type}_health_check_seconds
+// when set. A thing is marked offline after health_check_failures failed
+// checks in a row. Transitions are saved on the Thing, written to the state
+// history, turned into a Notification for every human and, when a thing
+// comes back, its background workers are restarted. Workers that die while
+// their thing is online are restarted as well.
+
+use crate::sam::memory::Thing;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_INTERVAL_SECONDS: u64 = 60;
+const DEFAULT_FAILURES: u64 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HealthStatus {
+    pub thing_oid: String,
+    pub name: String,
+    pub thing_type: String,
+    pub online: bool,
+    // Failed checks in a row
+    pub failures: u64,
+    pub last_error: Option<String>,
+    pub checked_at: i64,
+    // When online last flipped
+    pub changed_at: Option<i64>,
+    pub worker_restarts: u64,
+}
+
+fn statuses() -> &'static Mutex<HashMap<String, HealthStatus>> {
+    static STATUSES: OnceLock<Mutex<HashMap<String, HealthStatus>>> = OnceLock::new();
+    STATUSES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn now() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+}
+
+pub fn init(){
+    crate::sam::services::scheduler::every("thing_health", Duration::from_secs(5), check_due);
+}
+
+pub fn status(thing_oid: &str) -> Option<HealthStatus> {
+    return statuses().lock().unwrap().get(thing_oid).cloned();
+}
+
+pub fn all() -> Vec<HealthStatus> {
+    return statuses().lock().unwrap().values().cloned().collect();
+}
+
+fn interval(thing_type: &str) -> i64 {
+    let default = crate::sam::services::things::setting_seconds("health_check_seconds", DEFAULT_INTERVAL_SECONDS);
+    return crate::sam::services::things::setting_seconds(&format!("{}_health_check_seconds", thing_type), default) as i64;
+}
+
+pub fn check_due(){
+    let things = match Thing::select(None, None, None, None) {
+        Ok(things) => things,
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
+
+    let mut intervals: HashMap<String, i64> = HashMap::new();
+    for thing in things {
+        if crate::sam::services::things::driver_for(&thing.thing_type).is_none() {
+            continue;
+        }
+        let every = *intervals.entry(thing.thing_type.clone()).or_insert_with(|| interval(&thing.thing_type));
+        match status(&thing.oid) {
+            Some(status) => {
+                if now() - status.checked_at < every {
+                    continue;
+                }
+            },
+            None => {}
+        }
+        check(&thing);
+    }
+}
+
+// Runs one health check now and handles any transition
+pub fn check(thing: &Thing) -> HealthStatus {
+    let driver = match crate::sam::services::things::driver_for(&thing.thing_type) {
+        Some(driver) => driver,
+        None => {
+            return HealthStatus{
+                thing_oid: thing.oid.clone(),
+                name: thing.name.clone(),
+                thing_type: thing.thing_type.clone(),
+                online: thing.online,
+                failures: 0,
+                last_error: Some(format!("no driver for thing type {}", thing.thing_type)),
+                checked_at: now(),
+                changed_at: None,
+                worker_restarts: 0,
+            };
+        }
+    };
+
+    let result = driver.health_check(thing);
+    let timestamp = now();
+    let threshold = crate::sam::services::things::setting_seconds("health_check_failures", DEFAULT_FAILURES);
+
+    let mut status = match status(&thing.oid) {
+        Some(status) => status,
+        None => HealthStatus{
+            thing_oid: thing.oid.clone(),
+            name: thing.name.clone(),
+            thing_type: thing.thing_type.clone(),
+            online: thing.online,
+            failures: 0,
+            last_error: None,
+            checked_at: timestamp,
+            changed_at: None,
+            worker_restarts: 0,
+        }
+    };
+    status.name = thing.name.clone();
+    status.checked_at = timestamp;
+
+    let was_online = status.online;
+    match &result {
+        Ok(_) => {
+            status.failures = 0;
+            status.last_error = None;
+            status.online = true;
+        },
+        Err(e) => {
+            status.failures = status.failures + 1;
+            status.last_error = Some(format!("{}", e));
+            if status.failures >= threshold {
+                status.online = false;
+            }
+        }
+    }
+
+    if status.online != was_online || status.online != thing.online {
+        status.changed_at = Some(timestamp);
+        transition(thing, status.online, status.last_error.clone(), timestamp);
+        if status.online {
+            log::info!("{} is back online, restarting its workers", thing.name);
+            driver.restart_workers(thing);
+            status.worker_restarts = status.worker_restarts + 1;
+        }
+    } else if status.online {
+        if !driver.workers_alive(thing) {
+            log::info!("workers for {} have stopped, restarting them", thing.name);
+            driver.restart_workers(thing);
+            status.worker_restarts = status.worker_restarts + 1;
+        }
+        if result.is_ok() {
+            mark_seen(thing, timestamp);
+        }
+    }
+
+    statuses().lock().unwrap().insert(thing.oid.clone(), status.clone());
+    return status;
+}
+
+fn mark_seen(thing: &Thing, timestamp: i64) {
+    match Thing::save_health(&thing.oid, true, Some(timestamp)) {
+        Ok(_) => {},
+        Err(e) => log::error!("failed to save {}: {}", thing.name, e)
+    }
+}
+
+fn transition(thing: &Thing, online: bool, error: Option<String>, timestamp: i64) {
+    let last_seen_at = if online { Some(timestamp) } else { None };
+    match Thing::save_health(&thing.oid, online, last_seen_at) {
+        Ok(_) => {},
+        Err(e) => log::error!("failed to save {}: {}", thing.name, e)
+    }
+
+    let mut thing = thing.clone();
+    thing.online = online;
+    if online {
+        thing.last_seen_at = Some(timestamp);
+    }
+
+    crate::sam::services::things::cache::update(&thing, &serde_json::json!({"online": online}), "health");
+
+    let message = match (online, error) {
+        (true, _) => format!("{} is back online", thing.name),
+        (false, Some(error)) => format!("{} went offline ({})", thing.name, error),
+        (false, None) => format!("{} went offline", thing.name)
+    };
+    log::info!("{}", message);
+
+    match crate::sam::services::notifications::everyone() {
+        Ok(human_oids) => {
+            for human_oid in human_oids {
+                let mut notification = crate::sam::memory::Notification::new();
+                notification.message = message.clone();
+                notification.human_oid = human_oid;
+                crate::sam::services::notifications::create(notification);
+            }
+        },
+        Err(e) => log::error!("failed to notify humans about {}: {}", thing.name, e)
+    }
+}
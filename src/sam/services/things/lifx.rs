@@ -0,0 +1,133 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// LIFX bulbs. Uses the http api when a lifx Service is set up and the
+// lan protocol when it isn't or the api can't be reached.
+
+use crate::sam::memory::Thing;
+use crate::sam::services::lifx::{LifxResults, ThingStateChange};
+use crate::sam::services::things::{Capability, ThingCommand, ThingDriver, ThingState};
+use online::check;
+
+pub struct LifxDriver;
+
+impl ThingDriver for LifxDriver {
+    fn thing_type(&self) -> &'static str {
+        "lifx"
+    }
+
+    fn capabilities(&self, _thing: &Thing) -> Vec<Capability> {
+        vec![Capability::Switchable, Capability::Dimmable, Capability::Color]
+    }
+
+    fn init(&self) {
+        crate::sam::services::lifx::sync::init();
+    }
+
+    fn discover(&self) -> Result<Vec<Thing>, crate::sam::services::Error> {
+        crate::sam::services::lifx::sync::sync()?;
+        return crate::sam::services::things::of_type("lifx");
+    }
+
+    fn state(&self, thing: &Thing) -> Result<ThingState, crate::sam::services::Error> {
+        let selector = crate::sam::services::things::selector(thing);
+
+        match service_key() {
+            Some(key) => {
+                match crate::sam::services::lifx::api_list(key, check(Some(3)).is_ok(), selector.clone()) {
+                    Ok(lights) => {
+                        match lights.first() {
+                            Some(light) => {
+                                return Ok(ThingState{
+                                    online: light.connected,
+                                    power: Some(light.power.clone()),
+                                    brightness: Some(light.brightness),
+                                    color: Some(format!("hue:{} saturation:{}", light.color.hue, light.color.saturation)),
+                                    kelvin: Some(light.color.kelvin),
+                                    details: serde_json::to_value(light).unwrap_or(serde_json::Value::Null),
+                                });
+                            },
+                            None => {}
+                        }
+                    },
+                    Err(e) => log::error!("failed to read {} from the lifx api: {}", thing.name, e)
+                }
+            },
+            None => {}
+        }
+
+        let client = crate::sam::services::lifx::lan::LanClient::new()?;
+        match crate::sam::services::lifx::lan_bulbs_for_selector(&client, &selector)?.first() {
+            Some(bulb) => {
+                let state = client.get_state(bulb)?;
+                return Ok(ThingState{
+                    online: true,
+                    power: Some(if state.power { format!("on") } else { format!("off") }),
+                    brightness: Some(state.color.brightness_percent()),
+                    color: Some(format!("hue:{} saturation:{}", state.color.hue_degrees(), state.color.saturation_percent())),
+                    kelvin: Some(state.color.kelvin as i64),
+                    details: serde_json::to_value(&state).unwrap_or(serde_json::Value::Null),
+                });
+            },
+            None => return Ok(ThingState::default())
+        }
+    }
+
+    fn command(&self, thing: &Thing, command: &ThingCommand) -> Result<serde_json::Value, crate::sam::services::Error> {
+        let selector = crate::sam::services::things::selector(thing);
+
+        let results = match command {
+            ThingCommand::TurnOn{duration} => apply(selector, ThingStateChange{ power: Some(format!("on")), ..Default::default() }, *duration)?,
+            ThingCommand::TurnOff{duration} => apply(selector, ThingStateChange{ power: Some(format!("off")), ..Default::default() }, *duration)?,
+            ThingCommand::SetBrightness{brightness, duration} => apply(selector, ThingStateChange{ brightness: Some(*brightness), ..Default::default() }, *duration)?,
+            ThingCommand::SetColor{color, duration} => apply(selector, ThingStateChange{ color: Some(color.clone()), ..Default::default() }, *duration)?,
+            ThingCommand::Toggle{duration} => {
+                let duration = duration.unwrap_or(0.0);
+                match service_key() {
+                    Some(key) => crate::sam::services::lifx::toggle(key, selector, check(Some(3)).is_ok(), duration)?,
+                    None => crate::sam::services::lifx::toggle_lan(selector, std::time::Duration::from_secs_f64(duration.max(0.0)))?
+                }
+            },
+            _ => return Err(format!("lifx can't handle {:?}", command).into())
+        };
+
+        return Ok(serde_json::to_value(&results).unwrap_or(serde_json::Value::Null));
+    }
+
+    // Asks lifx (api or lan) rather than pinging, bulbs often ignore icmp
+    fn health_check(&self, thing: &Thing) -> Result<(), crate::sam::services::Error> {
+        if self.state(thing)?.online {
+            return Ok(());
+        }
+        return Err(format!("lifx doesn't see {}", thing.name).into());
+    }
+}
+
+fn apply(selector: String, change: ThingStateChange, duration: Option<f64>) -> Result<LifxResults, crate::sam::services::Error> {
+    let duration = duration.unwrap_or(0.0);
+    match service_key() {
+        Some(key) => crate::sam::services::lifx::apply_state(key, selector, check(Some(3)).is_ok(), &change, duration),
+        None => crate::sam::services::lifx::set_lan(selector, &change, std::time::Duration::from_secs_f64(duration.max(0.0)))
+    }
+}
+
+// The api key of the lifx Service, None when lifx hasn't been set up
+fn service_key() -> Option<String> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("lifx")));
+    pg_query.query_coulmns.push(format!("identifier ="));
+    match crate::sam::memory::Service::select(None, None, None, Some(pg_query)) {
+        Ok(services) => services.first().map(|s| s.secret.clone()),
+        Err(e) => {
+            log::error!("{}", e);
+            None
+        }
+    }
+}
+
@@ -0,0 +1,69 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// RTSP cameras. They're added by hand from /things.html, so discovery
+// just reports what's already stored.
+
+use crate::sam::memory::Thing;
+use crate::sam::services::things::{Capability, ThingCommand, ThingDriver, ThingState};
+
+pub struct RtspDriver;
+
+impl ThingDriver for RtspDriver {
+    fn thing_type(&self) -> &'static str {
+        "rtsp"
+    }
+
+    fn capabilities(&self, _thing: &Thing) -> Vec<Capability> {
+        vec![Capability::Camera, Capability::Microphone]
+    }
+
+    fn init(&self) {
+        crate::sam::services::rtsp::init();
+    }
+
+    fn discover(&self) -> Result<Vec<Thing>, crate::sam::services::Error> {
+        return crate::sam::services::things::of_type("rtsp");
+    }
+
+    fn state(&self, thing: &Thing) -> Result<ThingState, crate::sam::services::Error> {
+        return Ok(ThingState{
+            online: crate::sam::services::rtsp::probe(thing),
+            details: serde_json::json!({
+                "stream": format!("/streams/{}.m3u8", thing.oid)
+            }),
+            ..Default::default()
+        });
+    }
+
+    fn command(&self, thing: &Thing, command: &ThingCommand) -> Result<serde_json::Value, crate::sam::services::Error> {
+        let mut file = match command {
+            ThingCommand::Record{seconds} => crate::sam::services::rtsp::record(thing.clone(), *seconds)?,
+            ThingCommand::Snapshot => crate::sam::services::rtsp::snapshot(thing.clone())?,
+            _ => return Err(format!("rtsp can't handle {:?}", command).into())
+        };
+        file.file_data = None;
+        return Ok(serde_json::to_value(&file).unwrap_or(serde_json::Value::Null));
+    }
+
+    fn health_check(&self, thing: &Thing) -> Result<(), crate::sam::services::Error> {
+        if crate::sam::services::rtsp::probe(thing) {
+            return Ok(());
+        }
+        return Err(format!("rtsp port 554 on {} is closed", thing.ip_address).into());
+    }
+
+    fn workers_alive(&self, thing: &Thing) -> bool {
+        return crate::sam::services::rtsp::streams_running(&thing.oid);
+    }
+
+    fn restart_workers(&self, thing: &Thing) {
+        crate::sam::services::rtsp::start_streams(thing.clone());
+    }
+}
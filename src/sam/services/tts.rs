@@ -7,22 +7,307 @@
 // Developed by Caleb Mitchell Smith (PixelCoda)
 // Licensed under GPLv3....see LICENSE file.
 
+// tts.rs turns text into speech through a chain of engines, tried in the
+// order of the tts_engines setting (remote,local,offline by default) until
+// one answers within tts_{engine}_timeout_seconds. Engines that aren't
+// configured or installed are skipped.
+//
+// A Voice picks the speaker_id and style_wav, humans can have their own.
+// Synthesized wavs are cached in /opt/sam/tmp/tts/cache keyed by engine,
+// text and voice, so the same announcement only has to be synthesized once
+// and a fallback engine's wav isn't played once the better one is back. The
+// newest tts_cache_files (500) are kept.
+//
+//   GET /api/services/tts?text=hello&human_oid=...&speaker_id=...&style_wav=...
+//   GET /api/services/tts/engines
+//   DELETE /api/services/tts/cache
+//   GET /api/services/tts/announce (snapcast groups and the rooms they play)
+//   POST /api/services/tts/announce (text, chime, rooms, groups, human_oid, duck)
+
+pub mod announce;
+pub mod coqui;
+pub mod offline;
+
 use rouille::Request;
 use rouille::Response;
+use rouille::post_input;
+use serde::{Serialize, Deserialize};
+use std::sync::OnceLock;
 use std::thread;
 use std::time::Duration;
 
+const CACHE_DIR: &str = "/opt/sam/tmp/tts/cache";
+
+pub trait TtsEngine: Send + Sync {
+    fn name(&self) -> &'static str;
+    // Engines that aren't installed or configured are left out of the chain
+    fn available(&self) -> bool {
+        true
+    }
+    fn default_timeout(&self) -> Duration;
+    // A complete wav file
+    fn synthesize(&self, text: &str, voice: &Voice, timeout: Duration) -> Result<Vec<u8>, crate::sam::services::Error>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Voice {
+    pub speaker_id: Option<String>,
+    pub style_wav: Option<String>,
+}
+impl Voice {
+    // The voice a human picked, the default voice for everyone else
+    pub fn for_human(human_oid: &str) -> Voice {
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(human_oid.to_string()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        match crate::sam::memory::Human::select(None, None, None, Some(pg_query)) {
+            Ok(humans) => match humans.first() {
+                Some(human) => Voice {
+                    speaker_id: human.tts_speaker_id.clone(),
+                    style_wav: human.tts_style_wav.clone(),
+                },
+                None => Voice::default()
+            },
+            Err(e) => {
+                log::error!("failed to load voice for {}: {}", human_oid, e);
+                Voice::default()
+            }
+        }
+    }
+
+    pub fn cache_key(&self, engine: &str, text: &str) -> String {
+        let key = format!("{}\n{}\n{}\n{}", engine, text, self.speaker_id.clone().unwrap_or_default(), self.style_wav.clone().unwrap_or_default());
+        return openssl::sha::sha256(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EngineStatus {
+    pub engine: String,
+    pub available: bool,
+    pub timeout_seconds: u64,
+}
+
+pub fn engines() -> &'static Vec<Box<dyn TtsEngine>> {
+    static ENGINES: OnceLock<Vec<Box<dyn TtsEngine>>> = OnceLock::new();
+    ENGINES.get_or_init(|| vec![
+        Box::new(coqui::CoquiEngine::remote()),
+        Box::new(coqui::CoquiEngine::local()),
+        Box::new(offline::OfflineEngine),
+    ])
+}
+
+pub fn engine_for(name: &str) -> Option<&'static dyn TtsEngine> {
+    return engines().iter().find(|e| e.name() == name).map(|e| e.as_ref());
+}
+
+pub fn setting(key: &str) -> Option<String> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(key.to_string()));
+    pg_query.query_coulmns.push(format!("key ="));
+    match crate::sam::memory::Setting::select(None, None, None, Some(pg_query)) {
+        Ok(settings) => settings.first().and_then(|s| s.values.first().cloned()).map(|v| v.trim().to_string()).filter(|v| v.len() > 0),
+        Err(e) => {
+            log::error!("failed to load setting {}: {}", key, e);
+            None
+        }
+    }
+}
+
+// The engines in the order they're tried, unknown names in the setting are ignored
+pub fn chain() -> Vec<&'static dyn TtsEngine> {
+    let names: Vec<String> = setting("tts_engines").map(|v| v.split(',')
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| v.len() > 0)
+        .collect()).unwrap_or_default();
+    if names.len() == 0 {
+        return engines().iter().map(|e| e.as_ref()).collect();
+    }
+    return names.iter().filter_map(|name| engine_for(name)).collect();
+}
+
+pub fn timeout(engine: &dyn TtsEngine) -> Duration {
+    let key = format!("tts_{}_timeout_seconds", engine.name());
+    return Duration::from_secs(crate::sam::services::things::setting_seconds(&key, engine.default_timeout().as_secs()));
+}
+
+// The first engine to answer, engines that said it before answer from the cache
+pub fn synthesize(text: &str, voice: &Voice) -> Result<Vec<u8>, crate::sam::services::Error> {
+    let text = text.trim();
+    if text.len() == 0 {
+        return Err(format!("nothing to say").into());
+    }
+
+    let mut errors: Vec<String> = Vec::new();
+    for engine in chain() {
+        if !engine.available() {
+            continue;
+        }
+        let cache_path = format!("{}/{}.wav", CACHE_DIR, voice.cache_key(engine.name(), text));
+        match std::fs::read(&cache_path) {
+            Ok(wav) => return Ok(wav),
+            Err(_) => {}
+        }
+        match engine.synthesize(text, voice, timeout(engine)) {
+            Ok(wav) => {
+                if !wav.starts_with(b"RIFF") {
+                    errors.push(format!("{}: not a wav", engine.name()));
+                    continue;
+                }
+                match cache(&cache_path, &wav) {
+                    Ok(_) => {},
+                    Err(e) => log::error!("failed to cache tts: {}", e)
+                }
+                return Ok(wav);
+            },
+            Err(e) => {
+                log::warn!("tts engine {} failed: {}", engine.name(), e);
+                errors.push(format!("{}: {}", engine.name(), e));
+            }
+        }
+    }
+    if errors.len() == 0 {
+        return Err(format!("no tts engine is available").into());
+    }
+    return Err(format!("every tts engine failed ({})", errors.join(", ")).into());
+}
+
+// Written aside and renamed so a half written wav is never played
+fn cache(cache_path: &str, wav: &[u8]) -> Result<(), crate::sam::services::Error> {
+    std::fs::create_dir_all(CACHE_DIR)?;
+    let partial = format!("{}.partial", cache_path);
+    std::fs::write(&partial, wav)?;
+    std::fs::rename(&partial, cache_path)?;
+    prune()?;
+    return Ok(());
+}
+
+// Drops the least recently written wavs past tts_cache_files
+fn prune() -> Result<(), crate::sam::services::Error> {
+    let max_files = setting("tts_cache_files").and_then(|v| v.parse::<usize>().ok()).filter(|n| *n > 0).unwrap_or(500);
+    let mut files: Vec<(std::time::SystemTime, std::path::PathBuf)> = Vec::new();
+    for entry in std::fs::read_dir(CACHE_DIR)? {
+        let entry = entry?;
+        let modified = entry.metadata()?.modified()?;
+        files.push((modified, entry.path()));
+    }
+    if files.len() <= max_files {
+        return Ok(());
+    }
+    files.sort();
+    let excess = files.len() - max_files;
+    for (_, path) in files.into_iter().take(excess) {
+        let _ = std::fs::remove_file(path);
+    }
+    return Ok(());
+}
+
+pub fn clear_cache() -> Result<usize, crate::sam::services::Error> {
+    let mut removed = 0;
+    if !std::path::Path::new(CACHE_DIR).exists() {
+        return Ok(removed);
+    }
+    for entry in std::fs::read_dir(CACHE_DIR)? {
+        std::fs::remove_file(entry?.path())?;
+        removed = removed + 1;
+    }
+    return Ok(removed);
+}
 
 pub fn handle(_current_session: crate::sam::memory::WebSessions, request: &Request) -> Result<Response, crate::sam::http::Error> {
     if request.url() == "/api/services/tts" {
-        let input = request.get_param("text").unwrap();
-        return Ok(Response::from_data("audio/wav", crate::sam::services::tts::get(input).unwrap()));
-        
+        let text = match request.get_param("text") {
+            Some(text) => text,
+            None => return Ok(Response::text("text is required").with_status_code(400))
+        };
+        let mut voice = match request.get_param("human_oid") {
+            Some(human_oid) => Voice::for_human(&human_oid),
+            None => Voice::default()
+        };
+        match request.get_param("speaker_id").filter(|s| s.len() > 0) {
+            Some(speaker_id) => voice.speaker_id = Some(speaker_id),
+            None => {}
+        }
+        match request.get_param("style_wav").filter(|s| s.len() > 0) {
+            Some(style_wav) => voice.style_wav = Some(style_wav),
+            None => {}
+        }
+        match synthesize(&text, &voice) {
+            Ok(wav) => return Ok(Response::from_data("audio/wav", wav)),
+            Err(e) => return Ok(Response::text(format!("{}", e)).with_status_code(503))
+        }
     }
+
+    if request.url() == "/api/services/tts/engines" {
+        let status: Vec<EngineStatus> = chain().iter().map(|engine| EngineStatus {
+            engine: engine.name().to_string(),
+            available: engine.available(),
+            timeout_seconds: timeout(*engine).as_secs(),
+        }).collect();
+        return Ok(Response::json(&status));
+    }
+
+    if request.url() == "/api/services/tts/announce" && request.method() == "GET" {
+        let status = crate::sam::services::media::snapcast::Control::connect()?.status()?;
+        let rooms = crate::sam::memory::Room::select(None, None, None, None)?;
+        let groups: Vec<serde_json::Value> = status.groups.iter().map(|group| {
+            let room_oids: Vec<String> = rooms.iter()
+                .filter(|room| announce::group_for(&status, room).as_ref() == Some(&group.id))
+                .map(|room| room.oid.clone())
+                .collect();
+            serde_json::json!({
+                "id": group.id,
+                "name": group.name,
+                "stream_id": group.stream_id,
+                "clients": group.clients.iter().map(|c| c.config.name.clone()).collect::<Vec<String>>(),
+                "room_oids": room_oids,
+            })
+        }).collect();
+        return Ok(Response::json(&groups));
+    }
+
+    if request.url() == "/api/services/tts/announce" && request.method() == "POST" {
+        let input = post_input!(request, {
+            text: Option<String>,
+            chime: Option<String>,
+            rooms: Option<String>,
+            groups: Option<String>,
+            human_oid: Option<String>,
+            duck: Option<String>,
+        })?;
+        let list = |value: Option<String>| -> Vec<String> {
+            value.unwrap_or_default().split(',').map(|v| v.trim().to_string()).filter(|v| v.len() > 0).collect()
+        };
+        let announcement = announce::Announcement {
+            text: input.text,
+            chime: input.chime.filter(|c| c.len() > 0),
+            voice: match input.human_oid.filter(|h| h.len() > 0) {
+                Some(human_oid) => Voice::for_human(&human_oid),
+                None => Voice::default()
+            },
+            room_oids: list(input.rooms),
+            group_ids: list(input.groups),
+            duck: input.duck.map(|d| d != "false").unwrap_or(true),
+        };
+        match announce::announce(&announcement) {
+            Ok(announced) => return Ok(Response::json(&announced)),
+            Err(e) => return Ok(Response::text(format!("{}", e)).with_status_code(503))
+        }
+    }
+
+    if request.url() == "/api/services/tts/cache" && request.method() == "DELETE" {
+        let removed = clear_cache()?;
+        return Ok(Response::json(&serde_json::json!({"removed": removed})));
+    }
+
     return Ok(Response::empty_404());
 }
 
+// Only starts the embedded server when enable_embedded_tts_server is true
 pub fn init(){
+    if setting("enable_embedded_tts_server") != Some(format!("true")) {
+        return;
+    }
 
     let tts_thead = thread::Builder::new().name("mozillatts".to_string()).spawn(move || {
         crate::sam::tools::linux_cmd(format!("docker run -p 5002:5002 synesthesiam/mozillatts"));
@@ -38,41 +323,20 @@ pub fn init(){
 }
 
 pub fn get(text: String) -> Result<Vec<u8>, crate::sam::services::Error> {
-
-    match fetch_online(text.clone()) {
-        Ok(x) => {
-            return Ok(x);
-        },
-        Err(_e) => {
-
-            match fetch_local(text.clone()) {
-                Ok(x) => {
-                    return Ok(x);
-                },
-                Err(e) => {
-                    return Err(e);
-                }
-            }
-
-            
-        }
-    }
-
+    return synthesize(&text, &Voice::default());
 }
 
-pub fn fetch_online(text: String) -> Result<Vec<u8>, crate::sam::services::Error> {
-    let client = reqwest::blocking::Client::new();
-    let bytes = client.get(format!("https://tts.opensam.foundation/api/tts?text={}&speaker_id=&style_wav=", text))
-        .basic_auth("sam", Some("87654321"))
-        .timeout(Duration::from_secs(5))
-        .send()?.bytes()?;
-    Ok(bytes.to_vec())
+// Speaks text out loud on the local sound card
+pub fn speak(text: String) -> Result<(), crate::sam::services::Error> {
+    return speak_with(text, &Voice::default());
 }
 
-pub fn fetch_local(text: String) -> Result<Vec<u8>, crate::sam::services::Error> {
-    let client = reqwest::blocking::Client::new();
-    let bytes = client.get(format!("http://localhosy:5002/api/tts?text={}&speaker_id=&style_wav=", text))
-        .timeout(Duration::from_secs(5))
-        .send()?.bytes()?;
-    Ok(bytes.to_vec())
-}
\ No newline at end of file
+pub fn speak_with(text: String, voice: &Voice) -> Result<(), crate::sam::services::Error> {
+    let wav = synthesize(&text, voice)?;
+    let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+    let path = format!("/opt/sam/tmp/tts/{}.wav", timestamp);
+    std::fs::write(path.clone(), wav)?;
+    crate::sam::tools::linux_cmd(format!("aplay {}", path));
+    std::fs::remove_file(path)?;
+    Ok(())
+}
@@ -0,0 +1,258 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// Announcements through the house speakers. The chime (a file in
+// /opt/sam/chimes) and the spoken text are resampled to snapserver's pipe
+// format and written to the samfifo pipe while the chosen groups are
+// switched to that stream. Groups left playing something else are ducked to
+// tts_announce_duck_percent (30) of their volume. Everything is put back
+// once the announcement has played.
+//
+// A room plays through the group in its snapcast_group_id, or else the
+// group or client named like the room. No rooms or groups means everywhere.
+
+use crate::sam::services::media::snapcast::{Control, ServerStatus, Volume, FIFO_CHANNELS, FIFO_SAMPLE_RATE, SAM_FIFO, SAM_STREAM};
+use serde::{Serialize, Deserialize};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHIMES_DIR: &str = "/opt/sam/chimes";
+// Silence after the announcement so the last word isn't clipped
+const TAIL_MS: u64 = 300;
+// snapserver buffers about a second before the speakers play it
+const RESTORE_DELAY_MS: u64 = 1500;
+// O_NONBLOCK, opening the fifo fails instead of hanging when snapserver isn't reading
+const O_NONBLOCK: i32 = 0o4000;
+
+// One announcement at a time, otherwise the second would restore the first's stream
+static ANNOUNCING: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Announcement {
+    pub text: Option<String>,
+    pub chime: Option<String>,
+    pub voice: crate::sam::services::tts::Voice,
+    pub room_oids: Vec<String>,
+    pub group_ids: Vec<String>,
+    pub duck: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Announced {
+    pub group_ids: Vec<String>,
+    pub ducked_client_ids: Vec<String>,
+    pub duration_ms: u64,
+}
+
+// What to put back afterwards
+#[derive(Default)]
+struct Restore {
+    streams: Vec<(String, String)>,
+    volumes: Vec<(String, Volume)>,
+}
+
+pub fn announce(announcement: &Announcement) -> Result<Announced, crate::sam::services::Error> {
+    let text = announcement.text.clone().map(|t| t.trim().to_string()).filter(|t| t.len() > 0);
+    if text.is_none() && announcement.chime.is_none() {
+        return Err(format!("an announcement needs text or a chime").into());
+    }
+
+    // Synthesize before touching the speakers, tts can take a while
+    let mut pcm: Vec<u8> = Vec::new();
+    match &announcement.chime {
+        Some(chime) => pcm.extend(resample(&chime_path(chime)?)?),
+        None => {}
+    }
+    match &text {
+        Some(text) => {
+            let wav = crate::sam::services::tts::synthesize(text, &announcement.voice)?;
+            let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+            let path = format!("/opt/sam/tmp/tts/{}.announce.wav", timestamp);
+            std::fs::write(&path, wav)?;
+            let resampled = resample(&path);
+            let _ = std::fs::remove_file(&path);
+            pcm.extend(resampled?);
+        },
+        None => {}
+    }
+    let bytes_per_ms = (FIFO_SAMPLE_RATE * FIFO_CHANNELS * 2 / 1000) as usize;
+    pcm.extend(vec![0u8; TAIL_MS as usize * bytes_per_ms]);
+    let duration_ms = (pcm.len() / bytes_per_ms) as u64;
+
+    let _announcing = match ANNOUNCING.lock() {
+        Ok(announcing) => announcing,
+        Err(poisoned) => poisoned.into_inner()
+    };
+
+    let mut control = Control::connect()?;
+    let status = control.status()?;
+    if !status.streams.iter().any(|s| s.id == SAM_STREAM) {
+        return Err(format!("snapserver has no {} stream", SAM_STREAM).into());
+    }
+    let group_ids = targets(&status, &announcement.room_oids, &announcement.group_ids)?;
+
+    let mut restore = Restore::default();
+    let played = switch(&mut control, &status, &group_ids, announcement.duck, &mut restore)
+        .and_then(|_| play(&pcm, duration_ms));
+    if played.is_ok() {
+        thread::sleep(Duration::from_millis(RESTORE_DELAY_MS));
+    }
+
+    // The first connection may be the reason it failed
+    let restored = Control::connect().and_then(|mut control| put_back(&mut control, &restore));
+    played?;
+    restored?;
+
+    return Ok(Announced {
+        group_ids: group_ids,
+        ducked_client_ids: restore.volumes.iter().map(|(client_id, _)| client_id.clone()).collect(),
+        duration_ms: duration_ms,
+    });
+}
+
+fn chime_path(chime: &str) -> Result<String, crate::sam::services::Error> {
+    if chime.len() == 0 || !chime.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_') || chime.starts_with('.') {
+        return Err(format!("invalid chime {}", chime).into());
+    }
+    let path = format!("{}/{}", CHIMES_DIR, chime);
+    if !std::path::Path::new(&path).exists() {
+        return Err(format!("chime {} not found", chime).into());
+    }
+    return Ok(path);
+}
+
+// Any audio ffmpeg reads, as raw pcm in the pipe's sampleformat
+fn resample(path: &str) -> Result<Vec<u8>, crate::sam::services::Error> {
+    let output = format!("{}.pcm", path);
+    let converted = crate::sam::services::stt::run(Command::new("ffmpeg").args([
+        "-nostdin", "-loglevel", "error", "-y", "-i", path,
+        "-f", "s16le", "-ac", FIFO_CHANNELS.to_string().as_str(), "-ar", FIFO_SAMPLE_RATE.to_string().as_str(),
+        output.as_str(),
+    ]), Duration::from_secs(30));
+    let pcm = converted.and_then(|_| Ok(std::fs::read(&output)?));
+    let _ = std::fs::remove_file(&output);
+    return pcm;
+}
+
+pub fn targets(status: &ServerStatus, room_oids: &Vec<String>, group_ids: &Vec<String>) -> Result<Vec<String>, crate::sam::services::Error> {
+    if room_oids.len() == 0 && group_ids.len() == 0 {
+        return Ok(status.groups.iter().map(|g| g.id.clone()).collect());
+    }
+
+    let mut targets: Vec<String> = Vec::new();
+    for group_id in group_ids.iter() {
+        if !status.groups.iter().any(|g| &g.id == group_id) {
+            return Err(format!("snapcast group {} not found", group_id).into());
+        }
+        if !targets.contains(group_id) {
+            targets.push(group_id.clone());
+        }
+    }
+    for room_oid in room_oids.iter() {
+        let mut pg_query = crate::sam::memory::PostgresQueries::default();
+        pg_query.queries.push(crate::sam::memory::PGCol::String(room_oid.clone()));
+        pg_query.query_coulmns.push(format!("oid ="));
+        let room = match crate::sam::memory::Room::select(None, None, None, Some(pg_query))?.first() {
+            Some(room) => room.clone(),
+            None => return Err(format!("room {} not found", room_oid).into())
+        };
+        match group_for(status, &room) {
+            Some(group_id) => {
+                if !targets.contains(&group_id) {
+                    targets.push(group_id);
+                }
+            },
+            None => return Err(format!("room {} has no snapcast group", room.name).into())
+        }
+    }
+    return Ok(targets);
+}
+
+pub fn group_for(status: &ServerStatus, room: &crate::sam::memory::Room) -> Option<String> {
+    match &room.snapcast_group_id {
+        Some(group_id) => return status.groups.iter().find(|g| &g.id == group_id).map(|g| g.id.clone()),
+        None => {}
+    }
+    let name = room.name.trim().to_lowercase();
+    return status.groups.iter().find(|g| {
+        g.name.trim().to_lowercase() == name
+            || g.clients.iter().any(|c| c.config.name.trim().to_lowercase() == name || c.host.name.trim().to_lowercase() == name)
+    }).map(|g| g.id.clone());
+}
+
+fn switch(control: &mut Control, status: &ServerStatus, group_ids: &Vec<String>, duck: bool, restore: &mut Restore) -> Result<(), crate::sam::services::Error> {
+    for group in status.groups.iter().filter(|g| group_ids.contains(&g.id)) {
+        if group.stream_id != SAM_STREAM {
+            control.set_group_stream(&group.id, SAM_STREAM)?;
+            restore.streams.push((group.id.clone(), group.stream_id.clone()));
+        }
+    }
+    if !duck {
+        return Ok(());
+    }
+
+    let percent = crate::sam::services::tts::setting("tts_announce_duck_percent").and_then(|p| p.parse::<i64>().ok()).unwrap_or(30).clamp(0, 100);
+    let playing: Vec<&String> = status.streams.iter().filter(|s| s.status == "playing" && s.id != SAM_STREAM).map(|s| &s.id).collect();
+    for group in status.groups.iter().filter(|g| !group_ids.contains(&g.id) && !g.muted && playing.contains(&&g.stream_id)) {
+        for client in group.clients.iter().filter(|c| c.connected && !c.config.volume.muted) {
+            let ducked = Volume {
+                percent: client.config.volume.percent * percent / 100,
+                muted: false,
+            };
+            control.set_client_volume(&client.id, &ducked)?;
+            restore.volumes.push((client.id.clone(), client.config.volume.clone()));
+        }
+    }
+    return Ok(());
+}
+
+fn put_back(control: &mut Control, restore: &Restore) -> Result<(), crate::sam::services::Error> {
+    let mut failed: Vec<String> = Vec::new();
+    for (group_id, stream_id) in restore.streams.iter() {
+        match control.set_group_stream(group_id, stream_id) {
+            Ok(_) => {},
+            Err(e) => failed.push(format!("{}", e))
+        }
+    }
+    for (client_id, volume) in restore.volumes.iter() {
+        match control.set_client_volume(client_id, volume) {
+            Ok(_) => {},
+            Err(e) => failed.push(format!("{}", e))
+        }
+    }
+    if failed.len() > 0 {
+        return Err(format!("failed to restore snapcast ({})", failed.join(", ")).into());
+    }
+    return Ok(());
+}
+
+// The fifo only takes what snapserver has room for, so writing paces itself
+fn play(pcm: &[u8], duration_ms: u64) -> Result<(), crate::sam::services::Error> {
+    let mut fifo = std::fs::OpenOptions::new().write(true).custom_flags(O_NONBLOCK).open(SAM_FIFO)
+        .map_err(|e| format!("can't open {}, is snapserver running? {}", SAM_FIFO, e))?;
+    let deadline = Instant::now() + Duration::from_millis(duration_ms) + Duration::from_secs(10);
+    let mut written = 0;
+    while written < pcm.len() {
+        match fifo.write(&pcm[written..]) {
+            Ok(n) => written = written + n,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if Instant::now() > deadline {
+                    return Err(format!("snapserver stopped reading {}", SAM_FIFO).into());
+                }
+                thread::sleep(Duration::from_millis(10));
+            },
+            Err(e) => return Err(e.into())
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,100 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// The Coqui (Mozilla) TTS server api, GET /api/tts?text=&speaker_id=&style_wav=
+// answered with a wav. remote is the server in the Service row with the
+// identifier tts (endpoint, key as a bearer token or username/password as
+// basic auth), tts.opensam.foundation when the endpoint is left empty. local
+// is the embedded server on port 5002, used when enable_embedded_tts_server
+// is true.
+
+use crate::sam::services::tts::{TtsEngine, Voice};
+use std::time::Duration;
+
+const DEFAULT_ENDPOINT: &str = "https://tts.opensam.foundation";
+const LOCAL_ENDPOINT: &str = "http://localhost:5002";
+
+pub struct CoquiEngine {
+    pub name: &'static str,
+    pub remote: bool,
+}
+impl CoquiEngine {
+    pub fn remote() -> CoquiEngine {
+        CoquiEngine {
+            name: "remote",
+            remote: true,
+        }
+    }
+
+    pub fn local() -> CoquiEngine {
+        CoquiEngine {
+            name: "local",
+            remote: false,
+        }
+    }
+}
+
+pub fn service() -> Result<Option<crate::sam::memory::Service>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(format!("tts")));
+    pg_query.query_coulmns.push(format!("identifier ="));
+    let services = crate::sam::memory::Service::select(None, None, None, Some(pg_query))?;
+    return Ok(services.first().cloned());
+}
+
+impl TtsEngine for CoquiEngine {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn available(&self) -> bool {
+        if self.remote {
+            return match service() {
+                Ok(service) => service.is_some(),
+                Err(e) => {
+                    log::error!("failed to load tts service: {}", e);
+                    false
+                }
+            };
+        }
+        return crate::sam::services::tts::setting("enable_embedded_tts_server") == Some(format!("true"));
+    }
+
+    fn default_timeout(&self) -> Duration {
+        return Duration::from_secs(if self.remote { 5 } else { 10 });
+    }
+
+    fn synthesize(&self, text: &str, voice: &Voice, timeout: Duration) -> Result<Vec<u8>, crate::sam::services::Error> {
+        let service = if self.remote { service()? } else { None };
+        let endpoint = match &service {
+            Some(service) if service.endpoint.trim().len() > 0 => service.endpoint.trim().to_string(),
+            Some(_) => DEFAULT_ENDPOINT.to_string(),
+            None => LOCAL_ENDPOINT.to_string()
+        };
+
+        let client = reqwest::blocking::Client::builder().timeout(timeout).build()?;
+        let mut request = client.get(format!("{}/api/tts", endpoint.trim_end_matches('/')))
+            .query(&[
+                ("text", text.to_string()),
+                ("speaker_id", voice.speaker_id.clone().unwrap_or_default()),
+                ("style_wav", voice.style_wav.clone().unwrap_or_default()),
+            ]);
+        match &service {
+            Some(service) if service.key.len() > 0 => request = request.bearer_auth(&service.key),
+            Some(service) if service.username.len() > 0 => request = request.basic_auth(&service.username, Some(&service.password)),
+            _ => {}
+        }
+
+        let response = request.send()?;
+        if !response.status().is_success() {
+            return Err(format!("tts server answered {}", response.status()).into());
+        }
+        return Ok(response.bytes()?.to_vec());
+    }
+}
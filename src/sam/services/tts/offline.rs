@@ -0,0 +1,82 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// Speech without a network. piper at /opt/sam/bin/piper with the
+// tts_piper_voice model from /opt/sam/models/piper sounds far better and is
+// preferred, speaker_id picks the speaker of a multi speaker model. espeak-ng
+// is the fallback, speaker_id is taken as an espeak voice (en-gb) when it
+// isn't a number, otherwise tts_espeak_voice (en-us).
+
+use crate::sam::services::tts::{TtsEngine, Voice};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+const PIPER_BIN: &str = "/opt/sam/bin/piper";
+
+pub struct OfflineEngine;
+
+pub fn piper_model() -> String {
+    let voice = crate::sam::services::tts::setting("tts_piper_voice")
+        .filter(|v| v.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_'))
+        .unwrap_or(format!("en_US-lessac-medium"));
+    return format!("/opt/sam/models/piper/{}.onnx", voice);
+}
+
+fn has_piper() -> bool {
+    return Path::new(PIPER_BIN).exists() && Path::new(&piper_model()).exists();
+}
+
+fn has_espeak() -> bool {
+    let paths = std::env::var("PATH").unwrap_or_default();
+    return paths.split(':').any(|dir| Path::new(dir).join("espeak-ng").exists());
+}
+
+impl TtsEngine for OfflineEngine {
+    fn name(&self) -> &'static str {
+        "offline"
+    }
+
+    fn available(&self) -> bool {
+        return has_piper() || has_espeak();
+    }
+
+    fn default_timeout(&self) -> Duration {
+        return Duration::from_secs(10);
+    }
+
+    fn synthesize(&self, text: &str, voice: &Voice, timeout: Duration) -> Result<Vec<u8>, crate::sam::services::Error> {
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+        let path = format!("/opt/sam/tmp/tts/{}.offline.wav", timestamp);
+        let speaker_number = voice.speaker_id.clone().filter(|s| s.parse::<u32>().is_ok());
+
+        let result = if has_piper() {
+            let mut command = Command::new(PIPER_BIN);
+            command.args(["--model", piper_model().as_str(), "--output_file", path.as_str()]);
+            match &speaker_number {
+                Some(speaker) => {
+                    command.args(["--speaker", speaker.as_str()]);
+                },
+                None => {}
+            }
+            // piper reads a line of text per utterance
+            crate::sam::services::stt::run_with_input(&mut command, Some(text.replace('\n', " ").into_bytes()), timeout)
+        } else {
+            let espeak_voice = match &voice.speaker_id {
+                Some(speaker) if speaker_number.is_none() => speaker.clone(),
+                _ => crate::sam::services::tts::setting("tts_espeak_voice").unwrap_or(format!("en-us"))
+            };
+            crate::sam::services::stt::run(Command::new("espeak-ng").args(["-v", espeak_voice.as_str(), "-w", path.as_str(), "--", text]), timeout)
+        };
+
+        let wav = result.and_then(|_| Ok(std::fs::read(&path)?));
+        let _ = std::fs::remove_file(&path);
+        return wav;
+    }
+}
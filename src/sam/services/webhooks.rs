@@ -0,0 +1,222 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// webhooks.rs lets doorbells, NAS boxes, CI pipelines... tell sam something
+// happened by POSTing to /webhooks/{token}. Calls are signed with the
+// webhook's secret, either:
+//
+//   X-Sam-Timestamp: unix seconds
+//   X-Sam-Signature: sha256=hex(hmac_sha256(secret, "{timestamp}.{body}"))
+//
+// or github style X-Hub-Signature-256: sha256=hex(hmac_sha256(secret, body)).
+// Sam signatures older than SIGNATURE_TOLERANCE are refused so they can't be
+// replayed. Every call, accepted or not, is logged as a WebhookDelivery.
+
+use crate::sam::memory::{Webhook, WebhookAction, WebhookDelivery};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SIGNATURE_TOLERANCE: i64 = 300;
+
+// The signature headers of a request
+#[derive(Debug, Clone, Default)]
+pub struct WebhookSignature {
+    pub sam_signature: Option<String>,
+    pub sam_timestamp: Option<String>,
+    pub hub_signature: Option<String>,
+}
+
+fn now() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+}
+
+pub fn hmac_sha256_hex(secret: &str, data: &[u8]) -> Result<String, crate::sam::services::Error> {
+    let key = PKey::hmac(secret.as_bytes()).map_err(|e| format!("{}", e))?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(|e| format!("{}", e))?;
+    signer.update(data).map_err(|e| format!("{}", e))?;
+    let signature = signer.sign_to_vec().map_err(|e| format!("{}", e))?;
+    return Ok(signature.iter().map(|b| format!("{:02x}", b)).collect());
+}
+
+fn signature_matches(secret: &str, data: &[u8], header: &str) -> bool {
+    let given = header.trim().trim_start_matches("sha256=").to_lowercase();
+    match hmac_sha256_hex(secret, data) {
+        Ok(expected) => expected.len() == given.len() && openssl::memcmp::eq(expected.as_bytes(), given.as_bytes()),
+        Err(e) => {
+            log::error!("failed to sign webhook body: {}", e);
+            false
+        }
+    }
+}
+
+pub fn verify_signature(secret: &str, body: &[u8], signature: &WebhookSignature, timestamp: i64) -> bool {
+    match (&signature.sam_signature, &signature.sam_timestamp) {
+        (Some(header), Some(sent_at)) => {
+            let sent_at = match sent_at.trim().parse::<i64>() {
+                Ok(sent_at) => sent_at,
+                Err(_) => return false
+            };
+            if (timestamp - sent_at).abs() > SIGNATURE_TOLERANCE {
+                return false;
+            }
+            let mut signed = format!("{}.", sent_at).into_bytes();
+            signed.extend_from_slice(body);
+            return signature_matches(secret, &signed, header);
+        },
+        _ => {}
+    }
+    match &signature.hub_signature {
+        Some(header) => return signature_matches(secret, body, header),
+        None => return false
+    }
+}
+
+pub fn find_by_token(token: &str) -> Result<Option<Webhook>, crate::sam::services::Error> {
+    let mut pg_query = crate::sam::memory::PostgresQueries::default();
+    pg_query.queries.push(crate::sam::memory::PGCol::String(token.to_string()));
+    pg_query.query_coulmns.push(format!("token ="));
+    let webhooks = Webhook::select(None, None, None, Some(pg_query))?;
+    return Ok(webhooks.first().cloned());
+}
+
+// Picks the mapped fields out of the payload, the whole payload when there's no mapping
+pub fn map_payload(webhook: &Webhook, payload: &serde_json::Value) -> serde_json::Value {
+    if webhook.mapping.len() == 0 {
+        return payload.clone();
+    }
+    let mut mapped = serde_json::Map::new();
+    for mapping in webhook.mapping.iter() {
+        mapped.insert(mapping.field.clone(), payload.pointer(&mapping.pointer).cloned().unwrap_or(serde_json::Value::Null));
+    }
+    return serde_json::Value::Object(mapped);
+}
+
+// Replaces {{field}} with mapped values, {{name}} is the webhook's name
+pub fn render(template: &str, webhook: &Webhook, mapped: &serde_json::Value) -> String {
+    let mut rendered = template.replace("{{name}}", &webhook.name);
+    match mapped.as_object() {
+        Some(fields) => {
+            for (field, value) in fields {
+                let value = match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    serde_json::Value::Null => String::new(),
+                    other => other.to_string()
+                };
+                rendered = rendered.replace(&format!("{{{{{}}}}}", field), &value);
+            }
+        },
+        None => {}
+    }
+    return rendered;
+}
+
+// Handles a call to /webhooks/{token}. Returns the http status and the
+// delivery, None when the token doesn't belong to a webhook.
+pub fn receive(token: &str, body: &[u8], signature: &WebhookSignature, remote_addr: String) -> Result<(u16, Option<WebhookDelivery>), crate::sam::services::Error> {
+    let mut webhook = match find_by_token(token)? {
+        Some(webhook) => webhook,
+        None => return Ok((404, None))
+    };
+
+    let timestamp = now();
+    let mut delivery = WebhookDelivery::new();
+    delivery.webhook_oid = webhook.oid.clone();
+    delivery.remote_addr = remote_addr;
+    delivery.timestamp = timestamp;
+    delivery.signature_valid = verify_signature(&webhook.secret, body, signature, timestamp);
+
+    let text = String::from_utf8_lossy(body).to_string();
+    delivery.payload = serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text));
+
+    let code = if !webhook.enabled {
+        delivery.status = format!("rejected");
+        delivery.error = Some(format!("webhook is disabled"));
+        403
+    } else if webhook.require_signature && !delivery.signature_valid {
+        delivery.status = format!("rejected");
+        delivery.error = Some(format!("missing or invalid signature"));
+        401
+    } else {
+        delivery.mapped = map_payload(&webhook, &delivery.payload);
+        let mut errors: Vec<String> = Vec::new();
+        for action in webhook.actions.iter() {
+            match run_action(&webhook, action, &delivery.mapped) {
+                Ok(result) => delivery.results.push(result),
+                Err(e) => errors.push(format!("{}", e))
+            }
+        }
+        if errors.len() > 0 {
+            delivery.status = format!("failed");
+            delivery.error = Some(errors.join("; "));
+            500
+        } else {
+            delivery.status = format!("accepted");
+            202
+        }
+    };
+
+    match delivery.save() {
+        Ok(_) => {},
+        Err(e) => log::error!("failed to log webhook delivery: {}", e)
+    }
+
+    webhook.last_delivery_at = Some(timestamp);
+    webhook.updated_at = timestamp;
+    match webhook.save() {
+        Ok(_) => {},
+        Err(e) => log::error!("failed to save webhook {}: {}", webhook.name, e)
+    }
+
+    return Ok((code, Some(delivery)));
+}
+
+fn run_action(webhook: &Webhook, action: &WebhookAction, mapped: &serde_json::Value) -> Result<String, crate::sam::services::Error> {
+    match action {
+        WebhookAction::Observation{thing_oid, notes} => {
+            let mut observation = crate::sam::memory::Observation::new();
+            observation.observation_type = crate::sam::memory::ObservationType::WEBHOOK;
+            observation.observation_notes = if notes.len() > 0 {
+                notes.iter().map(|n| render(n, webhook, mapped)).collect()
+            } else {
+                vec![format!("{} webhook", webhook.name)]
+            };
+            match thing_oid {
+                Some(thing_oid) => observation.thing = crate::sam::services::things::find(thing_oid)?,
+                None => {}
+            }
+            let mut saved = observation.save()?;
+            saved.thing = observation.thing.clone();
+            crate::sam::services::bus::publish(crate::sam::services::bus::Event::ObservationRecorded{
+                observation: saved.clone(),
+                room_oid: observation.thing.as_ref().map(|t| t.room_oid.clone()),
+            });
+            return Ok(format!("recorded observation {}", saved.oid));
+        },
+        WebhookAction::Notification{message, human_oid} => {
+            let human_oids = match human_oid {
+                Some(human_oid) => vec![human_oid.clone()],
+                None => crate::sam::services::notifications::everyone()?
+            };
+            let message = render(message, webhook, mapped);
+            for human_oid in human_oids.iter() {
+                let mut notification = crate::sam::memory::Notification::new();
+                notification.message = message.clone();
+                notification.human_oid = human_oid.clone();
+                crate::sam::services::notifications::create(notification);
+            }
+            return Ok(format!("notified {} human(s)", human_oids.len()));
+        },
+        WebhookAction::Event => {
+            crate::sam::services::automations::webhook(webhook.name.clone(), mapped.clone());
+            return Ok(format!("published WebhookReceived {}", webhook.name));
+        }
+    }
+}
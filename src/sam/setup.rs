@@ -44,22 +44,22 @@ pub async fn install() {
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/models/nst"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/files"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/fonts"));
+    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/chimes"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/games"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/scripts"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/scripts/rivescript"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/scripts/who.io"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/scripts/who.io/dataset"));
-    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/scripts/sprec"));
-    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/scripts/sprec/audio"));
-    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/scripts/sprec/noise"));
-    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/scripts/sprec/noise/_background_noise_"));
-    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/scripts/sprec/noise/other"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/youtube"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/youtube/downloads"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/sound"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/observations"));
     crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/observations/vwav"));
+    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/tts"));
+    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/tts/cache"));
+    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/models/piper"));
+    crate::sam::tools::linux_cmd(format!("mkdir /opt/sam/tmp/recordings"));
     match crate::sam::services::darknet::install(){
         Ok(_) => {
             log::info!("darknet installed successfully");
@@ -7,6 +7,8 @@
 // Developed by Caleb Mitchell Smith (PixelCoda)
 // Licensed under GPLv3....see LICENSE file.
 
+pub mod cron;
+
 use std::fs;
 use std::io;
 use std::path::{Path};
@@ -83,29 +85,25 @@ pub fn linux_cmd(command: String){
     }
 }
 
+// True when the voice activity detector hears speech anywhere in the file
 pub fn does_wav_have_sounds(audio_filename: String) -> Result<bool>{
-    let mut has_sounds = false;
-	let threshold = 14000 as i16;
-
-	let mut audio_file = hound::WavReader::open(audio_filename)?;
-
-	let raw_samples = audio_file.samples::<i16>().into_iter().map(|x| x.unwrap()).collect::<Vec<i16>>();
-
-	let mut samples: Vec<i16> = Vec::new();
-
-	for i in 0..=raw_samples.len() - 1 {
-		if i % 100 == 0 {
-	
-
-			if raw_samples[i as usize] > threshold || raw_samples[i as usize] < -threshold{
-				has_sounds = true;
-			}
-
-			samples.push(raw_samples[i as usize]);
-		}
-	}
-
-    return Ok(has_sounds);
+    let audio_file = hound::WavReader::open(audio_filename)?;
+    let spec = audio_file.spec();
+    let channels = spec.channels.max(1) as usize;
+
+    // Only the first channel is listened to
+    let raw_samples = audio_file.into_samples::<i16>().collect::<std::result::Result<Vec<i16>, hound::Error>>()?;
+    let samples: Vec<i16> = raw_samples.iter().step_by(channels).cloned().collect();
+
+    let frame = (spec.sample_rate as usize * 30 / 1000).max(1);
+    let threshold = crate::sam::services::sound::vad::threshold_for(None);
+    let mut vad = crate::sam::services::sound::vad::Vad::new(spec.sample_rate, threshold);
+    for chunk in samples.chunks(frame) {
+        if vad.is_speech_samples(chunk) {
+            return Ok(true);
+        }
+    }
+    return Ok(false);
 }
 
 
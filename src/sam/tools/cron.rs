@@ -0,0 +1,293 @@
+// ███████     █████     ███    ███    
+// ██         ██   ██    ████  ████    
+// ███████    ███████    ██ ████ ██    
+//      ██    ██   ██    ██  ██  ██    
+// ███████ ██ ██   ██ ██ ██      ██ ██ 
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// cron.rs parses standard 5 field cron expressions
+// (minute hour day-of-month month day-of-week) in local time.
+// Supports *, lists (1,2,3), ranges (1-5), steps (*/15, 1-30/5)
+// and the @hourly, @daily, @weekly, @monthly and @yearly shortcuts.
+
+use chrono::{DateTime, Datelike, Duration, Local, Timelike, TimeZone};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    pub minutes: Vec<u32>,
+    pub hours: Vec<u32>,
+    pub days_of_month: Vec<u32>,
+    pub months: Vec<u32>,
+    pub days_of_week: Vec<u32>,
+    // Standard cron ORs day-of-month and day-of-week when both are restricted
+    day_of_month_any: bool,
+    day_of_week_any: bool,
+}
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<CronSchedule, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 fields but found {}", fields.len()));
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7)?;
+        // Sunday can be written as 0 or 7
+        if days_of_week.contains(&7) {
+            days_of_week.retain(|d| *d != 7);
+            if !days_of_week.contains(&0) {
+                days_of_week.insert(0, 0);
+            }
+        }
+
+        return Ok(CronSchedule {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week: days_of_week,
+            day_of_month_any: fields[2] == "*",
+            day_of_week_any: fields[4] == "*",
+        });
+    }
+
+    pub fn matches(&self, time: &DateTime<Local>) -> bool {
+        if !self.minutes.contains(&time.minute()) || !self.hours.contains(&time.hour()) || !self.months.contains(&time.month()) {
+            return false;
+        }
+        return self.day_matches(time);
+    }
+
+    // Next matching minute strictly after the given time, searches up to 4 years ahead.
+    // Steps through wall clock time, minutes skipped by a DST change never happen
+    // and minutes repeated by one run the first time.
+    pub fn next_after<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let timezone = time.timezone();
+        let start = time.naive_local();
+        let mut candidate = (start + Duration::minutes(1)).with_second(0)?.with_nanosecond(0)?;
+
+        let limit = start + Duration::days(366 * 4);
+        while candidate < limit {
+            if !self.months.contains(&candidate.month()) || !self.day_matches(&candidate) {
+                // Skip to the start of the next day
+                candidate = (candidate.date() + Duration::days(1)).and_hms(0, 0, 0);
+                continue;
+            }
+            if !self.hours.contains(&candidate.hour()) {
+                candidate = (candidate + Duration::hours(1)).with_minute(0)?;
+                continue;
+            }
+            if self.minutes.contains(&candidate.minute()) {
+                match timezone.from_local_datetime(&candidate).earliest() {
+                    Some(next) if next > *time => return Some(next),
+                    _ => {}
+                }
+            }
+            candidate = candidate + Duration::minutes(1);
+        }
+        return None;
+    }
+
+    fn day_matches<D: Datelike>(&self, time: &D) -> bool {
+        let dom = self.days_of_month.contains(&time.day());
+        let dow = self.days_of_week.contains(&time.weekday().num_days_from_sunday());
+        if self.day_of_month_any || self.day_of_week_any {
+            return dom && dow;
+        }
+        return dom || dow;
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>, String> {
+    let mut values: Vec<u32> = Vec::new();
+
+    for part in field.split(",") {
+        let (range, step) = match part.split_once("/") {
+            Some((range, step)) => {
+                let step = step.parse::<u32>().map_err(|_| format!("invalid step: {}", part))?;
+                if step == 0 {
+                    return Err(format!("invalid step: {}", part));
+                }
+                (range, step)
+            },
+            None => (part, 1)
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else {
+            match range.split_once("-") {
+                Some((start, end)) => {
+                    let start = start.parse::<u32>().map_err(|_| format!("invalid range: {}", part))?;
+                    let end = end.parse::<u32>().map_err(|_| format!("invalid range: {}", part))?;
+                    (start, end)
+                },
+                None => {
+                    let value = range.parse::<u32>().map_err(|_| format!("invalid value: {}", part))?;
+                    // "5/10" means starting at 5 every 10
+                    if part.contains("/") {
+                        (value, max)
+                    } else {
+                        (value, value)
+                    }
+                }
+            }
+        };
+
+        if start < min || end > max || start > end {
+            return Err(format!("{} is out of range {}-{}", part, min, max));
+        }
+
+        let mut value = start;
+        while value <= end {
+            if !values.contains(&value) {
+                values.push(value);
+            }
+            value = value + step;
+        }
+    }
+
+    values.sort();
+    return Ok(values);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, LocalResult, NaiveDate, NaiveDateTime};
+
+    // US eastern time in 2023: clocks jump from 02:00 to 03:00 on March 12th
+    // and fall back from 02:00 to 01:00 on November 5th
+    #[derive(Debug, Clone, Copy)]
+    struct Eastern;
+    impl Eastern {
+        fn est() -> FixedOffset { FixedOffset::west(5 * 3600) }
+        fn edt() -> FixedOffset { FixedOffset::west(4 * 3600) }
+        fn spring() -> NaiveDateTime { NaiveDate::from_ymd(2023, 3, 12).and_hms(2, 0, 0) }
+        fn fall() -> NaiveDateTime { NaiveDate::from_ymd(2023, 11, 5).and_hms(1, 0, 0) }
+    }
+    impl TimeZone for Eastern {
+        type Offset = FixedOffset;
+        fn from_offset(_offset: &FixedOffset) -> Eastern { Eastern }
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            return self.offset_from_local_datetime(&local.and_hms(12, 0, 0));
+        }
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            if *local < Eastern::spring() || *local >= Eastern::fall() + Duration::hours(1) {
+                return LocalResult::Single(Eastern::est());
+            }
+            if *local < Eastern::spring() + Duration::hours(1) {
+                return LocalResult::None;
+            }
+            if *local >= Eastern::fall() {
+                return LocalResult::Ambiguous(Eastern::edt(), Eastern::est());
+            }
+            return LocalResult::Single(Eastern::edt());
+        }
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            return self.offset_from_utc_datetime(&utc.and_hms(12, 0, 0));
+        }
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            if *utc >= Eastern::spring() + Duration::hours(5) && *utc < Eastern::fall() + Duration::hours(5) {
+                return Eastern::edt();
+            }
+            return Eastern::est();
+        }
+    }
+
+    fn eastern(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Eastern> {
+        return Eastern.from_local_datetime(&NaiveDate::from_ymd(y, mo, d).and_hms(h, mi, 0)).earliest().unwrap();
+    }
+
+    fn next(expression: &str, after: DateTime<Eastern>) -> Option<String> {
+        let next = CronSchedule::parse(expression).unwrap().next_after(&after)?;
+        return Some(format!("{} {}", next.naive_local(), next.offset()));
+    }
+
+    #[test]
+    fn parses_fields() {
+        let schedule = CronSchedule::parse("*/15 9-17 1,15 * 1-5").unwrap();
+        assert_eq!(schedule.minutes, vec![0, 15, 30, 45]);
+        assert_eq!(schedule.hours, vec![9, 10, 11, 12, 13, 14, 15, 16, 17]);
+        assert_eq!(schedule.days_of_month, vec![1, 15]);
+        assert_eq!(schedule.months.len(), 12);
+        assert_eq!(schedule.days_of_week, vec![1, 2, 3, 4, 5]);
+
+        assert_eq!(CronSchedule::parse("5/20 * * * *").unwrap().minutes, vec![5, 25, 45]);
+        assert_eq!(CronSchedule::parse("1-30/10 * * * *").unwrap().minutes, vec![1, 11, 21]);
+        assert_eq!(CronSchedule::parse("0 0 * * 7").unwrap().days_of_week, vec![0]);
+        assert_eq!(CronSchedule::parse("0 0 * * 5,7").unwrap().days_of_week, vec![0, 5]);
+        assert_eq!(CronSchedule::parse("@weekly").unwrap(), CronSchedule::parse("0 0 * * 0").unwrap());
+        assert_eq!(CronSchedule::parse("  @daily ").unwrap(), CronSchedule::parse("0 0 * * *").unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("* * * * * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("* 24 * * *").is_err());
+        assert!(CronSchedule::parse("* * 0 * *").is_err());
+        assert!(CronSchedule::parse("* * * 13 *").is_err());
+        assert!(CronSchedule::parse("* * * * 8").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("10-5 * * * *").is_err());
+        assert!(CronSchedule::parse("a * * * *").is_err());
+        assert!(CronSchedule::parse("@sometimes").is_err());
+    }
+
+    #[test]
+    fn next_run_times() {
+        // A Monday
+        let monday = eastern(2023, 1, 2, 10, 7);
+        assert_eq!(next("*/15 * * * *", monday), Some(format!("2023-01-02 10:15:00 -05:00")));
+        assert_eq!(next("0 9 * * *", monday), Some(format!("2023-01-03 09:00:00 -05:00")));
+        assert_eq!(next("30 8 * * 0", monday), Some(format!("2023-01-08 08:30:00 -05:00")));
+        assert_eq!(next("0 0 1 * *", monday), Some(format!("2023-02-01 00:00:00 -05:00")));
+        // Strictly after, a time that matches itself moves on
+        assert_eq!(next("7 10 * * *", monday), Some(format!("2023-01-03 10:07:00 -05:00")));
+        // Restricted day of month and day of week means either
+        assert_eq!(next("0 12 13 * 5", monday), Some(format!("2023-01-06 12:00:00 -05:00")));
+        assert_eq!(next("0 0 29 2 *", monday), Some(format!("2024-02-29 00:00:00 -05:00")));
+        assert_eq!(next("0 0 30 2 *", monday), None);
+    }
+
+    #[test]
+    fn spring_forward() {
+        let before = eastern(2023, 3, 12, 1, 0);
+        // 02:30 doesn't exist that night, the next one is a day later
+        assert_eq!(next("30 2 * * *", before), Some(format!("2023-03-13 02:30:00 -04:00")));
+        assert_eq!(next("0 3 * * *", before), Some(format!("2023-03-12 03:00:00 -04:00")));
+        assert_eq!(next("*/20 * * * *", eastern(2023, 3, 12, 1, 50)), Some(format!("2023-03-12 03:00:00 -04:00")));
+        // Daily jobs keep running, the day after the gap included
+        let schedule = CronSchedule::parse("0 0 * * *").unwrap();
+        let mut time = eastern(2023, 3, 10, 12, 0);
+        for day in 11..16 {
+            time = schedule.next_after(&time).unwrap();
+            assert_eq!(time.naive_local(), NaiveDate::from_ymd(2023, 3, day).and_hms(0, 0, 0));
+        }
+    }
+
+    #[test]
+    fn fall_back() {
+        let before = eastern(2023, 11, 5, 0, 0);
+        // 01:30 happens twice, the job runs the first time only
+        let first = CronSchedule::parse("30 1 * * *").unwrap().next_after(&before).unwrap();
+        assert_eq!(format!("{} {}", first.naive_local(), first.offset()), "2023-11-05 01:30:00 -04:00");
+        assert_eq!(next("30 1 * * *", first), Some(format!("2023-11-06 01:30:00 -05:00")));
+        // From inside the repeated hour it doesn't go back in time
+        let repeated = Eastern.from_local_datetime(&NaiveDate::from_ymd(2023, 11, 5).and_hms(1, 40, 0)).latest().unwrap();
+        assert_eq!(next("45 1 * * *", repeated), Some(format!("2023-11-06 01:45:00 -05:00")));
+        assert_eq!(next("0 2 * * *", repeated), Some(format!("2023-11-05 02:00:00 -05:00")));
+    }
+}